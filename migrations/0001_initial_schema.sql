@@ -0,0 +1,52 @@
+-- Initial schema for the SQL storage backend.
+-- Written in the common subset of SQLite and PostgreSQL so a single set of
+-- migrations serves both. IDs are ObjectId hex strings generated by the
+-- application, booleans are stored as 0/1.
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    email TEXT,
+    full_name TEXT,
+    password_hash TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    last_login BIGINT,
+    is_active SMALLINT NOT NULL DEFAULT 1,
+    role TEXT NOT NULL DEFAULT 'member'
+);
+
+CREATE TABLE urls (
+    id TEXT PRIMARY KEY,
+    original_url TEXT NOT NULL,
+    short_code TEXT NOT NULL UNIQUE,
+    created_at BIGINT,
+    expires_at BIGINT,
+    clicks BIGINT NOT NULL DEFAULT 0,
+    user_id TEXT
+);
+
+CREATE INDEX idx_urls_user_id ON urls (user_id);
+
+CREATE TABLE visitors (
+    id TEXT PRIMARY KEY,
+    short_code TEXT NOT NULL,
+    visitor_hash TEXT NOT NULL,
+    "timestamp" BIGINT NOT NULL,
+    user_agent TEXT,
+    referrer TEXT,
+    UNIQUE (short_code, visitor_hash)
+);
+
+CREATE TABLE qr_codes (
+    id TEXT PRIMARY KEY,
+    short_code TEXT NOT NULL,
+    original_url TEXT NOT NULL,
+    svg_content TEXT NOT NULL,
+    generated_at BIGINT NOT NULL,
+    target_type TEXT NOT NULL,
+    user_id TEXT,
+    UNIQUE (short_code, target_type)
+);
+
+CREATE INDEX idx_qr_codes_user_id ON qr_codes (user_id);
@@ -0,0 +1,27 @@
+-- Refresh tokens and the access token revocation list.
+-- Timestamps are Unix milliseconds, like the rest of the schema.
+
+CREATE TABLE refresh_tokens (
+    id TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_id TEXT NOT NULL,
+    family_id TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked_at BIGINT
+);
+
+CREATE INDEX idx_refresh_tokens_user_id ON refresh_tokens (user_id);
+CREATE INDEX idx_refresh_tokens_family_id ON refresh_tokens (family_id);
+
+-- Individually revoked access tokens, kept until they would have expired anyway
+CREATE TABLE revoked_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at BIGINT NOT NULL
+);
+
+-- Access tokens issued to a user before `revoked_before` are no longer accepted
+CREATE TABLE user_token_revocations (
+    user_id TEXT PRIMARY KEY,
+    revoked_before BIGINT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Failed login tracking and account lockout
+
+ALTER TABLE users ADD COLUMN failed_login_attempts BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN last_failed_login BIGINT;
+ALTER TABLE users ADD COLUMN locked_at BIGINT;
+ALTER TABLE users ADD COLUMN locked_until BIGINT;
@@ -0,0 +1,16 @@
+-- TOTP second factor. Recovery codes get their own table so using one is a single DELETE.
+
+CREATE TABLE totp_credentials (
+    user_id TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    enabled SMALLINT NOT NULL DEFAULT 0,
+    last_used_step BIGINT,
+    created_at BIGINT NOT NULL,
+    enabled_at BIGINT
+);
+
+CREATE TABLE totp_recovery_codes (
+    user_id TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
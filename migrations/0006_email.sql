@@ -0,0 +1,18 @@
+-- Email verification and single-use tokens for password resets and email verification
+
+ALTER TABLE users ADD COLUMN email_verified_at BIGINT;
+
+CREATE INDEX idx_users_email ON users (email);
+
+CREATE TABLE one_time_tokens (
+    id TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_id TEXT NOT NULL,
+    purpose TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at BIGINT
+);
+
+CREATE INDEX idx_one_time_tokens_user_id ON one_time_tokens (user_id);
@@ -0,0 +1,14 @@
+-- Accounts at external OpenID Connect providers, linked to users
+
+CREATE TABLE external_identities (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at BIGINT NOT NULL,
+    last_login_at BIGINT,
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX idx_external_identities_user_id ON external_identities (user_id);
@@ -0,0 +1,23 @@
+-- Invitations to sign up. Each signup made with an invite gets a row in invite_uses;
+-- use_count lets a signup claim a use with a single conditional UPDATE.
+
+CREATE TABLE invites (
+    id TEXT PRIMARY KEY,
+    code_prefix TEXT NOT NULL,
+    code_hash TEXT NOT NULL UNIQUE,
+    email TEXT,
+    role TEXT NOT NULL,
+    max_uses BIGINT NOT NULL,
+    use_count BIGINT NOT NULL DEFAULT 0,
+    created_by TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT
+);
+
+CREATE TABLE invite_uses (
+    invite_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    username TEXT NOT NULL,
+    used_at BIGINT NOT NULL,
+    PRIMARY KEY (invite_id, user_id)
+);
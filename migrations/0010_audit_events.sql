@@ -0,0 +1,18 @@
+-- Append-only log of changes made through the API. changes holds a JSON object of
+-- the changed fields with their values before and after.
+
+CREATE TABLE audit_events (
+    id TEXT PRIMARY KEY,
+    actor_id TEXT,
+    actor_username TEXT,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    changes TEXT,
+    ip_hash TEXT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX audit_events_created_at ON audit_events (created_at, id);
+CREATE INDEX audit_events_actor_id ON audit_events (actor_id, created_at);
+CREATE INDEX audit_events_action ON audit_events (action, created_at);
@@ -0,0 +1,28 @@
+-- Workspaces own short URLs and QR codes on behalf of a team. Each member has a role
+-- in workspace_members; refresh_tokens.workspace_id keeps the workspace a session
+-- switched to across token refreshes.
+
+CREATE TABLE workspaces (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE workspace_members (
+    workspace_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    added_at BIGINT NOT NULL,
+    PRIMARY KEY (workspace_id, user_id)
+);
+
+CREATE INDEX workspace_members_user_id ON workspace_members (user_id);
+
+ALTER TABLE urls ADD COLUMN workspace_id TEXT;
+ALTER TABLE qr_codes ADD COLUMN workspace_id TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN workspace_id TEXT;
+
+CREATE INDEX urls_workspace_id ON urls (workspace_id);
+CREATE INDEX qr_codes_workspace_id ON qr_codes (workspace_id);
@@ -0,0 +1,3 @@
+-- Short URLs of a deleted user can be kept but disabled; they then no longer redirect.
+
+ALTER TABLE urls ADD COLUMN disabled_at BIGINT;
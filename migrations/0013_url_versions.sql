@@ -0,0 +1,17 @@
+-- Short URLs can be edited. Every edit increments urls.version and keeps the state it
+-- replaced in url_versions, so earlier destinations can be listed and restored.
+
+ALTER TABLE urls ADD COLUMN title TEXT;
+ALTER TABLE urls ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+CREATE TABLE url_versions (
+    id TEXT PRIMARY KEY,
+    short_code TEXT NOT NULL,
+    version BIGINT NOT NULL,
+    original_url TEXT NOT NULL,
+    expires_at BIGINT,
+    title TEXT,
+    replaced_at BIGINT NOT NULL,
+    replaced_by TEXT,
+    UNIQUE (short_code, version)
+);
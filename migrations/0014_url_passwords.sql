@@ -0,0 +1,3 @@
+-- Short URLs can be protected by a password, which visitors enter before being redirected.
+
+ALTER TABLE urls ADD COLUMN password_hash TEXT;
@@ -0,0 +1,941 @@
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use actix_web::http::Method;
+use actix_web::http::header::HeaderName;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::utils::client_ip::IpNetwork;
+
+/// Configuration file read when `CONFIG_FILE` is not set. It is optional.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Application configuration, loaded once at startup from an optional TOML file
+/// and overridden by environment variables
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cors: CorsConfig,
+    pub tls: TlsConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub mail: MailConfig,
+    pub policy: PolicyConfig,
+    pub oidc: OidcConfig,
+    pub webauthn: WebauthnConfig,
+    pub audit: AuditConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server binds to (`BIND_ADDRESS`)
+    pub bind_address: String,
+    /// Port the HTTP server listens on (`PORT`)
+    pub port: u16,
+    /// Public base URL used to build short links and QR codes (`HOST`)
+    pub public_url: String,
+    /// Listen on this Unix domain socket instead of a TCP address (`UNIX_SOCKET`)
+    pub unix_socket: Option<String>,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed, as IP addresses or
+    /// CIDR ranges (`TRUSTED_PROXIES`)
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "127.0.0.1".to_string(),
+            port: 8080,
+            public_url: "http://localhost:8080".to_string(),
+            unix_socket: None,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, or `*` for any (`CORS_ALLOWED_ORIGINS`)
+    pub allowed_origins: Vec<String>,
+    /// HTTP methods allowed in cross-origin requests (`CORS_ALLOWED_METHODS`)
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests (`CORS_ALLOWED_HEADERS`)
+    pub allowed_headers: Vec<String>,
+    /// Allow cookies and authorization headers; cannot be combined with `*` origins
+    /// (`CORS_SUPPORTS_CREDENTIALS`)
+    pub supports_credentials: bool,
+    /// How long browsers may cache preflight responses, in seconds
+    pub max_age: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![
+                "http://localhost:5173".to_string(), // Default Bun dev server port
+                "http://localhost:4173".to_string(),
+            ],
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: ["Authorization", "Accept", "Content-Type", "X-API-Key"]
+                .map(String::from)
+                .to_vec(),
+            supports_credentials: true,
+            max_age: 3600,
+        }
+    }
+}
+
+/// Native TLS termination, enabled when both files are set.
+/// The certificate is reloaded from disk when the process receives SIGHUP.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain (`TLS_CERT_FILE`)
+    pub cert_file: Option<String>,
+    /// PEM private key (`TLS_KEY_FILE`)
+    pub key_file: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.cert_file.is_some() && self.key_file.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    MongoDb,
+    Sql,
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mongodb" => Ok(StorageBackend::MongoDb),
+            "sql" => Ok(StorageBackend::Sql),
+            "memory" => Ok(StorageBackend::Memory),
+            other => Err(anyhow::anyhow!(
+                "Unknown storage backend: {} (expected mongodb, sql or memory)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Which storage backend to use (`STORAGE_BACKEND`)
+    pub backend: StorageBackend,
+    /// MongoDB connection string (`MONGODB_URL`)
+    pub mongodb_url: Option<String>,
+    /// MongoDB database name (`MONGODB_DATABASE`)
+    pub mongodb_database: String,
+    /// SQLite or PostgreSQL connection string (`DATABASE_URL`)
+    pub database_url: Option<String>,
+    /// Apply pending migrations at startup (`MIGRATE_ON_STARTUP`)
+    pub migrate_on_startup: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            mongodb_url: None,
+            mongodb_database: "url_db".to_string(),
+            database_url: None,
+            migrate_on_startup: true,
+        }
+    }
+}
+
+/// Algorithm used to sign access tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum JwtAlgorithm {
+    #[default]
+    HS256,
+    RS256,
+    EdDSA,
+}
+
+impl FromStr for JwtAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HS256" => Ok(JwtAlgorithm::HS256),
+            "RS256" => Ok(JwtAlgorithm::RS256),
+            "EdDSA" => Ok(JwtAlgorithm::EdDSA),
+            other => Err(anyhow::anyhow!(
+                "Unknown JWT algorithm: {} (expected HS256, RS256 or EdDSA)",
+                other
+            )),
+        }
+    }
+}
+
+/// A private key used to sign (or, once retired, only to verify) access tokens
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningKeyConfig {
+    /// Key ID published in the JWKS and set as `kid` in token headers
+    pub kid: String,
+    /// PEM private key (PKCS#8, or PKCS#1 for RSA)
+    pub private_key_file: String,
+    /// When the key stopped signing new tokens. Tokens it signed are accepted
+    /// for one more token lifetime, after which the key is dropped.
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Algorithm used to sign access tokens (`JWT_ALGORITHM`)
+    pub jwt_algorithm: JwtAlgorithm,
+    /// Secret used to sign HS256 tokens (`JWT_SECRET`)
+    pub jwt_secret: String,
+    /// Keys used to sign RS256 and EdDSA tokens. Exactly one must not be retired.
+    /// A single key can also be given with `JWT_SIGNING_KEY_ID` and `JWT_SIGNING_KEY_FILE`.
+    pub signing_keys: Vec<SigningKeyConfig>,
+    /// `iss` claim of issued tokens (`JWT_ISSUER`)
+    pub jwt_issuer: String,
+    /// `aud` claim of issued tokens (`JWT_AUDIENCE`)
+    pub jwt_audience: String,
+    /// How long issued access tokens stay valid, in minutes (`TOKEN_LIFETIME_MINUTES`)
+    pub token_lifetime_minutes: i64,
+    /// How long refresh tokens stay valid, in days (`REFRESH_TOKEN_LIFETIME_DAYS`)
+    pub refresh_token_lifetime_days: i64,
+    /// Failed logins after which an account is locked (`MAX_FAILED_LOGINS`)
+    pub max_failed_logins: i64,
+    /// Failed logins after which a client IP is locked out (`MAX_FAILED_LOGINS_PER_IP`)
+    pub max_failed_logins_per_ip: i64,
+    /// How long a lockout lasts, in minutes (`LOCKOUT_MINUTES`)
+    pub lockout_minutes: i64,
+    /// Issuer shown by authenticator apps for TOTP enrollments (`TOTP_ISSUER`)
+    pub totp_issuer: String,
+    /// How long password reset tokens stay valid, in minutes (`PASSWORD_RESET_MINUTES`)
+    pub password_reset_minutes: i64,
+    /// How long email verification tokens stay valid, in hours (`EMAIL_VERIFICATION_HOURS`)
+    pub email_verification_hours: i64,
+    /// Argon2id memory cost of new password hashes, in KiB (`ARGON2_MEMORY_KIB`)
+    pub argon2_memory_kib: u32,
+    /// Argon2id iterations of new password hashes (`ARGON2_ITERATIONS`)
+    pub argon2_iterations: u32,
+    /// Argon2id lanes of new password hashes (`ARGON2_PARALLELISM`)
+    pub argon2_parallelism: u32,
+    /// Allow anyone to register through `/api/auth/signup` (`ALLOW_PUBLIC_SIGNUP`)
+    pub allow_public_signup: bool,
+    /// Allow logging in with a username and password or a passkey. Turning this off
+    /// leaves OpenID Connect as the only way to log in (`PASSWORD_LOGIN_ENABLED`)
+    pub password_login_enabled: bool,
+    /// Credentials for the initial superuser (`SUPERUSER_USERNAME`, `SUPERUSER_PASSWORD`)
+    pub superuser_username: Option<String>,
+    pub superuser_password: Option<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_algorithm: JwtAlgorithm::default(),
+            jwt_secret: String::new(),
+            signing_keys: Vec::new(),
+            jwt_issuer: "makemeshort".to_string(),
+            jwt_audience: "makemeshort".to_string(),
+            token_lifetime_minutes: 15,
+            refresh_token_lifetime_days: 30,
+            max_failed_logins: 5,
+            max_failed_logins_per_ip: 20,
+            lockout_minutes: 15,
+            totp_issuer: "MakeMeShort".to_string(),
+            password_reset_minutes: 60,
+            email_verification_hours: 48,
+            argon2_memory_kib: 19456,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            allow_public_signup: false,
+            password_login_enabled: true,
+            superuser_username: None,
+            superuser_password: None,
+        }
+    }
+}
+
+/// How outgoing mail is delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Write every message as an `.eml` file to a directory, for local development
+    #[default]
+    Outbox,
+    Smtp,
+}
+
+impl FromStr for MailTransport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "outbox" => Ok(MailTransport::Outbox),
+            "smtp" => Ok(MailTransport::Smtp),
+            other => Err(anyhow::anyhow!(
+                "Unknown mail transport: {} (expected outbox or smtp)",
+                other
+            )),
+        }
+    }
+}
+
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// TLS from the start of the connection, usually on port 465
+    Tls,
+    /// Upgrade a plain connection with STARTTLS, usually on port 587
+    #[default]
+    StartTls,
+    /// No encryption, only for local test servers
+    None,
+}
+
+impl FromStr for SmtpTls {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls" => Ok(SmtpTls::Tls),
+            "starttls" => Ok(SmtpTls::StartTls),
+            "none" => Ok(SmtpTls::None),
+            other => Err(anyhow::anyhow!(
+                "Unknown SMTP TLS mode: {} (expected tls, starttls or none)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// How mail is delivered (`MAIL_TRANSPORT`)
+    pub transport: MailTransport,
+    /// Sender of every message, e.g. `MakeMeShort <noreply@example.com>` (`MAIL_FROM`)
+    pub from: String,
+    /// Directory the outbox transport writes messages to (`MAIL_OUTBOX_DIR`)
+    pub outbox_dir: String,
+    /// SMTP server host name (`SMTP_HOST`)
+    pub smtp_host: Option<String>,
+    /// SMTP server port; defaults to the usual port of the TLS mode (`SMTP_PORT`)
+    pub smtp_port: Option<u16>,
+    /// SMTP credentials (`SMTP_USERNAME`, `SMTP_PASSWORD`)
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// How the SMTP connection is secured: tls, starttls or none (`SMTP_TLS`)
+    pub smtp_tls: SmtpTls,
+    /// Link sent in password reset mails, with `{token}` replaced by the token (`PASSWORD_RESET_URL`).
+    /// Without it, the mail contains the token itself.
+    pub password_reset_url: Option<String>,
+    /// Link sent in email verification mails, with `{token}` replaced by the token (`EMAIL_VERIFICATION_URL`).
+    /// Without it, the mail contains the token itself.
+    pub email_verification_url: Option<String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            transport: MailTransport::default(),
+            from: "MakeMeShort <noreply@localhost>".to_string(),
+            outbox_dir: "outbox".to_string(),
+            smtp_host: None,
+            smtp_port: None,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_tls: SmtpTls::default(),
+            password_reset_url: None,
+            email_verification_url: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// Minimum number of characters in a password (`PASSWORD_MIN_LENGTH`)
+    pub password_min_length: usize,
+    /// How many of lowercase letters, uppercase letters, digits and symbols a password
+    /// must contain, from 1 to 4 (`PASSWORD_MIN_CLASSES`)
+    pub password_min_classes: usize,
+    /// File of common or breached passwords, one per line, that are refused (`PASSWORD_BLOCKLIST_FILE`)
+    pub password_blocklist_file: Option<String>,
+    /// Allowed length of usernames (`USERNAME_MIN_LENGTH`, `USERNAME_MAX_LENGTH`)
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    /// Regular expression usernames must match (`USERNAME_PATTERN`)
+    pub username_pattern: String,
+    /// Refuse email addresses that another user already has, ignoring case (`UNIQUE_EMAILS`)
+    pub unique_emails: bool,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            password_min_length: 8,
+            password_min_classes: 1,
+            password_blocklist_file: None,
+            username_min_length: 3,
+            username_max_length: 32,
+            username_pattern: "^[A-Za-z0-9._-]+$".to_string(),
+            unique_emails: true,
+        }
+    }
+}
+
+/// OpenID Connect single sign-on; enabled by setting `issuer_url`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    /// Issuer of the identity provider; its configuration is discovered from
+    /// `{issuer_url}/.well-known/openid-configuration` (`OIDC_ISSUER_URL`)
+    pub issuer_url: Option<String>,
+    /// Client registered with the identity provider (`OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET`).
+    /// Without a secret the service authenticates as a public client, with PKCE only.
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Callback URL registered with the identity provider; defaults to
+    /// `{server.public_url}/api/auth/oidc/callback` (`OIDC_REDIRECT_URL`)
+    pub redirect_url: Option<String>,
+    /// Space-separated scopes to request (`OIDC_SCOPES`)
+    pub scopes: String,
+    /// ID token claim used as the username of new users (`OIDC_USERNAME_CLAIM`)
+    pub username_claim: String,
+    /// Create a user on the first login of an unknown identity (`OIDC_CREATE_USERS`)
+    pub create_users: bool,
+    /// Link the first login of an identity to the existing user with the same
+    /// verified email address (`OIDC_LINK_BY_EMAIL`)
+    pub link_by_email: bool,
+    /// Page the browser is sent to after logging in, with the tokens in the URL fragment.
+    /// Without it the callback responds with the tokens as JSON (`OIDC_FRONTEND_URL`).
+    pub frontend_url: Option<String>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            issuer_url: None,
+            client_id: String::new(),
+            client_secret: None,
+            redirect_url: None,
+            scopes: "openid email profile".to_string(),
+            username_claim: "preferred_username".to_string(),
+            create_users: true,
+            link_by_email: true,
+            frontend_url: None,
+        }
+    }
+}
+
+/// Relying party settings for passkey (WebAuthn) login
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebauthnConfig {
+    /// Domain passkeys are bound to; defaults to the host of `server.public_url` (`WEBAUTHN_RP_ID`).
+    /// Changing it invalidates every registered passkey.
+    pub rp_id: Option<String>,
+    /// Name shown by the browser when creating a passkey (`WEBAUTHN_RP_NAME`)
+    pub rp_name: String,
+    /// Origins of the pages that run the ceremonies, e.g. the frontend; defaults to the
+    /// origin of `server.public_url` (`WEBAUTHN_ORIGINS`)
+    pub origins: Vec<String>,
+}
+
+impl Default for WebauthnConfig {
+    fn default() -> Self {
+        Self {
+            rp_id: None,
+            rp_name: "MakeMeShort".to_string(),
+            origins: Vec::new(),
+        }
+    }
+}
+
+/// Audit log settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Secret key for hashing client IP addresses in the audit log (`AUDIT_IP_HASH_KEY`).
+    /// A random key is used if unset, so hashes only match within one run of the server.
+    pub ip_hash_key: Option<String>,
+}
+
+impl Config {
+    /// Load the configuration file named by `CONFIG_FILE` (or `config.toml` if it exists),
+    /// apply environment overrides and validate the result
+    pub fn load() -> Result<Self> {
+        let mut config = match std::env::var("CONFIG_FILE") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            Err(_) => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        env_parse("BIND_ADDRESS", &mut self.server.bind_address)?;
+        env_parse("PORT", &mut self.server.port)?;
+        env_parse("HOST", &mut self.server.public_url)?;
+        env_optional("UNIX_SOCKET", &mut self.server.unix_socket);
+        env_list("TRUSTED_PROXIES", &mut self.server.trusted_proxies);
+
+        env_list("CORS_ALLOWED_ORIGINS", &mut self.cors.allowed_origins);
+        env_list("CORS_ALLOWED_METHODS", &mut self.cors.allowed_methods);
+        env_list("CORS_ALLOWED_HEADERS", &mut self.cors.allowed_headers);
+        env_parse(
+            "CORS_SUPPORTS_CREDENTIALS",
+            &mut self.cors.supports_credentials,
+        )?;
+
+        env_optional("TLS_CERT_FILE", &mut self.tls.cert_file);
+        env_optional("TLS_KEY_FILE", &mut self.tls.key_file);
+
+        env_parse("STORAGE_BACKEND", &mut self.storage.backend)?;
+        env_optional("MONGODB_URL", &mut self.storage.mongodb_url);
+        env_parse("MONGODB_DATABASE", &mut self.storage.mongodb_database)?;
+        env_optional("DATABASE_URL", &mut self.storage.database_url);
+        env_bool("MIGRATE_ON_STARTUP", &mut self.storage.migrate_on_startup)?;
+
+        env_parse("JWT_ALGORITHM", &mut self.auth.jwt_algorithm)?;
+        env_parse("JWT_SECRET", &mut self.auth.jwt_secret)?;
+        if let (Ok(kid), Ok(private_key_file)) = (
+            std::env::var("JWT_SIGNING_KEY_ID"),
+            std::env::var("JWT_SIGNING_KEY_FILE"),
+        ) {
+            self.auth.signing_keys = vec![SigningKeyConfig {
+                kid,
+                private_key_file,
+                retired_at: None,
+            }];
+        }
+        env_parse("JWT_ISSUER", &mut self.auth.jwt_issuer)?;
+        env_parse("JWT_AUDIENCE", &mut self.auth.jwt_audience)?;
+        env_parse(
+            "TOKEN_LIFETIME_MINUTES",
+            &mut self.auth.token_lifetime_minutes,
+        )?;
+        env_parse(
+            "REFRESH_TOKEN_LIFETIME_DAYS",
+            &mut self.auth.refresh_token_lifetime_days,
+        )?;
+        env_parse("MAX_FAILED_LOGINS", &mut self.auth.max_failed_logins)?;
+        env_parse(
+            "MAX_FAILED_LOGINS_PER_IP",
+            &mut self.auth.max_failed_logins_per_ip,
+        )?;
+        env_parse("LOCKOUT_MINUTES", &mut self.auth.lockout_minutes)?;
+        env_parse("TOTP_ISSUER", &mut self.auth.totp_issuer)?;
+        env_parse(
+            "PASSWORD_RESET_MINUTES",
+            &mut self.auth.password_reset_minutes,
+        )?;
+        env_parse(
+            "EMAIL_VERIFICATION_HOURS",
+            &mut self.auth.email_verification_hours,
+        )?;
+        env_parse("ARGON2_MEMORY_KIB", &mut self.auth.argon2_memory_kib)?;
+        env_parse("ARGON2_ITERATIONS", &mut self.auth.argon2_iterations)?;
+        env_parse("ARGON2_PARALLELISM", &mut self.auth.argon2_parallelism)?;
+        env_bool("ALLOW_PUBLIC_SIGNUP", &mut self.auth.allow_public_signup)?;
+        env_bool(
+            "PASSWORD_LOGIN_ENABLED",
+            &mut self.auth.password_login_enabled,
+        )?;
+        env_optional("SUPERUSER_USERNAME", &mut self.auth.superuser_username);
+        env_optional("SUPERUSER_PASSWORD", &mut self.auth.superuser_password);
+
+        env_parse("MAIL_TRANSPORT", &mut self.mail.transport)?;
+        env_parse("MAIL_FROM", &mut self.mail.from)?;
+        env_parse("MAIL_OUTBOX_DIR", &mut self.mail.outbox_dir)?;
+        env_optional("SMTP_HOST", &mut self.mail.smtp_host);
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            self.mail.smtp_port = Some(
+                port.parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid value for SMTP_PORT: {}", e))?,
+            );
+        }
+        env_optional("SMTP_USERNAME", &mut self.mail.smtp_username);
+        env_optional("SMTP_PASSWORD", &mut self.mail.smtp_password);
+        env_parse("SMTP_TLS", &mut self.mail.smtp_tls)?;
+        env_optional("PASSWORD_RESET_URL", &mut self.mail.password_reset_url);
+        env_optional(
+            "EMAIL_VERIFICATION_URL",
+            &mut self.mail.email_verification_url,
+        );
+
+        env_parse("PASSWORD_MIN_LENGTH", &mut self.policy.password_min_length)?;
+        env_parse(
+            "PASSWORD_MIN_CLASSES",
+            &mut self.policy.password_min_classes,
+        )?;
+        env_optional(
+            "PASSWORD_BLOCKLIST_FILE",
+            &mut self.policy.password_blocklist_file,
+        );
+        env_parse("USERNAME_MIN_LENGTH", &mut self.policy.username_min_length)?;
+        env_parse("USERNAME_MAX_LENGTH", &mut self.policy.username_max_length)?;
+        env_parse("USERNAME_PATTERN", &mut self.policy.username_pattern)?;
+        env_bool("UNIQUE_EMAILS", &mut self.policy.unique_emails)?;
+
+        env_optional("OIDC_ISSUER_URL", &mut self.oidc.issuer_url);
+        env_parse("OIDC_CLIENT_ID", &mut self.oidc.client_id)?;
+        env_optional("OIDC_CLIENT_SECRET", &mut self.oidc.client_secret);
+        env_optional("OIDC_REDIRECT_URL", &mut self.oidc.redirect_url);
+        env_parse("OIDC_SCOPES", &mut self.oidc.scopes)?;
+        env_parse("OIDC_USERNAME_CLAIM", &mut self.oidc.username_claim)?;
+        env_bool("OIDC_CREATE_USERS", &mut self.oidc.create_users)?;
+        env_bool("OIDC_LINK_BY_EMAIL", &mut self.oidc.link_by_email)?;
+        env_optional("OIDC_FRONTEND_URL", &mut self.oidc.frontend_url);
+
+        env_optional("WEBAUTHN_RP_ID", &mut self.webauthn.rp_id);
+        env_parse("WEBAUTHN_RP_NAME", &mut self.webauthn.rp_name)?;
+        env_list("WEBAUTHN_ORIGINS", &mut self.webauthn.origins);
+
+        env_optional("AUDIT_IP_HASH_KEY", &mut self.audit.ip_hash_key);
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.server.bind_address.is_empty() {
+            bail!("server.bind_address (BIND_ADDRESS) must not be empty");
+        }
+
+        if !self.server.public_url.starts_with("http://")
+            && !self.server.public_url.starts_with("https://")
+        {
+            bail!("server.public_url (HOST) must start with http:// or https://");
+        }
+
+        for proxy in &self.server.trusted_proxies {
+            IpNetwork::from_str(proxy).with_context(|| {
+                format!(
+                    "Invalid server.trusted_proxies (TRUSTED_PROXIES) entry {}",
+                    proxy
+                )
+            })?;
+        }
+
+        for origin in &self.cors.allowed_origins {
+            if origin != "*" && !origin.starts_with("http://") && !origin.starts_with("https://") {
+                bail!(
+                    "Invalid CORS origin {} (expected * or an http:// or https:// origin)",
+                    origin
+                );
+            }
+        }
+
+        // actix-cors answers `*` with the caller's origin, so with credentials any site
+        // could make authenticated requests
+        if self.cors.supports_credentials && self.cors.allowed_origins.iter().any(|o| o == "*") {
+            bail!(
+                "cors.allowed_origins (CORS_ALLOWED_ORIGINS) cannot contain * while cors.supports_credentials is enabled"
+            );
+        }
+
+        for method in &self.cors.allowed_methods {
+            Method::from_str(method)
+                .map_err(|_| anyhow::anyhow!("Invalid CORS method {}", method))?;
+        }
+
+        for header in &self.cors.allowed_headers {
+            HeaderName::from_str(header)
+                .map_err(|_| anyhow::anyhow!("Invalid CORS header {}", header))?;
+        }
+
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            bail!(
+                "tls.cert_file (TLS_CERT_FILE) and tls.key_file (TLS_KEY_FILE) must be set together"
+            );
+        }
+
+        if self.tls.is_enabled() && self.server.unix_socket.is_some() {
+            bail!("TLS is not supported when listening on a Unix socket (UNIX_SOCKET)");
+        }
+
+        match self.storage.backend {
+            StorageBackend::MongoDb if self.storage.mongodb_url.is_none() => {
+                bail!("storage.mongodb_url (MONGODB_URL) is required for the mongodb backend")
+            }
+            StorageBackend::Sql if self.storage.database_url.is_none() => {
+                bail!("storage.database_url (DATABASE_URL) is required for the sql backend")
+            }
+            _ => {}
+        }
+
+        if self.storage.mongodb_database.is_empty() {
+            bail!("storage.mongodb_database (MONGODB_DATABASE) must not be empty");
+        }
+
+        match self.auth.jwt_algorithm {
+            JwtAlgorithm::HS256 if self.auth.jwt_secret.is_empty() => {
+                bail!("auth.jwt_secret (JWT_SECRET) is required for HS256")
+            }
+            JwtAlgorithm::RS256 | JwtAlgorithm::EdDSA => {
+                let active = self
+                    .auth
+                    .signing_keys
+                    .iter()
+                    .filter(|key| key.retired_at.is_none())
+                    .count();
+                if active != 1 {
+                    bail!(
+                        "auth.signing_keys (JWT_SIGNING_KEY_ID, JWT_SIGNING_KEY_FILE) must contain exactly one key without retired_at, found {}",
+                        active
+                    );
+                }
+
+                for (i, key) in self.auth.signing_keys.iter().enumerate() {
+                    if key.kid.is_empty() {
+                        bail!("auth.signing_keys: kid must not be empty");
+                    }
+                    if self.auth.signing_keys[..i]
+                        .iter()
+                        .any(|other| other.kid == key.kid)
+                    {
+                        bail!("auth.signing_keys: duplicate kid {}", key.kid);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if self.auth.jwt_issuer.is_empty() || self.auth.jwt_audience.is_empty() {
+            bail!(
+                "auth.jwt_issuer (JWT_ISSUER) and auth.jwt_audience (JWT_AUDIENCE) must not be empty"
+            );
+        }
+
+        if self.auth.token_lifetime_minutes <= 0 {
+            bail!("auth.token_lifetime_minutes (TOKEN_LIFETIME_MINUTES) must be positive");
+        }
+
+        if self.auth.refresh_token_lifetime_days <= 0 {
+            bail!(
+                "auth.refresh_token_lifetime_days (REFRESH_TOKEN_LIFETIME_DAYS) must be positive"
+            );
+        }
+
+        if self.auth.max_failed_logins <= 0 || self.auth.max_failed_logins_per_ip <= 0 {
+            bail!(
+                "auth.max_failed_logins (MAX_FAILED_LOGINS) and auth.max_failed_logins_per_ip (MAX_FAILED_LOGINS_PER_IP) must be positive"
+            );
+        }
+
+        if self.auth.lockout_minutes <= 0 {
+            bail!("auth.lockout_minutes (LOCKOUT_MINUTES) must be positive");
+        }
+
+        if self.auth.totp_issuer.is_empty() {
+            bail!("auth.totp_issuer (TOTP_ISSUER) must not be empty");
+        }
+
+        if self.auth.password_reset_minutes <= 0 || self.auth.email_verification_hours <= 0 {
+            bail!(
+                "auth.password_reset_minutes (PASSWORD_RESET_MINUTES) and auth.email_verification_hours (EMAIL_VERIFICATION_HOURS) must be positive"
+            );
+        }
+
+        if let Err(e) = argon2::Params::new(
+            self.auth.argon2_memory_kib,
+            self.auth.argon2_iterations,
+            self.auth.argon2_parallelism,
+            None,
+        ) {
+            bail!(
+                "auth.argon2_memory_kib (ARGON2_MEMORY_KIB), auth.argon2_iterations (ARGON2_ITERATIONS) and auth.argon2_parallelism (ARGON2_PARALLELISM) are invalid: {}",
+                e
+            );
+        }
+
+        if self.mail.transport == MailTransport::Smtp && self.mail.smtp_host.is_none() {
+            bail!("mail.smtp_host (SMTP_HOST) is required for the smtp transport");
+        }
+
+        if self.mail.smtp_username.is_some() != self.mail.smtp_password.is_some() {
+            bail!(
+                "mail.smtp_username (SMTP_USERNAME) and mail.smtp_password (SMTP_PASSWORD) must be set together"
+            );
+        }
+
+        for (name, url) in [
+            (
+                "mail.password_reset_url (PASSWORD_RESET_URL)",
+                &self.mail.password_reset_url,
+            ),
+            (
+                "mail.email_verification_url (EMAIL_VERIFICATION_URL)",
+                &self.mail.email_verification_url,
+            ),
+        ] {
+            if url.as_ref().is_some_and(|url| !url.contains("{token}")) {
+                bail!("{} must contain {{token}}", name);
+            }
+        }
+
+        if self.policy.password_min_length == 0 {
+            bail!("policy.password_min_length (PASSWORD_MIN_LENGTH) must be positive");
+        }
+
+        if !(1..=4).contains(&self.policy.password_min_classes) {
+            bail!("policy.password_min_classes (PASSWORD_MIN_CLASSES) must be between 1 and 4");
+        }
+
+        if self.policy.username_min_length == 0
+            || self.policy.username_min_length > self.policy.username_max_length
+        {
+            bail!(
+                "policy.username_min_length (USERNAME_MIN_LENGTH) must be positive and not above policy.username_max_length (USERNAME_MAX_LENGTH)"
+            );
+        }
+
+        if let Err(e) = regex::Regex::new(&self.policy.username_pattern) {
+            bail!(
+                "policy.username_pattern (USERNAME_PATTERN) is not a valid regular expression: {}",
+                e
+            );
+        }
+
+        if let Some(issuer_url) = &self.oidc.issuer_url {
+            if !issuer_url.starts_with("http://") && !issuer_url.starts_with("https://") {
+                bail!("oidc.issuer_url (OIDC_ISSUER_URL) must start with http:// or https://");
+            }
+
+            if self.oidc.client_id.is_empty() {
+                bail!("oidc.client_id (OIDC_CLIENT_ID) is required for single sign-on");
+            }
+
+            if !self
+                .oidc
+                .scopes
+                .split_whitespace()
+                .any(|scope| scope == "openid")
+            {
+                bail!("oidc.scopes (OIDC_SCOPES) must include openid");
+            }
+        } else if !self.auth.password_login_enabled {
+            bail!(
+                "auth.password_login_enabled (PASSWORD_LOGIN_ENABLED) can only be turned off when oidc.issuer_url (OIDC_ISSUER_URL) is set"
+            );
+        }
+
+        for origin in &self.webauthn.origins {
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                bail!(
+                    "Invalid WebAuthn origin {} (expected an http:// or https:// origin)",
+                    origin
+                );
+            }
+        }
+
+        if self.auth.superuser_username.is_some() != self.auth.superuser_password.is_some() {
+            bail!(
+                "auth.superuser_username (SUPERUSER_USERNAME) and auth.superuser_password (SUPERUSER_PASSWORD) must be set together"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Callback URL of the OpenID Connect login
+    pub fn oidc_redirect_url(&self) -> String {
+        self.oidc.redirect_url.clone().unwrap_or_else(|| {
+            format!(
+                "{}/api/auth/oidc/callback",
+                self.server.public_url.trim_end_matches('/')
+            )
+        })
+    }
+
+    /// Build the public short link for a short code
+    pub fn short_url(&self, code: &str) -> String {
+        format!(
+            "{}/r/{}",
+            self.server.public_url.trim_end_matches('/'),
+            code
+        )
+    }
+}
+
+/// Override a value with the parsed environment variable, if set
+fn env_parse<T>(name: &str, target: &mut T) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        *target = value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid value for {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Override an optional string with the environment variable, if set
+fn env_optional(name: &str, target: &mut Option<String>) {
+    if let Ok(value) = std::env::var(name) {
+        *target = Some(value);
+    }
+}
+
+/// Override a list with the comma-separated environment variable, if set
+fn env_list(name: &str, target: &mut Vec<String>) {
+    if let Ok(value) = std::env::var(name) {
+        *target = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect();
+    }
+}
+
+/// Override a flag with the environment variable, accepting true/false and 1/0
+fn env_bool(name: &str, target: &mut bool) -> Result<()> {
+    if let Ok(value) = std::env::var(name) {
+        *target = match value.to_lowercase().as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            other => bail!(
+                "Invalid value for {}: {} (expected true or false)",
+                name,
+                other
+            ),
+        };
+    }
+    Ok(())
+}
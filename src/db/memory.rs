@@ -0,0 +1,1186 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::api_key::ApiKey;
+use crate::models::audit_event::AuditEvent;
+use crate::models::identity::ExternalIdentity;
+use crate::models::invite::{Invite, InviteUse};
+use crate::models::one_time_token::{OneTimeToken, TokenPurpose};
+use crate::models::passkey::Passkey;
+use crate::models::qr_code::{QrCode, TargetType};
+use crate::models::refresh_token::RefreshToken;
+use crate::models::totp::TotpCredential;
+use crate::models::url::ShortenedUrl;
+use crate::models::url_version::UrlVersion;
+use crate::models::url_visitor::UrlVisitor;
+use crate::models::user::User;
+use crate::models::workspace::{Workspace, WorkspaceMember, WorkspaceRole};
+use crate::repositories::api_key_repository::ApiKeyRepository;
+use crate::repositories::audit_repository::{AuditFilter, AuditRepository};
+use crate::repositories::errors::DuplicateKeyError;
+use crate::repositories::health_repository::HealthRepository;
+use crate::repositories::identity_repository::IdentityRepository;
+use crate::repositories::invite_repository::InviteRepository;
+use crate::repositories::passkey_repository::PasskeyRepository;
+use crate::repositories::qr_code_repository::{QrCodeFilter, QrCodeRepository};
+use crate::repositories::token_repository::TokenRepository;
+use crate::repositories::totp_repository::TotpRepository;
+use crate::repositories::url_repository::{UrlFilter, UrlRepository};
+use crate::repositories::user_repository::{ResourcePolicy, UserRepository, UserUpdate};
+use crate::repositories::visitor_repository::VisitorRepository;
+use crate::repositories::workspace_repository::{MemberChange, WorkspaceRepository};
+
+/// Repository implementations that keep everything in process memory.
+/// Data is lost on restart, which makes this suitable for tests and small deployments.
+#[derive(Default)]
+pub struct MemoryStore {
+    urls: RwLock<Vec<ShortenedUrl>>,
+    url_versions: RwLock<Vec<UrlVersion>>,
+    visitors: RwLock<Vec<UrlVisitor>>,
+    qr_codes: RwLock<Vec<QrCode>>,
+    users: RwLock<Vec<User>>,
+    refresh_tokens: RwLock<Vec<RefreshToken>>,
+    one_time_tokens: RwLock<Vec<OneTimeToken>>,
+    revoked_tokens: RwLock<HashMap<String, i64>>, // jti -> expiry
+    user_token_revocations: RwLock<HashMap<String, i64>>, // user ID -> revoked before
+    api_keys: RwLock<Vec<ApiKey>>,
+    totp_credentials: RwLock<Vec<TotpCredential>>,
+    identities: RwLock<Vec<ExternalIdentity>>,
+    passkeys: RwLock<Vec<Passkey>>,
+    invites: RwLock<Vec<Invite>>,
+    audit_events: RwLock<Vec<AuditEvent>>,
+    workspaces: RwLock<Vec<Workspace>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Case-insensitive substring match on short code or original URL
+fn matches_search(search: Option<&str>, short_code: &str, original_url: &str) -> bool {
+    match search.filter(|s| !s.is_empty()) {
+        Some(search) => {
+            let search = search.to_lowercase();
+            short_code.to_lowercase().contains(&search)
+                || original_url.to_lowercase().contains(&search)
+        }
+        None => true,
+    }
+}
+
+#[async_trait]
+impl HealthRepository for MemoryStore {
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UrlRepository for MemoryStore {
+    async fn insert(&self, url: &ShortenedUrl) -> Result<ShortenedUrl> {
+        let mut inserted = url.clone();
+        inserted.id = Some(ObjectId::new());
+
+        let mut urls = self.urls.write().unwrap();
+        if urls
+            .iter()
+            .any(|existing| existing.short_code == url.short_code)
+        {
+            return Err(DuplicateKeyError.into());
+        }
+
+        urls.push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn find_by_code(&self, code: &str) -> Result<Option<ShortenedUrl>> {
+        let urls = self.urls.read().unwrap();
+        Ok(urls.iter().find(|url| url.short_code == code).cloned())
+    }
+
+    async fn find(&self, filter: &UrlFilter) -> Result<Vec<ShortenedUrl>> {
+        let urls = self.urls.read().unwrap();
+        Ok(urls
+            .iter()
+            .filter(|url| {
+                matches_search(filter.search.as_deref(), &url.short_code, &url.original_url)
+            })
+            .filter(|url| filter.user_id.is_none() || url.user_id == filter.user_id)
+            .filter(|url| filter.workspace_id.is_none() || url.workspace_id == filter.workspace_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn increment_clicks(&self, code: &str) -> Result<()> {
+        let mut urls = self.urls.write().unwrap();
+        if let Some(url) = urls.iter_mut().find(|url| url.short_code == code) {
+            url.clicks += 1;
+        }
+        Ok(())
+    }
+
+    async fn update_with_history(&self, url: &ShortenedUrl, previous: &UrlVersion) -> Result<bool> {
+        let mut urls = self.urls.write().unwrap();
+        let Some(stored) = urls
+            .iter_mut()
+            .find(|stored| stored.short_code == url.short_code)
+            .filter(|stored| stored.version == previous.version)
+        else {
+            return Ok(false);
+        };
+
+        stored.original_url = url.original_url.clone();
+        stored.expires_at = url.expires_at;
+        stored.title = url.title.clone();
+        stored.password_hash = url.password_hash.clone();
+        stored.version = url.version;
+
+        let mut kept = previous.clone();
+        kept.id = Some(ObjectId::new());
+        self.url_versions.write().unwrap().push(kept);
+        Ok(true)
+    }
+
+    async fn find_versions(&self, code: &str) -> Result<Vec<UrlVersion>> {
+        let url_versions = self.url_versions.read().unwrap();
+        let mut versions: Vec<UrlVersion> = url_versions
+            .iter()
+            .filter(|version| version.short_code == code)
+            .cloned()
+            .collect();
+        versions.sort_by_key(|version| std::cmp::Reverse(version.version));
+        Ok(versions)
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<bool> {
+        let mut urls = self.urls.write().unwrap();
+        let before = urls.len();
+        urls.retain(|url| url.short_code != code);
+        self.url_versions
+            .write()
+            .unwrap()
+            .retain(|version| version.short_code != code);
+        Ok(urls.len() < before)
+    }
+}
+
+#[async_trait]
+impl VisitorRepository for MemoryStore {
+    async fn insert_if_new(&self, visitor: &UrlVisitor) -> Result<bool> {
+        let mut visitors = self.visitors.write().unwrap();
+        let exists = visitors
+            .iter()
+            .any(|v| v.short_code == visitor.short_code && v.visitor_hash == visitor.visitor_hash);
+
+        if exists {
+            return Ok(false);
+        }
+
+        let mut inserted = visitor.clone();
+        inserted.id = Some(ObjectId::new());
+        visitors.push(inserted);
+        Ok(true)
+    }
+
+    async fn count_by_code(&self, code: &str) -> Result<u64> {
+        let visitors = self.visitors.read().unwrap();
+        Ok(visitors.iter().filter(|v| v.short_code == code).count() as u64)
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64> {
+        let mut visitors = self.visitors.write().unwrap();
+        let before = visitors.len();
+        visitors.retain(|v| v.short_code != code);
+        Ok((before - visitors.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl QrCodeRepository for MemoryStore {
+    async fn find_by_code(&self, code: &str, target_type: &TargetType) -> Result<Option<QrCode>> {
+        let qr_codes = self.qr_codes.read().unwrap();
+        Ok(qr_codes
+            .iter()
+            .find(|qr| qr.short_code == code && &qr.target_type == target_type)
+            .cloned())
+    }
+
+    async fn find_direct_by_url(&self, url: &str) -> Result<Option<QrCode>> {
+        let qr_codes = self.qr_codes.read().unwrap();
+        Ok(qr_codes
+            .iter()
+            .find(|qr| {
+                qr.original_url == url
+                    && qr.short_code.starts_with("direct-")
+                    && qr.target_type == TargetType::Original
+            })
+            .cloned())
+    }
+
+    async fn find(&self, filter: &QrCodeFilter) -> Result<Vec<QrCode>> {
+        let qr_codes = self.qr_codes.read().unwrap();
+        Ok(qr_codes
+            .iter()
+            .filter(|qr| matches_search(filter.search.as_deref(), &qr.short_code, &qr.original_url))
+            .filter(|qr| {
+                filter
+                    .target_type
+                    .as_ref()
+                    .is_none_or(|target_type| &qr.target_type == target_type)
+            })
+            .filter(|qr| !filter.direct_only || qr.short_code.starts_with("direct-"))
+            .filter(|qr| filter.user_id.is_none() || qr.user_id == filter.user_id)
+            .filter(|qr| filter.workspace_id.is_none() || qr.workspace_id == filter.workspace_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn upsert(&self, qr_code: &QrCode) -> Result<()> {
+        let mut qr_codes = self.qr_codes.write().unwrap();
+        let existing = qr_codes.iter_mut().find(|qr| {
+            qr.short_code == qr_code.short_code && qr.target_type == qr_code.target_type
+        });
+
+        match existing {
+            Some(qr) => {
+                qr.svg_content = qr_code.svg_content.clone();
+                qr.generated_at = qr_code.generated_at;
+            }
+            None => {
+                let mut inserted = qr_code.clone();
+                inserted.id = Some(ObjectId::new());
+                qr_codes.push(inserted);
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64> {
+        let mut qr_codes = self.qr_codes.write().unwrap();
+        let before = qr_codes.len();
+        qr_codes.retain(|qr| qr.short_code != code);
+        Ok((before - qr_codes.len()) as u64)
+    }
+
+    async fn retarget(&self, code: &str, original_url: &str) -> Result<u64> {
+        let mut qr_codes = self.qr_codes.write().unwrap();
+        let before = qr_codes.len();
+        qr_codes.retain(|qr| qr.short_code != code || qr.target_type != TargetType::Original);
+        for qr in qr_codes.iter_mut().filter(|qr| qr.short_code == code) {
+            qr.original_url = original_url.to_string();
+        }
+        Ok((before - qr_codes.len()) as u64)
+    }
+
+    async fn clear_workspace(&self, workspace_id: &str) -> Result<u64> {
+        let mut qr_codes = self.qr_codes.write().unwrap();
+        let mut cleared = 0;
+        for qr in qr_codes
+            .iter_mut()
+            .filter(|qr| qr.workspace_id.as_deref() == Some(workspace_id))
+        {
+            qr.workspace_id = None;
+            cleared += 1;
+        }
+        Ok(cleared)
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryStore {
+    async fn insert(&self, user: &User) -> Result<User> {
+        let mut inserted = user.clone();
+        inserted.id = Some(ObjectId::new());
+
+        let mut users = self.users.write().unwrap();
+        if users
+            .iter()
+            .any(|existing| existing.username == user.username)
+        {
+            return Err(DuplicateKeyError.into());
+        }
+
+        users.push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn find_by_id(&self, id: &ObjectId) -> Result<Option<User>> {
+        let users = self.users.read().unwrap();
+        Ok(users
+            .iter()
+            .find(|user| user.id.as_ref() == Some(id))
+            .cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        let users = self.users.read().unwrap();
+        Ok(users.iter().find(|user| user.username == username).cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Vec<User>> {
+        let users = self.users.read().unwrap();
+        Ok(users
+            .iter()
+            .filter(|user| {
+                user.email
+                    .as_deref()
+                    .is_some_and(|address| address.eq_ignore_ascii_case(email))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn find_all_except(&self, id: &ObjectId) -> Result<Vec<User>> {
+        let users = self.users.read().unwrap();
+        Ok(users
+            .iter()
+            .filter(|user| user.id.as_ref() != Some(id))
+            .cloned()
+            .collect())
+    }
+
+    async fn count(&self) -> Result<u64> {
+        Ok(self.users.read().unwrap().len() as u64)
+    }
+
+    async fn update(&self, id: &ObjectId, update: &UserUpdate) -> Result<Option<User>> {
+        let mut users = self.users.write().unwrap();
+
+        if let Some(username) = &update.username
+            && users
+                .iter()
+                .any(|user| user.username == *username && user.id.as_ref() != Some(id))
+        {
+            return Err(DuplicateKeyError.into());
+        }
+
+        let Some(user) = users.iter_mut().find(|user| user.id.as_ref() == Some(id)) else {
+            return Ok(None);
+        };
+
+        if let Some(username) = &update.username {
+            user.username = username.clone();
+        }
+
+        if let Some(email) = &update.email {
+            user.email = Some(email.clone());
+            user.email_verified_at = None;
+        }
+
+        if let Some(full_name) = &update.full_name {
+            user.full_name = Some(full_name.clone());
+        }
+
+        if let Some(password_hash) = &update.password_hash {
+            user.password_hash = password_hash.clone();
+        }
+
+        if let Some(is_active) = update.is_active {
+            user.is_active = is_active;
+        }
+
+        if let Some(role) = update.role {
+            user.role = role;
+        }
+
+        user.updated_at = chrono::Utc::now().timestamp_millis();
+        Ok(Some(user.clone()))
+    }
+
+    async fn set_last_login(&self, id: &ObjectId, timestamp: i64) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        if let Some(user) = users.iter_mut().find(|user| user.id.as_ref() == Some(id)) {
+            user.last_login = Some(timestamp);
+            user.failed_login_attempts = 0;
+        }
+        Ok(())
+    }
+
+    async fn record_failed_login(&self, id: &ObjectId, at: i64, reset_before: i64) -> Result<i64> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.iter_mut().find(|user| user.id.as_ref() == Some(id)) else {
+            return Ok(0);
+        };
+
+        if user
+            .last_failed_login
+            .is_none_or(|last_failed| last_failed < reset_before)
+        {
+            user.failed_login_attempts = 0;
+        }
+        user.failed_login_attempts += 1;
+        user.last_failed_login = Some(at);
+        Ok(user.failed_login_attempts)
+    }
+
+    async fn lock(&self, id: &ObjectId, locked_at: i64, locked_until: i64) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        if let Some(user) = users.iter_mut().find(|user| user.id.as_ref() == Some(id)) {
+            user.locked_at = Some(locked_at);
+            user.locked_until = Some(locked_until);
+            user.failed_login_attempts = 0;
+        }
+        Ok(())
+    }
+
+    async fn unlock(&self, id: &ObjectId) -> Result<Option<User>> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.iter_mut().find(|user| user.id.as_ref() == Some(id)) else {
+            return Ok(None);
+        };
+
+        user.locked_until = None;
+        user.failed_login_attempts = 0;
+        Ok(Some(user.clone()))
+    }
+
+    async fn set_email_verified(&self, id: &ObjectId, email: &str, at: i64) -> Result<bool> {
+        let mut users = self.users.write().unwrap();
+        match users
+            .iter_mut()
+            .find(|user| user.id.as_ref() == Some(id) && user.email.as_deref() == Some(email))
+        {
+            Some(user) => {
+                user.email_verified_at = Some(at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<bool> {
+        let mut users = self.users.write().unwrap();
+        let before = users.len();
+        users.retain(|user| user.id.as_ref() != Some(id));
+        Ok(users.len() < before)
+    }
+
+    async fn delete_with_resources(
+        &self,
+        id: &ObjectId,
+        policy: &ResourcePolicy,
+        at: i64,
+    ) -> Result<bool> {
+        // Hold every lock involved so the changes become visible at once
+        let mut urls = self.urls.write().unwrap();
+        let mut url_versions = self.url_versions.write().unwrap();
+        let mut visitors = self.visitors.write().unwrap();
+        let mut qr_codes = self.qr_codes.write().unwrap();
+        let mut users = self.users.write().unwrap();
+
+        let before = users.len();
+        users.retain(|user| user.id.as_ref() != Some(id));
+        if users.len() == before {
+            return Ok(false);
+        }
+
+        // Personal URLs and QR codes are the ones not owned by a workspace
+        let user_id = id.to_hex();
+        let personal = |owner: &Option<String>, workspace_id: &Option<String>| {
+            owner.as_deref() == Some(user_id.as_str()) && workspace_id.is_none()
+        };
+        match policy {
+            ResourcePolicy::Delete => {
+                let codes: HashSet<String> = urls
+                    .iter()
+                    .filter(|url| personal(&url.user_id, &url.workspace_id))
+                    .map(|url| url.short_code.clone())
+                    .collect();
+                urls.retain(|url| !codes.contains(&url.short_code));
+                url_versions.retain(|version| !codes.contains(&version.short_code));
+                visitors.retain(|visitor| !codes.contains(&visitor.short_code));
+                qr_codes.retain(|qr| {
+                    !codes.contains(&qr.short_code) && !personal(&qr.user_id, &qr.workspace_id)
+                });
+            }
+            ResourcePolicy::Disable => {
+                for url in urls
+                    .iter_mut()
+                    .filter(|url| personal(&url.user_id, &url.workspace_id))
+                {
+                    url.disabled_at = Some(at);
+                }
+            }
+            ResourcePolicy::Reassign(_) => {}
+        }
+
+        // Whatever is left gets the new owner, or none
+        let new_owner = match policy {
+            ResourcePolicy::Reassign(new_owner) => Some(new_owner.clone()),
+            _ => None,
+        };
+        for url in urls
+            .iter_mut()
+            .filter(|url| url.user_id.as_deref() == Some(user_id.as_str()))
+        {
+            url.user_id = new_owner.clone();
+        }
+        for qr in qr_codes
+            .iter_mut()
+            .filter(|qr| qr.user_id.as_deref() == Some(user_id.as_str()))
+        {
+            qr.user_id = new_owner.clone();
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MemoryStore {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<()> {
+        let mut inserted = token.clone();
+        inserted.id = Some(ObjectId::new());
+
+        self.refresh_tokens.write().unwrap().push(inserted);
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>> {
+        let tokens = self.refresh_tokens.read().unwrap();
+        Ok(tokens
+            .iter()
+            .find(|token| token.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn find_active_refresh_tokens(
+        &self,
+        user_id: &str,
+        now: i64,
+    ) -> Result<Vec<RefreshToken>> {
+        let tokens = self.refresh_tokens.read().unwrap();
+        let mut active: Vec<RefreshToken> = tokens
+            .iter()
+            .filter(|token| {
+                token.user_id == user_id && token.revoked_at.is_none() && token.expires_at > now
+            })
+            .cloned()
+            .collect();
+        active.sort_by_key(|token| std::cmp::Reverse(token.created_at));
+        Ok(active)
+    }
+
+    async fn revoke_refresh_token(&self, token_hash: &str, revoked_at: i64) -> Result<bool> {
+        let mut tokens = self.refresh_tokens.write().unwrap();
+        match tokens
+            .iter_mut()
+            .find(|token| token.token_hash == token_hash && token.revoked_at.is_none())
+        {
+            Some(token) => {
+                token.revoked_at = Some(revoked_at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn revoke_refresh_family(&self, family_id: &str, revoked_at: i64) -> Result<()> {
+        let mut tokens = self.refresh_tokens.write().unwrap();
+        for token in tokens
+            .iter_mut()
+            .filter(|token| token.family_id == family_id && token.revoked_at.is_none())
+        {
+            token.revoked_at = Some(revoked_at);
+        }
+        Ok(())
+    }
+
+    async fn revoke_access_token(&self, jti: &str, expires_at: i64) -> Result<bool> {
+        let mut revoked = self.revoked_tokens.write().unwrap();
+
+        // Forget tokens that have expired anyway
+        let now = chrono::Utc::now().timestamp_millis();
+        revoked.retain(|_, expiry| *expiry > now);
+
+        Ok(revoked.insert(jti.to_string(), expires_at).is_none())
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str, revoked_at: i64) -> Result<()> {
+        {
+            let mut tokens = self.refresh_tokens.write().unwrap();
+            for token in tokens
+                .iter_mut()
+                .filter(|token| token.user_id == user_id && token.revoked_at.is_none())
+            {
+                token.revoked_at = Some(revoked_at);
+            }
+        }
+
+        self.user_token_revocations
+            .write()
+            .unwrap()
+            .insert(user_id.to_string(), revoked_at);
+        Ok(())
+    }
+
+    async fn is_access_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at: i64,
+    ) -> Result<bool> {
+        if self.revoked_tokens.read().unwrap().contains_key(jti) {
+            return Ok(true);
+        }
+
+        let user_revocations = self.user_token_revocations.read().unwrap();
+        Ok(user_revocations
+            .get(user_id)
+            .is_some_and(|revoked_before| issued_at < *revoked_before))
+    }
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<()> {
+        let mut inserted = token.clone();
+        inserted.id = Some(ObjectId::new());
+
+        self.one_time_tokens.write().unwrap().push(inserted);
+        Ok(())
+    }
+
+    async fn use_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        used_at: i64,
+    ) -> Result<Option<OneTimeToken>> {
+        let mut tokens = self.one_time_tokens.write().unwrap();
+        match tokens.iter_mut().find(|token| {
+            token.token_hash == token_hash
+                && token.purpose == purpose
+                && token.used_at.is_none()
+                && token.expires_at > used_at
+        }) {
+            Some(token) => {
+                token.used_at = Some(used_at);
+                Ok(Some(token.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn invalidate_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+        at: i64,
+    ) -> Result<()> {
+        let mut tokens = self.one_time_tokens.write().unwrap();
+        for token in tokens.iter_mut().filter(|token| {
+            token.user_id == user_id && token.purpose == purpose && token.used_at.is_none()
+        }) {
+            token.used_at = Some(at);
+        }
+        Ok(())
+    }
+
+    async fn last_one_time_token_at(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<i64>> {
+        let tokens = self.one_time_tokens.read().unwrap();
+        Ok(tokens
+            .iter()
+            .filter(|token| token.user_id == user_id && token.purpose == purpose)
+            .map(|token| token.created_at)
+            .max())
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryStore {
+    async fn insert(&self, key: &ApiKey) -> Result<ApiKey> {
+        let mut inserted = key.clone();
+        inserted.id = Some(ObjectId::new());
+
+        let mut keys = self.api_keys.write().unwrap();
+        if keys
+            .iter()
+            .any(|existing| existing.key_hash == key.key_hash)
+        {
+            return Err(DuplicateKeyError.into());
+        }
+
+        keys.push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let keys = self.api_keys.read().unwrap();
+        Ok(keys.iter().find(|key| key.key_hash == key_hash).cloned())
+    }
+
+    async fn find_by_user(&self, user_id: &str) -> Result<Vec<ApiKey>> {
+        let keys = self.api_keys.read().unwrap();
+        Ok(keys
+            .iter()
+            .filter(|key| key.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_by_id(&self, user_id: &str, id: &ObjectId) -> Result<Option<ApiKey>> {
+        let keys = self.api_keys.read().unwrap();
+        Ok(keys
+            .iter()
+            .find(|key| key.id.as_ref() == Some(id) && key.user_id == user_id)
+            .cloned())
+    }
+
+    async fn set_last_used(&self, id: &ObjectId, timestamp: i64) -> Result<()> {
+        let mut keys = self.api_keys.write().unwrap();
+        if let Some(key) = keys.iter_mut().find(|key| key.id.as_ref() == Some(id)) {
+            key.last_used_at = Some(timestamp);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str, id: &ObjectId) -> Result<bool> {
+        let mut keys = self.api_keys.write().unwrap();
+        let before = keys.len();
+        keys.retain(|key| !(key.id.as_ref() == Some(id) && key.user_id == user_id));
+        Ok(keys.len() < before)
+    }
+
+    async fn delete_by_user(&self, user_id: &str) -> Result<u64> {
+        let mut keys = self.api_keys.write().unwrap();
+        let before = keys.len();
+        keys.retain(|key| key.user_id != user_id);
+        Ok((before - keys.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl TotpRepository for MemoryStore {
+    async fn find_totp(&self, user_id: &str) -> Result<Option<TotpCredential>> {
+        let credentials = self.totp_credentials.read().unwrap();
+        Ok(credentials
+            .iter()
+            .find(|credential| credential.user_id == user_id)
+            .cloned())
+    }
+
+    async fn save_pending_totp(&self, credential: &TotpCredential) -> Result<()> {
+        let mut credentials = self.totp_credentials.write().unwrap();
+        credentials.retain(|existing| existing.user_id != credential.user_id);
+        credentials.push(credential.clone());
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: &str,
+        recovery_codes: &[String],
+        enabled_at: i64,
+    ) -> Result<bool> {
+        let mut credentials = self.totp_credentials.write().unwrap();
+        match credentials
+            .iter_mut()
+            .find(|credential| credential.user_id == user_id && !credential.enabled)
+        {
+            Some(credential) => {
+                credential.enabled = true;
+                credential.enabled_at = Some(enabled_at);
+                credential.recovery_codes = recovery_codes.to_vec();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_recovery_codes(&self, user_id: &str, recovery_codes: &[String]) -> Result<()> {
+        let mut credentials = self.totp_credentials.write().unwrap();
+        if let Some(credential) = credentials
+            .iter_mut()
+            .find(|credential| credential.user_id == user_id && credential.enabled)
+        {
+            credential.recovery_codes = recovery_codes.to_vec();
+        }
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool> {
+        let mut credentials = self.totp_credentials.write().unwrap();
+        match credentials.iter_mut().find(|credential| {
+            credential.user_id == user_id
+                && credential.last_used_step.is_none_or(|last| last < step)
+        }) {
+            Some(credential) => {
+                credential.last_used_step = Some(step);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> Result<bool> {
+        let mut credentials = self.totp_credentials.write().unwrap();
+        let Some(credential) = credentials
+            .iter_mut()
+            .find(|credential| credential.user_id == user_id && credential.enabled)
+        else {
+            return Ok(false);
+        };
+
+        let before = credential.recovery_codes.len();
+        credential.recovery_codes.retain(|hash| hash != code_hash);
+        Ok(credential.recovery_codes.len() < before)
+    }
+
+    async fn delete_totp(&self, user_id: &str) -> Result<bool> {
+        let mut credentials = self.totp_credentials.write().unwrap();
+        let before = credentials.len();
+        credentials.retain(|credential| credential.user_id != user_id);
+        Ok(credentials.len() < before)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for MemoryStore {
+    async fn find_identity(&self, issuer: &str, subject: &str) -> Result<Option<ExternalIdentity>> {
+        let identities = self.identities.read().unwrap();
+        Ok(identities
+            .iter()
+            .find(|identity| identity.issuer == issuer && identity.subject == subject)
+            .cloned())
+    }
+
+    async fn insert_identity(&self, identity: &ExternalIdentity) -> Result<ExternalIdentity> {
+        let mut inserted = identity.clone();
+        inserted.id = Some(ObjectId::new());
+
+        let mut identities = self.identities.write().unwrap();
+        if identities.iter().any(|existing| {
+            existing.issuer == identity.issuer && existing.subject == identity.subject
+        }) {
+            return Err(DuplicateKeyError.into());
+        }
+
+        identities.push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn set_identity_login(&self, issuer: &str, subject: &str, timestamp: i64) -> Result<()> {
+        let mut identities = self.identities.write().unwrap();
+        if let Some(identity) = identities
+            .iter_mut()
+            .find(|identity| identity.issuer == issuer && identity.subject == subject)
+        {
+            identity.last_login_at = Some(timestamp);
+        }
+        Ok(())
+    }
+
+    async fn delete_identities_by_user(&self, user_id: &str) -> Result<u64> {
+        let mut identities = self.identities.write().unwrap();
+        let before = identities.len();
+        identities.retain(|identity| identity.user_id != user_id);
+        Ok((before - identities.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl PasskeyRepository for MemoryStore {
+    async fn insert_passkey(&self, passkey: &Passkey) -> Result<Passkey> {
+        let mut inserted = passkey.clone();
+        inserted.id = Some(ObjectId::new());
+
+        let mut passkeys = self.passkeys.write().unwrap();
+        if passkeys
+            .iter()
+            .any(|existing| existing.credential_id == passkey.credential_id)
+        {
+            return Err(DuplicateKeyError.into());
+        }
+
+        passkeys.push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn find_passkey(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        let passkeys = self.passkeys.read().unwrap();
+        Ok(passkeys
+            .iter()
+            .find(|passkey| passkey.credential_id == credential_id)
+            .cloned())
+    }
+
+    async fn find_passkeys_by_user(&self, user_id: &str) -> Result<Vec<Passkey>> {
+        let passkeys = self.passkeys.read().unwrap();
+        Ok(passkeys
+            .iter()
+            .filter(|passkey| passkey.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn record_passkey_use(
+        &self,
+        credential_id: &str,
+        sign_count: i64,
+        used_at: i64,
+    ) -> Result<()> {
+        let mut passkeys = self.passkeys.write().unwrap();
+        if let Some(passkey) = passkeys
+            .iter_mut()
+            .find(|passkey| passkey.credential_id == credential_id)
+        {
+            passkey.sign_count = sign_count;
+            passkey.last_used_at = Some(used_at);
+        }
+        Ok(())
+    }
+
+    async fn delete_passkey(&self, user_id: &str, id: &ObjectId) -> Result<bool> {
+        let mut passkeys = self.passkeys.write().unwrap();
+        let before = passkeys.len();
+        passkeys.retain(|passkey| !(passkey.id.as_ref() == Some(id) && passkey.user_id == user_id));
+        Ok(passkeys.len() < before)
+    }
+
+    async fn delete_passkeys_by_user(&self, user_id: &str) -> Result<u64> {
+        let mut passkeys = self.passkeys.write().unwrap();
+        let before = passkeys.len();
+        passkeys.retain(|passkey| passkey.user_id != user_id);
+        Ok((before - passkeys.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for MemoryStore {
+    async fn insert_invite(&self, invite: &Invite) -> Result<Invite> {
+        let mut inserted = invite.clone();
+        inserted.id = Some(ObjectId::new());
+
+        let mut invites = self.invites.write().unwrap();
+        if invites
+            .iter()
+            .any(|existing| existing.code_hash == invite.code_hash)
+        {
+            return Err(DuplicateKeyError.into());
+        }
+
+        invites.push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn find_invites(&self) -> Result<Vec<Invite>> {
+        let invites = self.invites.read().unwrap();
+        Ok(invites.iter().rev().cloned().collect())
+    }
+
+    async fn find_invite(&self, id: &ObjectId) -> Result<Option<Invite>> {
+        let invites = self.invites.read().unwrap();
+        Ok(invites
+            .iter()
+            .find(|invite| invite.id.as_ref() == Some(id))
+            .cloned())
+    }
+
+    async fn find_invite_by_hash(&self, code_hash: &str) -> Result<Option<Invite>> {
+        let invites = self.invites.read().unwrap();
+        Ok(invites
+            .iter()
+            .find(|invite| invite.code_hash == code_hash)
+            .cloned())
+    }
+
+    async fn redeem_invite(&self, id: &ObjectId, used: &InviteUse) -> Result<bool> {
+        let mut invites = self.invites.write().unwrap();
+        let Some(invite) = invites
+            .iter_mut()
+            .find(|invite| invite.id.as_ref() == Some(id))
+        else {
+            return Ok(false);
+        };
+
+        let expired = invite
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= used.used_at);
+        if expired || invite.is_used_up() {
+            return Ok(false);
+        }
+
+        invite.uses.push(used.clone());
+        Ok(true)
+    }
+
+    async fn delete_invite(&self, id: &ObjectId) -> Result<bool> {
+        let mut invites = self.invites.write().unwrap();
+        let before = invites.len();
+        invites.retain(|invite| invite.id.as_ref() != Some(id));
+        Ok(invites.len() < before)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MemoryStore {
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        let mut inserted = event.clone();
+        inserted.id = Some(ObjectId::new());
+
+        self.audit_events.write().unwrap().push(inserted);
+        Ok(())
+    }
+
+    async fn find_audit_events(&self, filter: &AuditFilter, limit: i64) -> Result<Vec<AuditEvent>> {
+        let events = self.audit_events.read().unwrap();
+        let mut matching: Vec<AuditEvent> = events
+            .iter()
+            .filter(|event| {
+                filter.actor.as_ref().is_none_or(|actor| {
+                    event.actor_id.as_ref() == Some(actor)
+                        || event.actor_username.as_ref() == Some(actor)
+                })
+            })
+            .filter(|event| filter.action.is_none_or(|action| event.action == action))
+            .filter(|event| filter.from.is_none_or(|from| event.created_at >= from))
+            .filter(|event| filter.to.is_none_or(|to| event.created_at < to))
+            .filter(|event| {
+                filter.before.is_none_or(|cursor| {
+                    (event.created_at, event.id) < (cursor.created_at, Some(cursor.id))
+                })
+            })
+            .cloned()
+            .collect();
+
+        matching.sort_by_key(|event| std::cmp::Reverse((event.created_at, event.id)));
+        matching.truncate(limit as usize);
+        Ok(matching)
+    }
+}
+
+#[async_trait]
+impl WorkspaceRepository for MemoryStore {
+    async fn insert_workspace(&self, workspace: &Workspace) -> Result<Workspace> {
+        let mut inserted = workspace.clone();
+        inserted.id = Some(ObjectId::new());
+
+        self.workspaces.write().unwrap().push(inserted.clone());
+        Ok(inserted)
+    }
+
+    async fn find_workspace(&self, id: &ObjectId) -> Result<Option<Workspace>> {
+        let workspaces = self.workspaces.read().unwrap();
+        Ok(workspaces
+            .iter()
+            .find(|workspace| workspace.id.as_ref() == Some(id))
+            .cloned())
+    }
+
+    async fn find_workspaces(&self, member_id: Option<&str>) -> Result<Vec<Workspace>> {
+        let workspaces = self.workspaces.read().unwrap();
+        Ok(workspaces
+            .iter()
+            .rev()
+            .filter(|workspace| member_id.is_none_or(|id| workspace.role_of(id).is_some()))
+            .cloned()
+            .collect())
+    }
+
+    async fn rename_workspace(&self, id: &ObjectId, name: &str, at: i64) -> Result<bool> {
+        let mut workspaces = self.workspaces.write().unwrap();
+        let Some(workspace) = workspaces
+            .iter_mut()
+            .find(|workspace| workspace.id.as_ref() == Some(id))
+        else {
+            return Ok(false);
+        };
+
+        workspace.name = name.to_string();
+        workspace.updated_at = at;
+        Ok(true)
+    }
+
+    async fn delete_workspace(&self, id: &ObjectId) -> Result<bool> {
+        let mut workspaces = self.workspaces.write().unwrap();
+        let before = workspaces.len();
+        workspaces.retain(|workspace| workspace.id.as_ref() != Some(id));
+        Ok(workspaces.len() < before)
+    }
+
+    async fn add_member(&self, id: &ObjectId, member: &WorkspaceMember) -> Result<bool> {
+        let mut workspaces = self.workspaces.write().unwrap();
+        let Some(workspace) = workspaces
+            .iter_mut()
+            .find(|workspace| workspace.id.as_ref() == Some(id))
+        else {
+            return Ok(false);
+        };
+        if workspace.role_of(&member.user_id).is_some() {
+            return Err(DuplicateKeyError.into());
+        }
+
+        workspace.members.push(member.clone());
+        workspace.updated_at = member.added_at;
+        Ok(true)
+    }
+
+    async fn set_member_role(
+        &self,
+        id: &ObjectId,
+        user_id: &str,
+        role: WorkspaceRole,
+        at: i64,
+    ) -> Result<MemberChange> {
+        let mut workspaces = self.workspaces.write().unwrap();
+        let Some(workspace) = workspaces
+            .iter_mut()
+            .find(|workspace| workspace.id.as_ref() == Some(id))
+        else {
+            return Ok(MemberChange::NotMember);
+        };
+        if role != WorkspaceRole::Owner && workspace.is_last_owner(user_id) {
+            return Ok(MemberChange::LastOwner);
+        }
+        let Some(member) = workspace
+            .members
+            .iter_mut()
+            .find(|member| member.user_id == user_id)
+        else {
+            return Ok(MemberChange::NotMember);
+        };
+
+        member.role = role;
+        workspace.updated_at = at;
+        Ok(MemberChange::Changed)
+    }
+
+    async fn remove_member(&self, id: &ObjectId, user_id: &str, at: i64) -> Result<MemberChange> {
+        let mut workspaces = self.workspaces.write().unwrap();
+        let Some(workspace) = workspaces
+            .iter_mut()
+            .find(|workspace| workspace.id.as_ref() == Some(id))
+        else {
+            return Ok(MemberChange::NotMember);
+        };
+        if workspace.role_of(user_id).is_none() {
+            return Ok(MemberChange::NotMember);
+        }
+        if workspace.is_last_owner(user_id) {
+            return Ok(MemberChange::LastOwner);
+        }
+
+        workspace.members.retain(|member| member.user_id != user_id);
+        workspace.updated_at = at;
+        Ok(MemberChange::Changed)
+    }
+
+    async fn remove_user_memberships(&self, user_id: &str) -> Result<u64> {
+        let mut workspaces = self.workspaces.write().unwrap();
+        let mut removed = 0;
+        for workspace in workspaces.iter_mut() {
+            let before = workspace.members.len();
+            workspace.members.retain(|member| member.user_id != user_id);
+            removed += (before - workspace.members.len()) as u64;
+        }
+        Ok(removed)
+    }
+}
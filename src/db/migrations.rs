@@ -0,0 +1,269 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use mongodb::Database;
+use mongodb::bson::{Bson, Document, doc};
+
+/// Collection recording which migrations have been applied
+const MIGRATIONS_COLLECTION: &str = "migrations";
+
+/// A single versioned change to the documents stored in MongoDB.
+/// Migrations are applied in ascending version order and each version runs at most once.
+#[async_trait]
+pub trait Migration: Send + Sync {
+    fn version(&self) -> i32;
+
+    fn name(&self) -> &'static str;
+
+    /// Apply the migration and return the number of affected documents.
+    /// In dry-run mode nothing is written and the number of documents that would change is returned.
+    async fn run(&self, db: &Database, dry_run: bool) -> Result<u64>;
+}
+
+/// Outcome of a migration that was applied (or would be, in dry-run mode).
+/// The number of affected documents is only known for MongoDB migrations.
+pub struct MigrationReport {
+    pub version: i64,
+    pub name: String,
+    pub affected: Option<u64>,
+}
+
+/// Log the migrations that were applied, or warn about pending ones that were skipped
+pub fn log_reports(reports: &[MigrationReport], applied: bool) {
+    for report in reports {
+        let affected = report
+            .affected
+            .map(|count| format!(" ({} documents)", count))
+            .unwrap_or_default();
+
+        if applied {
+            log::info!(
+                "Applied migration {} {}{}",
+                report.version,
+                report.name,
+                affected
+            );
+        } else {
+            log::warn!(
+                "Migration {} {} is pending{}; run `makemeshort migrate` to apply it",
+                report.version,
+                report.name,
+                affected
+            );
+        }
+    }
+}
+
+/// All known migrations, in the order they must be applied
+fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(BackfillUrlFields),
+        Box::new(BackfillUserRoles),
+        Box::new(DedupeVisitors),
+        Box::new(BackfillUrlVersions),
+    ]
+}
+
+/// Apply every migration that has not been recorded in the `migrations` collection yet.
+/// With `dry_run` set, pending migrations are only evaluated and nothing is recorded.
+pub async fn run_migrations(db: &Database, dry_run: bool) -> Result<Vec<MigrationReport>> {
+    let collection = db.collection::<Document>(MIGRATIONS_COLLECTION);
+
+    let applied: Vec<i32> = collection
+        .find(doc! {})
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .iter()
+        .filter_map(|record| record.get_i32("_id").ok())
+        .collect();
+
+    let mut reports = Vec::new();
+
+    for migration in migrations() {
+        if applied.contains(&migration.version()) {
+            continue;
+        }
+
+        let affected = migration.run(db, dry_run).await.map_err(|e| {
+            anyhow::anyhow!(
+                "Migration {} ({}) failed: {}",
+                migration.version(),
+                migration.name(),
+                e
+            )
+        })?;
+
+        if !dry_run {
+            // The version is the document ID, so concurrent runs cannot record it twice
+            collection
+                .insert_one(doc! {
+                    "_id": migration.version(),
+                    "name": migration.name(),
+                    "applied_at": chrono::Utc::now().timestamp_millis(),
+                })
+                .await?;
+        }
+
+        reports.push(MigrationReport {
+            version: migration.version().into(),
+            name: migration.name().to_string(),
+            affected: Some(affected),
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Populate `created_at` from the document ID and `clicks` with zero on URLs created
+/// before those fields were stored
+struct BackfillUrlFields;
+
+#[async_trait]
+impl Migration for BackfillUrlFields {
+    fn version(&self) -> i32 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "backfill_url_fields"
+    }
+
+    async fn run(&self, db: &Database, dry_run: bool) -> Result<u64> {
+        let urls = db.collection::<Document>("urls");
+        let missing_created_at = doc! { "created_at": { "$exists": false } };
+        let missing_clicks = doc! { "clicks": { "$exists": false } };
+
+        if dry_run {
+            let filter = doc! { "$or": [missing_created_at, missing_clicks] };
+            return Ok(urls.count_documents(filter).await?);
+        }
+
+        // The ObjectId embeds its creation time, which is the best estimate we have
+        let created_at = urls
+            .update_many(
+                missing_created_at,
+                vec![doc! { "$set": { "created_at": { "$toLong": { "$toDate": "$_id" } } } }],
+            )
+            .await?;
+
+        let clicks = urls
+            .update_many(missing_clicks, doc! { "$set": { "clicks": 0_i64 } })
+            .await?;
+
+        Ok(created_at.modified_count + clicks.modified_count)
+    }
+}
+
+/// Give users without a role the default `member` role. Nobody is promoted to `admin`,
+/// since the earliest account is not necessarily the one that should administer the
+/// instance; `makemeshort promote-admin` does that explicitly.
+struct BackfillUserRoles;
+
+#[async_trait]
+impl Migration for BackfillUserRoles {
+    fn version(&self) -> i32 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "backfill_user_roles"
+    }
+
+    async fn run(&self, db: &Database, dry_run: bool) -> Result<u64> {
+        let users = db.collection::<Document>("users");
+        let missing_role = doc! { "role": { "$exists": false } };
+
+        if dry_run {
+            return Ok(users.count_documents(missing_role).await?);
+        }
+
+        let result = users
+            .update_many(missing_role, doc! { "$set": { "role": "member" } })
+            .await?;
+        Ok(result.modified_count)
+    }
+}
+
+/// Version of [`DedupeVisitors`], which the unique visitor index has to wait for
+pub const DEDUPE_VISITORS_VERSION: i32 = 3;
+
+/// Remove repeated visitor records for the same link so the unique
+/// `(short_code, visitor_hash)` index can be built. The earliest visit is kept.
+struct DedupeVisitors;
+
+#[async_trait]
+impl Migration for DedupeVisitors {
+    fn version(&self) -> i32 {
+        DEDUPE_VISITORS_VERSION
+    }
+
+    fn name(&self) -> &'static str {
+        "dedupe_visitors"
+    }
+
+    async fn run(&self, db: &Database, dry_run: bool) -> Result<u64> {
+        let visitors = db.collection::<Document>("visitors");
+
+        let groups: Vec<Document> = visitors
+            .aggregate(vec![
+                doc! { "$sort": { "timestamp": 1, "_id": 1 } },
+                doc! {
+                    "$group": {
+                        "_id": { "short_code": "$short_code", "visitor_hash": "$visitor_hash" },
+                        "ids": { "$push": "$_id" },
+                        "count": { "$sum": 1 }
+                    }
+                },
+                doc! { "$match": { "count": { "$gt": 1 } } },
+            ])
+            .await?
+            .try_collect()
+            .await?;
+
+        // Everything but the first (earliest) visit in each group is a duplicate
+        let duplicates: Vec<Bson> = groups
+            .iter()
+            .filter_map(|group| group.get_array("ids").ok())
+            .flat_map(|ids| ids.iter().skip(1).cloned())
+            .collect();
+
+        if dry_run || duplicates.is_empty() {
+            return Ok(duplicates.len() as u64);
+        }
+
+        let result = visitors
+            .delete_many(doc! { "_id": { "$in": duplicates } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+/// Number the URLs created before they could be edited as version 1, so edits can
+/// match on the version they started from
+struct BackfillUrlVersions;
+
+#[async_trait]
+impl Migration for BackfillUrlVersions {
+    fn version(&self) -> i32 {
+        4
+    }
+
+    fn name(&self) -> &'static str {
+        "backfill_url_versions"
+    }
+
+    async fn run(&self, db: &Database, dry_run: bool) -> Result<u64> {
+        let urls = db.collection::<Document>("urls");
+        let missing_version = doc! { "version": { "$exists": false } };
+
+        if dry_run {
+            return Ok(urls.count_documents(missing_version).await?);
+        }
+
+        let result = urls
+            .update_many(missing_version, doc! { "$set": { "version": 1_i64 } })
+            .await?;
+        Ok(result.modified_count)
+    }
+}
@@ -1 +1,4 @@
+pub mod memory;
+pub mod migrations;
 pub mod mongodb;
+pub mod sql;
@@ -1,10 +1,50 @@
-use std::env;
+use std::time::Duration;
 
-use anyhow::Result;
-use mongodb::{Client, Database, options::ClientOptions};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use mongodb::bson::{Bson, DateTime, Document, doc, oid::ObjectId};
+use mongodb::error::{Error as MongoError, ErrorKind, WriteFailure};
+use mongodb::options::{ClientOptions, Collation, CollationStrength, IndexOptions, ReturnDocument};
+use mongodb::{Client, ClientSession, Collection, Database, IndexModel};
 
-pub async fn get_database() -> Result<Database> {
-    let mongodb_string = env::var("MONGODB_URL").expect("MONGODB_URL not set.");
+use crate::config::app_config::StorageConfig;
+use crate::db::migrations::{DEDUPE_VISITORS_VERSION, log_reports, run_migrations};
+use crate::models::api_key::ApiKey;
+use crate::models::audit_event::AuditEvent;
+use crate::models::identity::ExternalIdentity;
+use crate::models::invite::{Invite, InviteUse};
+use crate::models::one_time_token::{OneTimeToken, TokenPurpose};
+use crate::models::passkey::Passkey;
+use crate::models::qr_code::{QrCode, TargetType};
+use crate::models::refresh_token::RefreshToken;
+use crate::models::totp::TotpCredential;
+use crate::models::url::ShortenedUrl;
+use crate::models::url_version::UrlVersion;
+use crate::models::url_visitor::UrlVisitor;
+use crate::models::user::User;
+use crate::models::workspace::{Workspace, WorkspaceMember, WorkspaceRole};
+use crate::repositories::api_key_repository::ApiKeyRepository;
+use crate::repositories::audit_repository::{AuditFilter, AuditRepository};
+use crate::repositories::errors::DuplicateKeyError;
+use crate::repositories::health_repository::HealthRepository;
+use crate::repositories::identity_repository::IdentityRepository;
+use crate::repositories::invite_repository::InviteRepository;
+use crate::repositories::passkey_repository::PasskeyRepository;
+use crate::repositories::qr_code_repository::{QrCodeFilter, QrCodeRepository};
+use crate::repositories::token_repository::TokenRepository;
+use crate::repositories::totp_repository::TotpRepository;
+use crate::repositories::url_repository::{UrlFilter, UrlRepository};
+use crate::repositories::user_repository::{ResourcePolicy, UserRepository, UserUpdate};
+use crate::repositories::visitor_repository::VisitorRepository;
+use crate::repositories::workspace_repository::{MemberChange, WorkspaceRepository};
+
+/// Connect to the configured MongoDB database
+pub async fn connect(config: &StorageConfig) -> Result<Database> {
+    let mongodb_string = config
+        .mongodb_url
+        .as_deref()
+        .context("MongoDB connection string not configured")?;
     let client_options = ClientOptions::parse(mongodb_string)
         .await
         .map_err(|e| anyhow::anyhow!("Failed to parse MongoDB connection string: {}", e))?;
@@ -12,5 +52,1466 @@ pub async fn get_database() -> Result<Database> {
     let client = Client::with_options(client_options)
         .map_err(|e| anyhow::anyhow!("Failed to create MongoDB client: {}", e))?;
 
-    Ok(client.database("url_db"))
+    Ok(client.database(&config.mongodb_database))
+}
+
+/// Connect to MongoDB, bring the stored documents up to date and create the required indexes
+pub async fn get_database(config: &StorageConfig) -> Result<Database> {
+    let db = connect(config).await?;
+
+    // Migrations run first so they can clean up data that would violate the unique indexes
+    let reports = run_migrations(&db, !config.migrate_on_startup).await?;
+    log_reports(&reports, config.migrate_on_startup);
+
+    // Without the migrations, duplicate visits may still be stored and the unique index
+    // on them would fail to build
+    let dedupe_pending = !config.migrate_on_startup
+        && reports
+            .iter()
+            .any(|report| report.version == i64::from(DEDUPE_VISITORS_VERSION));
+    ensure_indexes(&db, dedupe_pending).await?;
+
+    Ok(db)
+}
+
+/// MongoDB server error code for duplicate key violations
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+fn index(keys: Document, unique: bool) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(unique).build())
+        .build()
+}
+
+/// Create the indexes the repositories rely on for lookups and uniqueness
+/// guarantees. Creating an index that already exists is a no-op. The unique visitor
+/// index is left out while duplicate visits may still exist.
+async fn ensure_indexes(db: &Database, dedupe_pending: bool) -> Result<()> {
+    let indexes = [
+        (
+            "urls",
+            vec![
+                index(doc! { "short_code": 1 }, true),
+                index(doc! { "user_id": 1 }, false),
+                index(doc! { "workspace_id": 1 }, false),
+            ],
+        ),
+        (
+            "url_versions",
+            vec![index(doc! { "short_code": 1, "version": 1 }, true)],
+        ),
+        (
+            "visitors",
+            vec![index(doc! { "short_code": 1, "visitor_hash": 1 }, true)],
+        ),
+        (
+            "qr_codes",
+            vec![
+                index(doc! { "short_code": 1, "target_type": 1 }, true),
+                index(doc! { "user_id": 1 }, false),
+                index(doc! { "workspace_id": 1 }, false),
+            ],
+        ),
+        (
+            "users",
+            vec![
+                index(doc! { "username": 1 }, true),
+                index(doc! { "email": 1 }, false),
+            ],
+        ),
+        (
+            "refresh_tokens",
+            vec![
+                index(doc! { "token_hash": 1 }, true),
+                index(doc! { "family_id": 1 }, false),
+                index(doc! { "user_id": 1 }, false),
+            ],
+        ),
+        (
+            "api_keys",
+            vec![
+                index(doc! { "key_hash": 1 }, true),
+                index(doc! { "user_id": 1 }, false),
+            ],
+        ),
+        ("totp_credentials", vec![index(doc! { "user_id": 1 }, true)]),
+        (
+            "external_identities",
+            vec![
+                index(doc! { "issuer": 1, "subject": 1 }, true),
+                index(doc! { "user_id": 1 }, false),
+            ],
+        ),
+        (
+            "passkeys",
+            vec![
+                index(doc! { "credential_id": 1 }, true),
+                index(doc! { "user_id": 1 }, false),
+            ],
+        ),
+        ("invites", vec![index(doc! { "code_hash": 1 }, true)]),
+        (
+            "audit_events",
+            vec![
+                index(doc! { "created_at": -1, "_id": -1 }, false),
+                index(doc! { "actor_id": 1, "created_at": -1 }, false),
+                index(doc! { "action": 1, "created_at": -1 }, false),
+            ],
+        ),
+        (
+            "workspaces",
+            vec![index(doc! { "members.user_id": 1 }, false)],
+        ),
+        (
+            "revoked_tokens",
+            // Entries are removed by MongoDB once the token would have expired anyway
+            vec![
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+                    .build(),
+            ],
+        ),
+    ];
+
+    for (collection, models) in indexes {
+        if collection == "visitors" && dedupe_pending {
+            log::warn!(
+                "Not creating the unique index on visitors until migration {} is applied; \
+                 repeated visits are counted again until then. Run `makemeshort migrate` to apply it",
+                DEDUPE_VISITORS_VERSION
+            );
+            continue;
+        }
+
+        db.collection::<Document>(collection)
+            .create_indexes(models)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create indexes on {}: {}", collection, e))?;
+    }
+
+    Ok(())
+}
+
+/// Whether a MongoDB error was caused by a unique index violation
+fn is_duplicate_key(err: &MongoError) -> bool {
+    match err.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY_CODE,
+        ErrorKind::Command(e) => e.code == DUPLICATE_KEY_CODE,
+        _ => false,
+    }
+}
+
+/// Convert duplicate key errors into `DuplicateKeyError` so handlers can detect them
+fn map_write_error(err: MongoError) -> anyhow::Error {
+    if is_duplicate_key(&err) {
+        DuplicateKeyError.into()
+    } else {
+        err.into()
+    }
+}
+
+/// Repository implementations backed by MongoDB collections
+pub struct MongoStore {
+    db: Database,
+}
+
+impl MongoStore {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn urls(&self) -> Collection<ShortenedUrl> {
+        self.db.collection("urls")
+    }
+
+    fn url_versions(&self) -> Collection<UrlVersion> {
+        self.db.collection("url_versions")
+    }
+
+    fn visitors(&self) -> Collection<UrlVisitor> {
+        self.db.collection("visitors")
+    }
+
+    fn qr_codes(&self) -> Collection<QrCode> {
+        self.db.collection("qr_codes")
+    }
+
+    fn users(&self) -> Collection<User> {
+        self.db.collection("users")
+    }
+
+    fn refresh_tokens(&self) -> Collection<RefreshToken> {
+        self.db.collection("refresh_tokens")
+    }
+
+    fn one_time_tokens(&self) -> Collection<OneTimeToken> {
+        self.db.collection("one_time_tokens")
+    }
+
+    fn revoked_tokens(&self) -> Collection<Document> {
+        self.db.collection("revoked_tokens")
+    }
+
+    fn user_token_revocations(&self) -> Collection<Document> {
+        self.db.collection("user_token_revocations")
+    }
+
+    fn api_keys(&self) -> Collection<ApiKey> {
+        self.db.collection("api_keys")
+    }
+
+    fn totp_credentials(&self) -> Collection<TotpCredential> {
+        self.db.collection("totp_credentials")
+    }
+
+    fn identities(&self) -> Collection<ExternalIdentity> {
+        self.db.collection("external_identities")
+    }
+
+    fn passkeys(&self) -> Collection<Passkey> {
+        self.db.collection("passkeys")
+    }
+
+    fn invites(&self) -> Collection<Invite> {
+        self.db.collection("invites")
+    }
+
+    fn audit_events(&self) -> Collection<AuditEvent> {
+        self.db.collection("audit_events")
+    }
+
+    fn workspaces(&self) -> Collection<Workspace> {
+        self.db.collection("workspaces")
+    }
+}
+
+/// Case-insensitive match on short code or original URL
+fn search_filter(search: &str) -> Document {
+    doc! {
+        "$or": [
+            { "short_code": { "$regex": search, "$options": "i" } },
+            { "original_url": { "$regex": search, "$options": "i" } }
+        ]
+    }
+}
+
+#[async_trait]
+impl HealthRepository for MongoStore {
+    async fn ping(&self) -> Result<()> {
+        self.db.run_command(doc! { "ping": 1 }).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UrlRepository for MongoStore {
+    async fn insert(&self, url: &ShortenedUrl) -> Result<ShortenedUrl> {
+        let result = self.urls().insert_one(url).await.map_err(map_write_error)?;
+
+        let mut inserted = url.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn find_by_code(&self, code: &str) -> Result<Option<ShortenedUrl>> {
+        Ok(self.urls().find_one(doc! { "short_code": code }).await?)
+    }
+
+    async fn find(&self, filter: &UrlFilter) -> Result<Vec<ShortenedUrl>> {
+        let mut conditions = Vec::new();
+
+        if let Some(search) = filter.search.as_deref().filter(|s| !s.is_empty()) {
+            conditions.push(search_filter(search));
+        }
+
+        if let Some(user_id) = &filter.user_id {
+            conditions.push(doc! { "user_id": user_id });
+        }
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            conditions.push(doc! { "workspace_id": workspace_id });
+        }
+
+        let query = if conditions.is_empty() {
+            doc! {}
+        } else {
+            doc! { "$and": conditions }
+        };
+
+        Ok(self.urls().find(query).await?.try_collect().await?)
+    }
+
+    async fn increment_clicks(&self, code: &str) -> Result<()> {
+        self.urls()
+            .update_one(
+                doc! { "short_code": code },
+                doc! { "$inc": { "clicks": 1 } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn update_with_history(&self, url: &ShortenedUrl, previous: &UrlVersion) -> Result<bool> {
+        // The unique (short_code, version) index lets only one edit of a version through
+        match self.url_versions().insert_one(previous).await {
+            Ok(_) => {}
+            Err(e) if is_duplicate_key(&e) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        let result = self
+            .urls()
+            .update_one(
+                doc! { "short_code": &url.short_code, "version": previous.version },
+                doc! {
+                    "$set": {
+                        "original_url": &url.original_url,
+                        "expires_at": url.expires_at,
+                        "title": &url.title,
+                        "password_hash": &url.password_hash,
+                        "version": url.version,
+                    }
+                },
+            )
+            .await?;
+        if result.matched_count == 0 {
+            // The URL was deleted in the meantime
+            self.url_versions()
+                .delete_one(doc! { "short_code": &url.short_code, "version": previous.version })
+                .await?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    async fn find_versions(&self, code: &str) -> Result<Vec<UrlVersion>> {
+        Ok(self
+            .url_versions()
+            .find(doc! { "short_code": code })
+            .sort(doc! { "version": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<bool> {
+        let result = self.urls().delete_one(doc! { "short_code": code }).await?;
+        self.url_versions()
+            .delete_many(doc! { "short_code": code })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl VisitorRepository for MongoStore {
+    async fn insert_if_new(&self, visitor: &UrlVisitor) -> Result<bool> {
+        // The unique (short_code, visitor_hash) index rejects repeat visitors
+        match self.visitors().insert_one(visitor).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn count_by_code(&self, code: &str) -> Result<u64> {
+        Ok(self
+            .visitors()
+            .count_documents(doc! { "short_code": code })
+            .await?)
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64> {
+        let result = self
+            .visitors()
+            .delete_many(doc! { "short_code": code })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl QrCodeRepository for MongoStore {
+    async fn find_by_code(&self, code: &str, target_type: &TargetType) -> Result<Option<QrCode>> {
+        Ok(self
+            .qr_codes()
+            .find_one(doc! {
+                "short_code": code,
+                "target_type": target_type.as_str()
+            })
+            .await?)
+    }
+
+    async fn find_direct_by_url(&self, url: &str) -> Result<Option<QrCode>> {
+        Ok(self
+            .qr_codes()
+            .find_one(doc! {
+                "original_url": url,
+                "short_code": { "$regex": "^direct-" }, // Find direct QR codes
+                "target_type": "original"
+            })
+            .await?)
+    }
+
+    async fn find(&self, filter: &QrCodeFilter) -> Result<Vec<QrCode>> {
+        let mut conditions = Vec::new();
+
+        if let Some(search) = filter.search.as_deref().filter(|s| !s.is_empty()) {
+            conditions.push(search_filter(search));
+        }
+
+        if let Some(target_type) = &filter.target_type {
+            conditions.push(doc! { "target_type": target_type.as_str() });
+        }
+
+        if filter.direct_only {
+            conditions.push(doc! { "short_code": { "$regex": "^direct-" } });
+        }
+
+        if let Some(user_id) = &filter.user_id {
+            conditions.push(doc! { "user_id": user_id });
+        }
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            conditions.push(doc! { "workspace_id": workspace_id });
+        }
+
+        let query = if conditions.is_empty() {
+            doc! {}
+        } else {
+            doc! { "$and": conditions }
+        };
+
+        Ok(self.qr_codes().find(query).await?.try_collect().await?)
+    }
+
+    async fn upsert(&self, qr_code: &QrCode) -> Result<()> {
+        self.qr_codes()
+            .update_one(
+                doc! {
+                    "short_code": &qr_code.short_code,
+                    "target_type": qr_code.target_type.as_str()
+                },
+                doc! {
+                    "$set": {
+                        "svg_content": &qr_code.svg_content,
+                        "generated_at": qr_code.generated_at,
+                    },
+                    "$setOnInsert": {
+                        "original_url": &qr_code.original_url,
+                        "user_id": &qr_code.user_id,
+                        "workspace_id": &qr_code.workspace_id,
+                    }
+                },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64> {
+        let result = self
+            .qr_codes()
+            .delete_many(doc! { "short_code": code })
+            .await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn retarget(&self, code: &str, original_url: &str) -> Result<u64> {
+        let result = self
+            .qr_codes()
+            .delete_many(doc! {
+                "short_code": code,
+                "target_type": TargetType::Original.as_str(),
+            })
+            .await?;
+        self.qr_codes()
+            .update_many(
+                doc! { "short_code": code },
+                doc! { "$set": { "original_url": original_url } },
+            )
+            .await?;
+        Ok(result.deleted_count)
+    }
+
+    async fn clear_workspace(&self, workspace_id: &str) -> Result<u64> {
+        let result = self
+            .qr_codes()
+            .update_many(
+                doc! { "workspace_id": workspace_id },
+                doc! { "$unset": { "workspace_id": "" } },
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+}
+
+#[async_trait]
+impl UserRepository for MongoStore {
+    async fn insert(&self, user: &User) -> Result<User> {
+        let result = self
+            .users()
+            .insert_one(user)
+            .await
+            .map_err(map_write_error)?;
+
+        let mut inserted = user.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn find_by_id(&self, id: &ObjectId) -> Result<Option<User>> {
+        Ok(self.users().find_one(doc! { "_id": id }).await?)
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        Ok(self.users().find_one(doc! { "username": username }).await?)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Vec<User>> {
+        // Secondary strength compares case-insensitively
+        let collation = Collation::builder()
+            .locale("en")
+            .strength(CollationStrength::Secondary)
+            .build();
+
+        Ok(self
+            .users()
+            .find(doc! { "email": email })
+            .collation(collation)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_all_except(&self, id: &ObjectId) -> Result<Vec<User>> {
+        Ok(self
+            .users()
+            .find(doc! { "_id": { "$ne": id } })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn count(&self) -> Result<u64> {
+        Ok(self.users().count_documents(doc! {}).await?)
+    }
+
+    async fn update(&self, id: &ObjectId, update: &UserUpdate) -> Result<Option<User>> {
+        // Build update document
+        let mut set = doc! { "updated_at": chrono::Utc::now().timestamp_millis() };
+
+        if let Some(username) = &update.username {
+            set.insert("username", username);
+        }
+
+        if let Some(email) = &update.email {
+            set.insert("email", email);
+            set.insert("email_verified_at", Bson::Null);
+        }
+
+        if let Some(full_name) = &update.full_name {
+            set.insert("full_name", full_name);
+        }
+
+        if let Some(password_hash) = &update.password_hash {
+            set.insert("password_hash", password_hash);
+        }
+
+        if let Some(is_active) = update.is_active {
+            set.insert("is_active", is_active);
+        }
+
+        if let Some(role) = update.role {
+            set.insert("role", role.as_str());
+        }
+
+        Ok(self
+            .users()
+            .find_one_and_update(doc! { "_id": id }, doc! { "$set": set })
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(map_write_error)?)
+    }
+
+    async fn set_last_login(&self, id: &ObjectId, timestamp: i64) -> Result<()> {
+        self.users()
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "last_login": timestamp, "failed_login_attempts": 0_i64 } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn record_failed_login(&self, id: &ObjectId, at: i64, reset_before: i64) -> Result<i64> {
+        // Pipeline update so the count is reset or incremented atomically
+        let user = self
+            .users()
+            .find_one_and_update(
+                doc! { "_id": id },
+                vec![doc! {
+                    "$set": {
+                        "failed_login_attempts": {
+                            "$cond": [
+                                { "$lt": [{ "$ifNull": ["$last_failed_login", 0_i64] }, reset_before] },
+                                1_i64,
+                                { "$add": [{ "$ifNull": ["$failed_login_attempts", 0_i64] }, 1_i64] }
+                            ]
+                        },
+                        "last_failed_login": at
+                    }
+                }],
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(user.map_or(0, |user| user.failed_login_attempts))
+    }
+
+    async fn lock(&self, id: &ObjectId, locked_at: i64, locked_until: i64) -> Result<()> {
+        self.users()
+            .update_one(
+                doc! { "_id": id },
+                doc! {
+                    "$set": {
+                        "locked_at": locked_at,
+                        "locked_until": locked_until,
+                        "failed_login_attempts": 0_i64
+                    }
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn unlock(&self, id: &ObjectId) -> Result<Option<User>> {
+        Ok(self
+            .users()
+            .find_one_and_update(
+                doc! { "_id": id },
+                doc! { "$set": { "locked_until": null, "failed_login_attempts": 0_i64 } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    async fn set_email_verified(&self, id: &ObjectId, email: &str, at: i64) -> Result<bool> {
+        let result = self
+            .users()
+            .update_one(
+                doc! { "_id": id, "email": email },
+                doc! { "$set": { "email_verified_at": at } },
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<bool> {
+        let result = self.users().delete_one(doc! { "_id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn delete_with_resources(
+        &self,
+        id: &ObjectId,
+        policy: &ResourcePolicy,
+        at: i64,
+    ) -> Result<bool> {
+        let mut session = self.db.client().start_session().await?;
+        // Standalone servers don't support transactions; the changes are then applied
+        // one after another, deleting the user last so a failed attempt can be repeated
+        let in_transaction = match session.start_transaction().await {
+            Ok(()) => true,
+            Err(e) if matches!(*e.kind, ErrorKind::Transaction { .. }) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        let result = self
+            .delete_user_in_session(&mut session, id, policy, at)
+            .await;
+        if in_transaction {
+            match result {
+                Ok(true) => session.commit_transaction().await?,
+                // The original error is more useful than one from aborting
+                _ => session.abort_transaction().await.unwrap_or_default(),
+            }
+        }
+        result
+    }
+}
+
+impl MongoStore {
+    /// The changes of `delete_with_resources`, made within the session
+    async fn delete_user_in_session(
+        &self,
+        session: &mut ClientSession,
+        id: &ObjectId,
+        policy: &ResourcePolicy,
+        at: i64,
+    ) -> Result<bool> {
+        if self
+            .users()
+            .find_one(doc! { "_id": id })
+            .session(&mut *session)
+            .await?
+            .is_none()
+        {
+            return Ok(false);
+        }
+
+        // Personal URLs and QR codes are the ones not owned by a workspace
+        let user_id = id.to_hex();
+        let personal = doc! { "user_id": &user_id, "workspace_id": null };
+        match policy {
+            ResourcePolicy::Delete => {
+                let codes = self
+                    .urls()
+                    .distinct("short_code", personal.clone())
+                    .session(&mut *session)
+                    .await?;
+                self.visitors()
+                    .delete_many(doc! { "short_code": { "$in": &codes } })
+                    .session(&mut *session)
+                    .await?;
+                self.qr_codes()
+                    .delete_many(doc! {
+                        "$or": [{ "short_code": { "$in": &codes } }, personal.clone()]
+                    })
+                    .session(&mut *session)
+                    .await?;
+                self.url_versions()
+                    .delete_many(doc! { "short_code": { "$in": &codes } })
+                    .session(&mut *session)
+                    .await?;
+                self.urls()
+                    .delete_many(personal)
+                    .session(&mut *session)
+                    .await?;
+            }
+            ResourcePolicy::Disable => {
+                self.urls()
+                    .update_many(personal, doc! { "$set": { "disabled_at": at } })
+                    .session(&mut *session)
+                    .await?;
+            }
+            ResourcePolicy::Reassign(_) => {}
+        }
+
+        // Whatever is left gets the new owner, or none
+        let new_owner = match policy {
+            ResourcePolicy::Reassign(new_owner) => Bson::String(new_owner.clone()),
+            _ => Bson::Null,
+        };
+        let update = doc! { "$set": { "user_id": new_owner } };
+        self.urls()
+            .update_many(doc! { "user_id": &user_id }, update.clone())
+            .session(&mut *session)
+            .await?;
+        self.qr_codes()
+            .update_many(doc! { "user_id": &user_id }, update)
+            .session(&mut *session)
+            .await?;
+
+        self.users()
+            .delete_one(doc! { "_id": id })
+            .session(&mut *session)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MongoStore {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<()> {
+        self.refresh_tokens().insert_one(token).await?;
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>> {
+        Ok(self
+            .refresh_tokens()
+            .find_one(doc! { "token_hash": token_hash })
+            .await?)
+    }
+
+    async fn find_active_refresh_tokens(
+        &self,
+        user_id: &str,
+        now: i64,
+    ) -> Result<Vec<RefreshToken>> {
+        Ok(self
+            .refresh_tokens()
+            .find(doc! {
+                "user_id": user_id,
+                "revoked_at": null,
+                "expires_at": { "$gt": now },
+            })
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn revoke_refresh_token(&self, token_hash: &str, revoked_at: i64) -> Result<bool> {
+        let result = self
+            .refresh_tokens()
+            .update_one(
+                doc! { "token_hash": token_hash, "revoked_at": null },
+                doc! { "$set": { "revoked_at": revoked_at } },
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn revoke_refresh_family(&self, family_id: &str, revoked_at: i64) -> Result<()> {
+        self.refresh_tokens()
+            .update_many(
+                doc! { "family_id": family_id, "revoked_at": null },
+                doc! { "$set": { "revoked_at": revoked_at } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_access_token(&self, jti: &str, expires_at: i64) -> Result<bool> {
+        // Two concurrent upserts of the same _id can race, and the loser sees a
+        // duplicate key error rather than a no-op
+        match self
+            .revoked_tokens()
+            .update_one(
+                doc! { "_id": jti },
+                doc! { "$setOnInsert": { "expires_at": DateTime::from_millis(expires_at) } },
+            )
+            .upsert(true)
+            .await
+        {
+            Ok(result) => Ok(result.upserted_id.is_some()),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str, revoked_at: i64) -> Result<()> {
+        self.refresh_tokens()
+            .update_many(
+                doc! { "user_id": user_id, "revoked_at": null },
+                doc! { "$set": { "revoked_at": revoked_at } },
+            )
+            .await?;
+
+        self.user_token_revocations()
+            .update_one(
+                doc! { "_id": user_id },
+                doc! { "$set": { "revoked_before": revoked_at } },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn is_access_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at: i64,
+    ) -> Result<bool> {
+        if self
+            .revoked_tokens()
+            .find_one(doc! { "_id": jti })
+            .await?
+            .is_some()
+        {
+            return Ok(true);
+        }
+
+        Ok(self
+            .user_token_revocations()
+            .find_one(doc! { "_id": user_id, "revoked_before": { "$gt": issued_at } })
+            .await?
+            .is_some())
+    }
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<()> {
+        self.one_time_tokens().insert_one(token).await?;
+        Ok(())
+    }
+
+    async fn use_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        used_at: i64,
+    ) -> Result<Option<OneTimeToken>> {
+        Ok(self
+            .one_time_tokens()
+            .find_one_and_update(
+                doc! {
+                    "token_hash": token_hash,
+                    "purpose": purpose.as_str(),
+                    "used_at": null,
+                    "expires_at": { "$gt": used_at }
+                },
+                doc! { "$set": { "used_at": used_at } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    async fn invalidate_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+        at: i64,
+    ) -> Result<()> {
+        self.one_time_tokens()
+            .update_many(
+                doc! { "user_id": user_id, "purpose": purpose.as_str(), "used_at": null },
+                doc! { "$set": { "used_at": at } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn last_one_time_token_at(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<i64>> {
+        Ok(self
+            .one_time_tokens()
+            .find_one(doc! { "user_id": user_id, "purpose": purpose.as_str() })
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .map(|token| token.created_at))
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MongoStore {
+    async fn insert(&self, key: &ApiKey) -> Result<ApiKey> {
+        let result = self
+            .api_keys()
+            .insert_one(key)
+            .await
+            .map_err(map_write_error)?;
+
+        let mut inserted = key.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        Ok(self
+            .api_keys()
+            .find_one(doc! { "key_hash": key_hash })
+            .await?)
+    }
+
+    async fn find_by_user(&self, user_id: &str) -> Result<Vec<ApiKey>> {
+        Ok(self
+            .api_keys()
+            .find(doc! { "user_id": user_id })
+            .sort(doc! { "created_at": 1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_by_id(&self, user_id: &str, id: &ObjectId) -> Result<Option<ApiKey>> {
+        Ok(self
+            .api_keys()
+            .find_one(doc! { "_id": id, "user_id": user_id })
+            .await?)
+    }
+
+    async fn set_last_used(&self, id: &ObjectId, timestamp: i64) -> Result<()> {
+        self.api_keys()
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "last_used_at": timestamp } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str, id: &ObjectId) -> Result<bool> {
+        let result = self
+            .api_keys()
+            .delete_one(doc! { "_id": id, "user_id": user_id })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn delete_by_user(&self, user_id: &str) -> Result<u64> {
+        let result = self
+            .api_keys()
+            .delete_many(doc! { "user_id": user_id })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl TotpRepository for MongoStore {
+    async fn find_totp(&self, user_id: &str) -> Result<Option<TotpCredential>> {
+        Ok(self
+            .totp_credentials()
+            .find_one(doc! { "user_id": user_id })
+            .await?)
+    }
+
+    async fn save_pending_totp(&self, credential: &TotpCredential) -> Result<()> {
+        self.totp_credentials()
+            .replace_one(doc! { "user_id": &credential.user_id }, credential)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: &str,
+        recovery_codes: &[String],
+        enabled_at: i64,
+    ) -> Result<bool> {
+        let result = self
+            .totp_credentials()
+            .update_one(
+                doc! { "user_id": user_id, "enabled": false },
+                doc! { "$set": {
+                    "enabled": true,
+                    "enabled_at": enabled_at,
+                    "recovery_codes": recovery_codes,
+                } },
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn set_recovery_codes(&self, user_id: &str, recovery_codes: &[String]) -> Result<()> {
+        self.totp_credentials()
+            .update_one(
+                doc! { "user_id": user_id, "enabled": true },
+                doc! { "$set": { "recovery_codes": recovery_codes } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool> {
+        let result = self
+            .totp_credentials()
+            .update_one(
+                doc! {
+                    "user_id": user_id,
+                    "$or": [
+                        { "last_used_step": null },
+                        { "last_used_step": { "$lt": step } }
+                    ]
+                },
+                doc! { "$set": { "last_used_step": step } },
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> Result<bool> {
+        let result = self
+            .totp_credentials()
+            .update_one(
+                doc! { "user_id": user_id, "enabled": true, "recovery_codes": code_hash },
+                doc! { "$pull": { "recovery_codes": code_hash } },
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn delete_totp(&self, user_id: &str) -> Result<bool> {
+        let result = self
+            .totp_credentials()
+            .delete_one(doc! { "user_id": user_id })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for MongoStore {
+    async fn find_identity(&self, issuer: &str, subject: &str) -> Result<Option<ExternalIdentity>> {
+        Ok(self
+            .identities()
+            .find_one(doc! { "issuer": issuer, "subject": subject })
+            .await?)
+    }
+
+    async fn insert_identity(&self, identity: &ExternalIdentity) -> Result<ExternalIdentity> {
+        let result = self
+            .identities()
+            .insert_one(identity)
+            .await
+            .map_err(map_write_error)?;
+
+        let mut inserted = identity.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn set_identity_login(&self, issuer: &str, subject: &str, timestamp: i64) -> Result<()> {
+        self.identities()
+            .update_one(
+                doc! { "issuer": issuer, "subject": subject },
+                doc! { "$set": { "last_login_at": timestamp } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_identities_by_user(&self, user_id: &str) -> Result<u64> {
+        let result = self
+            .identities()
+            .delete_many(doc! { "user_id": user_id })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl PasskeyRepository for MongoStore {
+    async fn insert_passkey(&self, passkey: &Passkey) -> Result<Passkey> {
+        let result = self
+            .passkeys()
+            .insert_one(passkey)
+            .await
+            .map_err(map_write_error)?;
+
+        let mut inserted = passkey.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn find_passkey(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        Ok(self
+            .passkeys()
+            .find_one(doc! { "credential_id": credential_id })
+            .await?)
+    }
+
+    async fn find_passkeys_by_user(&self, user_id: &str) -> Result<Vec<Passkey>> {
+        Ok(self
+            .passkeys()
+            .find(doc! { "user_id": user_id })
+            .sort(doc! { "created_at": 1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn record_passkey_use(
+        &self,
+        credential_id: &str,
+        sign_count: i64,
+        used_at: i64,
+    ) -> Result<()> {
+        self.passkeys()
+            .update_one(
+                doc! { "credential_id": credential_id },
+                doc! { "$set": { "sign_count": sign_count, "last_used_at": used_at } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_passkey(&self, user_id: &str, id: &ObjectId) -> Result<bool> {
+        let result = self
+            .passkeys()
+            .delete_one(doc! { "_id": id, "user_id": user_id })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn delete_passkeys_by_user(&self, user_id: &str) -> Result<u64> {
+        let result = self
+            .passkeys()
+            .delete_many(doc! { "user_id": user_id })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for MongoStore {
+    async fn insert_invite(&self, invite: &Invite) -> Result<Invite> {
+        let result = self
+            .invites()
+            .insert_one(invite)
+            .await
+            .map_err(map_write_error)?;
+
+        let mut inserted = invite.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn find_invites(&self) -> Result<Vec<Invite>> {
+        Ok(self
+            .invites()
+            .find(doc! {})
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_invite(&self, id: &ObjectId) -> Result<Option<Invite>> {
+        Ok(self.invites().find_one(doc! { "_id": id }).await?)
+    }
+
+    async fn find_invite_by_hash(&self, code_hash: &str) -> Result<Option<Invite>> {
+        Ok(self
+            .invites()
+            .find_one(doc! { "code_hash": code_hash })
+            .await?)
+    }
+
+    async fn redeem_invite(&self, id: &ObjectId, used: &InviteUse) -> Result<bool> {
+        // The checks are part of the filter, so concurrent signups cannot exceed max_uses
+        let result = self
+            .invites()
+            .update_one(
+                doc! {
+                    "_id": id,
+                    "$expr": { "$lt": [{ "$size": "$uses" }, "$max_uses"] },
+                    "$or": [
+                        { "expires_at": null },
+                        { "expires_at": { "$gt": used.used_at } },
+                    ],
+                },
+                doc! { "$push": { "uses": {
+                    "user_id": &used.user_id,
+                    "username": &used.username,
+                    "used_at": used.used_at,
+                } } },
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn delete_invite(&self, id: &ObjectId) -> Result<bool> {
+        let result = self.invites().delete_one(doc! { "_id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MongoStore {
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        self.audit_events().insert_one(event).await?;
+        Ok(())
+    }
+
+    async fn find_audit_events(&self, filter: &AuditFilter, limit: i64) -> Result<Vec<AuditEvent>> {
+        let mut conditions = Vec::new();
+
+        if let Some(actor) = &filter.actor {
+            conditions.push(doc! { "$or": [{ "actor_id": actor }, { "actor_username": actor }] });
+        }
+
+        if let Some(action) = &filter.action {
+            conditions.push(doc! { "action": action.as_str() });
+        }
+
+        if let Some(from) = filter.from {
+            conditions.push(doc! { "created_at": { "$gte": from } });
+        }
+
+        if let Some(to) = filter.to {
+            conditions.push(doc! { "created_at": { "$lt": to } });
+        }
+
+        if let Some(cursor) = &filter.before {
+            conditions.push(doc! {
+                "$or": [
+                    { "created_at": { "$lt": cursor.created_at } },
+                    { "created_at": cursor.created_at, "_id": { "$lt": cursor.id } },
+                ]
+            });
+        }
+
+        let query = if conditions.is_empty() {
+            doc! {}
+        } else {
+            doc! { "$and": conditions }
+        };
+
+        Ok(self
+            .audit_events()
+            .find(query)
+            .sort(doc! { "created_at": -1, "_id": -1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?)
+    }
+}
+
+/// Filter matching a workspace only if the member may lose the owner role, because
+/// either they are not an owner or another member is
+fn keeps_an_owner(user_id: &str) -> Document {
+    doc! {
+        "$or": [
+            { "members": { "$elemMatch": { "user_id": user_id, "role": { "$ne": "owner" } } } },
+            { "members": { "$elemMatch": { "user_id": { "$ne": user_id }, "role": "owner" } } },
+        ]
+    }
+}
+
+impl MongoStore {
+    /// Explain why a guarded membership update did not match
+    async fn member_change_refused(&self, id: &ObjectId, user_id: &str) -> Result<MemberChange> {
+        let is_member = self
+            .find_workspace(id)
+            .await?
+            .is_some_and(|workspace| workspace.role_of(user_id).is_some());
+
+        Ok(if is_member {
+            MemberChange::LastOwner
+        } else {
+            MemberChange::NotMember
+        })
+    }
+}
+
+#[async_trait]
+impl WorkspaceRepository for MongoStore {
+    async fn insert_workspace(&self, workspace: &Workspace) -> Result<Workspace> {
+        let result = self.workspaces().insert_one(workspace).await?;
+
+        let mut inserted = workspace.clone();
+        inserted.id = result.inserted_id.as_object_id();
+        Ok(inserted)
+    }
+
+    async fn find_workspace(&self, id: &ObjectId) -> Result<Option<Workspace>> {
+        Ok(self.workspaces().find_one(doc! { "_id": id }).await?)
+    }
+
+    async fn find_workspaces(&self, member_id: Option<&str>) -> Result<Vec<Workspace>> {
+        let query = match member_id {
+            Some(member_id) => doc! { "members.user_id": member_id },
+            None => doc! {},
+        };
+
+        Ok(self
+            .workspaces()
+            .find(query)
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn rename_workspace(&self, id: &ObjectId, name: &str, at: i64) -> Result<bool> {
+        let result = self
+            .workspaces()
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "name": name, "updated_at": at } },
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete_workspace(&self, id: &ObjectId) -> Result<bool> {
+        let result = self.workspaces().delete_one(doc! { "_id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn add_member(&self, id: &ObjectId, member: &WorkspaceMember) -> Result<bool> {
+        let result = self
+            .workspaces()
+            .update_one(
+                doc! { "_id": id, "members.user_id": { "$ne": &member.user_id } },
+                doc! {
+                    "$push": { "members": {
+                        "user_id": &member.user_id,
+                        "role": member.role.as_str(),
+                        "added_at": member.added_at,
+                    } },
+                    "$set": { "updated_at": member.added_at },
+                },
+            )
+            .await?;
+        if result.matched_count > 0 {
+            return Ok(true);
+        }
+
+        // Either the workspace does not exist or the user is already a member
+        match self.find_workspace(id).await? {
+            Some(_) => Err(DuplicateKeyError.into()),
+            None => Ok(false),
+        }
+    }
+
+    async fn set_member_role(
+        &self,
+        id: &ObjectId,
+        user_id: &str,
+        role: WorkspaceRole,
+        at: i64,
+    ) -> Result<MemberChange> {
+        // Demoting an owner is only allowed while another owner remains
+        let mut query = doc! { "_id": id, "members.user_id": user_id };
+        if role != WorkspaceRole::Owner {
+            query.extend(keeps_an_owner(user_id));
+        }
+
+        let result = self
+            .workspaces()
+            .update_one(
+                query,
+                doc! { "$set": { "members.$[m].role": role.as_str(), "updated_at": at } },
+            )
+            .array_filters(vec![doc! { "m.user_id": user_id }])
+            .await?;
+        if result.matched_count > 0 {
+            return Ok(MemberChange::Changed);
+        }
+
+        self.member_change_refused(id, user_id).await
+    }
+
+    async fn remove_member(&self, id: &ObjectId, user_id: &str, at: i64) -> Result<MemberChange> {
+        let mut query = doc! { "_id": id, "members.user_id": user_id };
+        query.extend(keeps_an_owner(user_id));
+
+        let result = self
+            .workspaces()
+            .update_one(
+                query,
+                doc! {
+                    "$pull": { "members": { "user_id": user_id } },
+                    "$set": { "updated_at": at },
+                },
+            )
+            .await?;
+        if result.matched_count > 0 {
+            return Ok(MemberChange::Changed);
+        }
+
+        self.member_change_refused(id, user_id).await
+    }
+
+    async fn remove_user_memberships(&self, user_id: &str) -> Result<u64> {
+        let result = self
+            .workspaces()
+            .update_many(
+                doc! { "members.user_id": user_id },
+                doc! { "$pull": { "members": { "user_id": user_id } } },
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
 }
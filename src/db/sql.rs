@@ -0,0 +1,2076 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+use sqlx::any::{AnyPoolOptions, AnyRow, install_default_drivers};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{AnyPool, AssertSqlSafe, Row};
+
+use crate::config::app_config::StorageConfig;
+use crate::db::migrations::{MigrationReport, log_reports};
+use crate::models::api_key::{ApiKey, Scope};
+use crate::models::audit_event::AuditEvent;
+use crate::models::identity::ExternalIdentity;
+use crate::models::invite::{Invite, InviteUse};
+use crate::models::one_time_token::{OneTimeToken, TokenPurpose};
+use crate::models::passkey::Passkey;
+use crate::models::qr_code::{QrCode, TargetType};
+use crate::models::refresh_token::RefreshToken;
+use crate::models::totp::TotpCredential;
+use crate::models::url::ShortenedUrl;
+use crate::models::url_version::UrlVersion;
+use crate::models::url_visitor::UrlVisitor;
+use crate::models::user::User;
+use crate::models::workspace::{Workspace, WorkspaceMember, WorkspaceRole};
+use crate::repositories::api_key_repository::ApiKeyRepository;
+use crate::repositories::audit_repository::{AuditFilter, AuditRepository};
+use crate::repositories::errors::DuplicateKeyError;
+use crate::repositories::health_repository::HealthRepository;
+use crate::repositories::identity_repository::IdentityRepository;
+use crate::repositories::invite_repository::InviteRepository;
+use crate::repositories::passkey_repository::PasskeyRepository;
+use crate::repositories::qr_code_repository::{QrCodeFilter, QrCodeRepository};
+use crate::repositories::token_repository::TokenRepository;
+use crate::repositories::totp_repository::TotpRepository;
+use crate::repositories::url_repository::{UrlFilter, UrlRepository};
+use crate::repositories::user_repository::{ResourcePolicy, UserRepository, UserUpdate};
+use crate::repositories::visitor_repository::VisitorRepository;
+use crate::repositories::workspace_repository::{MemberChange, WorkspaceRepository};
+
+/// Schema migrations embedded from the `migrations/` directory
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Connect to the configured SQL database (SQLite or PostgreSQL)
+pub async fn connect(config: &StorageConfig) -> Result<AnyPool> {
+    let database_url = config
+        .database_url
+        .as_deref()
+        .context("SQL database URL not configured")?;
+    install_default_drivers();
+
+    AnyPoolOptions::new()
+        .connect(database_url)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to connect to SQL database: {}", e))
+}
+
+/// Connect to the SQL database and apply any pending schema migrations
+pub async fn get_pool(config: &StorageConfig) -> Result<AnyPool> {
+    let pool = connect(config).await?;
+
+    let reports = run_migrations(&pool, !config.migrate_on_startup).await?;
+    log_reports(&reports, config.migrate_on_startup);
+
+    Ok(pool)
+}
+
+/// Apply the schema migrations that have not been run yet.
+/// With `dry_run` set, pending migrations are only listed.
+pub async fn run_migrations(pool: &AnyPool, dry_run: bool) -> Result<Vec<MigrationReport>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table(&MIGRATOR.table_name).await?;
+    let applied = conn.list_applied_migrations(&MIGRATOR.table_name).await?;
+    drop(conn);
+
+    let reports = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .filter(|migration| !applied.iter().any(|a| a.version == migration.version))
+        .map(|migration| MigrationReport {
+            version: migration.version,
+            name: migration.description.to_string(),
+            affected: None,
+        })
+        .collect();
+
+    if !dry_run {
+        MIGRATOR
+            .run(pool)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run SQL migrations: {}", e))?;
+    }
+
+    Ok(reports)
+}
+
+/// Repository implementations backed by a SQLite or PostgreSQL database
+pub struct SqlStore {
+    pool: AnyPool,
+}
+
+impl SqlStore {
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool }
+    }
+}
+
+const URL_COLUMNS: &str = "id, original_url, short_code, created_at, expires_at, clicks, \
+     user_id, workspace_id, disabled_at, title, version, password_hash";
+const URL_VERSION_COLUMNS: &str =
+    "id, short_code, version, original_url, expires_at, title, replaced_at, replaced_by";
+const VISITOR_COLUMNS: &str = "id, short_code, visitor_hash, \"timestamp\", user_agent, referrer";
+const QR_CODE_COLUMNS: &str =
+    "id, short_code, original_url, svg_content, generated_at, target_type, user_id, workspace_id";
+const USER_COLUMNS: &str = "id, username, email, full_name, password_hash, created_at, \
+     updated_at, last_login, is_active, role, failed_login_attempts, last_failed_login, \
+     locked_at, locked_until, email_verified_at";
+const REFRESH_TOKEN_COLUMNS: &str =
+    "id, token_hash, user_id, family_id, created_at, expires_at, revoked_at, workspace_id";
+const ONE_TIME_TOKEN_COLUMNS: &str =
+    "id, token_hash, user_id, purpose, email, created_at, expires_at, used_at";
+const API_KEY_COLUMNS: &str = "id, user_id, name, key_prefix, key_hash, scopes, created_at, \
+     expires_at, last_used_at";
+const TOTP_COLUMNS: &str = "user_id, secret, enabled, last_used_step, created_at, enabled_at";
+const IDENTITY_COLUMNS: &str = "id, user_id, issuer, subject, email, created_at, last_login_at";
+const PASSKEY_COLUMNS: &str = "id, user_id, credential_id, name, public_key, sign_count, \
+     transports, created_at, last_used_at";
+const INVITE_COLUMNS: &str = "id, code_prefix, code_hash, email, role, max_uses, created_by, \
+     created_at, expires_at";
+const AUDIT_EVENT_COLUMNS: &str = "id, actor_id, actor_username, action, target_type, \
+     target_id, changes, ip_hash, created_at";
+const WORKSPACE_COLUMNS: &str = "id, name, created_by, created_at, updated_at";
+
+fn parse_id(row: &AnyRow) -> Result<Option<ObjectId>> {
+    let id: String = row.try_get("id")?;
+    Ok(Some(ObjectId::parse_str(&id)?))
+}
+
+fn url_from_row(row: &AnyRow) -> Result<ShortenedUrl> {
+    Ok(ShortenedUrl {
+        id: parse_id(row)?,
+        original_url: row.try_get("original_url")?,
+        short_code: row.try_get("short_code")?,
+        created_at: row.try_get("created_at")?,
+        expires_at: row.try_get("expires_at")?,
+        clicks: row.try_get("clicks")?,
+        user_id: row.try_get("user_id")?,
+        workspace_id: row.try_get("workspace_id")?,
+        disabled_at: row.try_get("disabled_at")?,
+        title: row.try_get("title")?,
+        version: row.try_get("version")?,
+        password_hash: row.try_get("password_hash")?,
+    })
+}
+
+fn url_version_from_row(row: &AnyRow) -> Result<UrlVersion> {
+    Ok(UrlVersion {
+        id: parse_id(row)?,
+        short_code: row.try_get("short_code")?,
+        version: row.try_get("version")?,
+        original_url: row.try_get("original_url")?,
+        expires_at: row.try_get("expires_at")?,
+        title: row.try_get("title")?,
+        replaced_at: row.try_get("replaced_at")?,
+        replaced_by: row.try_get("replaced_by")?,
+    })
+}
+
+fn qr_code_from_row(row: &AnyRow) -> Result<QrCode> {
+    let target_type: String = row.try_get("target_type")?;
+
+    Ok(QrCode {
+        id: parse_id(row)?,
+        short_code: row.try_get("short_code")?,
+        original_url: row.try_get("original_url")?,
+        svg_content: row.try_get("svg_content")?,
+        generated_at: row.try_get("generated_at")?,
+        target_type: target_type.parse()?,
+        user_id: row.try_get("user_id")?,
+        workspace_id: row.try_get("workspace_id")?,
+    })
+}
+
+fn user_from_row(row: &AnyRow) -> Result<User> {
+    let is_active: i64 = row.try_get("is_active")?;
+    let role: String = row.try_get("role")?;
+
+    Ok(User {
+        id: parse_id(row)?,
+        username: row.try_get("username")?,
+        email: row.try_get("email")?,
+        full_name: row.try_get("full_name")?,
+        password_hash: row.try_get("password_hash")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        last_login: row.try_get("last_login")?,
+        is_active: is_active != 0,
+        role: role.parse()?,
+        failed_login_attempts: row.try_get("failed_login_attempts")?,
+        last_failed_login: row.try_get("last_failed_login")?,
+        locked_at: row.try_get("locked_at")?,
+        locked_until: row.try_get("locked_until")?,
+        email_verified_at: row.try_get("email_verified_at")?,
+    })
+}
+
+fn refresh_token_from_row(row: &AnyRow) -> Result<RefreshToken> {
+    Ok(RefreshToken {
+        id: parse_id(row)?,
+        token_hash: row.try_get("token_hash")?,
+        user_id: row.try_get("user_id")?,
+        family_id: row.try_get("family_id")?,
+        created_at: row.try_get("created_at")?,
+        expires_at: row.try_get("expires_at")?,
+        revoked_at: row.try_get("revoked_at")?,
+        workspace_id: row.try_get("workspace_id")?,
+    })
+}
+
+fn one_time_token_from_row(row: &AnyRow) -> Result<OneTimeToken> {
+    let purpose: String = row.try_get("purpose")?;
+
+    Ok(OneTimeToken {
+        id: parse_id(row)?,
+        token_hash: row.try_get("token_hash")?,
+        user_id: row.try_get("user_id")?,
+        purpose: purpose.parse()?,
+        email: row.try_get("email")?,
+        created_at: row.try_get("created_at")?,
+        expires_at: row.try_get("expires_at")?,
+        used_at: row.try_get("used_at")?,
+    })
+}
+
+fn api_key_from_row(row: &AnyRow) -> Result<ApiKey> {
+    let scopes: String = row.try_get("scopes")?;
+
+    Ok(ApiKey {
+        id: parse_id(row)?,
+        user_id: row.try_get("user_id")?,
+        name: row.try_get("name")?,
+        key_prefix: row.try_get("key_prefix")?,
+        key_hash: row.try_get("key_hash")?,
+        scopes: scopes
+            .split(',')
+            .filter(|scope| !scope.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Scope>>>()?,
+        created_at: row.try_get("created_at")?,
+        expires_at: row.try_get("expires_at")?,
+        last_used_at: row.try_get("last_used_at")?,
+    })
+}
+
+fn totp_from_row(row: &AnyRow, recovery_codes: Vec<String>) -> Result<TotpCredential> {
+    let enabled: i64 = row.try_get("enabled")?;
+
+    Ok(TotpCredential {
+        user_id: row.try_get("user_id")?,
+        secret: row.try_get("secret")?,
+        enabled: enabled != 0,
+        last_used_step: row.try_get("last_used_step")?,
+        recovery_codes,
+        created_at: row.try_get("created_at")?,
+        enabled_at: row.try_get("enabled_at")?,
+    })
+}
+
+fn identity_from_row(row: &AnyRow) -> Result<ExternalIdentity> {
+    Ok(ExternalIdentity {
+        id: parse_id(row)?,
+        user_id: row.try_get("user_id")?,
+        issuer: row.try_get("issuer")?,
+        subject: row.try_get("subject")?,
+        email: row.try_get("email")?,
+        created_at: row.try_get("created_at")?,
+        last_login_at: row.try_get("last_login_at")?,
+    })
+}
+
+fn passkey_from_row(row: &AnyRow) -> Result<Passkey> {
+    let transports: String = row.try_get("transports")?;
+
+    Ok(Passkey {
+        id: parse_id(row)?,
+        user_id: row.try_get("user_id")?,
+        credential_id: row.try_get("credential_id")?,
+        name: row.try_get("name")?,
+        public_key: row.try_get("public_key")?,
+        sign_count: row.try_get("sign_count")?,
+        transports: transports
+            .split(',')
+            .filter(|transport| !transport.is_empty())
+            .map(str::to_string)
+            .collect(),
+        created_at: row.try_get("created_at")?,
+        last_used_at: row.try_get("last_used_at")?,
+    })
+}
+
+fn invite_from_row(row: &AnyRow, uses: Vec<InviteUse>) -> Result<Invite> {
+    let role: String = row.try_get("role")?;
+
+    Ok(Invite {
+        id: parse_id(row)?,
+        code_prefix: row.try_get("code_prefix")?,
+        code_hash: row.try_get("code_hash")?,
+        email: row.try_get("email")?,
+        role: role.parse()?,
+        max_uses: row.try_get("max_uses")?,
+        uses,
+        created_by: row.try_get("created_by")?,
+        created_at: row.try_get("created_at")?,
+        expires_at: row.try_get("expires_at")?,
+    })
+}
+
+fn audit_event_from_row(row: &AnyRow) -> Result<AuditEvent> {
+    let action: String = row.try_get("action")?;
+    let target_type: String = row.try_get("target_type")?;
+    let changes: Option<String> = row.try_get("changes")?;
+
+    Ok(AuditEvent {
+        id: parse_id(row)?,
+        actor_id: row.try_get("actor_id")?,
+        actor_username: row.try_get("actor_username")?,
+        action: action.parse()?,
+        target_type: target_type.parse()?,
+        target_id: row.try_get("target_id")?,
+        changes: changes.as_deref().map(serde_json::from_str).transpose()?,
+        ip_hash: row.try_get("ip_hash")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn workspace_from_row(row: &AnyRow, members: Vec<WorkspaceMember>) -> Result<Workspace> {
+    Ok(Workspace {
+        id: parse_id(row)?,
+        name: row.try_get("name")?,
+        members,
+        created_by: row.try_get("created_by")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+/// Convert unique constraint violations into `DuplicateKeyError` so handlers can detect them
+fn map_write_error(err: sqlx::Error) -> anyhow::Error {
+    match &err {
+        sqlx::Error::Database(e) if e.is_unique_violation() => DuplicateKeyError.into(),
+        _ => err.into(),
+    }
+}
+
+/// Build a case-insensitive LIKE pattern, escaping the wildcard characters in the search term
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Append a WHERE clause built from the given conditions.
+/// Conditions only ever contain column names and `$n` placeholders; values are always bound.
+fn with_conditions(sql: String, conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        sql
+    } else {
+        format!("{} WHERE {}", sql, conditions.join(" AND "))
+    }
+}
+
+#[async_trait]
+impl HealthRepository for SqlStore {
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UrlRepository for SqlStore {
+    async fn insert(&self, url: &ShortenedUrl) -> Result<ShortenedUrl> {
+        let mut inserted = url.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let sql = format!(
+            "INSERT INTO urls ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+            URL_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&url.original_url)
+            .bind(&url.short_code)
+            .bind(url.created_at)
+            .bind(url.expires_at)
+            .bind(url.clicks)
+            .bind(&url.user_id)
+            .bind(&url.workspace_id)
+            .bind(url.disabled_at)
+            .bind(&url.title)
+            .bind(url.version)
+            .bind(&url.password_hash)
+            .execute(&self.pool)
+            .await
+            .map_err(map_write_error)?;
+
+        Ok(inserted)
+    }
+
+    async fn find_by_code(&self, code: &str) -> Result<Option<ShortenedUrl>> {
+        let sql = format!("SELECT {} FROM urls WHERE short_code = $1", URL_COLUMNS);
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(code)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(url_from_row).transpose()
+    }
+
+    async fn find(&self, filter: &UrlFilter) -> Result<Vec<ShortenedUrl>> {
+        let mut conditions = Vec::new();
+        let mut args = Vec::new();
+
+        if let Some(search) = filter.search.as_deref().filter(|s| !s.is_empty()) {
+            args.push(like_pattern(search));
+            let n = args.len();
+            conditions.push(format!(
+                "(LOWER(short_code) LIKE ${n} ESCAPE '\\' OR LOWER(original_url) LIKE ${n} ESCAPE '\\')"
+            ));
+        }
+
+        if let Some(user_id) = &filter.user_id {
+            args.push(user_id.clone());
+            conditions.push(format!("user_id = ${}", args.len()));
+        }
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            args.push(workspace_id.clone());
+            conditions.push(format!("workspace_id = ${}", args.len()));
+        }
+
+        let sql = with_conditions(format!("SELECT {} FROM urls", URL_COLUMNS), &conditions);
+        let mut query = sqlx::query(AssertSqlSafe(sql));
+        for arg in args {
+            query = query.bind(arg);
+        }
+
+        let rows = query.fetch_all(&self.pool).await?;
+        rows.iter().map(url_from_row).collect()
+    }
+
+    async fn increment_clicks(&self, code: &str) -> Result<()> {
+        sqlx::query("UPDATE urls SET clicks = clicks + 1 WHERE short_code = $1")
+            .bind(code)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_with_history(&self, url: &ShortenedUrl, previous: &UrlVersion) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE urls SET original_url = $3, expires_at = $4, title = $5, version = $6, \
+             password_hash = $7 WHERE short_code = $1 AND version = $2",
+        )
+        .bind(&url.short_code)
+        .bind(previous.version)
+        .bind(&url.original_url)
+        .bind(url.expires_at)
+        .bind(&url.title)
+        .bind(url.version)
+        .bind(&url.password_hash)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let sql = format!(
+            "INSERT INTO url_versions ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            URL_VERSION_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(ObjectId::new().to_hex())
+            .bind(&previous.short_code)
+            .bind(previous.version)
+            .bind(&previous.original_url)
+            .bind(previous.expires_at)
+            .bind(&previous.title)
+            .bind(previous.replaced_at)
+            .bind(&previous.replaced_by)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn find_versions(&self, code: &str) -> Result<Vec<UrlVersion>> {
+        let sql = format!(
+            "SELECT {} FROM url_versions WHERE short_code = $1 ORDER BY version DESC",
+            URL_VERSION_COLUMNS
+        );
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .bind(code)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(url_version_from_row).collect()
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM urls WHERE short_code = $1")
+            .bind(code)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM url_versions WHERE short_code = $1")
+            .bind(code)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl VisitorRepository for SqlStore {
+    async fn insert_if_new(&self, visitor: &UrlVisitor) -> Result<bool> {
+        // The unique (short_code, visitor_hash) constraint turns repeat visits into no-ops
+        let sql = format!(
+            "INSERT INTO visitors ({}) VALUES ($1, $2, $3, $4, $5, $6) \
+             ON CONFLICT (short_code, visitor_hash) DO NOTHING",
+            VISITOR_COLUMNS
+        );
+        let result = sqlx::query(AssertSqlSafe(sql))
+            .bind(ObjectId::new().to_hex())
+            .bind(&visitor.short_code)
+            .bind(&visitor.visitor_hash)
+            .bind(visitor.timestamp)
+            .bind(&visitor.user_agent)
+            .bind(&visitor.referrer)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn count_by_code(&self, code: &str) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visitors WHERE short_code = $1")
+            .bind(code)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u64)
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM visitors WHERE short_code = $1")
+            .bind(code)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl QrCodeRepository for SqlStore {
+    async fn find_by_code(&self, code: &str, target_type: &TargetType) -> Result<Option<QrCode>> {
+        let sql = format!(
+            "SELECT {} FROM qr_codes WHERE short_code = $1 AND target_type = $2",
+            QR_CODE_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(code)
+            .bind(target_type.as_str())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(qr_code_from_row).transpose()
+    }
+
+    async fn find_direct_by_url(&self, url: &str) -> Result<Option<QrCode>> {
+        let sql = format!(
+            "SELECT {} FROM qr_codes WHERE original_url = $1 \
+             AND short_code LIKE 'direct-%' AND target_type = 'original'",
+            QR_CODE_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(url)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(qr_code_from_row).transpose()
+    }
+
+    async fn find(&self, filter: &QrCodeFilter) -> Result<Vec<QrCode>> {
+        let mut conditions = Vec::new();
+        let mut args = Vec::new();
+
+        if let Some(search) = filter.search.as_deref().filter(|s| !s.is_empty()) {
+            args.push(like_pattern(search));
+            let n = args.len();
+            conditions.push(format!(
+                "(LOWER(short_code) LIKE ${n} ESCAPE '\\' OR LOWER(original_url) LIKE ${n} ESCAPE '\\')"
+            ));
+        }
+
+        if let Some(target_type) = &filter.target_type {
+            args.push(target_type.as_str().to_string());
+            conditions.push(format!("target_type = ${}", args.len()));
+        }
+
+        if filter.direct_only {
+            conditions.push("short_code LIKE 'direct-%'".to_string());
+        }
+
+        if let Some(user_id) = &filter.user_id {
+            args.push(user_id.clone());
+            conditions.push(format!("user_id = ${}", args.len()));
+        }
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            args.push(workspace_id.clone());
+            conditions.push(format!("workspace_id = ${}", args.len()));
+        }
+
+        let sql = with_conditions(
+            format!("SELECT {} FROM qr_codes", QR_CODE_COLUMNS),
+            &conditions,
+        );
+        let mut query = sqlx::query(AssertSqlSafe(sql));
+        for arg in args {
+            query = query.bind(arg);
+        }
+
+        let rows = query.fetch_all(&self.pool).await?;
+        rows.iter().map(qr_code_from_row).collect()
+    }
+
+    async fn upsert(&self, qr_code: &QrCode) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO qr_codes ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (short_code, target_type) DO UPDATE \
+             SET svg_content = excluded.svg_content, generated_at = excluded.generated_at",
+            QR_CODE_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(ObjectId::new().to_hex())
+            .bind(&qr_code.short_code)
+            .bind(&qr_code.original_url)
+            .bind(&qr_code.svg_content)
+            .bind(qr_code.generated_at)
+            .bind(qr_code.target_type.as_str())
+            .bind(&qr_code.user_id)
+            .bind(&qr_code.workspace_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM qr_codes WHERE short_code = $1")
+            .bind(code)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn retarget(&self, code: &str, original_url: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM qr_codes WHERE short_code = $1 AND target_type = $2")
+            .bind(code)
+            .bind(TargetType::Original.as_str())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE qr_codes SET original_url = $2 WHERE short_code = $1")
+            .bind(code)
+            .bind(original_url)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn clear_workspace(&self, workspace_id: &str) -> Result<u64> {
+        let result = sqlx::query("UPDATE qr_codes SET workspace_id = NULL WHERE workspace_id = $1")
+            .bind(workspace_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqlStore {
+    async fn insert(&self, user: &User) -> Result<User> {
+        let mut inserted = user.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let sql = format!(
+            "INSERT INTO users ({}) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+            USER_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&user.username)
+            .bind(&user.email)
+            .bind(&user.full_name)
+            .bind(&user.password_hash)
+            .bind(user.created_at)
+            .bind(user.updated_at)
+            .bind(user.last_login)
+            .bind(user.is_active as i16)
+            .bind(user.role.as_str())
+            .bind(user.failed_login_attempts)
+            .bind(user.last_failed_login)
+            .bind(user.locked_at)
+            .bind(user.locked_until)
+            .bind(user.email_verified_at)
+            .execute(&self.pool)
+            .await
+            .map_err(map_write_error)?;
+
+        Ok(inserted)
+    }
+
+    async fn find_by_id(&self, id: &ObjectId) -> Result<Option<User>> {
+        let sql = format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS);
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        let sql = format!("SELECT {} FROM users WHERE username = $1", USER_COLUMNS);
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Vec<User>> {
+        let sql = format!(
+            "SELECT {} FROM users WHERE LOWER(email) = LOWER($1) ORDER BY created_at",
+            USER_COLUMNS
+        );
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .bind(email)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(user_from_row).collect()
+    }
+
+    async fn find_all_except(&self, id: &ObjectId) -> Result<Vec<User>> {
+        let sql = format!("SELECT {} FROM users WHERE id <> $1", USER_COLUMNS);
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(user_from_row).collect()
+    }
+
+    async fn count(&self) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u64)
+    }
+
+    async fn update(&self, id: &ObjectId, update: &UserUpdate) -> Result<Option<User>> {
+        // NULL parameters keep the current column value
+        sqlx::query(
+            "UPDATE users SET \
+             username = COALESCE($2, username), \
+             full_name = COALESCE($3, full_name), \
+             password_hash = COALESCE($4, password_hash), \
+             is_active = COALESCE($5, is_active), \
+             role = COALESCE($6, role), \
+             updated_at = $7, \
+             email = COALESCE($8, email), \
+             email_verified_at = CASE WHEN $9 = 1 THEN NULL ELSE email_verified_at END \
+             WHERE id = $1",
+        )
+        .bind(id.to_hex())
+        .bind(&update.username)
+        .bind(&update.full_name)
+        .bind(&update.password_hash)
+        .bind(update.is_active.map(|is_active| is_active as i16))
+        .bind(update.role.map(|role| role.as_str()))
+        .bind(chrono::Utc::now().timestamp_millis())
+        .bind(&update.email)
+        .bind(update.email.is_some() as i16)
+        .execute(&self.pool)
+        .await
+        .map_err(map_write_error)?;
+
+        UserRepository::find_by_id(self, id).await
+    }
+
+    async fn set_last_login(&self, id: &ObjectId, timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE users SET last_login = $1, failed_login_attempts = 0 WHERE id = $2")
+            .bind(timestamp)
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn record_failed_login(&self, id: &ObjectId, at: i64, reset_before: i64) -> Result<i64> {
+        sqlx::query(
+            "UPDATE users SET \
+             failed_login_attempts = CASE \
+                 WHEN last_failed_login IS NULL OR last_failed_login < $2 THEN 1 \
+                 ELSE failed_login_attempts + 1 END, \
+             last_failed_login = $3 \
+             WHERE id = $1",
+        )
+        .bind(id.to_hex())
+        .bind(reset_before)
+        .bind(at)
+        .execute(&self.pool)
+        .await?;
+
+        let attempts: Option<i64> =
+            sqlx::query_scalar("SELECT failed_login_attempts FROM users WHERE id = $1")
+                .bind(id.to_hex())
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(attempts.unwrap_or(0))
+    }
+
+    async fn lock(&self, id: &ObjectId, locked_at: i64, locked_until: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE users SET locked_at = $1, locked_until = $2, failed_login_attempts = 0 \
+             WHERE id = $3",
+        )
+        .bind(locked_at)
+        .bind(locked_until)
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn unlock(&self, id: &ObjectId) -> Result<Option<User>> {
+        sqlx::query(
+            "UPDATE users SET locked_until = NULL, failed_login_attempts = 0 WHERE id = $1",
+        )
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+
+        UserRepository::find_by_id(self, id).await
+    }
+
+    async fn set_email_verified(&self, id: &ObjectId, email: &str, at: i64) -> Result<bool> {
+        let result =
+            sqlx::query("UPDATE users SET email_verified_at = $1 WHERE id = $2 AND email = $3")
+                .bind(at)
+                .bind(id.to_hex())
+                .bind(email)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_with_resources(
+        &self,
+        id: &ObjectId,
+        policy: &ResourcePolicy,
+        at: i64,
+    ) -> Result<bool> {
+        let user_id = id.to_hex();
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        // Personal URLs are the ones not owned by a workspace
+        let personal_codes =
+            "SELECT short_code FROM urls WHERE user_id = $1 AND workspace_id IS NULL";
+        match policy {
+            ResourcePolicy::Delete => {
+                for table in ["visitors", "url_versions"] {
+                    let sql = format!(
+                        "DELETE FROM {} WHERE short_code IN ({})",
+                        table, personal_codes
+                    );
+                    sqlx::query(AssertSqlSafe(sql))
+                        .bind(&user_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                let sql = format!(
+                    "DELETE FROM qr_codes WHERE short_code IN ({}) \
+                     OR (user_id = $1 AND workspace_id IS NULL)",
+                    personal_codes
+                );
+                sqlx::query(AssertSqlSafe(sql))
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM urls WHERE user_id = $1 AND workspace_id IS NULL")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            ResourcePolicy::Disable => {
+                sqlx::query(
+                    "UPDATE urls SET disabled_at = $2 WHERE user_id = $1 AND workspace_id IS NULL",
+                )
+                .bind(&user_id)
+                .bind(at)
+                .execute(&mut *tx)
+                .await?;
+            }
+            ResourcePolicy::Reassign(_) => {}
+        }
+
+        // Whatever is left gets the new owner, or none
+        let new_owner = match policy {
+            ResourcePolicy::Reassign(new_owner) => Some(new_owner.as_str()),
+            _ => None,
+        };
+        for table in ["urls", "qr_codes"] {
+            let sql = format!("UPDATE {} SET user_id = $2 WHERE user_id = $1", table);
+            sqlx::query(AssertSqlSafe(sql))
+                .bind(&user_id)
+                .bind(new_owner)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for SqlStore {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO refresh_tokens ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            REFRESH_TOKEN_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(ObjectId::new().to_hex())
+            .bind(&token.token_hash)
+            .bind(&token.user_id)
+            .bind(&token.family_id)
+            .bind(token.created_at)
+            .bind(token.expires_at)
+            .bind(token.revoked_at)
+            .bind(&token.workspace_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>> {
+        let sql = format!(
+            "SELECT {} FROM refresh_tokens WHERE token_hash = $1",
+            REFRESH_TOKEN_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(refresh_token_from_row).transpose()
+    }
+
+    async fn find_active_refresh_tokens(
+        &self,
+        user_id: &str,
+        now: i64,
+    ) -> Result<Vec<RefreshToken>> {
+        let sql = format!(
+            "SELECT {} FROM refresh_tokens \
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > $2 \
+             ORDER BY created_at DESC",
+            REFRESH_TOKEN_COLUMNS
+        );
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .bind(user_id)
+            .bind(now)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(refresh_token_from_row).collect()
+    }
+
+    async fn revoke_refresh_token(&self, token_hash: &str, revoked_at: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1 \
+             WHERE token_hash = $2 AND revoked_at IS NULL",
+        )
+        .bind(revoked_at)
+        .bind(token_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_refresh_family(&self, family_id: &str, revoked_at: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1 \
+             WHERE family_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(revoked_at)
+        .bind(family_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn revoke_access_token(&self, jti: &str, expires_at: i64) -> Result<bool> {
+        // Entries for tokens that have expired since are no longer needed
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1")
+            .bind(chrono::Utc::now().timestamp_millis())
+            .execute(&self.pool)
+            .await?;
+
+        let result = sqlx::query(
+            "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) \
+             ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(jti)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str, revoked_at: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = $1 \
+             WHERE user_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(revoked_at)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO user_token_revocations (user_id, revoked_before) VALUES ($1, $2) \
+             ON CONFLICT (user_id) DO UPDATE SET revoked_before = excluded.revoked_before",
+        )
+        .bind(user_id)
+        .bind(revoked_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_access_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at: i64,
+    ) -> Result<bool> {
+        let revoked: i64 = sqlx::query_scalar(
+            "SELECT (SELECT COUNT(*) FROM revoked_tokens WHERE jti = $1) + \
+             (SELECT COUNT(*) FROM user_token_revocations \
+              WHERE user_id = $2 AND revoked_before > $3)",
+        )
+        .bind(jti)
+        .bind(user_id)
+        .bind(issued_at)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(revoked > 0)
+    }
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO one_time_tokens ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            ONE_TIME_TOKEN_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(ObjectId::new().to_hex())
+            .bind(&token.token_hash)
+            .bind(&token.user_id)
+            .bind(token.purpose.as_str())
+            .bind(&token.email)
+            .bind(token.created_at)
+            .bind(token.expires_at)
+            .bind(token.used_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn use_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        used_at: i64,
+    ) -> Result<Option<OneTimeToken>> {
+        // The conditional update makes sure concurrent requests cannot both use the token
+        let result = sqlx::query(
+            "UPDATE one_time_tokens SET used_at = $1 \
+             WHERE token_hash = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > $1",
+        )
+        .bind(used_at)
+        .bind(token_hash)
+        .bind(purpose.as_str())
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let sql = format!(
+            "SELECT {} FROM one_time_tokens WHERE token_hash = $1",
+            ONE_TIME_TOKEN_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(one_time_token_from_row).transpose()
+    }
+
+    async fn invalidate_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+        at: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE one_time_tokens SET used_at = $1 \
+             WHERE user_id = $2 AND purpose = $3 AND used_at IS NULL",
+        )
+        .bind(at)
+        .bind(user_id)
+        .bind(purpose.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn last_one_time_token_at(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<i64>> {
+        Ok(sqlx::query_scalar(
+            "SELECT MAX(created_at) FROM one_time_tokens WHERE user_id = $1 AND purpose = $2",
+        )
+        .bind(user_id)
+        .bind(purpose.as_str())
+        .fetch_one(&self.pool)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for SqlStore {
+    async fn insert(&self, key: &ApiKey) -> Result<ApiKey> {
+        let mut inserted = key.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let scopes: Vec<&str> = key.scopes.iter().map(Scope::as_str).collect();
+
+        let sql = format!(
+            "INSERT INTO api_keys ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            API_KEY_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&key.user_id)
+            .bind(&key.name)
+            .bind(&key.key_prefix)
+            .bind(&key.key_hash)
+            .bind(scopes.join(","))
+            .bind(key.created_at)
+            .bind(key.expires_at)
+            .bind(key.last_used_at)
+            .execute(&self.pool)
+            .await
+            .map_err(map_write_error)?;
+
+        Ok(inserted)
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let sql = format!(
+            "SELECT {} FROM api_keys WHERE key_hash = $1",
+            API_KEY_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(key_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(api_key_from_row).transpose()
+    }
+
+    async fn find_by_user(&self, user_id: &str) -> Result<Vec<ApiKey>> {
+        let sql = format!(
+            "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY created_at",
+            API_KEY_COLUMNS
+        );
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(api_key_from_row).collect()
+    }
+
+    async fn find_by_id(&self, user_id: &str, id: &ObjectId) -> Result<Option<ApiKey>> {
+        let sql = format!(
+            "SELECT {} FROM api_keys WHERE id = $1 AND user_id = $2",
+            API_KEY_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(api_key_from_row).transpose()
+    }
+
+    async fn set_last_used(&self, id: &ObjectId, timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE api_keys SET last_used_at = $1 WHERE id = $2")
+            .bind(timestamp)
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str, id: &ObjectId) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE id = $1 AND user_id = $2")
+            .bind(id.to_hex())
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_by_user(&self, user_id: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl TotpRepository for SqlStore {
+    async fn find_totp(&self, user_id: &str) -> Result<Option<TotpCredential>> {
+        let sql = format!(
+            "SELECT {} FROM totp_credentials WHERE user_id = $1",
+            TOTP_COLUMNS
+        );
+        let Some(row) = sqlx::query(AssertSqlSafe(sql))
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let recovery_codes =
+            sqlx::query("SELECT code_hash FROM totp_recovery_codes WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await?
+                .iter()
+                .map(|row| row.try_get("code_hash"))
+                .collect::<Result<Vec<String>, _>>()?;
+
+        totp_from_row(&row, recovery_codes).map(Some)
+    }
+
+    async fn save_pending_totp(&self, credential: &TotpCredential) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+            .bind(&credential.user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let sql = format!(
+            "INSERT INTO totp_credentials ({}) VALUES ($1, $2, $3, $4, $5, $6) \
+             ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, \
+             enabled = excluded.enabled, last_used_step = excluded.last_used_step, \
+             created_at = excluded.created_at, enabled_at = excluded.enabled_at",
+            TOTP_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(&credential.user_id)
+            .bind(&credential.secret)
+            .bind(credential.enabled as i16)
+            .bind(credential.last_used_step)
+            .bind(credential.created_at)
+            .bind(credential.enabled_at)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: &str,
+        recovery_codes: &[String],
+        enabled_at: i64,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE totp_credentials SET enabled = 1, enabled_at = $1 \
+             WHERE user_id = $2 AND enabled = 0",
+        )
+        .bind(enabled_at)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        for code_hash in recovery_codes {
+            sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn set_recovery_codes(&self, user_id: &str, recovery_codes: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for code_hash in recovery_codes {
+            sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE totp_credentials SET last_used_step = $1 \
+             WHERE user_id = $2 AND (last_used_step IS NULL OR last_used_step < $1)",
+        )
+        .bind(step)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> Result<bool> {
+        let result =
+            sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash = $2")
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_totp(&self, user_id: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM totp_credentials WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for SqlStore {
+    async fn find_identity(&self, issuer: &str, subject: &str) -> Result<Option<ExternalIdentity>> {
+        let sql = format!(
+            "SELECT {} FROM external_identities WHERE issuer = $1 AND subject = $2",
+            IDENTITY_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(issuer)
+            .bind(subject)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(identity_from_row).transpose()
+    }
+
+    async fn insert_identity(&self, identity: &ExternalIdentity) -> Result<ExternalIdentity> {
+        let mut inserted = identity.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let sql = format!(
+            "INSERT INTO external_identities ({}) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            IDENTITY_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&identity.user_id)
+            .bind(&identity.issuer)
+            .bind(&identity.subject)
+            .bind(&identity.email)
+            .bind(identity.created_at)
+            .bind(identity.last_login_at)
+            .execute(&self.pool)
+            .await
+            .map_err(map_write_error)?;
+
+        Ok(inserted)
+    }
+
+    async fn set_identity_login(&self, issuer: &str, subject: &str, timestamp: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE external_identities SET last_login_at = $1 WHERE issuer = $2 AND subject = $3",
+        )
+        .bind(timestamp)
+        .bind(issuer)
+        .bind(subject)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_identities_by_user(&self, user_id: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM external_identities WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl PasskeyRepository for SqlStore {
+    async fn insert_passkey(&self, passkey: &Passkey) -> Result<Passkey> {
+        let mut inserted = passkey.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let sql = format!(
+            "INSERT INTO passkeys ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            PASSKEY_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&passkey.user_id)
+            .bind(&passkey.credential_id)
+            .bind(&passkey.name)
+            .bind(&passkey.public_key)
+            .bind(passkey.sign_count)
+            .bind(passkey.transports.join(","))
+            .bind(passkey.created_at)
+            .bind(passkey.last_used_at)
+            .execute(&self.pool)
+            .await
+            .map_err(map_write_error)?;
+
+        Ok(inserted)
+    }
+
+    async fn find_passkey(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        let sql = format!(
+            "SELECT {} FROM passkeys WHERE credential_id = $1",
+            PASSKEY_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(credential_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(passkey_from_row).transpose()
+    }
+
+    async fn find_passkeys_by_user(&self, user_id: &str) -> Result<Vec<Passkey>> {
+        let sql = format!(
+            "SELECT {} FROM passkeys WHERE user_id = $1 ORDER BY created_at",
+            PASSKEY_COLUMNS
+        );
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(passkey_from_row).collect()
+    }
+
+    async fn record_passkey_use(
+        &self,
+        credential_id: &str,
+        sign_count: i64,
+        used_at: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE passkeys SET sign_count = $1, last_used_at = $2 WHERE credential_id = $3",
+        )
+        .bind(sign_count)
+        .bind(used_at)
+        .bind(credential_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_passkey(&self, user_id: &str, id: &ObjectId) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM passkeys WHERE id = $1 AND user_id = $2")
+            .bind(id.to_hex())
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_passkeys_by_user(&self, user_id: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM passkeys WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl SqlStore {
+    async fn find_invite_uses(&self, invite_id: &str) -> Result<Vec<InviteUse>> {
+        let rows = sqlx::query(
+            "SELECT user_id, username, used_at FROM invite_uses WHERE invite_id = $1 \
+             ORDER BY used_at",
+        )
+        .bind(invite_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(InviteUse {
+                    user_id: row.try_get("user_id")?,
+                    username: row.try_get("username")?,
+                    used_at: row.try_get("used_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn invite_with_uses(&self, row: Option<AnyRow>) -> Result<Option<Invite>> {
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let id: String = row.try_get("id")?;
+        let uses = self.find_invite_uses(&id).await?;
+
+        invite_from_row(&row, uses).map(Some)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for SqlStore {
+    async fn insert_invite(&self, invite: &Invite) -> Result<Invite> {
+        let mut inserted = invite.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let sql = format!(
+            "INSERT INTO invites ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            INVITE_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&invite.code_prefix)
+            .bind(&invite.code_hash)
+            .bind(&invite.email)
+            .bind(invite.role.as_str())
+            .bind(invite.max_uses)
+            .bind(&invite.created_by)
+            .bind(invite.created_at)
+            .bind(invite.expires_at)
+            .execute(&self.pool)
+            .await
+            .map_err(map_write_error)?;
+
+        Ok(inserted)
+    }
+
+    async fn find_invites(&self) -> Result<Vec<Invite>> {
+        let sql = format!(
+            "SELECT {} FROM invites ORDER BY created_at DESC",
+            INVITE_COLUMNS
+        );
+        let rows = sqlx::query(AssertSqlSafe(sql))
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut invites = Vec::with_capacity(rows.len());
+        for row in rows {
+            invites.extend(self.invite_with_uses(Some(row)).await?);
+        }
+        Ok(invites)
+    }
+
+    async fn find_invite(&self, id: &ObjectId) -> Result<Option<Invite>> {
+        let sql = format!("SELECT {} FROM invites WHERE id = $1", INVITE_COLUMNS);
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        self.invite_with_uses(row).await
+    }
+
+    async fn find_invite_by_hash(&self, code_hash: &str) -> Result<Option<Invite>> {
+        let sql = format!(
+            "SELECT {} FROM invites WHERE code_hash = $1",
+            INVITE_COLUMNS
+        );
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(code_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        self.invite_with_uses(row).await
+    }
+
+    async fn redeem_invite(&self, id: &ObjectId, used: &InviteUse) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        // The checks are part of the UPDATE, so concurrent signups cannot exceed max_uses
+        let result = sqlx::query(
+            "UPDATE invites SET use_count = use_count + 1 \
+             WHERE id = $1 AND use_count < max_uses AND (expires_at IS NULL OR expires_at > $2)",
+        )
+        .bind(id.to_hex())
+        .bind(used.used_at)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "INSERT INTO invite_uses (invite_id, user_id, username, used_at) \
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(id.to_hex())
+        .bind(&used.user_id)
+        .bind(&used.username)
+        .bind(used.used_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete_invite(&self, id: &ObjectId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM invite_uses WHERE invite_id = $1")
+            .bind(id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM invites WHERE id = $1")
+            .bind(id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for SqlStore {
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        let changes = event
+            .changes
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+
+        let sql = format!(
+            "INSERT INTO audit_events ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            AUDIT_EVENT_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(ObjectId::new().to_hex())
+            .bind(&event.actor_id)
+            .bind(&event.actor_username)
+            .bind(event.action.as_str())
+            .bind(event.target_type.as_str())
+            .bind(&event.target_id)
+            .bind(changes)
+            .bind(&event.ip_hash)
+            .bind(event.created_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_audit_events(&self, filter: &AuditFilter, limit: i64) -> Result<Vec<AuditEvent>> {
+        // Timestamps are integers and written into the query; only text is bound
+        let mut conditions = Vec::new();
+        let mut args = Vec::new();
+
+        if let Some(actor) = &filter.actor {
+            args.push(actor.clone());
+            let n = args.len();
+            conditions.push(format!("(actor_id = ${n} OR actor_username = ${n})"));
+        }
+
+        if let Some(action) = &filter.action {
+            args.push(action.as_str().to_string());
+            conditions.push(format!("action = ${}", args.len()));
+        }
+
+        if let Some(from) = filter.from {
+            conditions.push(format!("created_at >= {}", from));
+        }
+
+        if let Some(to) = filter.to {
+            conditions.push(format!("created_at < {}", to));
+        }
+
+        if let Some(cursor) = &filter.before {
+            args.push(cursor.id.to_hex());
+            conditions.push(format!(
+                "(created_at < {time} OR (created_at = {time} AND id < ${n}))",
+                time = cursor.created_at,
+                n = args.len()
+            ));
+        }
+
+        let sql = format!(
+            "{} ORDER BY created_at DESC, id DESC LIMIT {}",
+            with_conditions(
+                format!("SELECT {} FROM audit_events", AUDIT_EVENT_COLUMNS),
+                &conditions
+            ),
+            limit
+        );
+        let mut query = sqlx::query(AssertSqlSafe(sql));
+        for arg in args {
+            query = query.bind(arg);
+        }
+
+        let rows = query.fetch_all(&self.pool).await?;
+        rows.iter().map(audit_event_from_row).collect()
+    }
+}
+
+impl SqlStore {
+    async fn find_workspace_members(&self, workspace_id: &str) -> Result<Vec<WorkspaceMember>> {
+        let rows = sqlx::query(
+            "SELECT user_id, role, added_at FROM workspace_members WHERE workspace_id = $1 \
+             ORDER BY added_at",
+        )
+        .bind(workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let role: String = row.try_get("role")?;
+                Ok(WorkspaceMember {
+                    user_id: row.try_get("user_id")?,
+                    role: role.parse()?,
+                    added_at: row.try_get("added_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Mark the workspace as changed, which locks it for the rest of the transaction,
+    /// and return the member's current role
+    async fn lock_member(
+        tx: &mut sqlx::Transaction<'_, sqlx::Any>,
+        id: &ObjectId,
+        user_id: &str,
+        at: i64,
+    ) -> Result<Option<WorkspaceRole>> {
+        let result = sqlx::query("UPDATE workspaces SET updated_at = $1 WHERE id = $2")
+            .bind(at)
+            .bind(id.to_hex())
+            .execute(&mut **tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let role: Option<String> = sqlx::query_scalar(
+            "SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+        )
+        .bind(id.to_hex())
+        .bind(user_id)
+        .fetch_optional(&mut **tx)
+        .await?;
+        role.as_deref().map(str::parse).transpose()
+    }
+
+    async fn has_other_owner(
+        tx: &mut sqlx::Transaction<'_, sqlx::Any>,
+        id: &ObjectId,
+        user_id: &str,
+    ) -> Result<bool> {
+        let owners: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM workspace_members \
+             WHERE workspace_id = $1 AND user_id <> $2 AND role = 'owner'",
+        )
+        .bind(id.to_hex())
+        .bind(user_id)
+        .fetch_one(&mut **tx)
+        .await?;
+        Ok(owners > 0)
+    }
+
+    async fn workspace_with_members(&self, row: Option<AnyRow>) -> Result<Option<Workspace>> {
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let id: String = row.try_get("id")?;
+        let members = self.find_workspace_members(&id).await?;
+
+        workspace_from_row(&row, members).map(Some)
+    }
+}
+
+#[async_trait]
+impl WorkspaceRepository for SqlStore {
+    async fn insert_workspace(&self, workspace: &Workspace) -> Result<Workspace> {
+        let mut inserted = workspace.clone();
+        let id = ObjectId::new();
+        inserted.id = Some(id);
+
+        let mut tx = self.pool.begin().await?;
+
+        let sql = format!(
+            "INSERT INTO workspaces ({}) VALUES ($1, $2, $3, $4, $5)",
+            WORKSPACE_COLUMNS
+        );
+        sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .bind(&workspace.name)
+            .bind(&workspace.created_by)
+            .bind(workspace.created_at)
+            .bind(workspace.updated_at)
+            .execute(&mut *tx)
+            .await?;
+
+        for member in &workspace.members {
+            sqlx::query(
+                "INSERT INTO workspace_members (workspace_id, user_id, role, added_at) \
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(id.to_hex())
+            .bind(&member.user_id)
+            .bind(member.role.as_str())
+            .bind(member.added_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(map_write_error)?;
+        }
+
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    async fn find_workspace(&self, id: &ObjectId) -> Result<Option<Workspace>> {
+        let sql = format!("SELECT {} FROM workspaces WHERE id = $1", WORKSPACE_COLUMNS);
+        let row = sqlx::query(AssertSqlSafe(sql))
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        self.workspace_with_members(row).await
+    }
+
+    async fn find_workspaces(&self, member_id: Option<&str>) -> Result<Vec<Workspace>> {
+        let mut conditions = Vec::new();
+        let mut args = Vec::new();
+
+        if let Some(member_id) = member_id {
+            args.push(member_id.to_string());
+            conditions.push(format!(
+                "id IN (SELECT workspace_id FROM workspace_members WHERE user_id = ${})",
+                args.len()
+            ));
+        }
+
+        let sql = format!(
+            "{} ORDER BY created_at DESC",
+            with_conditions(
+                format!("SELECT {} FROM workspaces", WORKSPACE_COLUMNS),
+                &conditions
+            )
+        );
+        let mut query = sqlx::query(AssertSqlSafe(sql));
+        for arg in args {
+            query = query.bind(arg);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+
+        let mut workspaces = Vec::with_capacity(rows.len());
+        for row in rows {
+            workspaces.extend(self.workspace_with_members(Some(row)).await?);
+        }
+        Ok(workspaces)
+    }
+
+    async fn rename_workspace(&self, id: &ObjectId, name: &str, at: i64) -> Result<bool> {
+        let result = sqlx::query("UPDATE workspaces SET name = $1, updated_at = $2 WHERE id = $3")
+            .bind(name)
+            .bind(at)
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_workspace(&self, id: &ObjectId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM workspace_members WHERE workspace_id = $1")
+            .bind(id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn add_member(&self, id: &ObjectId, member: &WorkspaceMember) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query("UPDATE workspaces SET updated_at = $1 WHERE id = $2")
+            .bind(member.added_at)
+            .bind(id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "INSERT INTO workspace_members (workspace_id, user_id, role, added_at) \
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(id.to_hex())
+        .bind(&member.user_id)
+        .bind(member.role.as_str())
+        .bind(member.added_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(map_write_error)?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn set_member_role(
+        &self,
+        id: &ObjectId,
+        user_id: &str,
+        role: WorkspaceRole,
+        at: i64,
+    ) -> Result<MemberChange> {
+        let mut tx = self.pool.begin().await?;
+
+        // Touching the workspace first locks it, so concurrent changes cannot both
+        // demote what each of them sees as the other owner
+        let Some(current) = Self::lock_member(&mut tx, id, user_id, at).await? else {
+            return Ok(MemberChange::NotMember);
+        };
+        if current == WorkspaceRole::Owner
+            && role != WorkspaceRole::Owner
+            && !Self::has_other_owner(&mut tx, id, user_id).await?
+        {
+            return Ok(MemberChange::LastOwner);
+        }
+
+        sqlx::query(
+            "UPDATE workspace_members SET role = $1 WHERE workspace_id = $2 AND user_id = $3",
+        )
+        .bind(role.as_str())
+        .bind(id.to_hex())
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(MemberChange::Changed)
+    }
+
+    async fn remove_member(&self, id: &ObjectId, user_id: &str, at: i64) -> Result<MemberChange> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(current) = Self::lock_member(&mut tx, id, user_id, at).await? else {
+            return Ok(MemberChange::NotMember);
+        };
+        if current == WorkspaceRole::Owner && !Self::has_other_owner(&mut tx, id, user_id).await? {
+            return Ok(MemberChange::LastOwner);
+        }
+
+        sqlx::query("DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2")
+            .bind(id.to_hex())
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(MemberChange::Changed)
+    }
+
+    async fn remove_user_memberships(&self, user_id: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM workspace_members WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
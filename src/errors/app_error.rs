@@ -0,0 +1,162 @@
+use std::fmt;
+
+use actix_web::http::{StatusCode, header};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use validator::ValidationErrors;
+
+/// Error returned by handlers and middlewares.
+/// Rendered as an RFC 7807 `application/problem+json` body with a stable `code`.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Validation(ValidationErrors),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Gone(String),
+    /// Rate limited; the client may retry after the given number of seconds
+    TooManyRequests(String, u64),
+    /// Unexpected failure. The detail is logged but never sent to the client.
+    Internal(anyhow::Error),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// RFC 7807 problem details body
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a ValidationErrors>,
+}
+
+impl AppError {
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        AppError::BadRequest(detail.into())
+    }
+
+    pub fn unauthorized(detail: impl Into<String>) -> Self {
+        AppError::Unauthorized(detail.into())
+    }
+
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        AppError::Forbidden(detail.into())
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        AppError::NotFound(detail.into())
+    }
+
+    pub fn conflict(detail: impl Into<String>) -> Self {
+        AppError::Conflict(detail.into())
+    }
+
+    pub fn gone(detail: impl Into<String>) -> Self {
+        AppError::Gone(detail.into())
+    }
+
+    pub fn too_many_requests(detail: impl Into<String>, retry_after: u64) -> Self {
+        AppError::TooManyRequests(detail.into(), retry_after)
+    }
+
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Gone(_) => "gone",
+            AppError::TooManyRequests(..) => "too_many_requests",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Human-readable explanation that is safe to show to clients
+    fn detail(&self) -> String {
+        match self {
+            AppError::BadRequest(detail)
+            | AppError::Unauthorized(detail)
+            | AppError::Forbidden(detail)
+            | AppError::NotFound(detail)
+            | AppError::Conflict(detail)
+            | AppError::Gone(detail)
+            | AppError::TooManyRequests(detail, _) => detail.clone(),
+            AppError::Validation(_) => "Request validation failed".to_string(),
+            AppError::Internal(_) => "An internal error occurred".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Internal(e) => write!(f, "{:#}", e),
+            other => write!(f, "{}", other.detail()),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Gone(_) => StatusCode::GONE,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let AppError::Internal(e) = self {
+            log::error!("Internal error: {:#}", e);
+        }
+
+        let status = self.status_code();
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code(),
+            errors: match self {
+                AppError::Validation(errors) => Some(errors),
+                _ => None,
+            },
+        };
+
+        let mut response = HttpResponse::build(status);
+        if let AppError::TooManyRequests(_, retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+
+        response
+            .content_type("application/problem+json")
+            .json(problem)
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        AppError::Internal(err)
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::Validation(errors)
+    }
+}
@@ -0,0 +1,342 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::auth_handlers::{client_ip, require_password_login};
+use crate::mail::{Email, send_in_background};
+use crate::models::one_time_token::{OneTimeToken, TokenPurpose};
+use crate::models::user::User;
+use crate::repositories::user_repository::UserUpdate;
+use crate::state::app_state::AppState;
+use crate::structs::account::{
+    ConfirmPasswordResetRequest, PasswordResetRequest, VerifyEmailRequest,
+};
+use crate::utils::jwt::Claims;
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// Minimum time between two password reset mails to the same account, so nobody can
+/// flood a mailbox through the unauthenticated reset endpoint
+const PASSWORD_RESET_COOLDOWN_MINUTES: i64 = 5;
+
+/// Store a new single-use token for the user and return the token itself
+async fn issue_one_time_token(
+    app_state: &AppState,
+    user_id: &str,
+    purpose: TokenPurpose,
+    email: &str,
+    lifetime_minutes: i64,
+) -> AppResult<String> {
+    let token = generate_token();
+    app_state
+        .tokens
+        .insert_one_time_token(&OneTimeToken::new(
+            hash_token(&token),
+            user_id.to_string(),
+            purpose,
+            email.to_string(),
+            lifetime_minutes,
+        ))
+        .await?;
+
+    Ok(token)
+}
+
+/// The part of a mail that tells the user how to use a token: the configured link,
+/// or the token itself and the endpoint that takes it
+fn token_instructions(url_template: Option<&str>, token: &str, endpoint: &str) -> String {
+    match url_template {
+        Some(template) => template.replace("{token}", token),
+        None => format!("Use this token with POST {}:\n\n{}", endpoint, token),
+    }
+}
+
+/// Check the username, password and email address of a new account against the policy.
+/// Violations are reported together with those of the request's own validation.
+pub async fn check_new_account(
+    app_state: &AppState,
+    request: &impl Validate,
+    username: &str,
+    password: &str,
+    email: Option<&str>,
+) -> AppResult<()> {
+    let mut errors = request.validate().err().unwrap_or_default();
+    app_state.policy.check_username(username, &mut errors);
+    app_state
+        .policy
+        .check_password("password", password, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    if let Some(email) = email {
+        check_email_available(app_state, email, None).await?;
+    }
+
+    Ok(())
+}
+
+/// Refuse an email address that a user other than `user_id` already has,
+/// if the policy requires unique addresses
+pub async fn check_email_available(
+    app_state: &AppState,
+    email: &str,
+    user_id: Option<&ObjectId>,
+) -> AppResult<()> {
+    if !app_state.policy.unique_emails() {
+        return Ok(());
+    }
+
+    let users = app_state.users.find_by_email(email).await?;
+    if users.iter().any(|user| user.id.as_ref() != user_id) {
+        return Err(AppError::conflict("Email address already in use"));
+    }
+
+    Ok(())
+}
+
+/// Mail a verification token for the user's email address, if they have one
+pub async fn send_verification_email(app_state: &AppState, user: &User) -> AppResult<()> {
+    let Some(email) = &user.email else {
+        return Ok(());
+    };
+    let user_id = user.id.context("User has no ID")?.to_hex();
+    let hours = app_state.config.auth.email_verification_hours;
+
+    // Only the address mailed last can be verified
+    let now = chrono::Utc::now().timestamp_millis();
+    app_state
+        .tokens
+        .invalidate_one_time_tokens(&user_id, TokenPurpose::EmailVerification, now)
+        .await?;
+
+    let token = issue_one_time_token(
+        app_state,
+        &user_id,
+        TokenPurpose::EmailVerification,
+        email,
+        hours * 60,
+    )
+    .await?;
+
+    let instructions = token_instructions(
+        app_state.config.mail.email_verification_url.as_deref(),
+        &token,
+        "/api/auth/verify-email",
+    );
+    send_in_background(
+        app_state.mailer.clone(),
+        Email {
+            to: email.clone(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Hi {},\n\nPlease confirm that this is your email address:\n\n{}\n\n\
+                 This expires in {} hours. If you did not expect this email, you can ignore it.\n",
+                user.username, instructions, hours
+            ),
+        },
+    );
+
+    Ok(())
+}
+
+/// Mail a password reset token to every active account that verified the given address.
+/// The response is the same whether or not such an account exists.
+/// Earlier tokens keep working, so new requests cannot keep anyone from resetting.
+pub async fn request_password_reset(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    web::Json(req): web::Json<PasswordResetRequest>,
+) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+    req.validate()?;
+    let auth_config = &app_state.config.auth;
+    let minutes = auth_config.password_reset_minutes;
+    let now = chrono::Utc::now().timestamp_millis();
+
+    // Every request counts against the client IP, like a failed login
+    let ip = client_ip(&app_state, &http_req);
+    if let Some(retry_after) = app_state.password_reset_throttle.retry_after(&ip) {
+        return Err(AppError::too_many_requests(
+            "Too many password reset requests, try again later",
+            retry_after,
+        ));
+    }
+    app_state.password_reset_throttle.record_failure(
+        &ip,
+        auth_config.max_failed_logins_per_ip,
+        auth_config.lockout_minutes * 60 * 1000,
+    );
+
+    // An unverified address may not be the user's, so it cannot take over the account
+    let users = app_state.users.find_by_email(req.email.trim()).await?;
+    for user in users
+        .into_iter()
+        .filter(|user| user.is_active && user.email_verified_at.is_some())
+    {
+        let (Some(user_id), Some(email)) = (user.id, &user.email) else {
+            continue;
+        };
+
+        // Accounts that were sent a token recently are skipped silently
+        let cooldown_start = now - PASSWORD_RESET_COOLDOWN_MINUTES * 60 * 1000;
+        if app_state
+            .tokens
+            .last_one_time_token_at(&user_id.to_hex(), TokenPurpose::PasswordReset)
+            .await?
+            .is_some_and(|issued_at| issued_at > cooldown_start)
+        {
+            continue;
+        }
+
+        let token = issue_one_time_token(
+            &app_state,
+            &user_id.to_hex(),
+            TokenPurpose::PasswordReset,
+            email,
+            minutes,
+        )
+        .await?;
+
+        let instructions = token_instructions(
+            app_state.config.mail.password_reset_url.as_deref(),
+            &token,
+            "/api/auth/password-reset/confirm",
+        );
+        send_in_background(
+            app_state.mailer.clone(),
+            Email {
+                to: email.clone(),
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\nA password reset was requested for your account. \
+                     To choose a new password:\n\n{}\n\n\
+                     This expires in {} minutes. If you did not request it, you can ignore this email.\n",
+                    user.username, instructions, minutes
+                ),
+            },
+        );
+    }
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "If an account with this email address exists, a password reset email has been sent"
+    })))
+}
+
+/// Set a new password with a password reset token. Every session of the user is ended.
+pub async fn reset_password(
+    app_state: web::Data<AppState>,
+    web::Json(req): web::Json<ConfirmPasswordResetRequest>,
+) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+
+    // Checked before the token is used up, so a rejected password can be corrected
+    app_state
+        .policy
+        .validate_password("new_password", &req.new_password)?;
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let token = app_state
+        .tokens
+        .use_one_time_token(&hash_token(&req.token), TokenPurpose::PasswordReset, now)
+        .await?
+        .ok_or_else(|| AppError::bad_request("Invalid or expired token"))?;
+
+    let object_id = ObjectId::parse_str(&token.user_id).context("Invalid user ID in token")?;
+
+    // The token only proves access to the address it was sent to, which may no longer
+    // be the user's. Disabled users keep their password until they are enabled again.
+    app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .filter(|user| user.is_active && user.email.as_deref() == Some(token.email.as_str()))
+        .ok_or_else(|| AppError::bad_request("Invalid or expired token"))?;
+
+    let password_hash = app_state.passwords.hash(&req.new_password).await?;
+
+    let update = UserUpdate {
+        password_hash: Some(password_hash),
+        ..Default::default()
+    };
+    app_state
+        .users
+        .update(&object_id, &update)
+        .await?
+        .ok_or_else(|| AppError::bad_request("Invalid or expired token"))?;
+
+    // Proving access to the mailbox also lifts a lockout from failed logins
+    app_state.users.unlock(&object_id).await?;
+    app_state
+        .tokens
+        .revoke_user_tokens(&token.user_id, now)
+        .await?;
+    app_state
+        .tokens
+        .invalidate_one_time_tokens(&token.user_id, TokenPurpose::PasswordReset, now)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Mark an email address as verified with the token mailed to it
+pub async fn verify_email(
+    app_state: web::Data<AppState>,
+    web::Json(req): web::Json<VerifyEmailRequest>,
+) -> AppResult<HttpResponse> {
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let token = app_state
+        .tokens
+        .use_one_time_token(
+            &hash_token(&req.token),
+            TokenPurpose::EmailVerification,
+            now,
+        )
+        .await?
+        .ok_or_else(|| AppError::bad_request("Invalid or expired token"))?;
+
+    // The token only proves access to the address it was sent to
+    let object_id = ObjectId::parse_str(&token.user_id).context("Invalid user ID in token")?;
+    if !app_state
+        .users
+        .set_email_verified(&object_id, &token.email, now)
+        .await?
+    {
+        return Err(AppError::bad_request("Invalid or expired token"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Send a new verification email to the current user
+pub async fn resend_verification_email(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+
+    let object_id = ObjectId::parse_str(&claims.user_id).context("Invalid user ID in token")?;
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    if user.email.is_none() {
+        return Err(AppError::bad_request("No email address on file"));
+    }
+    if user.email_verified_at.is_some() {
+        return Err(AppError::conflict("Email address is already verified"));
+    }
+
+    send_verification_email(&app_state, &user).await?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "Verification email sent"
+    })))
+}
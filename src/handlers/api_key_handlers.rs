@@ -0,0 +1,103 @@
+use actix_web::{HttpResponse, web};
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::models::api_key::ApiKey;
+use crate::state::app_state::AppState;
+use crate::structs::api_key::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse};
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// Prefix of every API key, so leaked keys are easy to recognize
+const API_KEY_PREFIX: &str = "mms_";
+
+/// Number of characters of the key that are kept to identify it in listings
+const KEY_PREFIX_LENGTH: usize = 12;
+
+/// List the API keys of a user
+pub async fn get_api_keys(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let user_id = path.into_inner();
+
+    let keys = app_state.api_keys.find_by_user(&user_id).await?;
+    let keys: Vec<ApiKeyResponse> = keys.into_iter().map(ApiKeyResponse::from).collect();
+
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// Create an API key. The key is only ever returned in this response.
+pub async fn create_api_key(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    web::Json(req): web::Json<CreateApiKeyRequest>,
+) -> AppResult<HttpResponse> {
+    req.validate()?;
+    let user_id = path.into_inner();
+
+    // Admins may create keys for other users, but only for ones that exist
+    let object_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| AppError::bad_request("Invalid user ID format"))?;
+    if app_state.users.find_by_id(&object_id).await?.is_none() {
+        return Err(AppError::not_found("User not found"));
+    }
+
+    let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+
+    let mut scopes = Vec::new();
+    for scope in req.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let api_key = ApiKey::new(
+        user_id,
+        req.name,
+        key[..KEY_PREFIX_LENGTH].to_string(),
+        hash_token(&key),
+        scopes,
+        req.expires_in_days,
+    );
+    let inserted = app_state.api_keys.insert(&api_key).await?;
+
+    Ok(HttpResponse::Created().json(CreatedApiKeyResponse {
+        key,
+        details: ApiKeyResponse::from(inserted),
+    }))
+}
+
+/// Get a single API key of a user
+pub async fn get_api_key(
+    app_state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> AppResult<HttpResponse> {
+    let (user_id, key_id) = path.into_inner();
+    let key_id =
+        ObjectId::parse_str(&key_id).map_err(|_| AppError::bad_request("Invalid key ID format"))?;
+
+    let key = app_state
+        .api_keys
+        .find_by_id(&user_id, &key_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("API key not found"))?;
+
+    Ok(HttpResponse::Ok().json(ApiKeyResponse::from(key)))
+}
+
+/// Delete (revoke) an API key of a user
+pub async fn delete_api_key(
+    app_state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> AppResult<HttpResponse> {
+    let (user_id, key_id) = path.into_inner();
+    let key_id =
+        ObjectId::parse_str(&key_id).map_err(|_| AppError::bad_request("Invalid key ID format"))?;
+
+    if !app_state.api_keys.delete(&user_id, &key_id).await? {
+        return Err(AppError::not_found("API key not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
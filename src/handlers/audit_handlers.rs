@@ -0,0 +1,173 @@
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use futures_util::stream;
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::auth_handlers::client_ip;
+use crate::models::audit_event::AuditEvent;
+use crate::repositories::audit_repository::{AuditCursor, AuditFilter};
+use crate::state::app_state::AppState;
+use crate::structs::audit::{AuditEventPage, AuditEventResponse, AuditQueryParams};
+use crate::utils::jwt::Claims;
+
+/// Number of events listed when the query does not give a limit
+const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// Number of events read from storage at a time during an export
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+/// Append an event to the audit log. The actor is the logged-in user unless the event
+/// already names one. A failure to write is logged rather than failing the request,
+/// whose change has already been made.
+pub async fn record_audit_event(app_state: &AppState, req: &HttpRequest, mut event: AuditEvent) {
+    if event.actor_id.is_none()
+        && let Some(claims) = req.extensions().get::<Claims>()
+    {
+        event.actor_id = Some(claims.user_id.clone());
+        event.actor_username = Some(claims.sub.clone());
+    }
+    event.ip_hash = Some(app_state.audit_ip_hasher.hash(&client_ip(app_state, req)));
+
+    if let Err(e) = app_state.audit.insert_audit_event(&event).await {
+        log::error!(
+            "Failed to record audit event {} on {} {}: {:#}",
+            event.action.as_str(),
+            event.target_type.as_str(),
+            event.target_id,
+            e
+        );
+    }
+}
+
+fn parse_filter(params: &AuditQueryParams) -> AppResult<AuditFilter> {
+    let action = params
+        .action
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(|_| AppError::bad_request("Unknown audit action"))?;
+    let before = params.before.as_deref().map(parse_cursor).transpose()?;
+
+    Ok(AuditFilter {
+        actor: params.actor.clone().filter(|actor| !actor.is_empty()),
+        action,
+        from: params.from,
+        to: params.to,
+        before,
+    })
+}
+
+/// Cursors are the creation time and ID of the last event of a page
+fn format_cursor(event: &AuditEvent) -> Option<String> {
+    event
+        .id
+        .map(|id| format!("{}_{}", event.created_at, id.to_hex()))
+}
+
+fn parse_cursor(cursor: &str) -> AppResult<AuditCursor> {
+    cursor
+        .split_once('_')
+        .and_then(|(created_at, id)| {
+            Some(AuditCursor {
+                created_at: created_at.parse().ok()?,
+                id: ObjectId::parse_str(id).ok()?,
+            })
+        })
+        .ok_or_else(|| AppError::bad_request("Invalid cursor"))
+}
+
+/// List audit events, newest first, filtered by actor, action and time range
+pub async fn get_audit_events(
+    app_state: web::Data<AppState>,
+    query: web::Query<AuditQueryParams>,
+) -> AppResult<HttpResponse> {
+    query.validate()?;
+    let filter = parse_filter(&query)?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    let events = app_state.audit.find_audit_events(&filter, limit).await?;
+
+    // A full page may be followed by more events
+    let next_cursor = if events.len() as i64 == limit {
+        events.last().and_then(format_cursor)
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(AuditEventPage {
+        events: events.into_iter().map(AuditEventResponse::from).collect(),
+        next_cursor,
+    }))
+}
+
+/// Export every audit event matching the filters as newline-delimited JSON, newest first.
+/// Events are read and sent in batches, so large exports are not held in memory.
+pub async fn export_audit_events(
+    app_state: web::Data<AppState>,
+    query: web::Query<AuditQueryParams>,
+) -> AppResult<HttpResponse> {
+    query.validate()?;
+    let filter = parse_filter(&query)?;
+
+    let batches = stream::unfold(Some(filter), move |filter| {
+        let app_state = app_state.clone();
+        async move {
+            let mut filter = filter?;
+            let events = match app_state
+                .audit
+                .find_audit_events(&filter, EXPORT_BATCH_SIZE)
+                .await
+            {
+                Ok(events) => events,
+                Err(e) => {
+                    log::error!("Audit log export failed: {:#}", e);
+                    let error =
+                        actix_web::error::ErrorInternalServerError("Audit log export failed");
+                    return Some((Err(error), None));
+                }
+            };
+
+            // The last batch is shorter than the batch size. A full batch continues after
+            // its last event, which cannot be found again without an ID; starting over
+            // from the top would repeat the export forever.
+            let next = match events.last() {
+                Some(last) if events.len() as i64 == EXPORT_BATCH_SIZE => {
+                    let Some(id) = last.id else {
+                        log::error!("Audit log export failed: stored event without an ID");
+                        let error =
+                            actix_web::error::ErrorInternalServerError("Audit log export failed");
+                        return Some((Err(error), None));
+                    };
+                    filter.before = Some(AuditCursor {
+                        created_at: last.created_at,
+                        id,
+                    });
+                    Some(filter)
+                }
+                _ => None,
+            };
+
+            let mut lines = Vec::new();
+            for event in events {
+                if let Err(e) = serde_json::to_writer(&mut lines, &AuditEventResponse::from(event))
+                {
+                    return Some((Err(actix_web::error::ErrorInternalServerError(e)), None));
+                }
+                lines.push(b'\n');
+            }
+
+            Some((Ok::<_, actix_web::Error>(Bytes::from(lines)), next))
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"audit-events.ndjson\"",
+        ))
+        .streaming(batches))
+}
@@ -1,13 +1,25 @@
-use actix_web::{HttpResponse, Result, error, web};
-use bcrypt::{DEFAULT_COST, hash, verify};
-use mongodb::bson::doc;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, http::header, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
-use crate::models::user::User;
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::account_handlers::{check_new_account, send_verification_email};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::handlers::totp_handlers::verify_second_factor;
+use crate::handlers::workspace_handlers::workspace_role;
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+use crate::models::invite::{Invite, InviteUse};
+use crate::models::refresh_token::RefreshToken;
+use crate::models::user::{Role, User};
+use crate::repositories::errors::is_duplicate_key;
+use crate::repositories::user_repository::UserUpdate;
 use crate::state::app_state::AppState;
 use crate::structs::user::SignupRequest;
 use crate::structs::user::UserResponse;
-use crate::utils::jwt::create_token;
+use crate::utils::jwt::{CHALLENGE_LIFETIME_MINUTES, Claims};
+use crate::utils::login_throttle::{backoff_seconds, seconds_until};
+use crate::utils::tokens::{generate_token, hash_token};
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
@@ -18,109 +30,387 @@ pub struct LoginRequest {
 #[derive(Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserResponse,
 }
 
+/// Returned by `login` instead of the tokens when the user has two-factor authentication enabled
+#[derive(Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub challenge_token: String,
+    pub expires_in: i64, // Seconds until the challenge token expires
+}
+
+#[derive(Deserialize)]
+pub struct TotpLoginRequest {
+    pub challenge_token: String,
+    pub code: String, // TOTP code or recovery code
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+/// Create an access token and a new refresh token for the user.
+/// Without a `family_id` the refresh token starts a new family (a new login).
+/// Both tokens carry the workspace the session has switched to, if any.
+pub async fn issue_tokens(
+    app_state: &AppState,
+    user: &User,
+    family_id: Option<String>,
+    workspace_id: Option<String>,
+) -> AppResult<TokenResponse> {
+    let user_id = user.id.context("User has no ID")?.to_hex();
+    let auth_config = &app_state.config.auth;
+
+    let family_id = family_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let token = app_state.jwt.create_token(
+        &user.username,
+        &user_id,
+        user.role,
+        &family_id,
+        workspace_id.as_deref(),
+    )?;
+
+    // Only the hash is stored; the token itself is handed to the client once
+    let refresh_token = generate_token();
+    app_state
+        .tokens
+        .insert_refresh_token(&RefreshToken::new(
+            hash_token(&refresh_token),
+            user_id,
+            family_id,
+            workspace_id,
+            auth_config.refresh_token_lifetime_days,
+        ))
+        .await?;
+
+    Ok(TokenResponse {
+        token,
+        refresh_token,
+    })
+}
+
+/// Refuse the password and passkey endpoints when single sign-on is the only way to log in
+pub fn require_password_login(app_state: &AppState) -> AppResult<()> {
+    if app_state.config.auth.password_login_enabled {
+        Ok(())
+    } else {
+        Err(AppError::forbidden(
+            "Password login is disabled, log in with single sign-on",
+        ))
+    }
+}
+
 pub async fn login(
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     web::Json(req): web::Json<LoginRequest>,
-) -> Result<HttpResponse> {
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
+) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let ip = client_ip(&app_state, &http_req);
 
-    // Find user
-    let user = users_collection
-        .find_one(doc! { "username": &req.username })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorUnauthorized("Invalid username or password"))?;
-
-    // Check if user is active
-    if !user.is_active {
-        return Err(error::ErrorUnauthorized("Account is disabled"));
+    if let Some(retry_after) = app_state.login_throttle.retry_after(&ip) {
+        return Err(too_many_attempts(retry_after));
     }
 
+    // Find user. Unknown usernames take as long as wrong passwords.
+    let Some(user) = app_state.users.find_by_username(&req.username).await? else {
+        app_state.passwords.verify_missing(&req.password).await?;
+        record_failed_ip(&app_state, &ip);
+        return Err(AppError::unauthorized("Invalid username or password"));
+    };
+    let object_id = user.id.context("User has no ID")?;
+
     // Verify password
-    let is_valid = verify(&req.password, &user.password_hash).map_err(|e| {
-        error::ErrorInternalServerError(format!("Failed to verify password: {}", e))
-    })?;
+    let is_valid = app_state
+        .passwords
+        .verify(&req.password, &user.password_hash)
+        .await?;
+
+    // Locked and throttled accounts get the same answer as wrong passwords, so it does
+    // not reveal which accounts exist; only the audit log tells them apart
+    if check_not_locked(&user, now).is_err() {
+        record_failed_ip(&app_state, &ip);
+        let event = AuditEvent::new(
+            AuditAction::AuthLoginLocked,
+            AuditTarget::User,
+            object_id.to_hex(),
+        )
+        .with_actor(object_id.to_hex(), &user.username);
+        record_audit_event(&app_state, &http_req, event).await;
+        return Err(AppError::unauthorized("Invalid username or password"));
+    }
 
     if !is_valid {
-        return Err(error::ErrorUnauthorized("Invalid username or password"));
+        record_failed_login(&app_state, &http_req, &user, now).await?;
+        return Err(AppError::unauthorized("Invalid username or password"));
+    }
+
+    // Disabled accounts get the same answer, so it does not reveal which accounts exist
+    if !user.is_active {
+        return Err(AppError::unauthorized("Invalid username or password"));
+    }
+
+    // Move bcrypt and outdated Argon2 hashes to the current parameters while the password is at hand
+    if app_state.passwords.needs_rehash(&user.password_hash) {
+        rehash_password(&app_state, &object_id, &req.password).await;
+    }
+
+    // With two-factor authentication enabled, the tokens are only issued by `login_totp`
+    if let Some(credential) = app_state.totp.find_totp(&object_id.to_hex()).await?
+        && credential.enabled
+    {
+        let challenge_token = app_state.jwt.create_challenge_token(&object_id.to_hex())?;
+
+        return Ok(HttpResponse::Ok().json(MfaChallengeResponse {
+            mfa_required: true,
+            challenge_token,
+            expires_in: CHALLENGE_LIFETIME_MINUTES * 60,
+        }));
+    }
+
+    complete_login(&app_state, &http_req, user, now).await
+}
+
+/// Store a new hash of a password that was just verified. Failures are only logged,
+/// the old hash keeps working.
+async fn rehash_password(app_state: &AppState, object_id: &ObjectId, password: &str) {
+    let result = async {
+        let update = UserUpdate {
+            password_hash: Some(app_state.passwords.hash(password).await?),
+            ..Default::default()
+        };
+        app_state.users.update(object_id, &update).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        log::warn!(
+            "Failed to rehash the password of user {}: {:#}",
+            object_id,
+            e
+        );
+    }
+}
+
+/// Second login step for users with two-factor authentication: exchange the challenge token
+/// from `login` and a TOTP or recovery code for the access and refresh tokens
+pub async fn login_totp(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    web::Json(req): web::Json<TotpLoginRequest>,
+) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let ip = client_ip(&app_state, &http_req);
+
+    if let Some(retry_after) = app_state.login_throttle.retry_after(&ip) {
+        return Err(too_many_attempts(retry_after));
     }
 
-    // Get user ID for the token
-    let user_id = user.id.unwrap().to_hex();
+    let claims = app_state
+        .jwt
+        .validate_challenge_token(&req.challenge_token)
+        .map_err(|_| AppError::unauthorized("Invalid or expired challenge token"))?;
+    let object_id = ObjectId::parse_str(&claims.sub).context("Invalid user ID in token")?;
+
+    // The account may have been disabled, or 2FA turned off, since the password step
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .filter(|user| user.is_active)
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired challenge token"))?;
+    let credential = app_state
+        .totp
+        .find_totp(&claims.sub)
+        .await?
+        .filter(|credential| credential.enabled)
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired challenge token"))?;
+
+    // Wrong codes count towards the same lockout as wrong passwords
+    check_not_locked(&user, now)?;
+
+    if !verify_second_factor(&app_state, &credential, &req.code).await? {
+        record_failed_login(&app_state, &http_req, &user, now).await?;
+        return Err(AppError::unauthorized("Invalid authentication code"));
+    }
+
+    complete_login(&app_state, &http_req, user, now).await
+}
+
+/// Issue the tokens of a successful login and record it
+pub async fn complete_login(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user: User,
+    now: i64,
+) -> AppResult<HttpResponse> {
+    let object_id = user.id.context("User has no ID")?;
 
-    // Create JWT token
-    let token = create_token(&user.username, &user_id)
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to create token: {}", e)))?;
+    // Create the access and refresh tokens
+    let tokens = issue_tokens(app_state, &user, None, None).await?;
 
     // Update last login
-    users_collection
-        .update_one(
-            doc! { "username": &req.username },
-            doc! { "$set": { "last_login": chrono::Utc::now().timestamp_millis() } },
-        )
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    app_state.users.set_last_login(&object_id, now).await?;
+
+    let event = AuditEvent::new(
+        AuditAction::AuthLogin,
+        AuditTarget::User,
+        object_id.to_hex(),
+    )
+    .with_actor(object_id.to_hex(), &user.username);
+    record_audit_event(app_state, req, event).await;
 
     let response = LoginResponse {
-        token,
-        user: UserResponse {
-            id: user_id,
-            username: user.username,
-            email: user.email,
-            full_name: user.full_name,
-            created_at: user.created_at,
-            updated_at: user.updated_at,
-            last_login: user.last_login,
-            is_active: user.is_active,
-        },
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+        user: UserResponse::from(user),
     };
 
     Ok(HttpResponse::Ok().json(response))
 }
 
-// Add endpoint to create initial superuser
-pub async fn create_superuser(app_state: web::Data<AppState>) -> Result<HttpResponse> {
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
+/// Get the client's IP address for per-IP throttling
+pub fn client_ip(app_state: &AppState, req: &HttpRequest) -> String {
+    app_state.trusted_proxies.client_ip(req)
+}
+
+/// Refuse locked accounts, and slow down repeated failures
+pub fn check_not_locked(user: &User, now: i64) -> AppResult<()> {
+    let retry_at = match user.locked_until {
+        Some(locked_until) if user.is_locked() => locked_until,
+        _ => {
+            user.last_failed_login.unwrap_or(0) + backoff_seconds(user.failed_login_attempts) * 1000
+        }
+    };
+
+    match seconds_until(retry_at, now) {
+        Some(retry_after) => Err(too_many_attempts(retry_after)),
+        None => Ok(()),
+    }
+}
+
+/// Count a failed attempt against the client IP and the account, locking the account
+/// once it reaches the limit
+pub async fn record_failed_login(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user: &User,
+    now: i64,
+) -> AppResult<()> {
+    let auth_config = &app_state.config.auth;
+    let lockout_ms = auth_config.lockout_minutes * 60 * 1000;
+    let object_id = user.id.context("User has no ID")?;
+
+    record_failed_ip(app_state, &client_ip(app_state, req));
+    let event = AuditEvent::new(
+        AuditAction::AuthLoginFailed,
+        AuditTarget::User,
+        object_id.to_hex(),
+    )
+    .with_actor(object_id.to_hex(), &user.username);
+    record_audit_event(app_state, req, event).await;
+
+    let failures = app_state
+        .users
+        .record_failed_login(&object_id, now, now - lockout_ms)
+        .await?;
+    if failures >= auth_config.max_failed_logins {
+        log::warn!(
+            "Locking account {} after {} failed login attempts",
+            user.username,
+            failures
+        );
+        app_state
+            .users
+            .lock(&object_id, now, now + lockout_ms)
+            .await?;
+    }
+
+    Ok(())
+}
 
+/// Count a failed attempt against the client IP only
+pub fn record_failed_ip(app_state: &AppState, ip: &str) {
+    let auth_config = &app_state.config.auth;
+    app_state.login_throttle.record_failure(
+        ip,
+        auth_config.max_failed_logins_per_ip,
+        auth_config.lockout_minutes * 60 * 1000,
+    );
+}
+
+pub fn too_many_attempts(retry_after: u64) -> AppError {
+    AppError::too_many_requests(
+        "Too many failed login attempts, try again later",
+        retry_after,
+    )
+}
+
+// Add endpoint to create initial superuser
+pub async fn create_superuser(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
     // Check if any user exists already
-    let count = users_collection
-        .count_documents(doc! {})
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let count = app_state.users.count().await?;
 
     if count > 0 {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Users already exist, cannot create initial superuser"
-        })));
+        return Err(AppError::bad_request(
+            "Users already exist, cannot create initial superuser",
+        ));
     }
 
-    // Get superuser credentials from environment variables
-    let username = std::env::var("SUPERUSER_USERNAME")
-        .map_err(|_| error::ErrorInternalServerError("SUPERUSER_USERNAME not set"))?;
-    let password = std::env::var("SUPERUSER_PASSWORD")
-        .map_err(|_| error::ErrorInternalServerError("SUPERUSER_PASSWORD not set"))?;
+    // Get superuser credentials from the configuration
+    let auth_config = &app_state.config.auth;
+    let (Some(username), Some(password)) = (
+        auth_config.superuser_username.clone(),
+        auth_config.superuser_password.as_deref(),
+    ) else {
+        return Err(AppError::Internal(anyhow::anyhow!(
+            "Superuser credentials not configured"
+        )));
+    };
 
     // Hash password
-    let password_hash = hash(password, DEFAULT_COST)
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to hash password: {}", e)))?;
+    let password_hash = app_state.passwords.hash(password).await?;
 
-    // Create superuser with all roles
+    // Create superuser with the admin role
     let superuser = User::new(
         username.clone(),
         Some("admin@example.com".to_string()),
         Some("Super User".to_string()),
         password_hash,
+        Role::Admin,
     );
 
     // Insert into database
-    users_collection.insert_one(&superuser).await.map_err(|e| {
-        error::ErrorInternalServerError(format!("Failed to create superuser: {}", e))
-    })?;
+    let inserted = app_state
+        .users
+        .insert(&superuser)
+        .await
+        .context("Failed to create superuser")?;
+
+    let response = UserResponse::from(inserted);
+    let event = AuditEvent::new(AuditAction::UserInit, AuditTarget::User, &response.id)
+        .with_changes(None, Some(&response));
+    record_audit_event(&app_state, &req, event).await;
 
     Ok(HttpResponse::Created().json(serde_json::json!({
         "message": "Superuser created successfully",
@@ -130,68 +420,232 @@ pub async fn create_superuser(app_state: web::Data<AppState>) -> Result<HttpResp
 
 pub async fn signup(
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     web::Json(req): web::Json<SignupRequest>,
-) -> Result<HttpResponse> {
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
-
-    // Check if signup is allowed (configuration option)
-    let allow_signup = std::env::var("ALLOW_PUBLIC_SIGNUP")
-        .unwrap_or_else(|_| "false".to_string())
-        .to_lowercase()
-        == "true";
+) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+
+    // Without public signup (configuration option), only invited users can sign up
+    let invite = match &req.invite_code {
+        Some(code) => Some(find_usable_invite(&app_state, code).await?),
+        None if app_state.config.auth.allow_public_signup => None,
+        None => {
+            return Err(AppError::forbidden(
+                "Public signup is disabled, an invite code is required",
+            ));
+        }
+    };
 
-    if !allow_signup {
-        return Err(error::ErrorForbidden("Public signup is disabled"));
+    // An invite for an email address is only valid with that address, which is used if none is given
+    let mut email = req.email.clone();
+    if let Some(invite_email) = invite.as_ref().and_then(|invite| invite.email.as_ref()) {
+        match &email {
+            Some(email) if !email.eq_ignore_ascii_case(invite_email) => {
+                return Err(AppError::forbidden(
+                    "This invite is for a different email address",
+                ));
+            }
+            _ => email = Some(invite_email.clone()),
+        }
     }
 
-    // Check if username already exists
-    let existing_user = users_collection
-        .find_one(doc! { "username": &req.username })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
-
-    if existing_user.is_some() {
-        return Err(error::ErrorBadRequest("Username already exists"));
-    }
+    check_new_account(
+        &app_state,
+        &req,
+        &req.username,
+        &req.password,
+        email.as_deref(),
+    )
+    .await?;
 
     // Hash password
-    let password_hash = hash(&req.password, DEFAULT_COST)
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to hash password: {}", e)))?;
+    let password_hash = app_state.passwords.hash(&req.password).await?;
 
-    // Create new user with default permissions
+    // Create new user with default permissions, or the role of the invite
     let new_user = User::new(
         req.username.clone(),
-        req.email,
+        email,
         req.full_name,
         password_hash,
+        invite.as_ref().map_or(Role::Member, |invite| invite.role),
     );
 
-    // Insert into database
-    let result = users_collection
-        .insert_one(&new_user)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to create user: {}", e)))?;
-
-    let id = result.inserted_id.as_object_id().unwrap();
+    // Insert into database; the unique username index rejects duplicates
+    let inserted_user = app_state.users.insert(&new_user).await.map_err(|e| {
+        if is_duplicate_key(&e) {
+            AppError::conflict("Username already exists")
+        } else {
+            AppError::Internal(e.context("Failed to create user"))
+        }
+    })?;
+    let object_id = inserted_user.id.context("User has no ID")?;
+
+    if let Some(invite) = &invite {
+        let used = InviteUse {
+            user_id: object_id.to_hex(),
+            username: inserted_user.username.clone(),
+            used_at: chrono::Utc::now().timestamp_millis(),
+        };
+        let invite_id = invite.id.context("Invite has no ID")?;
+
+        // Concurrent signups may have taken the last use since the invite was checked
+        if !app_state.invites.redeem_invite(&invite_id, &used).await? {
+            app_state.users.delete(&object_id).await?;
+            return Err(invalid_invite());
+        }
+    }
 
-    // Retrieve the inserted user
-    let inserted_user = users_collection
-        .find_one(doc! { "_id": id })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorInternalServerError("User created but not found"))?;
+    send_verification_email(&app_state, &inserted_user).await?;
 
-    // Create JWT token for the new user
-    let user_id = inserted_user.id.unwrap().to_hex();
-    let token = create_token(&req.username, &user_id)
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to create token: {}", e)))?;
+    // Create the access and refresh tokens for the new user
+    let tokens = issue_tokens(&app_state, &inserted_user, None, None).await?;
 
-    // Return the new user details and token
+    // Return the new user details and tokens
     let response = LoginResponse {
-        token,
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
         user: UserResponse::from(inserted_user),
     };
 
+    let event = AuditEvent::new(
+        AuditAction::UserSignup,
+        AuditTarget::User,
+        &response.user.id,
+    )
+    .with_actor(&response.user.id, &response.user.username)
+    .with_changes(None, Some(&response.user));
+    record_audit_event(&app_state, &http_req, event).await;
+
     Ok(HttpResponse::Created().json(response))
 }
+
+/// Find the invite of a code, if it can still be used
+async fn find_usable_invite(app_state: &AppState, code: &str) -> AppResult<Invite> {
+    app_state
+        .invites
+        .find_invite_by_hash(&hash_token(code.trim()))
+        .await?
+        .filter(|invite| !invite.is_expired() && !invite.is_used_up())
+        .ok_or_else(invalid_invite)
+}
+
+fn invalid_invite() -> AppError {
+    AppError::forbidden("Invalid, expired or used up invite code")
+}
+
+/// Exchange a refresh token for a new access token and refresh token.
+/// Every refresh token can be used once; presenting a used one again revokes its whole family.
+pub async fn refresh(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    web::Json(req): web::Json<RefreshRequest>,
+) -> AppResult<HttpResponse> {
+    let token_hash = hash_token(&req.refresh_token);
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let stored = app_state
+        .tokens
+        .find_refresh_token(&token_hash)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid refresh token"))?;
+
+    // A rotated token being used again means it leaked, so end the session altogether
+    if stored.revoked_at.is_some() {
+        app_state
+            .tokens
+            .revoke_refresh_family(&stored.family_id, now)
+            .await?;
+        let event = AuditEvent::new(
+            AuditAction::AuthRefreshReuse,
+            AuditTarget::User,
+            &stored.user_id,
+        );
+        record_audit_event(&app_state, &http_req, event).await;
+        return Err(AppError::unauthorized("Refresh token has been revoked"));
+    }
+
+    if stored.is_expired() {
+        return Err(AppError::unauthorized("Refresh token has expired"));
+    }
+
+    // Consume the token; losing the race against a concurrent refresh counts as reuse
+    if !app_state
+        .tokens
+        .revoke_refresh_token(&token_hash, now)
+        .await?
+    {
+        app_state
+            .tokens
+            .revoke_refresh_family(&stored.family_id, now)
+            .await?;
+        return Err(AppError::unauthorized("Refresh token has been revoked"));
+    }
+
+    // Pick up the current role, and refuse users that were disabled or deleted
+    let user_id = ObjectId::parse_str(&stored.user_id).context("Invalid user ID in token")?;
+    let user = app_state
+        .users
+        .find_by_id(&user_id)
+        .await?
+        .filter(|user| user.is_active)
+        .ok_or_else(|| AppError::unauthorized("Account is disabled"))?;
+
+    // Stay in the workspace the session switched to, unless the user has left it since
+    let workspace_id = match stored.workspace_id {
+        Some(workspace_id) => workspace_role(&app_state, &workspace_id, &stored.user_id)
+            .await?
+            .map(|_| workspace_id),
+        None => None,
+    };
+
+    let tokens = issue_tokens(&app_state, &user, Some(stored.family_id), workspace_id).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Revoke the current access token and, if given, the refresh token of the same session
+pub async fn logout(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    body: Option<web::Json<LogoutRequest>>,
+) -> AppResult<HttpResponse> {
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+    let now = chrono::Utc::now().timestamp_millis();
+
+    app_state
+        .tokens
+        .revoke_access_token(&claims.jti, claims.exp as i64 * 1000)
+        .await?;
+
+    if let Some(refresh_token) = body.and_then(|body| body.into_inner().refresh_token) {
+        let stored = app_state
+            .tokens
+            .find_refresh_token(&hash_token(&refresh_token))
+            .await?;
+
+        // Ignore refresh tokens that belong to someone else
+        if let Some(stored) = stored
+            && stored.user_id == claims.user_id
+        {
+            app_state
+                .tokens
+                .revoke_refresh_family(&stored.family_id, now)
+                .await?;
+        }
+    }
+
+    let event = AuditEvent::new(AuditAction::AuthLogout, AuditTarget::User, &claims.user_id);
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Publish the public keys that verify access tokens, so other services can check them
+pub async fn jwks(app_state: web::Data<AppState>) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(app_state.jwt.jwks()))
+}
@@ -1,15 +1,16 @@
 use actix_web::{HttpResponse, web};
-use mongodb::bson::doc;
+use anyhow::Context;
 
+use crate::errors::app_error::AppResult;
 use crate::state::app_state::AppState;
 
-pub async fn health_check(state: web::Data<AppState>) -> HttpResponse {
-    // Perform a simple ping operation to check the database connection
-    let ping_result = state.db.run_command(doc! { "ping": 1 }).await;
+pub async fn health_check(state: web::Data<AppState>) -> AppResult<HttpResponse> {
+    // Perform a simple ping operation to check the storage connection
+    state
+        .health
+        .ping()
+        .await
+        .context("Database connection failed")?;
 
-    match ping_result {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "success": true })),
-        Err(_) => HttpResponse::InternalServerError()
-            .json(serde_json::json!({ "success": false, "error": "Database connection failed" })),
-    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true })))
 }
@@ -0,0 +1,94 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::models::invite::Invite;
+use crate::state::app_state::AppState;
+use crate::structs::invite::{CreateInviteRequest, CreatedInviteResponse, InviteResponse};
+use crate::utils::jwt::Claims;
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// Prefix of every invite code, so codes are easy to recognize
+const INVITE_CODE_PREFIX: &str = "inv_";
+
+/// Number of characters of the code that are kept to identify it in listings
+const CODE_PREFIX_LENGTH: usize = 12;
+
+fn parse_invite_id(invite_id: &str) -> AppResult<ObjectId> {
+    ObjectId::parse_str(invite_id).map_err(|_| AppError::bad_request("Invalid invite ID format"))
+}
+
+/// List every invite with its uses
+pub async fn get_invites(app_state: web::Data<AppState>) -> AppResult<HttpResponse> {
+    let invites: Vec<InviteResponse> = app_state
+        .invites
+        .find_invites()
+        .await?
+        .into_iter()
+        .map(InviteResponse::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+/// Create an invite. The code is only ever returned in this response.
+pub async fn create_invite(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<CreateInviteRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()?;
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+
+    let code = format!("{}{}", INVITE_CODE_PREFIX, generate_token());
+
+    let invite = Invite::new(
+        code[..CODE_PREFIX_LENGTH].to_string(),
+        hash_token(&code),
+        body.email,
+        body.role.unwrap_or_default(),
+        body.max_uses.unwrap_or(1),
+        claims.user_id,
+        body.expires_in_days,
+    );
+    let inserted = app_state.invites.insert_invite(&invite).await?;
+
+    Ok(HttpResponse::Created().json(CreatedInviteResponse {
+        code,
+        details: InviteResponse::from(inserted),
+    }))
+}
+
+/// Get a single invite with its uses
+pub async fn get_invite(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let invite_id = parse_invite_id(&path.into_inner())?;
+
+    let invite = app_state
+        .invites
+        .find_invite(&invite_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Invite not found"))?;
+
+    Ok(HttpResponse::Ok().json(InviteResponse::from(invite)))
+}
+
+/// Delete (revoke) an invite. Users who already signed up with it are not affected.
+pub async fn delete_invite(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let invite_id = parse_invite_id(&path.into_inner())?;
+
+    if !app_state.invites.delete_invite(&invite_id).await? {
+        return Err(AppError::not_found("Invite not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
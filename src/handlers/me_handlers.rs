@@ -0,0 +1,246 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::account_handlers::{check_email_available, send_verification_email};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::handlers::auth_handlers::{
+    check_not_locked, client_ip, record_failed_login, too_many_attempts,
+};
+use crate::handlers::user_handlers::remove_user;
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+use crate::models::one_time_token::TokenPurpose;
+use crate::models::user::User;
+use crate::repositories::user_repository::{ResourcePolicy, UserUpdate};
+use crate::state::app_state::AppState;
+use crate::structs::account::{
+    ChangePasswordRequest, DeleteAccountRequest, SessionResponse, UpdateProfileRequest,
+};
+use crate::structs::user::{DeletePolicy, UserResponse};
+use crate::utils::jwt::Claims;
+
+/// The claims of the caller and their user record
+pub async fn current_user(app_state: &AppState, req: &HttpRequest) -> AppResult<(Claims, User)> {
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+    let object_id = ObjectId::parse_str(&claims.user_id).context("Invalid user ID in token")?;
+
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    Ok((claims, user))
+}
+
+/// Check the caller's password before a sensitive change.
+/// Wrong passwords count towards the same throttling and lockout as failed logins.
+pub async fn confirm_password(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user: &User,
+    password: &str,
+) -> AppResult<()> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let ip = client_ip(app_state, req);
+
+    if let Some(retry_after) = app_state.login_throttle.retry_after(&ip) {
+        return Err(too_many_attempts(retry_after));
+    }
+    check_not_locked(user, now)?;
+
+    if !app_state
+        .passwords
+        .verify(password, &user.password_hash)
+        .await?
+    {
+        record_failed_login(app_state, req, user, now).await?;
+        return Err(AppError::forbidden("Current password is incorrect"));
+    }
+
+    Ok(())
+}
+
+/// Get the caller's profile
+pub async fn get_me(app_state: web::Data<AppState>, req: HttpRequest) -> AppResult<HttpResponse> {
+    let (_, user) = current_user(&app_state, &req).await?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::from(user)))
+}
+
+/// Update the caller's name and email address. A new address is sent a verification email.
+/// Password resets are mailed to the address, so changing it takes the current password.
+pub async fn update_me(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<UpdateProfileRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()?;
+    let (_, user) = current_user(&app_state, &req).await?;
+    let object_id = user.id.context("User has no ID")?;
+
+    // Resubmitting the current address keeps its verification
+    let email = body
+        .email
+        .filter(|email| user.email.as_ref() != Some(email));
+    let email_changed = email.is_some();
+    if let Some(email) = &email {
+        let current_password = body.current_password.as_deref().ok_or_else(|| {
+            AppError::bad_request("current_password is required to change the email address")
+        })?;
+        confirm_password(&app_state, &req, &user, current_password).await?;
+        check_email_available(&app_state, email, Some(&object_id)).await?;
+    }
+
+    let update = UserUpdate {
+        email,
+        full_name: body.full_name,
+        ..Default::default()
+    };
+    let updated_user = app_state
+        .users
+        .update(&object_id, &update)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    if email_changed {
+        send_verification_email(&app_state, &updated_user).await?;
+    }
+
+    let response = UserResponse::from(updated_user);
+    let event = AuditEvent::new(
+        AuditAction::UserUpdate,
+        AuditTarget::User,
+        object_id.to_hex(),
+    )
+    .with_changes(Some(&UserResponse::from(user)), Some(&response));
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Change the caller's password. Every session, including the current one, is signed out.
+pub async fn change_password(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<ChangePasswordRequest>,
+) -> AppResult<HttpResponse> {
+    app_state
+        .policy
+        .validate_password("new_password", &body.new_password)?;
+    let (claims, user) = current_user(&app_state, &req).await?;
+    let object_id = user.id.context("User has no ID")?;
+
+    confirm_password(&app_state, &req, &user, &body.current_password).await?;
+
+    let password_hash = app_state.passwords.hash(&body.new_password).await?;
+    let update = UserUpdate {
+        password_hash: Some(password_hash),
+        ..Default::default()
+    };
+    app_state.users.update(&object_id, &update).await?;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    app_state
+        .tokens
+        .revoke_user_tokens(&claims.user_id, now)
+        .await?;
+    app_state
+        .tokens
+        .invalidate_one_time_tokens(&claims.user_id, TokenPurpose::PasswordReset, now)
+        .await?;
+
+    let event = AuditEvent::new(AuditAction::UserUpdate, AuditTarget::User, &claims.user_id)
+        .with_redacted_change("password");
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List the caller's active login sessions
+pub async fn get_sessions(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let (claims, _) = current_user(&app_state, &req).await?;
+
+    let sessions: Vec<SessionResponse> = app_state
+        .tokens
+        .find_active_refresh_tokens(&claims.user_id, chrono::Utc::now().timestamp_millis())
+        .await?
+        .into_iter()
+        .map(|token| SessionResponse::new(token, &claims.sid))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Sign out one of the caller's sessions. Its refresh token stops working; access tokens
+/// already issued for other sessions stay valid until they expire.
+pub async fn delete_session(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let session_id = path.into_inner();
+    let (claims, _) = current_user(&app_state, &req).await?;
+    let now = chrono::Utc::now().timestamp_millis();
+
+    // Only sessions of the caller can be revoked
+    let sessions = app_state
+        .tokens
+        .find_active_refresh_tokens(&claims.user_id, now)
+        .await?;
+    if !sessions.iter().any(|token| token.family_id == session_id) {
+        return Err(AppError::not_found("Session not found"));
+    }
+
+    app_state
+        .tokens
+        .revoke_refresh_family(&session_id, now)
+        .await?;
+
+    // Signing out the current session also ends the access token of this request
+    if session_id == claims.sid {
+        app_state
+            .tokens
+            .revoke_access_token(&claims.jti, claims.exp as i64 * 1000)
+            .await?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete the caller's own account after confirming the password
+pub async fn delete_me(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<DeleteAccountRequest>,
+) -> AppResult<HttpResponse> {
+    let (_, user) = current_user(&app_state, &req).await?;
+
+    confirm_password(&app_state, &req, &user, &body.password).await?;
+
+    let policy = match body.policy {
+        DeletePolicy::Reassign => {
+            return Err(AppError::bad_request(
+                "Links of a deleted account can only be disabled or deleted",
+            ));
+        }
+        DeletePolicy::Disable => ResourcePolicy::Disable,
+        DeletePolicy::Delete => ResourcePolicy::Delete,
+    };
+    remove_user(&app_state, &user, &policy).await?;
+
+    let response = UserResponse::from(user);
+    let event = AuditEvent::new(AuditAction::UserDelete, AuditTarget::User, &response.id)
+        .with_changes(Some(&response), None);
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
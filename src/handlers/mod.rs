@@ -1,5 +1,14 @@
+pub mod account_handlers;
+pub mod api_key_handlers;
+pub mod audit_handlers;
 pub mod auth_handlers;
 pub mod health_handlers;
+pub mod invite_handlers;
+pub mod me_handlers;
+pub mod oidc_handlers;
+pub mod passkey_handlers;
 pub mod qr_handlers;
+pub mod totp_handlers;
 pub mod url_handlers;
 pub mod user_handlers;
+pub mod workspace_handlers;
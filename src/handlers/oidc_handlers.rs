@@ -0,0 +1,340 @@
+use actix_web::cookie::{Cookie, SameSite, time};
+use actix_web::{HttpRequest, HttpResponse, http::header, web};
+use anyhow::Context;
+use serde::Deserialize;
+use validator::ValidationErrors;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::handlers::auth_handlers::{LoginResponse, issue_tokens};
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+use crate::models::identity::ExternalIdentity;
+use crate::models::user::{Role, User};
+use crate::repositories::errors::is_duplicate_key;
+use crate::state::app_state::AppState;
+use crate::structs::user::UserResponse;
+use crate::utils::jwt::OIDC_STATE_LIFETIME_MINUTES;
+use crate::utils::oidc::{IdTokenClaims, OidcClient, code_verifier};
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// Cookie that carries the state of a login while the browser is at the identity provider
+const STATE_COOKIE: &str = "oidc_state";
+const STATE_COOKIE_PATH: &str = "/api/auth/oidc";
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+fn oidc_client(app_state: &AppState) -> AppResult<&OidcClient> {
+    app_state
+        .oidc
+        .as_ref()
+        .ok_or_else(|| AppError::not_found("Single sign-on is not configured"))
+}
+
+fn state_cookie(app_state: &AppState, value: String) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, value)
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        // Lax, so the cookie comes along when the provider redirects back
+        .same_site(SameSite::Lax)
+        .secure(app_state.config.oidc_redirect_url().starts_with("https://"))
+        .max_age(time::Duration::minutes(OIDC_STATE_LIFETIME_MINUTES))
+        .finish()
+}
+
+/// Start a login at the identity provider. The browser is redirected to the
+/// provider, which sends it back to `oidc_callback`.
+pub async fn oidc_login(app_state: web::Data<AppState>) -> AppResult<HttpResponse> {
+    let oidc = oidc_client(&app_state)?;
+
+    let state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = code_verifier();
+
+    let authorization_url = oidc
+        .authorization_url(&state, &nonce, &code_verifier)
+        .await?;
+    let state_token = app_state
+        .jwt
+        .create_oidc_state_token(&state, &nonce, &code_verifier)?;
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, authorization_url))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .cookie(state_cookie(&app_state, state_token))
+        .finish())
+}
+
+/// Finish a login at the identity provider: exchange the authorization code, find or
+/// create the user of the identity and issue the service's own tokens
+pub async fn oidc_callback(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Query(query): web::Query<CallbackQuery>,
+) -> AppResult<HttpResponse> {
+    let oidc = oidc_client(&app_state)?;
+
+    if let Some(error) = query.error {
+        log::info!(
+            "Identity provider refused the login: {} {}",
+            error,
+            query.error_description.unwrap_or_default()
+        );
+        return Err(AppError::unauthorized(format!(
+            "The identity provider refused the login: {}",
+            error
+        )));
+    }
+
+    // The state must match the login started in this browser
+    let state = req
+        .cookie(STATE_COOKIE)
+        .and_then(|cookie| app_state.jwt.validate_oidc_state_token(cookie.value()).ok())
+        .filter(|state| query.state.as_deref() == Some(state.state.as_str()))
+        .ok_or_else(|| AppError::bad_request("Invalid or expired login state, log in again"))?;
+    let code = query
+        .code
+        .ok_or_else(|| AppError::bad_request("Missing authorization code"))?;
+
+    let claims = oidc
+        .exchange_code(&code, &state.code_verifier, &state.nonce)
+        .await
+        .map_err(|e| {
+            log::warn!("Single sign-on failed: {:#}", e);
+            AppError::unauthorized("Login with the identity provider failed")
+        })?;
+
+    let user = find_or_create_user(&app_state, oidc.issuer(), &claims).await?;
+    let object_id = user.id.context("User has no ID")?;
+
+    // Disabled accounts cannot log in through the provider either
+    if !user.is_active {
+        return Err(AppError::forbidden("This account is disabled"));
+    }
+
+    // The provider authenticated the user, so the local second factor is not asked for
+    let now = chrono::Utc::now().timestamp_millis();
+    let tokens = issue_tokens(&app_state, &user, None, None).await?;
+    app_state.users.set_last_login(&object_id, now).await?;
+    app_state
+        .identities
+        .set_identity_login(oidc.issuer(), &claims.sub, now)
+        .await?;
+
+    let event = AuditEvent::new(
+        AuditAction::AuthLogin,
+        AuditTarget::User,
+        object_id.to_hex(),
+    )
+    .with_actor(object_id.to_hex(), &user.username);
+    record_audit_event(&app_state, &req, event).await;
+
+    let mut removal = state_cookie(&app_state, String::new());
+    removal.make_removal();
+
+    // Browsers are sent on to the frontend, with the tokens in the fragment so they stay out of logs
+    if let Some(frontend_url) = &app_state.config.oidc.frontend_url {
+        let location = format!(
+            "{}#token={}&refresh_token={}",
+            frontend_url, tokens.token, tokens.refresh_token
+        );
+        return Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, location))
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .cookie(removal)
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .cookie(removal)
+        .json(LoginResponse {
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
+            user: UserResponse::from(user),
+        }))
+}
+
+/// The user an identity logs in as. An unknown identity is linked to the user with the
+/// same verified email address, or gets a new user, depending on the configuration.
+async fn find_or_create_user(
+    app_state: &AppState,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> AppResult<User> {
+    if let Some(identity) = app_state
+        .identities
+        .find_identity(issuer, &claims.sub)
+        .await?
+    {
+        let object_id = mongodb::bson::oid::ObjectId::parse_str(&identity.user_id)
+            .context("Invalid user ID in identity")?;
+        return app_state
+            .users
+            .find_by_id(&object_id)
+            .await?
+            .ok_or_else(|| AppError::forbidden("The linked account no longer exists"));
+    }
+
+    let oidc_config = &app_state.config.oidc;
+    let email = claims.email.as_deref().filter(|_| claims.email_verified);
+
+    // Both sides must have verified the address, or anyone could claim an account by its email
+    if oidc_config.link_by_email
+        && let Some(email) = email
+    {
+        let mut users: Vec<User> = app_state
+            .users
+            .find_by_email(email)
+            .await?
+            .into_iter()
+            .filter(|user| user.email_verified_at.is_some())
+            .collect();
+        if users.len() > 1 {
+            return Err(AppError::conflict(
+                "Several accounts have this email address, ask an admin to link your identity",
+            ));
+        }
+        if let Some(user) = users.pop() {
+            link_identity(app_state, &user, issuer, claims).await?;
+            log::info!(
+                "Linked identity {} of {} to user {}",
+                claims.sub,
+                issuer,
+                user.username
+            );
+            return Ok(user);
+        }
+    }
+
+    if !oidc_config.create_users {
+        return Err(AppError::forbidden(
+            "No account is linked to this identity, ask an admin to create one",
+        ));
+    }
+
+    create_user(app_state, issuer, claims).await
+}
+
+async fn link_identity(
+    app_state: &AppState,
+    user: &User,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> AppResult<()> {
+    let user_id = user.id.context("User has no ID")?.to_hex();
+    let identity = ExternalIdentity::new(
+        user_id,
+        issuer.to_string(),
+        claims.sub.clone(),
+        claims.email.clone(),
+    );
+
+    // Two logins of the same new identity at once; the first one wins
+    app_state
+        .identities
+        .insert_identity(&identity)
+        .await
+        .map_err(|e| {
+            if is_duplicate_key(&e) {
+                AppError::conflict("This identity was just linked, log in again")
+            } else {
+                AppError::Internal(e.context("Failed to link identity"))
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Create a member for a new identity. It has no password, so it can only log in
+/// through the provider.
+async fn create_user(
+    app_state: &AppState,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> AppResult<User> {
+    let username = pick_username(app_state, issuer, claims).await?;
+
+    // An address another account already has is left out rather than failing the login
+    let mut email = claims.email.clone();
+    if let Some(address) = &email
+        && app_state.policy.unique_emails()
+        && !app_state.users.find_by_email(address).await?.is_empty()
+    {
+        email = None;
+    }
+
+    let user = User::new(
+        username,
+        email.clone(),
+        claims.claim("name").map(str::to_string),
+        String::new(),
+        Role::Member,
+    );
+    let mut inserted = app_state.users.insert(&user).await.map_err(|e| {
+        if is_duplicate_key(&e) {
+            AppError::conflict("Username already exists, log in again")
+        } else {
+            AppError::Internal(e.context("Failed to create user"))
+        }
+    })?;
+    let object_id = inserted.id.context("User has no ID")?;
+
+    if let Some(email) = email.filter(|_| claims.email_verified) {
+        let now = chrono::Utc::now().timestamp_millis();
+        app_state
+            .users
+            .set_email_verified(&object_id, &email, now)
+            .await?;
+        inserted.email_verified_at = Some(now);
+    }
+
+    link_identity(app_state, &inserted, issuer, claims).await?;
+    log::info!(
+        "Created user {} for identity {} of {}",
+        inserted.username,
+        claims.sub,
+        issuer
+    );
+
+    Ok(inserted)
+}
+
+/// Username for a new user: the configured claim, else the local part of the email
+/// address, else one derived from the identity. The first that the policy allows
+/// and nobody has taken is used.
+async fn pick_username(
+    app_state: &AppState,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> AppResult<String> {
+    let derived = format!(
+        "user-{}",
+        &hash_token(&format!("{} {}", issuer, claims.sub))[..12]
+    );
+    let candidates = [
+        claims.claim(&app_state.config.oidc.username_claim),
+        claims
+            .email
+            .as_deref()
+            .and_then(|email| email.split('@').next()),
+        Some(derived.as_str()),
+    ];
+
+    for candidate in candidates.into_iter().flatten() {
+        let mut errors = ValidationErrors::new();
+        app_state.policy.check_username(candidate, &mut errors);
+        if errors.is_empty() && app_state.users.find_by_username(candidate).await?.is_none() {
+            return Ok(candidate.to_string());
+        }
+    }
+
+    Err(AppError::conflict(
+        "No valid username could be chosen for this identity, ask an admin to create the account",
+    ))
+}
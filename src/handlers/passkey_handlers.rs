@@ -0,0 +1,289 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::auth_handlers::{
+    check_not_locked, client_ip, complete_login, record_failed_ip, record_failed_login,
+    require_password_login, too_many_attempts,
+};
+use crate::handlers::me_handlers::{confirm_password, current_user};
+use crate::handlers::totp_handlers::confirm_second_factor;
+use crate::models::passkey::Passkey;
+use crate::repositories::errors::is_duplicate_key;
+use crate::state::app_state::AppState;
+use crate::structs::passkey::{
+    PasskeyLoginRequest, PasskeyOptionsResponse, PasskeyResponse, RegisterPasskeyRequest,
+};
+use crate::utils::jwt::{
+    Claims, PASSKEY_CHALLENGE_LIFETIME_MINUTES, PasskeyCeremony, PasskeyChallengeClaims,
+};
+use crate::utils::webauthn::{generate_challenge, normalize_credential_id};
+
+fn current_claims(req: &HttpRequest) -> AppResult<Claims> {
+    req.extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("User claims not found in request")))
+}
+
+/// Start a ceremony: a fresh challenge, carried to the second step in a signed token
+fn new_challenge(
+    app_state: &AppState,
+    ceremony: PasskeyCeremony,
+    user_id: &str,
+) -> AppResult<(String, String)> {
+    let challenge = generate_challenge();
+    let challenge_token = app_state
+        .jwt
+        .create_passkey_challenge_token(ceremony, user_id, &challenge)?;
+
+    Ok((challenge, challenge_token))
+}
+
+/// Check the challenge token of a ceremony and use it up, so a ceremony's result
+/// cannot be replayed
+async fn take_challenge(
+    app_state: &AppState,
+    ceremony: PasskeyCeremony,
+    token: &str,
+) -> AppResult<PasskeyChallengeClaims> {
+    let claims = app_state
+        .jwt
+        .validate_passkey_challenge_token(ceremony, token)
+        .map_err(|_| AppError::unauthorized("Invalid or expired challenge token"))?;
+
+    // Using the token up and checking it was unused is one step, so two requests racing
+    // with the same challenge cannot both get through
+    if !app_state
+        .tokens
+        .revoke_access_token(&claims.jti, claims.exp as i64 * 1000)
+        .await?
+    {
+        return Err(AppError::unauthorized("Invalid or expired challenge token"));
+    }
+
+    Ok(claims)
+}
+
+/// The user handle the authenticator stores with a passkey: the raw bytes of the user ID
+fn user_handle(object_id: &ObjectId) -> [u8; 12] {
+    object_id.bytes()
+}
+
+/// List the current user's passkeys
+pub async fn get_passkeys(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let claims = current_claims(&req)?;
+
+    let passkeys: Vec<PasskeyResponse> = app_state
+        .passkeys
+        .find_passkeys_by_user(&claims.user_id)
+        .await?
+        .into_iter()
+        .map(PasskeyResponse::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(passkeys))
+}
+
+/// First registration step: the options for `navigator.credentials.create()`
+pub async fn passkey_registration_options(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let claims = current_claims(&req)?;
+    let object_id = ObjectId::parse_str(&claims.user_id).context("Invalid user ID in token")?;
+
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    let existing = app_state
+        .passkeys
+        .find_passkeys_by_user(&claims.user_id)
+        .await?;
+
+    let (challenge, challenge_token) =
+        new_challenge(&app_state, PasskeyCeremony::Registration, &claims.user_id)?;
+    let public_key = app_state.webauthn.creation_options(
+        &challenge,
+        &user_handle(&object_id),
+        &user.username,
+        user.full_name.as_deref().unwrap_or(&user.username),
+        &existing,
+    );
+
+    Ok(HttpResponse::Ok().json(PasskeyOptionsResponse {
+        challenge_token,
+        public_key,
+        expires_in: PASSKEY_CHALLENGE_LIFETIME_MINUTES * 60,
+    }))
+}
+
+/// Second registration step: verify the new credential and store it. A passkey logs in
+/// without the password or TOTP, so adding one takes both, like turning TOTP off.
+pub async fn register_passkey(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<RegisterPasskeyRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()?;
+    let (claims, user) = current_user(&app_state, &req).await?;
+
+    // Checked before the challenge is used up, so a mistyped password can be corrected
+    confirm_password(&app_state, &req, &user, &body.password).await?;
+    let totp = app_state
+        .totp
+        .find_totp(&claims.user_id)
+        .await?
+        .filter(|credential| credential.enabled);
+    if let Some(credential) = totp {
+        let code = body.code.as_deref().ok_or_else(|| {
+            AppError::bad_request("code is required when two-factor authentication is enabled")
+        })?;
+        confirm_second_factor(&app_state, &req, &user, &credential, code).await?;
+    }
+
+    let challenge = take_challenge(
+        &app_state,
+        PasskeyCeremony::Registration,
+        &body.challenge_token,
+    )
+    .await?;
+    // The ceremony must have been started by the same user
+    if challenge.sub != claims.user_id {
+        return Err(AppError::unauthorized("Invalid or expired challenge token"));
+    }
+
+    let verified = app_state
+        .webauthn
+        .verify_registration(&body.credential, &challenge.challenge)
+        .map_err(|e| {
+            log::info!("Passkey registration failed: {:#}", e);
+            AppError::bad_request(format!("Invalid passkey: {}", e))
+        })?;
+
+    let passkey = Passkey::new(
+        claims.user_id,
+        verified.credential_id,
+        body.name.trim().to_string(),
+        verified.public_key,
+        verified.sign_count as i64,
+        body.credential.response.transports,
+    );
+    let inserted = app_state
+        .passkeys
+        .insert_passkey(&passkey)
+        .await
+        .map_err(|e| {
+            if is_duplicate_key(&e) {
+                AppError::conflict("This passkey is already registered")
+            } else {
+                AppError::Internal(e.context("Failed to store passkey"))
+            }
+        })?;
+
+    Ok(HttpResponse::Created().json(PasskeyResponse::from(inserted)))
+}
+
+/// Remove one of the current user's passkeys
+pub async fn delete_passkey(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let claims = current_claims(&req)?;
+    let passkey_id = ObjectId::parse_str(path.into_inner())
+        .map_err(|_| AppError::bad_request("Invalid passkey ID"))?;
+
+    if !app_state
+        .passkeys
+        .delete_passkey(&claims.user_id, &passkey_id)
+        .await?
+    {
+        return Err(AppError::not_found("Passkey not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// First login step: the options for `navigator.credentials.get()`
+pub async fn passkey_login_options(app_state: web::Data<AppState>) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+
+    // The user is not known until the authenticator answers
+    let (challenge, challenge_token) = new_challenge(&app_state, PasskeyCeremony::Login, "")?;
+
+    Ok(HttpResponse::Ok().json(PasskeyOptionsResponse {
+        challenge_token,
+        public_key: app_state.webauthn.request_options(&challenge),
+        expires_in: PASSKEY_CHALLENGE_LIFETIME_MINUTES * 60,
+    }))
+}
+
+/// Second login step: verify the assertion and issue the same tokens as a password login.
+/// A passkey with user verification already is two factors, so TOTP is not asked for.
+pub async fn login_passkey(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    web::Json(req): web::Json<PasskeyLoginRequest>,
+) -> AppResult<HttpResponse> {
+    require_password_login(&app_state)?;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let ip = client_ip(&app_state, &http_req);
+
+    if let Some(retry_after) = app_state.login_throttle.retry_after(&ip) {
+        return Err(too_many_attempts(retry_after));
+    }
+
+    let challenge =
+        take_challenge(&app_state, PasskeyCeremony::Login, &req.challenge_token).await?;
+
+    let credential_id = normalize_credential_id(&req.credential.raw_id)
+        .map_err(|_| AppError::bad_request("Invalid credential ID"))?;
+    let Some(passkey) = app_state.passkeys.find_passkey(&credential_id).await? else {
+        record_failed_ip(&app_state, &ip);
+        return Err(AppError::unauthorized("Unknown passkey"));
+    };
+    let object_id = ObjectId::parse_str(&passkey.user_id).context("Invalid user ID in passkey")?;
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Unknown passkey"))?;
+
+    check_not_locked(&user, now)?;
+
+    let sign_count = match app_state.webauthn.verify_authentication(
+        &req.credential,
+        &challenge.challenge,
+        &passkey,
+        &user_handle(&object_id),
+    ) {
+        Ok(sign_count) => sign_count,
+        Err(e) => {
+            log::info!("Passkey login of user {} failed: {:#}", user.username, e);
+            record_failed_login(&app_state, &http_req, &user, now).await?;
+            return Err(AppError::unauthorized("Passkey verification failed"));
+        }
+    };
+
+    // Only the holder of the passkey gets this far, so unlike a password login this can
+    // tell them why
+    if !user.is_active {
+        return Err(AppError::unauthorized("This account is disabled"));
+    }
+
+    app_state
+        .passkeys
+        .record_passkey_use(&credential_id, sign_count as i64, now)
+        .await?;
+
+    complete_login(&app_state, &http_req, user, now).await
+}
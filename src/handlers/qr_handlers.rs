@@ -1,23 +1,41 @@
-use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, Result, error, web};
-use mongodb::bson::doc;
-use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use anyhow::Context;
 use qrcode::QrCode as QrCodeGenerator;
 use qrcode::render::svg;
 use validator::Validate;
 
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::handlers::url_handlers::{can_see_destination, find_url_to_modify, search_matches_code};
+use crate::handlers::workspace_handlers::{can_modify, current_workspace};
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
 use crate::models::qr_code::{QrCode as QrCodeModel, TargetType};
-use crate::models::url::ShortenedUrl;
+use crate::models::workspace::{Workspace, WorkspaceRole};
+use crate::repositories::qr_code_repository::QrCodeFilter;
 use crate::state::app_state::AppState;
 use crate::structs::qr_request::{CreateQrRequest, RegenerateQrParams};
 use crate::structs::qr_request::{QrCodeResponse, QrSearchParams};
 use crate::utils::jwt::Claims;
-use futures_util::TryStreamExt;
 
+/// Render data as an SVG QR code of at least `size` pixels square
+pub fn render_qr_svg(data: &str, size: u32) -> anyhow::Result<String> {
+    let qr_code = QrCodeGenerator::new(data.as_bytes()).context("Failed to generate QR code")?;
+
+    Ok(qr_code
+        .render::<svg::Color>()
+        .min_dimensions(size, size)
+        .quiet_zone(true)
+        .build())
+}
+
+/// Get the QR code of a short URL the caller may change, generating it if there is none
+/// yet or `force` is set
 pub async fn regenerate_qr(
     app_state: web::Data<AppState>,
+    req: HttpRequest,
     path: web::Path<String>,
     query: web::Query<RegenerateQrParams>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     let code = path.into_inner();
     let force = query.force.unwrap_or(false);
 
@@ -27,101 +45,60 @@ pub async fn regenerate_qr(
         _ => TargetType::Shortened,
     };
 
-    let db = &app_state.db;
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
-    let qr_codes_collection = db.collection::<QrCodeModel>("qr_codes");
-
-    // Find the URL by short code
-    let url_doc = urls_collection
-        .find_one(doc! {"short_code": &code})
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
-
-    match url_doc {
-        Some(url) => {
-            // Check if URL has expired
-            if url.is_expired() {
-                return Ok(HttpResponse::Gone().json(serde_json::json!({
-                    "error": "This QR code has expired"
-                })));
-            }
+    // Only the URL's owner, the editors of its workspace and admins, like changing the URL
+    let url = find_url_to_modify(&app_state, &req, &code).await?;
 
-            // Check if QR code already exists and if force=false, return existing QR
-            if !force {
-                let existing_qr = qr_codes_collection
-                    .find_one(doc! {
-                        "short_code": &code,
-                        "target_type": match target_type {
-                            TargetType::Original => "original",
-                            TargetType::Shortened => "shortened",
-                        }
-                    })
-                    .await
-                    .map_err(|e| {
-                        error::ErrorInternalServerError(format!("Database error: {}", e))
-                    })?;
-
-                if let Some(qr) = existing_qr {
-                    return Ok(HttpResponse::Ok()
-                        .content_type("image/svg+xml")
-                        .body(qr.svg_content));
-                }
-            }
+    // Check if URL has expired
+    if url.is_expired() {
+        return Err(AppError::gone("This QR code has expired"));
+    }
 
-            // Generate QR code
-            let target_url = match target_type {
-                TargetType::Original => url.original_url.clone(),
-                TargetType::Shortened => {
-                    let host = std::env::var("HOST")
-                        .unwrap_or_else(|_| String::from("http://localhost:8080"));
-                    format!("{}/r/{}", host, code)
-                }
-            };
-
-            let qr_code = QrCodeGenerator::new(target_url.as_bytes()).map_err(|e| {
-                error::ErrorInternalServerError(format!("QR code generation error: {}", e))
-            })?;
-
-            let svg_output = qr_code
-                .render::<svg::Color>()
-                .min_dimensions(200, 200)
-                .quiet_zone(true)
-                .build();
-
-            // Save the regenerated SVG
-            let find_options = FindOneAndUpdateOptions::builder()
-                .return_document(ReturnDocument::After)
-                .build();
-
-            // Update or insert QR code
-            qr_codes_collection
-                .find_one_and_update(
-                    doc! {
-                        "short_code": &code,
-                        "target_type": match target_type {
-                            TargetType::Original => "original",
-                            TargetType::Shortened => "shortened",
-                        }
-                    },
-                    doc! {
-                        "$set": {
-                            "svg_content": &svg_output,
-                            "generated_at": chrono::Utc::now().timestamp_millis(),
-                        }
-                    },
-                )
-                .with_options(find_options)
-                .await
-                .map_err(|e| {
-                    error::ErrorInternalServerError(format!("Failed to update QR code: {}", e))
-                })?;
-
-            Ok(HttpResponse::Ok()
-                .content_type("image/svg+xml")
-                .body(svg_output))
-        }
-        None => Ok(HttpResponse::NotFound().body("URL not found")),
+    // Links of deleted users may be kept but disabled
+    if url.disabled_at.is_some() {
+        return Err(AppError::gone("This URL has been disabled"));
+    }
+
+    // Check if QR code already exists and if force=false, return existing QR
+    let existing_qr = app_state.qr_codes.find_by_code(&code, &target_type).await?;
+    if !force && let Some(qr) = existing_qr {
+        return Ok(HttpResponse::Ok()
+            .content_type("image/svg+xml")
+            .body(qr.svg_content));
     }
+
+    // Generate QR code. The QR code of a password-protected URL leads to the
+    // short URL either way, or anyone holding it could skip the password.
+    let target_url = match target_type {
+        TargetType::Original if url.password_hash.is_none() => url.original_url.clone(),
+        _ => app_state.config.short_url(&code),
+    };
+
+    let svg_output = render_qr_svg(&target_url, 200)?;
+
+    // Update or insert QR code
+    let target_id = format!("{}/{}", code, target_type.as_str());
+    let qr_model = QrCodeModel::new(
+        code,
+        url.original_url,
+        svg_output.clone(),
+        target_type,
+        url.user_id,
+        url.workspace_id,
+    );
+
+    app_state.qr_codes.upsert(&qr_model).await?;
+
+    let generated_at = |qr: &QrCodeModel| serde_json::json!({ "generated_at": qr.generated_at });
+    let event = AuditEvent::new(AuditAction::QrRegenerate, AuditTarget::QrCode, target_id)
+        .with_changes(
+            existing_qr.as_ref().map(generated_at).as_ref(),
+            Some(&generated_at(&qr_model)),
+        );
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(svg_output))
 }
 
 /// Generate QR code directly from a URL without requiring a short code
@@ -129,100 +106,75 @@ pub async fn generate_direct_qr(
     app_state: web::Data<AppState>,
     req: HttpRequest,
     web::Json(req_body): web::Json<CreateQrRequest>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     // Validate the URL
-    if let Err(errors) = req_body.validate() {
-        return Ok(HttpResponse::BadRequest().json(errors));
-    }
+    req_body.validate()?;
 
-    // Get user ID from request extensions
-    let user_id = req
-        .extensions()
-        .get::<Claims>()
-        .map(|claims| claims.user_id.clone());
-
-    let db = &app_state.db;
-    let qr_codes_collection = db.collection::<QrCodeModel>("qr_codes");
+    // Get the user and, if switched to one, the workspace that will own the QR code
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+    let workspace_id = current_workspace(&app_state, &claims, WorkspaceRole::Editor).await?;
 
     // First check if we already have a QR code for this URL
-    let existing_qr = qr_codes_collection
-        .find_one(doc! {
-            "original_url": &req_body.url,
-            "short_code": { "$regex": "^direct-" }, // Find direct QR codes
-            "target_type": "original"
-        })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let existing_qr = app_state.qr_codes.find_direct_by_url(&req_body.url).await?;
 
     // Check if QR exists and handle regeneration
-    let has_existing_qr = existing_qr.is_some();
-    if has_existing_qr {
-        if !req_body.force_regenerate.unwrap_or(false) {
-            return Ok(HttpResponse::Ok()
-                .content_type("image/svg+xml")
-                .body(existing_qr.unwrap().svg_content));
-        }
+    if let Some(qr) = &existing_qr
+        && !req_body.force_regenerate.unwrap_or(false)
+    {
+        return Ok(HttpResponse::Ok()
+            .content_type("image/svg+xml")
+            .body(qr.svg_content.clone()));
     }
 
     // Set dimensions (default or from request)
     let dimensions = req_body.size.unwrap_or(200);
 
     // Generate QR code
-    let qr_code = QrCodeGenerator::new(req_body.url.as_bytes())
-        .map_err(|e| error::ErrorInternalServerError(format!("QR code generation error: {}", e)))?;
-
-    // Render as SVG
-    let svg_output = qr_code
-        .render::<svg::Color>()
-        .min_dimensions(dimensions, dimensions)
-        .quiet_zone(true)
-        .build();
-
-    // Generate a unique ID for this direct QR code
-    let unique_id = format!(
-        "direct-{}",
-        uuid::Uuid::new_v4().to_string().split('-').next().unwrap()
-    );
+    let svg_output = render_qr_svg(&req_body.url, dimensions)?;
+
+    // Regenerating keeps the ID and owner of the existing QR code, so only those who may
+    // change it can; otherwise a new direct QR code gets a unique ID
+    let (unique_id, user_id, workspace_id) = match existing_qr {
+        Some(qr) => {
+            if !can_modify(
+                &app_state,
+                &claims,
+                qr.user_id.as_deref(),
+                qr.workspace_id.as_deref(),
+            )
+            .await?
+            {
+                return Err(AppError::forbidden(
+                    "You do not have permission to regenerate this QR code",
+                ));
+            }
+            (qr.short_code, qr.user_id, qr.workspace_id)
+        }
+        None => (
+            format!(
+                "direct-{}",
+                uuid::Uuid::new_v4().to_string().split('-').next().unwrap()
+            ),
+            Some(claims.user_id.clone()),
+            workspace_id,
+        ),
+    };
 
     // Create the QR code model
     let qr_model = QrCodeModel::new(
-        unique_id.clone(),
+        unique_id,
         req_body.url.clone(),
         svg_output.clone(),
         TargetType::Original, // Direct QR codes always point to the original URL
-        user_id.clone(),
+        user_id,
+        workspace_id,
     );
 
     // Save the QR code to the database (upsert if it already exists)
-    if existing_qr.is_some() {
-        // Update existing QR code
-        qr_codes_collection
-            .update_one(
-                doc! {
-                    "original_url": &req_body.url,
-                    "short_code": { "$regex": "^direct-" },
-                    "target_type": "original"
-                },
-                doc! {
-                    "$set": {
-                        "svg_content": &svg_output,
-                        "generated_at": chrono::Utc::now().timestamp_millis(),
-                    }
-                },
-            )
-            .await
-            .map_err(|e| {
-                error::ErrorInternalServerError(format!("Failed to update QR code: {}", e))
-            })?;
-    } else {
-        // Insert new QR code
-        qr_codes_collection
-            .insert_one(&qr_model)
-            .await
-            .map_err(|e| {
-                error::ErrorInternalServerError(format!("Failed to save QR code: {}", e))
-            })?;
-    }
+    app_state.qr_codes.upsert(&qr_model).await?;
 
     // Return the SVG directly
     Ok(HttpResponse::Ok()
@@ -230,93 +182,118 @@ pub async fn generate_direct_qr(
         .body(svg_output))
 }
 
-/// Get all QR codes
-pub async fn get_all_qr_codes(
-    app_state: web::Data<AppState>,
-    req: HttpRequest,
-    query: web::Query<QrSearchParams>,
-) -> Result<impl Responder> {
-    let db = &app_state.db;
-    let qr_codes_collection = db.collection::<QrCodeModel>("qr_codes");
+/// Parse the target type query parameter, ignoring unknown values
+fn parse_target_type(target_type: Option<&str>) -> Option<TargetType> {
+    target_type.and_then(|target_type| target_type.parse().ok())
+}
 
-    // Get current user ID from request
-    let current_user_id = req
-        .extensions()
-        .get::<Claims>()
-        .map(|claims| claims.user_id.clone());
+/// Transform a QR code model into a response object
+fn to_qr_response(
+    qr: QrCodeModel,
+    current_user_id: &Option<String>,
+    current_workspace_id: &Option<String>,
+) -> QrCodeResponse {
+    let owned_by_current_user = match (current_user_id, &qr.user_id) {
+        (Some(current_id), Some(qr_id)) => current_id == qr_id,
+        _ => false,
+    } || (qr.workspace_id.is_some()
+        && &qr.workspace_id == current_workspace_id);
+    let is_direct = qr.short_code.starts_with("direct-");
+
+    QrCodeResponse {
+        id: qr.id.map_or_else(|| "".to_string(), |id| id.to_hex()),
+        short_code: qr.short_code,
+        original_url: Some(qr.original_url),
+        generated_at: qr.generated_at,
+        target_type: match qr.target_type {
+            TargetType::Original => "original".to_string(),
+            TargetType::Shortened => "shortened".to_string(),
+        },
+        is_direct,
+        owned_by_current_user,
+        user_id: qr.user_id,
+        workspace_id: qr.workspace_id,
+        svg_content: qr.svg_content,
+    }
+}
 
-    // Build filter based on search parameters
-    let mut filter = doc! {};
-
-    // Filter by search term if provided
-    if let Some(search) = &query.search {
-        if !search.is_empty() {
-            filter = doc! {
-                "$or": [
-                    { "short_code": { "$regex": search, "$options": "i" } },
-                    { "original_url": { "$regex": search, "$options": "i" } }
-                ]
-            };
+/// Transform QR code models into response objects, hiding the destination of
+/// password-protected URLs from those who may not change them
+async fn to_qr_responses(
+    app_state: &AppState,
+    qr_codes: Vec<QrCodeModel>,
+    claims: Option<&Claims>,
+    current_workspace_id: &Option<String>,
+    search: Option<&str>,
+) -> AppResult<Vec<QrCodeResponse>> {
+    let current_user_id = claims.map(|claims| claims.user_id.clone());
+    let mut responses = Vec::with_capacity(qr_codes.len());
+
+    for qr in qr_codes {
+        // Direct QR codes have no short URL, and so no password
+        let show_destination = match app_state.urls.find_by_code(&qr.short_code).await? {
+            Some(url) => can_see_destination(app_state, claims, &url).await?,
+            None => true,
+        };
+        if !show_destination && !search_matches_code(search, &qr.short_code) {
+            continue;
         }
-    }
 
-    // Filter by target type if provided
-    if let Some(target_type) = &query.target_type {
-        if target_type == "original" || target_type == "shortened" {
-            filter.insert("target_type", target_type);
+        let mut response = to_qr_response(qr, &current_user_id, current_workspace_id);
+        if !show_destination {
+            response.original_url = None;
         }
+        responses.push(response);
     }
 
-    // Filter direct QR codes if requested
-    if query.direct_only.unwrap_or(false) {
-        filter.insert("short_code", doc! { "$regex": "^direct-" });
-    }
+    Ok(responses)
+}
 
-    // Filter for user's own QR codes if requested
-    if query.owned_only.unwrap_or(false) {
-        if let Some(user_id) = &current_user_id {
-            filter.insert("user_id", user_id);
-        }
-    }
+/// Get all QR codes
+pub async fn get_all_qr_codes(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<QrSearchParams>,
+) -> AppResult<HttpResponse> {
+    // Get current user and workspace IDs from request
+    let claims = req.extensions().get::<Claims>().cloned();
+    let (current_user_id, current_workspace_id) = claims
+        .as_ref()
+        .map(|claims| (Some(claims.user_id.clone()), claims.workspace_id.clone()))
+        .unwrap_or_default();
 
-    // Find QR codes
-    let cursor = qr_codes_collection
-        .find(filter)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    // Build filter based on search parameters
+    let owned_only = query.owned_only.unwrap_or(false);
+    let filter = QrCodeFilter {
+        search: query.search.clone(),
+        target_type: parse_target_type(query.target_type.as_deref()),
+        direct_only: query.direct_only.unwrap_or(false),
+        // Filter for the current workspace's QR codes, or without one the user's own,
+        // if requested
+        user_id: if owned_only && current_workspace_id.is_none() {
+            current_user_id.clone()
+        } else {
+            None
+        },
+        workspace_id: if owned_only {
+            current_workspace_id.clone()
+        } else {
+            None
+        },
+    };
 
-    // Process results
-    let qr_codes = cursor
-        .try_collect::<Vec<QrCodeModel>>()
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    // Find QR codes
+    let qr_codes = app_state.qr_codes.find(&filter).await?;
 
     // Transform to response objects
-    let qr_responses: Vec<QrCodeResponse> = qr_codes
-        .into_iter()
-        .map(|qr| {
-            let owned_by_current_user = match (&current_user_id, &qr.user_id) {
-                (Some(current_id), Some(qr_id)) => current_id == qr_id,
-                _ => false,
-            };
-            let is_direct = qr.short_code.starts_with("direct-");
-
-            QrCodeResponse {
-                id: qr.id.map_or_else(|| "".to_string(), |id| id.to_hex()),
-                short_code: qr.short_code,
-                original_url: qr.original_url,
-                generated_at: qr.generated_at,
-                target_type: match qr.target_type {
-                    TargetType::Original => "original".to_string(),
-                    TargetType::Shortened => "shortened".to_string(),
-                },
-                is_direct,
-                owned_by_current_user,
-                user_id: qr.user_id,
-                svg_content: qr.svg_content,
-            }
-        })
-        .collect();
+    let qr_responses = to_qr_responses(
+        &app_state,
+        qr_codes,
+        claims.as_ref(),
+        &current_workspace_id,
+        query.search.as_deref(),
+    )
+    .await?;
 
     Ok(HttpResponse::Ok().json(qr_responses))
 }
@@ -327,94 +304,73 @@ pub async fn get_user_qr_codes(
     req: HttpRequest,
     path: web::Path<String>,
     query: web::Query<QrSearchParams>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     let user_id = path.into_inner();
-    let db = &app_state.db;
-    let qr_codes_collection = db.collection::<QrCodeModel>("qr_codes");
 
-    // Get current user ID from request
-    let current_user_id = req
-        .extensions()
-        .get::<Claims>()
-        .map(|claims| claims.user_id.clone());
+    // Get current user and workspace IDs from request
+    let claims = req.extensions().get::<Claims>().cloned();
+    let current_workspace_id = claims
+        .as_ref()
+        .and_then(|claims| claims.workspace_id.clone());
+
+    // Build filter combining the owner with the search parameters
+    let filter = QrCodeFilter {
+        search: query.search.clone(),
+        target_type: parse_target_type(query.target_type.as_deref()),
+        direct_only: query.direct_only.unwrap_or(false),
+        user_id: Some(user_id),
+        workspace_id: None,
+    };
 
-    // Build filter based on search parameters
-    let mut filter = doc! { "user_id": &user_id };
-
-    // Filter by search term if provided
-    if let Some(search) = &query.search {
-        if !search.is_empty() {
-            // Combine user_id with search
-            filter = doc! {
-                "$and": [
-                    { "user_id": &user_id },
-                    { "$or": [
-                        { "short_code": { "$regex": search, "$options": "i" } },
-                        { "original_url": { "$regex": search, "$options": "i" } }
-                    ]}
-                ]
-            };
-        }
-    }
+    // Find QR codes
+    let qr_codes = app_state.qr_codes.find(&filter).await?;
 
-    // Filter by target type if provided
-    if let Some(target_type) = &query.target_type {
-        if target_type == "original" || target_type == "shortened" {
-            if let Some(and_array) = filter.get_array_mut("$and").ok() {
-                and_array.push(doc! { "target_type": target_type }.into());
-            } else {
-                filter.insert("target_type", target_type);
-            }
-        }
-    }
+    // Transform to response objects
+    let qr_responses = to_qr_responses(
+        &app_state,
+        qr_codes,
+        claims.as_ref(),
+        &current_workspace_id,
+        query.search.as_deref(),
+    )
+    .await?;
 
-    // Filter direct QR codes if requested
-    if query.direct_only.unwrap_or(false) {
-        if let Some(and_array) = filter.get_array_mut("$and").ok() {
-            and_array.push(doc! { "short_code": { "$regex": "^direct-" } }.into());
-        } else {
-            filter.insert("short_code", doc! { "$regex": "^direct-" });
-        }
-    }
+    Ok(HttpResponse::Ok().json(qr_responses))
+}
 
-    // Find QR codes
-    let cursor = qr_codes_collection
-        .find(filter)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+/// List the QR codes owned by a workspace
+pub async fn get_workspace_qr_codes(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<QrSearchParams>,
+) -> AppResult<HttpResponse> {
+    // The workspace was loaded by the membership check of the route
+    let workspace_id = req
+        .extensions()
+        .get::<Workspace>()
+        .and_then(|workspace| workspace.id)
+        .map(|id| id.to_hex())
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Workspace not found in request")))?;
+    let claims = req.extensions().get::<Claims>().cloned();
+
+    let filter = QrCodeFilter {
+        search: query.search.clone(),
+        target_type: parse_target_type(query.target_type.as_deref()),
+        direct_only: query.direct_only.unwrap_or(false),
+        user_id: None,
+        workspace_id: Some(workspace_id.clone()),
+    };
 
-    // Process results
-    let qr_codes = cursor
-        .try_collect::<Vec<QrCodeModel>>()
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let qr_codes = app_state.qr_codes.find(&filter).await?;
 
-    // Transform to response objects
-    let qr_responses: Vec<QrCodeResponse> = qr_codes
-        .into_iter()
-        .map(|qr| {
-            let owned_by_current_user = match (&current_user_id, &qr.user_id) {
-                (Some(current_id), Some(qr_id)) => current_id == qr_id,
-                _ => false,
-            };
-
-            let short_code = qr.short_code.clone();
-            QrCodeResponse {
-                id: qr.id.map_or_else(|| "".to_string(), |id| id.to_hex()),
-                short_code: short_code.clone(),
-                original_url: qr.original_url,
-                generated_at: qr.generated_at,
-                target_type: match qr.target_type {
-                    TargetType::Original => "original".to_string(),
-                    TargetType::Shortened => "shortened".to_string(),
-                },
-                is_direct: short_code.starts_with("direct-"),
-                owned_by_current_user,
-                user_id: qr.user_id,
-                svg_content: qr.svg_content,
-            }
-        })
-        .collect();
+    let qr_responses = to_qr_responses(
+        &app_state,
+        qr_codes,
+        claims.as_ref(),
+        &Some(workspace_id),
+        query.search.as_deref(),
+    )
+    .await?;
 
     Ok(HttpResponse::Ok().json(qr_responses))
 }
@@ -0,0 +1,247 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::auth_handlers::record_failed_login;
+use crate::handlers::me_handlers::{confirm_password, current_user};
+use crate::handlers::qr_handlers::render_qr_svg;
+use crate::models::totp::TotpCredential;
+use crate::models::user::User;
+use crate::state::app_state::AppState;
+use crate::structs::totp::{
+    RecoveryCodesResponse, TotpCodeRequest, TotpConfirmRequest, TotpSetupResponse,
+    TotpStatusResponse,
+};
+use crate::utils::jwt::Claims;
+use crate::utils::tokens::{generate_token, hash_token};
+use crate::utils::totp;
+
+/// Number of recovery codes handed out when TOTP is enabled
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Check a second factor code: a TOTP code from the authenticator app, or an unused recovery code.
+/// Either can only be used once.
+pub async fn verify_second_factor(
+    app_state: &AppState,
+    credential: &TotpCredential,
+    code: &str,
+) -> AppResult<bool> {
+    let code = code.trim();
+
+    if code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit()) {
+        let now = chrono::Utc::now().timestamp();
+        let Some(step) = totp::verify(&credential.secret, code, now) else {
+            return Ok(false);
+        };
+        return Ok(app_state
+            .totp
+            .use_totp_step(&credential.user_id, step)
+            .await?);
+    }
+
+    let code_hash = hash_token(&normalize_recovery_code(code));
+    Ok(app_state
+        .totp
+        .use_recovery_code(&credential.user_id, &code_hash)
+        .await?)
+}
+
+/// Recovery codes are accepted regardless of case and dashes
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Generate a set of recovery codes, returning them together with the hashes to store
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let token = generate_token();
+            let code = format!("{}-{}", &token[..5], &token[5..10]);
+            let code_hash = hash_token(&normalize_recovery_code(&code));
+            (code, code_hash)
+        })
+        .unzip()
+}
+
+fn current_claims(req: &HttpRequest) -> AppResult<Claims> {
+    req.extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("User claims not found in request")))
+}
+
+/// Whether the current user has two-factor authentication enabled
+pub async fn get_totp_status(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let claims = current_claims(&req)?;
+
+    let credential = app_state.totp.find_totp(&claims.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(TotpStatusResponse::from(credential)))
+}
+
+/// Start enrolling an authenticator app. The new secret stays pending until confirmed with `enable`.
+pub async fn setup_totp(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let claims = current_claims(&req)?;
+
+    if let Some(credential) = app_state.totp.find_totp(&claims.user_id).await?
+        && credential.enabled
+    {
+        return Err(AppError::conflict(
+            "Two-factor authentication is already enabled",
+        ));
+    }
+
+    // Label the entry with the current username, not the one in a possibly stale token
+    let object_id = ObjectId::parse_str(&claims.user_id).context("Invalid user ID in token")?;
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    let secret = totp::generate_secret();
+    let provisioning_uri =
+        totp::provisioning_uri(&secret, &app_state.config.auth.totp_issuer, &user.username);
+    let qr_code_svg = render_qr_svg(&provisioning_uri, 200)?;
+
+    app_state
+        .totp
+        .save_pending_totp(&TotpCredential::new(claims.user_id, secret.clone()))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(TotpSetupResponse {
+        secret,
+        provisioning_uri,
+        qr_code_svg,
+    }))
+}
+
+/// Confirm the pending secret with a code from the authenticator app and turn on two-factor
+/// authentication. The recovery codes are only ever returned in this response.
+pub async fn enable_totp(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<TotpCodeRequest>,
+) -> AppResult<HttpResponse> {
+    let claims = current_claims(&req)?;
+
+    let credential = match app_state.totp.find_totp(&claims.user_id).await? {
+        Some(credential) if credential.enabled => {
+            return Err(AppError::conflict(
+                "Two-factor authentication is already enabled",
+            ));
+        }
+        Some(credential) => credential,
+        None => {
+            return Err(AppError::bad_request(
+                "Start the two-factor authentication setup first",
+            ));
+        }
+    };
+
+    if !verify_second_factor(&app_state, &credential, &body.code).await? {
+        return Err(AppError::bad_request("Invalid authentication code"));
+    }
+
+    let (recovery_codes, code_hashes) = generate_recovery_codes();
+    let enabled = app_state
+        .totp
+        .enable_totp(
+            &claims.user_id,
+            &code_hashes,
+            chrono::Utc::now().timestamp_millis(),
+        )
+        .await?;
+    if !enabled {
+        return Err(AppError::conflict(
+            "Two-factor authentication is already enabled",
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turn off two-factor authentication. Requires the password and a current code or a
+/// recovery code.
+pub async fn disable_totp(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<TotpConfirmRequest>,
+) -> AppResult<HttpResponse> {
+    let claims = confirm_both_factors(&app_state, &req, &body).await?;
+
+    app_state.totp.delete_totp(&claims.user_id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Replace the recovery codes, invalidating the old ones. Requires the password and a
+/// current code or a recovery code.
+pub async fn regenerate_recovery_codes(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<TotpConfirmRequest>,
+) -> AppResult<HttpResponse> {
+    let claims = confirm_both_factors(&app_state, &req, &body).await?;
+
+    let (recovery_codes, code_hashes) = generate_recovery_codes();
+    app_state
+        .totp
+        .set_recovery_codes(&claims.user_id, &code_hashes)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Check the caller's password and second factor before a change to two-factor
+/// authentication, so a stolen access token alone cannot turn it off. Wrong passwords
+/// and codes count towards the same throttling and lockout as failed logins.
+async fn confirm_both_factors(
+    app_state: &AppState,
+    req: &HttpRequest,
+    body: &TotpConfirmRequest,
+) -> AppResult<Claims> {
+    let (claims, user) = current_user(app_state, req).await?;
+    let credential = enabled_credential(app_state, &claims.user_id).await?;
+
+    confirm_password(app_state, req, &user, &body.password).await?;
+    confirm_second_factor(app_state, req, &user, &credential, &body.code).await?;
+
+    Ok(claims)
+}
+
+/// Check a second factor code of the caller, counting a wrong one as a failed login
+pub async fn confirm_second_factor(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user: &User,
+    credential: &TotpCredential,
+    code: &str,
+) -> AppResult<()> {
+    if !verify_second_factor(app_state, credential, code).await? {
+        let now = chrono::Utc::now().timestamp_millis();
+        record_failed_login(app_state, req, user, now).await?;
+        return Err(AppError::bad_request("Invalid authentication code"));
+    }
+
+    Ok(())
+}
+
+async fn enabled_credential(app_state: &AppState, user_id: &str) -> AppResult<TotpCredential> {
+    app_state
+        .totp
+        .find_totp(user_id)
+        .await?
+        .filter(|credential| credential.enabled)
+        .ok_or_else(|| AppError::bad_request("Two-factor authentication is not enabled"))
+}
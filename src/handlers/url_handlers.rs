@@ -1,313 +1,512 @@
-use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, Result, error, http, web};
-use futures_util::StreamExt;
-use mongodb::bson::doc;
+use actix_web::cookie::{Cookie, SameSite, time};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, http, web};
 use nanoid::nanoid;
 use validator::Validate;
 
-use crate::models::qr_code::{QrCode, TargetType};
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::handlers::auth_handlers::client_ip;
+use crate::handlers::workspace_handlers::{can_modify, can_view, current_workspace};
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+use crate::models::qr_code::TargetType;
 use crate::models::url::ShortenedUrl;
+use crate::models::url_version::UrlVersion;
 use crate::models::url_visitor::UrlVisitor;
+use crate::models::workspace::{Workspace, WorkspaceRole};
+use crate::repositories::errors::is_duplicate_key;
+use crate::repositories::url_repository::UrlFilter;
 use crate::state::app_state::AppState;
 use crate::structs::qr_request::QrRequest;
 use crate::structs::url_request::{
-    UrlAnalyticsResponse, UrlListResponse, UrlRequest, UrlResponse, UrlSearchParams,
+    RevertUrlRequest, UnlockUrlForm, UpdateUrlRequest, UrlAnalyticsResponse, UrlHistoryResponse,
+    UrlListResponse, UrlRequest, UrlResponse, UrlSearchParams, UrlVersionResponse,
 };
 use crate::utils::hash_ip::hash_ip;
-use crate::utils::jwt::Claims;
+use crate::utils::jwt::{Claims, LINK_ACCESS_LIFETIME_MINUTES};
+use crate::utils::tokens::hash_token;
+
+/// Number of times a randomly generated short code is retried after a collision
+const MAX_SHORT_CODE_ATTEMPTS: u32 = 5;
+
+/// Cookie that lets a visitor through to a password-protected URL after entering the password
+const LINK_ACCESS_COOKIE: &str = "link_access";
+
+/// Page that asks visitors of a password-protected URL for the password
+const PASSWORD_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Password required</title>
+</head>
+<body>
+<form method="post">
+<p>This link is protected by a password.</p>
+{error}<p><input type="password" name="password" aria-label="Password" required autofocus>
+<button type="submit">Continue</button></p>
+</form>
+</body>
+</html>
+"#;
 
 /// Create a shortened URL
 pub async fn create_short_url(
     app_state: web::Data<AppState>,
     req: HttpRequest,
     web::Json(req_body): web::Json<UrlRequest>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     // Validate the URL
-    if let Err(errors) = req_body.validate() {
-        return Ok(HttpResponse::BadRequest().json(errors));
-    }
-
-    // Get user ID from request extensions
-    let user_id = req
-        .extensions()
-        .get::<Claims>()
-        .map(|claims| claims.user_id.clone());
-
-    let db = &app_state.db;
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
+    req_body.validate()?;
+
+    // Get the user and, if switched to one, the workspace that will own the URL
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+    let workspace_id = current_workspace(&app_state, &claims, WorkspaceRole::Editor).await?;
+    let user_id = Some(claims.user_id);
+
+    // Use the custom code if provided, otherwise generate a random one
+    let custom_code = req_body.custom_code.filter(|code| !code.is_empty());
+
+    let password_hash = match req_body.password.filter(|password| !password.is_empty()) {
+        Some(password) => Some(app_state.passwords.hash(&password).await?),
+        None => None,
+    };
 
-    // Generate short code - either use custom or generate random
-    let short_code = match req_body.custom_code {
-        Some(code) if !code.is_empty() => {
-            // Check if custom code already exists
-            let existing = urls_collection
-                .find_one(doc! {"short_code": &code})
-                .await
-                .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    // Save to database, relying on the unique short code index to detect collisions.
+    // Generated codes are retried a few times; a taken custom code is reported as a conflict.
+    let mut attempts = 0;
+    let shortened_url = loop {
+        let short_code = custom_code.clone().unwrap_or_else(|| nanoid!(6));
+        let mut shortened_url = ShortenedUrl::new(
+            req_body.url.clone(),
+            short_code,
+            req_body.expires_in_days,
+            req_body.title.clone().filter(|title| !title.is_empty()),
+            user_id.clone(),
+            workspace_id.clone(),
+        );
+        shortened_url.password_hash = password_hash.clone();
+
+        match app_state.urls.insert(&shortened_url).await {
+            Ok(inserted) => break inserted,
+            Err(e) if is_duplicate_key(&e) => {
+                if custom_code.is_some() {
+                    return Err(AppError::conflict("Custom code already in use"));
+                }
 
-            if existing.is_some() {
-                return Ok(HttpResponse::Conflict().json(serde_json::json!({
-                    "error": "Custom code already in use"
-                })));
+                attempts += 1;
+                if attempts >= MAX_SHORT_CODE_ATTEMPTS {
+                    return Err(AppError::Internal(anyhow::anyhow!(
+                        "Failed to generate a unique short code after {} attempts",
+                        attempts
+                    )));
+                }
             }
-
-            code
+            Err(e) => return Err(e.into()),
         }
-        _ => nanoid!(6), // Generate a 6-character nanoid
     };
 
-    // Create new shortened URL
-    let shortened_url = ShortenedUrl::new(
-        req_body.url.clone(),
-        short_code.clone(),
-        req_body.expires_in_days,
-        user_id,
-    );
+    // Return response
+    let response = to_url_response(&app_state, shortened_url);
 
-    // Save to database
-    urls_collection
-        .insert_one(&shortened_url)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let event = AuditEvent::new(
+        AuditAction::UrlCreate,
+        AuditTarget::Url,
+        &response.short_code,
+    )
+    .with_changes(None, Some(&response));
+    record_audit_event(&app_state, &req, event).await;
 
-    // Construct the short URL
-    let host = std::env::var("HOST").unwrap_or_else(|_| String::from("http://localhost:8080"));
-    let short_url = format!("{}/r/{}", host, short_code);
+    Ok(HttpResponse::Created().json(response))
+}
 
-    // Return response
-    let response = UrlResponse {
-        original_url: req_body.url,
-        short_url,
-        short_code,
-        expires_at: shortened_url.expires_at,
-        user_id: shortened_url.user_id,
-    };
+fn to_url_response(app_state: &AppState, url: ShortenedUrl) -> UrlResponse {
+    UrlResponse {
+        short_url: app_state.config.short_url(&url.short_code),
+        original_url: url.original_url,
+        short_code: url.short_code,
+        expires_at: url.expires_at,
+        user_id: url.user_id,
+        workspace_id: url.workspace_id,
+        title: url.title,
+        version: url.version,
+        password_protected: url.password_hash.is_some(),
+    }
+}
 
-    Ok(HttpResponse::Created().json(response))
+/// Whether the caller may see where a URL leads. The destination of a password-protected
+/// URL is only shown to those who may change the URL; visitors need the password.
+pub async fn can_see_destination(
+    app_state: &AppState,
+    claims: Option<&Claims>,
+    url: &ShortenedUrl,
+) -> AppResult<bool> {
+    match (&url.password_hash, claims) {
+        (None, _) => Ok(true),
+        (Some(_), Some(claims)) => {
+            can_modify(
+                app_state,
+                claims,
+                url.user_id.as_deref(),
+                url.workspace_id.as_deref(),
+            )
+            .await
+        }
+        (Some(_), None) => Ok(false),
+    }
 }
 
-/// Redirect to original URL
+/// Whether a search term matches the short code. Searches also match the destination,
+/// so a URL whose destination is hidden is only listed if its short code matches, or
+/// searching would reveal the destination bit by bit.
+pub fn search_matches_code(search: Option<&str>, short_code: &str) -> bool {
+    search
+        .filter(|search| !search.is_empty())
+        .is_none_or(|search| short_code.to_lowercase().contains(&search.to_lowercase()))
+}
+
+/// Redirect to original URL. Visitors of a password-protected URL get a password form
+/// instead, unless they entered the password recently.
 pub async fn redirect_to_url(
     app_state: web::Data<AppState>,
     req: HttpRequest,
     path: web::Path<String>,
-) -> Result<impl Responder> {
-    let code = path.into_inner();
-    let db = &app_state.db;
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
+) -> AppResult<HttpResponse> {
+    let url = find_url_to_visit(&app_state, &path).await?;
 
-    // Find the URL by short code
-    let url_doc = urls_collection
-        .find_one(doc! {"short_code": &code})
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
-
-    match url_doc {
-        Some(url) => {
-            // Check if URL has expired
-            if url.is_expired() {
-                return Ok(HttpResponse::Gone().json(serde_json::json!({
-                    "error": "This URL has expired"
-                })));
-            }
+    if let Some(password_hash) = &url.password_hash
+        && !has_link_access(&app_state, &req, &url.short_code, password_hash)
+    {
+        return Ok(password_form(http::StatusCode::OK, None));
+    }
 
-            // Get visitor's IP address
-            let ip = req
-                .connection_info()
-                .realip_remote_addr()
-                .unwrap_or("unknown")
-                .to_string();
-
-            // Create a unique visitor identifier by hashing the IP
-            let visitor_hash = hash_ip(&ip);
-
-            // Get optional user agent and referrer
-            let user_agent = req
-                .headers()
-                .get(http::header::USER_AGENT)
-                .and_then(|v| v.to_str().ok())
-                .map(String::from);
-
-            let referrer = req
-                .headers()
-                .get(http::header::REFERER)
-                .and_then(|v| v.to_str().ok())
-                .map(String::from);
-
-            // Increment the click counter asynchronously
-            // We don't wait for the result to avoid slowing down the redirect
-            let original_url = url.original_url.clone();
-            let code_clone = code.clone();
-
-            let visitors_collection = db.collection::<UrlVisitor>("visitors");
-
-            // Update click count and unique visitors in the background
-            actix_web::rt::spawn(async move {
-                // Increment the click counter and add the visitor hash if it's new
-                let _ = urls_collection
-                    .update_one(
-                        doc! {"short_code": &code_clone},
-                        doc! {
-                            "$inc": {"clicks": 1},
-                        },
-                    )
-                    .await;
-
-                // Then, check if this visitor has already visited this URL
-                let existing_visitor = visitors_collection
-                    .find_one(doc! {
-                        "short_code": &code_clone,
-                        "visitor_hash": &visitor_hash
-                    })
-                    .await;
-
-                if let Ok(None) = existing_visitor {
-                    // If this is a new visitor, add to the visitors collection
-                    let visitor = UrlVisitor::new(code_clone, visitor_hash, user_agent, referrer);
-                    let _ = visitors_collection.insert_one(&visitor).await;
-                }
-            });
+    track_visit(&app_state, &req, &url.short_code);
 
-            Ok(HttpResponse::Found()
-                .append_header((http::header::LOCATION, original_url))
-                .finish())
-        }
-        None => Ok(HttpResponse::NotFound().body("Short URL not found")),
-    }
+    Ok(HttpResponse::Found()
+        .append_header((http::header::LOCATION, url.original_url))
+        .finish())
 }
 
-pub async fn get_all_urls(
+/// Check the password a visitor entered for a password-protected URL, and redirect to
+/// the original URL if it is right. Wrong passwords count towards a lockout of the
+/// client IP, like failed logins. They also delay the next attempt on the URL, so
+/// guesses spread over many addresses are slowed down as well; the URL itself is never
+/// locked, which would let anyone shut its visitors out.
+pub async fn unlock_url(
     app_state: web::Data<AppState>,
     req: HttpRequest,
-    query: web::Query<UrlSearchParams>,
-) -> Result<impl Responder> {
-    let db = &app_state.db;
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
-    let visitors_collection = db.collection::<UrlVisitor>("visitors");
-    let qr_codes_collection = db.collection::<QrCode>("qr_codes");
-
-    // Get current user ID from request
-    let current_user_id = req
-        .extensions()
-        .get::<Claims>()
-        .map(|claims| claims.user_id.clone());
+    path: web::Path<String>,
+    web::Form(form): web::Form<UnlockUrlForm>,
+) -> AppResult<HttpResponse> {
+    let url = find_url_to_visit(&app_state, &path).await?;
+    let ip = client_ip(&app_state, &req);
+
+    let mut response = HttpResponse::SeeOther();
+    if let Some(password_hash) = &url.password_hash {
+        // Checked before hashing, so throttled guesses cost no Argon2 time
+        let retry_after = app_state
+            .link_password_throttle
+            .retry_after(&ip)
+            .max(app_state.link_code_throttle.retry_after(&url.short_code));
+        if let Some(retry_after) = retry_after {
+            let mut response = password_form(
+                http::StatusCode::TOO_MANY_REQUESTS,
+                Some("Too many wrong passwords, please try again later."),
+            );
+            response
+                .headers_mut()
+                .insert(http::header::RETRY_AFTER, retry_after.into());
+            return Ok(response);
+        }
 
-    // Build filter
-    let mut filter = doc! {};
-
-    // Add search filter if provided
-    if let Some(search) = &query.search {
-        if !search.is_empty() {
-            filter = doc! {
-                "$or": [
-                    { "short_code": { "$regex": search, "$options": "i" } },
-                    { "original_url": { "$regex": search, "$options": "i" } }
-                ]
-            };
+        if !app_state
+            .passwords
+            .verify(&form.password, password_hash)
+            .await?
+        {
+            let auth_config = &app_state.config.auth;
+            let lockout_ms = auth_config.lockout_minutes * 60 * 1000;
+            app_state.link_password_throttle.record_failure(
+                &ip,
+                auth_config.max_failed_logins_per_ip,
+                lockout_ms,
+            );
+            app_state
+                .link_code_throttle
+                .record_delay(&url.short_code, lockout_ms);
+            return Ok(password_form(
+                http::StatusCode::UNAUTHORIZED,
+                Some("Wrong password, please try again."),
+            ));
         }
+
+        let token = app_state
+            .jwt
+            .create_link_access_token(&url.short_code, &hash_token(password_hash))?;
+        response.cookie(link_access_cookie(&app_state, &req, &url.short_code, token));
     }
 
-    // Filter for user's own URLs if requested
-    if query.owned_only.unwrap_or(false) {
-        if let Some(user_id) = &current_user_id {
-            filter.insert("user_id", user_id);
-        }
+    track_visit(&app_state, &req, &url.short_code);
+
+    Ok(response
+        .append_header((http::header::LOCATION, url.original_url))
+        .finish())
+}
+
+/// Find a URL that can be visited: it exists, has not expired and is not disabled
+async fn find_url_to_visit(app_state: &AppState, code: &str) -> AppResult<ShortenedUrl> {
+    let url = app_state
+        .urls
+        .find_by_code(code)
+        .await?
+        .ok_or_else(|| AppError::not_found("Short URL not found"))?;
+
+    // Check if URL has expired
+    if url.is_expired() {
+        return Err(AppError::gone("This URL has expired"));
     }
 
-    // Find URLs matching the filter
-    let mut cursor = urls_collection
-        .find(filter)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    // Links of deleted users may be kept but disabled
+    if url.disabled_at.is_some() {
+        return Err(AppError::gone("This URL has been disabled"));
+    }
 
-    let mut urls = Vec::new();
+    Ok(url)
+}
 
-    while let Some(result) = cursor.next().await {
-        if let Ok(url) = result {
-            // Convert ObjectId to string
-            let id_str = url.id.map(|oid| oid.to_hex());
+/// Whether the visitor has a valid access cookie for the URL. Cookies issued before the
+/// password was changed no longer match its hash.
+fn has_link_access(
+    app_state: &AppState,
+    req: &HttpRequest,
+    code: &str,
+    password_hash: &str,
+) -> bool {
+    req.cookie(LINK_ACCESS_COOKIE)
+        .and_then(|cookie| {
+            app_state
+                .jwt
+                .validate_link_access_token(cookie.value())
+                .ok()
+        })
+        .is_some_and(|claims| claims.sub == code && claims.pwd == hash_token(password_hash))
+}
 
-            // Get the short code
-            let short_code = url.short_code.clone();
+fn link_access_cookie(
+    app_state: &AppState,
+    req: &HttpRequest,
+    code: &str,
+    token: String,
+) -> Cookie<'static> {
+    Cookie::build(LINK_ACCESS_COOKIE, token)
+        // Only sent along to this short URL, so every URL has a cookie of its own
+        .path(req.path().to_string())
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(app_state.config.short_url(code).starts_with("https://"))
+        .max_age(time::Duration::minutes(LINK_ACCESS_LIFETIME_MINUTES))
+        .finish()
+}
 
-            // Count unique visitors for this URL
-            let unique_visitor_count = visitors_collection
-                .count_documents(doc! {"short_code": &short_code})
-                .await
-                .unwrap_or(0) as usize;
+fn password_form(status: http::StatusCode, error: Option<&str>) -> HttpResponse {
+    let error = error
+        .map(|error| format!("<p role=\"alert\">{error}</p>\n"))
+        .unwrap_or_default();
 
-            // Check if QR codes exist for this URL
-            let has_shortened_qr = qr_codes_collection
-                .count_documents(doc! {
-                    "short_code": &short_code,
-                    "target_type": "shortened"
-                })
-                .await
-                .unwrap_or(0)
-                > 0;
-
-            let has_original_qr = qr_codes_collection
-                .count_documents(doc! {
-                    "short_code": &short_code,
-                    "target_type": "original"
-                })
-                .await
-                .unwrap_or(0)
-                > 0;
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .append_header((http::header::CACHE_CONTROL, "no-store"))
+        .body(PASSWORD_FORM.replace("{error}", &error))
+}
 
-            // Determine if this URL is owned by the current user
-            let owned_by_current_user = match (&current_user_id, &url.user_id) {
-                (Some(current_id), Some(url_id)) => current_id == url_id,
-                _ => false,
-            };
+/// Count a click and record the visitor in the background,
+/// so the redirect is not slowed down
+fn track_visit(app_state: &web::Data<AppState>, req: &HttpRequest, code: &str) {
+    // Create a unique visitor identifier by hashing the IP
+    let visitor_hash = hash_ip(&client_ip(app_state, req));
+
+    // Get optional user agent and referrer
+    let user_agent = req
+        .headers()
+        .get(http::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+
+    let referrer = req
+        .headers()
+        .get(http::header::REFERER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+
+    let app_state = app_state.clone();
+    let code = code.to_string();
+
+    actix_web::rt::spawn(async move {
+        // Increment the click counter
+        let _ = app_state.urls.increment_clicks(&code).await;
+
+        // Record the visitor if they haven't visited this URL before
+        let visitor = UrlVisitor::new(code, visitor_hash, user_agent, referrer);
+        let _ = app_state.visitors.insert_if_new(&visitor).await;
+    });
+}
 
-            urls.push(UrlListResponse {
-                id: id_str,
-                original_url: url.original_url,
-                short_code,
-                created_at: url.created_at,
-                expires_at: url.expires_at,
-                has_shortened_qr,
-                has_original_qr,
-                clicks: url.clicks,
-                unique_clicks: unique_visitor_count,
-                owned_by_current_user,
-                user_id: url.user_id,
-            });
+/// Build list entries for URLs, including visitor counts and QR code availability
+async fn build_url_list(
+    app_state: &AppState,
+    urls: Vec<ShortenedUrl>,
+    claims: Option<&Claims>,
+    current_user_id: Option<String>,
+    current_workspace_id: Option<String>,
+    search: Option<&str>,
+) -> Vec<UrlListResponse> {
+    let mut responses = Vec::with_capacity(urls.len());
+
+    for url in urls {
+        // Hide the destination when in doubt
+        let show_destination = can_see_destination(app_state, claims, &url)
+            .await
+            .unwrap_or(false);
+        if !show_destination && !search_matches_code(search, &url.short_code) {
+            continue;
         }
+
+        // Convert ObjectId to string
+        let id_str = url.id.map(|oid| oid.to_hex());
+
+        // Get the short code
+        let short_code = url.short_code.clone();
+
+        // Count unique visitors for this URL
+        let unique_visitor_count = app_state
+            .visitors
+            .count_by_code(&short_code)
+            .await
+            .unwrap_or(0) as usize;
+
+        // Check if QR codes exist for this URL
+        let has_shortened_qr = app_state
+            .qr_codes
+            .find_by_code(&short_code, &TargetType::Shortened)
+            .await
+            .ok()
+            .flatten()
+            .is_some();
+
+        let has_original_qr = app_state
+            .qr_codes
+            .find_by_code(&short_code, &TargetType::Original)
+            .await
+            .ok()
+            .flatten()
+            .is_some();
+
+        // Determine if this URL is owned by the current user or their current workspace
+        let owned_by_current_user = match (&current_user_id, &url.user_id) {
+            (Some(current_id), Some(url_id)) => current_id == url_id,
+            _ => false,
+        } || (url.workspace_id.is_some()
+            && url.workspace_id == current_workspace_id);
+
+        responses.push(UrlListResponse {
+            id: id_str,
+            original_url: show_destination.then_some(url.original_url),
+            short_code,
+            created_at: url.created_at,
+            expires_at: url.expires_at,
+            has_shortened_qr,
+            has_original_qr,
+            clicks: url.clicks,
+            unique_clicks: unique_visitor_count,
+            owned_by_current_user,
+            user_id: url.user_id,
+            workspace_id: url.workspace_id,
+            title: url.title,
+            version: url.version,
+            disabled_at: url.disabled_at,
+            password_protected: url.password_hash.is_some(),
+        });
     }
 
+    responses
+}
+
+pub async fn get_all_urls(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<UrlSearchParams>,
+) -> AppResult<HttpResponse> {
+    // Get current user and workspace IDs from request
+    let claims = req.extensions().get::<Claims>().cloned();
+    let (current_user_id, current_workspace_id) = claims
+        .as_ref()
+        .map(|claims| (Some(claims.user_id.clone()), claims.workspace_id.clone()))
+        .unwrap_or_default();
+
+    // Build filter, restricting to the current workspace's URLs, or without one the
+    // user's own URLs, if requested
+    let owned_only = query.owned_only.unwrap_or(false);
+    let filter = UrlFilter {
+        search: query.search.clone(),
+        user_id: if owned_only && current_workspace_id.is_none() {
+            current_user_id.clone()
+        } else {
+            None
+        },
+        workspace_id: if owned_only {
+            current_workspace_id.clone()
+        } else {
+            None
+        },
+    };
+
+    // Find URLs matching the filter
+    let urls = app_state.urls.find(&filter).await?;
+
+    let urls = build_url_list(
+        &app_state,
+        urls,
+        claims.as_ref(),
+        current_user_id,
+        current_workspace_id,
+        query.search.as_deref(),
+    )
+    .await;
+
     Ok(HttpResponse::Ok().json(urls))
 }
 
-/// Get QR code as SVG
 /// Get QR code as SVG
 pub async fn get_qr_code_direct(
     app_state: web::Data<AppState>,
+    req: HttpRequest,
     path: web::Path<String>,
     query: web::Query<QrRequest>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     let code = path.into_inner();
-    let db = &app_state.db;
 
     // Determine target type from query parameter
-    let target_type = match query.url_type.as_deref() {
+    let mut target_type = match query.url_type.as_deref() {
         Some("original") => TargetType::Original,
         _ => TargetType::Shortened,
     };
 
-    let qr_codes_collection = db.collection::<QrCode>("qr_codes");
+    // Those who may not see the destination of a password-protected URL get the QR code
+    // of the short URL, which asks for the password
+    if target_type == TargetType::Original
+        && let Some(url) = app_state.urls.find_by_code(&code).await?
+    {
+        let claims = req.extensions().get::<Claims>().cloned();
+        if !can_see_destination(&app_state, claims.as_ref(), &url).await? {
+            target_type = TargetType::Shortened;
+        }
+    }
 
     // Find the QR code by short code and target type
-    let qr_doc = qr_codes_collection
-        .find_one(doc! {
-            "short_code": &code,
-            "target_type": match target_type {
-                TargetType::Original => "original",
-                TargetType::Shortened => "shortened",
-            }
-        })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let qr_doc = app_state.qr_codes.find_by_code(&code, &target_type).await?;
 
     match qr_doc {
         Some(qr) => {
@@ -316,50 +515,41 @@ pub async fn get_qr_code_direct(
                 .content_type("image/svg+xml")
                 .body(qr.svg_content))
         }
-        None => Ok(HttpResponse::NotFound().body("QR code not found for this URL")),
+        None => Err(AppError::not_found("QR code not found for this URL")),
     }
 }
 
 /// Get analytics for a specific URL
 pub async fn get_url_analytics(
     app_state: web::Data<AppState>,
+    req: HttpRequest,
     path: web::Path<String>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     let code = path.into_inner();
-    let db = &app_state.db;
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
-    let visitors_collection = db.collection::<UrlVisitor>("visitors");
-    let qr_codes_collection = db.collection::<QrCode>("qr_codes");
 
     // Find the URL by short code
-    let url_doc = urls_collection
-        .find_one(doc! {"short_code": &code})
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let url_doc = app_state.urls.find_by_code(&code).await?;
 
     match url_doc {
         Some(url) => {
+            let claims = req.extensions().get::<Claims>().cloned();
+            let show_destination = can_see_destination(&app_state, claims.as_ref(), &url).await?;
+
             // Count unique visitors for this URL
-            let unique_visitor_count = visitors_collection
-                .count_documents(doc! {"short_code": &code})
-                .await
-                .unwrap_or(0) as usize;
+            let unique_visitor_count =
+                app_state.visitors.count_by_code(&code).await.unwrap_or(0) as usize;
 
             // Check if QR codes exist for this URL
-            let shortened_qr = qr_codes_collection
-                .find_one(doc! {
-                    "short_code": &code,
-                    "target_type": "shortened"
-                })
+            let shortened_qr = app_state
+                .qr_codes
+                .find_by_code(&code, &TargetType::Shortened)
                 .await
                 .ok()
                 .flatten();
 
-            let original_qr = qr_codes_collection
-                .find_one(doc! {
-                    "short_code": &code,
-                    "target_type": "original"
-                })
+            let original_qr = app_state
+                .qr_codes
+                .find_by_code(&code, &TargetType::Original)
                 .await
                 .ok()
                 .flatten();
@@ -372,7 +562,7 @@ pub async fn get_url_analytics(
 
             let analytics = UrlAnalyticsResponse {
                 short_code: url.short_code,
-                original_url: url.original_url,
+                original_url: show_destination.then_some(url.original_url),
                 created_at: url.created_at,
                 expires_at: url.expires_at,
                 clicks: url.clicks,
@@ -382,13 +572,16 @@ pub async fn get_url_analytics(
                 shortened_qr_generated_at,
                 original_qr_generated_at,
                 user_id: url.user_id,
+                workspace_id: url.workspace_id,
+                title: url.title,
+                version: url.version,
+                disabled_at: url.disabled_at,
+                password_protected: url.password_hash.is_some(),
             };
 
             Ok(HttpResponse::Ok().json(analytics))
         }
-        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "error": "URL not found"
-        }))),
+        None => Err(AppError::not_found("URL not found")),
     }
 }
 
@@ -398,100 +591,72 @@ pub async fn get_user_urls(
     req: HttpRequest,
     path: web::Path<String>,
     query: web::Query<UrlSearchParams>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     let user_id = path.into_inner();
-    let db = &app_state.db;
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
-    let visitors_collection = db.collection::<UrlVisitor>("visitors");
-    let qr_codes_collection = db.collection::<QrCode>("qr_codes");
 
-    // Get current user ID from request
-    let current_user_id = req
-        .extensions()
-        .get::<Claims>()
-        .map(|claims| claims.user_id.clone());
-
-    // Build filter
-    let mut filter = doc! { "user_id": &user_id };
-
-    // Add search filter if provided
-    if let Some(search) = &query.search {
-        if !search.is_empty() {
-            // This is a bit more complex now - we need to combine user_id with search
-            filter = doc! {
-                "$and": [
-                    { "user_id": &user_id },
-                    { "$or": [
-                        { "short_code": { "$regex": search, "$options": "i" } },
-                        { "original_url": { "$regex": search, "$options": "i" } }
-                    ]}
-                ]
-            };
-        }
-    }
+    // Get current user and workspace IDs from request
+    let claims = req.extensions().get::<Claims>().cloned();
+    let (current_user_id, current_workspace_id) = claims
+        .as_ref()
+        .map(|claims| (Some(claims.user_id.clone()), claims.workspace_id.clone()))
+        .unwrap_or_default();
+
+    // Build filter combining the owner with the optional search term
+    let filter = UrlFilter {
+        search: query.search.clone(),
+        user_id: Some(user_id),
+        workspace_id: None,
+    };
 
     // Find URLs matching the filter
-    let mut cursor = urls_collection
-        .find(filter)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    let urls = app_state.urls.find(&filter).await?;
+
+    let urls = build_url_list(
+        &app_state,
+        urls,
+        claims.as_ref(),
+        current_user_id,
+        current_workspace_id,
+        query.search.as_deref(),
+    )
+    .await;
 
-    let mut urls = Vec::new();
-
-    while let Some(result) = cursor.next().await {
-        if let Ok(url) = result {
-            // Convert ObjectId to string
-            let id_str = url.id.map(|oid| oid.to_hex());
-
-            // Get the short code
-            let short_code = url.short_code.clone();
-
-            // Count unique visitors for this URL
-            let unique_visitor_count = visitors_collection
-                .count_documents(doc! {"short_code": &short_code})
-                .await
-                .unwrap_or(0) as usize;
+    Ok(HttpResponse::Ok().json(urls))
+}
 
-            // Check if QR codes exist for this URL
-            let has_shortened_qr = qr_codes_collection
-                .count_documents(doc! {
-                    "short_code": &short_code,
-                    "target_type": "shortened"
-                })
-                .await
-                .unwrap_or(0)
-                > 0;
-
-            let has_original_qr = qr_codes_collection
-                .count_documents(doc! {
-                    "short_code": &short_code,
-                    "target_type": "original"
-                })
-                .await
-                .unwrap_or(0)
-                > 0;
+/// List the URLs owned by a workspace
+pub async fn get_workspace_urls(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<UrlSearchParams>,
+) -> AppResult<HttpResponse> {
+    // The workspace was loaded by the membership check of the route
+    let workspace_id = req
+        .extensions()
+        .get::<Workspace>()
+        .and_then(|workspace| workspace.id)
+        .map(|id| id.to_hex())
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Workspace not found in request")))?;
+    let claims = req.extensions().get::<Claims>().cloned();
+    let current_user_id = claims.as_ref().map(|claims| claims.user_id.clone());
+
+    let filter = UrlFilter {
+        search: query.search.clone(),
+        user_id: None,
+        workspace_id: Some(workspace_id.clone()),
+    };
 
-            // Determine if this URL is owned by the current user
-            let owned_by_current_user = match (&current_user_id, &url.user_id) {
-                (Some(current_id), Some(url_id)) => current_id == url_id,
-                _ => false,
-            };
+    let urls = app_state.urls.find(&filter).await?;
 
-            urls.push(UrlListResponse {
-                id: id_str,
-                original_url: url.original_url,
-                short_code,
-                created_at: url.created_at,
-                expires_at: url.expires_at,
-                has_shortened_qr,
-                has_original_qr,
-                clicks: url.clicks,
-                unique_clicks: unique_visitor_count,
-                owned_by_current_user,
-                user_id: url.user_id,
-            });
-        }
-    }
+    let urls = build_url_list(
+        &app_state,
+        urls,
+        claims.as_ref(),
+        current_user_id,
+        Some(workspace_id),
+        query.search.as_deref(),
+    )
+    .await;
 
     Ok(HttpResponse::Ok().json(urls))
 }
@@ -501,51 +666,261 @@ pub async fn delete_short_url(
     app_state: web::Data<AppState>,
     req: HttpRequest,
     path: web::Path<String>,
-) -> Result<impl Responder> {
+) -> AppResult<HttpResponse> {
     let code = path.into_inner();
-    let db = &app_state.db;
-
-    let urls_collection = db.collection::<ShortenedUrl>("urls");
-    let qr_codes_collection = db.collection::<QrCode>("qr_codes");
-    let visitors_collection = db.collection::<UrlVisitor>("visitors");
 
     // Get the current user's ID from the token claims
-    let extensions = req.extensions();
-    let claims = extensions
-        .get::<Claims>()
-        .ok_or_else(|| error::ErrorInternalServerError("User claims not found in request"))?;
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
 
     // Find the URL to be deleted
-    let url_to_delete = urls_collection
-        .find_one(doc! { "short_code": &code })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorNotFound("URL not found"))?;
+    let url_to_delete = app_state
+        .urls
+        .find_by_code(&code)
+        .await?
+        .ok_or_else(|| AppError::not_found("URL not found"))?;
 
     // --- Ownership Check ---
-    // Ensure the user deleting the URL is the one who created it
-    if url_to_delete.user_id.as_deref() != Some(&claims.user_id) {
-        // You could also allow admins to delete any URL here
-        return Err(error::ErrorForbidden("You do not have permission to delete this URL"));
+    // Ensure the user deleting the URL created it or edits the workspace that owns it
+    // (admins may delete any URL)
+    if !can_modify(
+        &app_state,
+        &claims,
+        url_to_delete.user_id.as_deref(),
+        url_to_delete.workspace_id.as_deref(),
+    )
+    .await?
+    {
+        return Err(AppError::forbidden(
+            "You do not have permission to delete this URL",
+        ));
     }
 
     // Delete the URL document
-    urls_collection
-        .delete_one(doc! { "_id": url_to_delete.id })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to delete URL: {}", e)))?;
+    app_state.urls.delete_by_code(&code).await?;
 
     // Delete associated QR codes
-    qr_codes_collection
-        .delete_many(doc! { "short_code": &code })
-        .await
-        .ok(); // Use .ok() to ignore errors if deletion fails
+    app_state.qr_codes.delete_by_code(&code).await.ok(); // Use .ok() to ignore errors if deletion fails
 
     // Delete associated visitor analytics
-    visitors_collection
-        .delete_many(doc! { "short_code": &code })
-        .await
-        .ok(); // Use .ok() to ignore errors if deletion fails
+    app_state.visitors.delete_by_code(&code).await.ok(); // Use .ok() to ignore errors if deletion fails
+
+    let deleted = to_url_response(&app_state, url_to_delete);
+    let event = AuditEvent::new(
+        AuditAction::UrlDelete,
+        AuditTarget::Url,
+        &deleted.short_code,
+    )
+    .with_changes(Some(&deleted), None);
+    record_audit_event(&app_state, &req, event).await;
 
     Ok(HttpResponse::NoContent().finish())
 }
+
+/// Change the destination, expiry, title or password of a shortened URL
+pub async fn update_short_url(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    web::Json(req_body): web::Json<UpdateUrlRequest>,
+) -> AppResult<HttpResponse> {
+    req_body.validate()?;
+    if let Some(Some(expires_at)) = req_body.expires_at
+        && expires_at <= chrono::Utc::now().timestamp_millis()
+    {
+        return Err(AppError::bad_request("expires_at must be in the future"));
+    }
+
+    let url = find_url_to_modify(&app_state, &req, &path).await?;
+    let mut edited = url.clone();
+    if let Some(original_url) = req_body.url {
+        edited.original_url = original_url;
+    }
+    if let Some(expires_at) = req_body.expires_at {
+        edited.expires_at = expires_at;
+    }
+    if let Some(title) = req_body.title {
+        edited.title = title.filter(|title| !title.is_empty());
+    }
+    if let Some(password) = req_body.password {
+        // Setting a password, even the same one again, locks out visitors who entered the old one
+        edited.password_hash = match password.filter(|password| !password.is_empty()) {
+            Some(password) => Some(app_state.passwords.hash(&password).await?),
+            None => None,
+        };
+    }
+
+    apply_url_edit(&app_state, &req, url, edited).await
+}
+
+/// List the earlier versions of a shortened URL, for its owner or the members of the
+/// workspace that owns it
+pub async fn get_url_history(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let code = path.into_inner();
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+
+    let url = app_state
+        .urls
+        .find_by_code(&code)
+        .await?
+        .ok_or_else(|| AppError::not_found("URL not found"))?;
+
+    if !can_view(
+        &app_state,
+        &claims,
+        url.user_id.as_deref(),
+        url.workspace_id.as_deref(),
+    )
+    .await?
+    {
+        return Err(AppError::forbidden(
+            "You do not have permission to view the history of this URL",
+        ));
+    }
+
+    let versions = app_state.urls.find_versions(&code).await?;
+
+    // Earlier destinations of a password-protected URL are as secret as the current one
+    let show_destination = can_see_destination(&app_state, Some(&claims), &url).await?;
+    let versions = versions
+        .into_iter()
+        .map(UrlVersionResponse::from)
+        .map(|mut version| {
+            if !show_destination {
+                version.original_url = None;
+            }
+            version
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(UrlHistoryResponse {
+        short_code: url.short_code,
+        version: url.version,
+        versions,
+    }))
+}
+
+/// Restore the destination and title of an earlier version of a shortened URL.
+/// The restored state becomes a new version; the expiry and password are left as they are.
+pub async fn revert_short_url(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    web::Json(req_body): web::Json<RevertUrlRequest>,
+) -> AppResult<HttpResponse> {
+    let url = find_url_to_modify(&app_state, &req, &path).await?;
+    let version = app_state
+        .urls
+        .find_versions(&url.short_code)
+        .await?
+        .into_iter()
+        .find(|version| version.version == req_body.version)
+        .ok_or_else(|| AppError::not_found("Version not found"))?;
+
+    let mut edited = url.clone();
+    edited.original_url = version.original_url;
+    edited.title = version.title;
+
+    apply_url_edit(&app_state, &req, url, edited).await
+}
+
+/// Find a URL the caller may change: one they created, one of a workspace they edit,
+/// or any URL for admins
+pub async fn find_url_to_modify(
+    app_state: &AppState,
+    req: &HttpRequest,
+    code: &str,
+) -> AppResult<ShortenedUrl> {
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
+
+    let url = app_state
+        .urls
+        .find_by_code(code)
+        .await?
+        .ok_or_else(|| AppError::not_found("URL not found"))?;
+
+    if !can_modify(
+        app_state,
+        &claims,
+        url.user_id.as_deref(),
+        url.workspace_id.as_deref(),
+    )
+    .await?
+    {
+        return Err(AppError::forbidden(
+            "You do not have permission to modify this URL",
+        ));
+    }
+
+    Ok(url)
+}
+
+/// Store an edited URL as its next version. QR codes encoding the old destination are
+/// deleted, so they are generated anew when requested.
+async fn apply_url_edit(
+    app_state: &AppState,
+    req: &HttpRequest,
+    url: ShortenedUrl,
+    mut edited: ShortenedUrl,
+) -> AppResult<HttpResponse> {
+    // Nothing changed, so there is no new version either
+    if edited.original_url == url.original_url
+        && edited.expires_at == url.expires_at
+        && edited.title == url.title
+        && edited.password_hash == url.password_hash
+    {
+        return Ok(HttpResponse::Ok().json(to_url_response(app_state, url)));
+    }
+
+    let user_id = req
+        .extensions()
+        .get::<Claims>()
+        .map(|claims| claims.user_id.clone());
+    let previous = UrlVersion::of(&url, user_id);
+    edited.version = url.version + 1;
+
+    if !app_state
+        .urls
+        .update_with_history(&edited, &previous)
+        .await?
+    {
+        return Err(AppError::conflict(
+            "The URL was changed at the same time, please try again",
+        ));
+    }
+
+    // QR codes of the destination are replaced by ones of the short URL while the URL
+    // is protected by a password, and the other way round
+    if edited.original_url != url.original_url
+        || edited.password_hash.is_some() != url.password_hash.is_some()
+    {
+        app_state
+            .qr_codes
+            .retarget(&edited.short_code, &edited.original_url)
+            .await?;
+    }
+
+    let password_changed = edited.password_hash != url.password_hash;
+    let before = to_url_response(app_state, url);
+    let after = to_url_response(app_state, edited);
+    let mut event = AuditEvent::new(AuditAction::UrlUpdate, AuditTarget::Url, &after.short_code)
+        .with_changes(Some(&before), Some(&after));
+    if password_changed {
+        event = event.with_redacted_change("password");
+    }
+    record_audit_event(app_state, req, event).await;
+
+    Ok(HttpResponse::Ok().json(after))
+}
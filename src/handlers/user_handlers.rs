@@ -1,40 +1,40 @@
-use crate::models::user::User;
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::account_handlers::{check_new_account, send_verification_email};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+use crate::models::user::{Role, User};
+use crate::repositories::errors::is_duplicate_key;
+use crate::repositories::qr_code_repository::QrCodeFilter;
+use crate::repositories::url_repository::UrlFilter;
+use crate::repositories::user_repository::{ResourcePolicy, UserUpdate};
 use crate::state::app_state::AppState;
-use crate::structs::user::{CreateUserRequest, EditUserRequest, UserResponse};
+use crate::structs::user::{
+    CreateUserRequest, DeletePolicy, DeleteUserParams, DeletionPreviewResponse, EditUserRequest,
+    UserResponse,
+};
 use crate::utils::jwt::Claims;
 use actix_web::HttpMessage;
-use actix_web::{HttpResponse, Result, error, web};
-use bcrypt::{DEFAULT_COST, hash};
-use futures_util::TryStreamExt;
-use mongodb::bson::{doc, oid::ObjectId};
+use actix_web::{HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
+use validator::ValidationErrors;
 
 pub async fn get_all_users(
     app_state: web::Data<AppState>,
     req: actix_web::HttpRequest,
-) -> Result<HttpResponse> {
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
-
+) -> AppResult<HttpResponse> {
     // Get current user claims from the request extensions
-    let extensions = req.extensions();
-    let claims = extensions
-        .get::<Claims>()
-        .ok_or_else(|| error::ErrorInternalServerError("User claims not found in request"))?;
+    let claims =
+        req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("User claims not found in request"))
+        })?;
 
     // Get current user ID directly from claims
-    let current_user_id = ObjectId::parse_str(&claims.user_id)
-        .map_err(|_| error::ErrorInternalServerError("Invalid user ID in token"))?;
+    let current_user_id =
+        ObjectId::parse_str(&claims.user_id).context("Invalid user ID in token")?;
 
-    // Find all users except the current user (SuperUser)
-    let filter = doc! { "_id": { "$ne": current_user_id } };
-
-    let users = users_collection
-        .find(filter)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .try_collect::<Vec<User>>()
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
+    // Find all users except the current user (the calling admin)
+    let users = app_state.users.find_all_except(&current_user_id).await?;
 
     let user_responses: Vec<UserResponse> = users.into_iter().map(UserResponse::from).collect();
 
@@ -44,165 +44,398 @@ pub async fn get_all_users(
 pub async fn get_user(
     app_state: web::Data<AppState>,
     path: web::Path<String>,
-) -> Result<HttpResponse> {
+) -> AppResult<HttpResponse> {
     let user_id = path.into_inner();
     let object_id = ObjectId::parse_str(&user_id)
-        .map_err(|_| error::ErrorBadRequest("Invalid user ID format"))?;
-
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
+        .map_err(|_| AppError::bad_request("Invalid user ID format"))?;
 
-    let user = users_collection
-        .find_one(doc! { "_id": object_id })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorNotFound("User not found"))?;
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
 
     Ok(HttpResponse::Ok().json(UserResponse::from(user)))
 }
 
 pub async fn create_user(
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     web::Json(req): web::Json<CreateUserRequest>,
-) -> Result<HttpResponse> {
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
-
-    // Check if username already exists
-    let existing_user = users_collection
-        .find_one(doc! { "username": &req.username })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?;
-
-    if existing_user.is_some() {
-        return Err(error::ErrorBadRequest("Username already exists"));
-    }
+) -> AppResult<HttpResponse> {
+    check_new_account(
+        &app_state,
+        &req,
+        &req.username,
+        &req.password,
+        req.email.as_deref(),
+    )
+    .await?;
 
     // Hash password
-    let password_hash = hash(&req.password, DEFAULT_COST)
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to hash password: {}", e)))?;
+    let password_hash = app_state.passwords.hash(&req.password).await?;
 
     // Create new user
-    let new_user = User::new(req.username, req.email, req.full_name, password_hash);
-
-    // Insert into database
-    let result = users_collection
-        .insert_one(&new_user)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to create user: {}", e)))?;
+    let new_user = User::new(
+        req.username,
+        req.email,
+        req.full_name,
+        password_hash,
+        req.role.unwrap_or_default(),
+    );
+
+    // Insert into database; the unique username index rejects duplicates
+    let inserted_user = app_state.users.insert(&new_user).await.map_err(|e| {
+        if is_duplicate_key(&e) {
+            AppError::conflict("Username already exists")
+        } else {
+            AppError::Internal(e.context("Failed to create user"))
+        }
+    })?;
 
-    let id = result.inserted_id.as_object_id().unwrap();
+    send_verification_email(&app_state, &inserted_user).await?;
 
-    // Retrieve the inserted user
-    let inserted_user = users_collection
-        .find_one(doc! { "_id": id })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorInternalServerError("User created but not found"))?;
+    let response = UserResponse::from(inserted_user);
+    let event = AuditEvent::new(AuditAction::UserCreate, AuditTarget::User, &response.id)
+        .with_changes(None, Some(&response));
+    record_audit_event(&app_state, &http_req, event).await;
 
-    Ok(HttpResponse::Created().json(UserResponse::from(inserted_user)))
+    Ok(HttpResponse::Created().json(response))
 }
 
 pub async fn edit_user(
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     path: web::Path<String>,
     web::Json(req): web::Json<EditUserRequest>,
-) -> Result<HttpResponse> {
+) -> AppResult<HttpResponse> {
     let user_id = path.into_inner();
     let object_id = ObjectId::parse_str(&user_id)
-        .map_err(|_| error::ErrorBadRequest("Invalid user ID format"))?;
+        .map_err(|_| AppError::bad_request("Invalid user ID format"))?;
+
+    let mut errors = ValidationErrors::new();
+    if let Some(username) = &req.username {
+        app_state.policy.check_username(username, &mut errors);
+    }
+    if let Some(password) = &req.password {
+        app_state
+            .policy
+            .check_password("password", password, &mut errors);
+    }
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    // Hash the new password if one was provided
+    let password_hash = match req.password {
+        Some(password) => Some(app_state.passwords.hash(&password).await?),
+        None => None,
+    };
 
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
+    let before = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    let password_changed = password_hash.is_some();
 
-    // Check if user exists
-    let _user = users_collection
-        .find_one(doc! { "_id": object_id.clone() })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorNotFound("User not found"))?;
+    // Demoting or disabling an admin must leave another admin to manage the users
+    if req.role.is_some_and(|role| role != Role::Admin) || req.is_active == Some(false) {
+        check_not_last_admin(&app_state, &before).await?;
+    }
 
-    // Build update document
-    let mut update_doc = doc! {
-        "$set": {
-            "updated_at": chrono::Utc::now().timestamp_millis(),
-        }
+    let update = UserUpdate {
+        username: req.username,
+        email: None,
+        full_name: req.full_name,
+        password_hash,
+        is_active: req.is_active,
+        role: req.role,
     };
 
-    if let Some(username) = req.username {
-        update_doc
-            .get_document_mut("$set")
-            .unwrap()
-            .insert("username", username);
+    // Update user and retrieve the updated document
+    let updated_user = app_state
+        .users
+        .update(&object_id, &update)
+        .await
+        .map_err(|e| {
+            if is_duplicate_key(&e) {
+                AppError::conflict("Username already exists")
+            } else {
+                AppError::Internal(e.context("Failed to update user"))
+            }
+        })?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    // A disabled user must not keep using the tokens issued before, nor anyone the
+    // permissions of a role they no longer have
+    if !updated_user.is_active || updated_user.role != before.role {
+        app_state
+            .tokens
+            .revoke_user_tokens(&user_id, chrono::Utc::now().timestamp_millis())
+            .await?;
     }
 
-    if let Some(full_name) = req.full_name {
-        update_doc
-            .get_document_mut("$set")
-            .unwrap()
-            .insert("full_name", full_name);
+    let response = UserResponse::from(updated_user);
+    let mut event = AuditEvent::new(AuditAction::UserUpdate, AuditTarget::User, &user_id)
+        .with_changes(Some(&UserResponse::from(before)), Some(&response));
+    if password_changed {
+        event = event.with_redacted_change("password");
     }
+    record_audit_event(&app_state, &http_req, event).await;
 
-    if let Some(password) = req.password {
-        let password_hash = hash(&password, DEFAULT_COST).map_err(|e| {
-            error::ErrorInternalServerError(format!("Failed to hash password: {}", e))
-        })?;
-        update_doc
-            .get_document_mut("$set")
-            .unwrap()
-            .insert("password_hash", password_hash);
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn delete_user(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DeleteUserParams>,
+) -> AppResult<HttpResponse> {
+    let user_id = path.into_inner();
+    let object_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| AppError::bad_request("Invalid user ID format"))?;
+
+    let user = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    let policy = resource_policy(&app_state, &user_id, &query).await?;
+
+    if !remove_user(&app_state, &user, &policy).await? {
+        return Err(AppError::not_found("User not found"));
     }
 
-    if let Some(is_active) = req.is_active {
-        update_doc
-            .get_document_mut("$set")
-            .unwrap()
-            .insert("is_active", is_active);
+    let event = AuditEvent::new(AuditAction::UserDelete, AuditTarget::User, &user_id)
+        .with_changes(Some(&UserResponse::from(user)), None);
+    record_audit_event(&app_state, &http_req, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Report what deleting a user with a policy would affect, without deleting anything
+pub async fn preview_user_deletion(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<DeleteUserParams>,
+) -> AppResult<HttpResponse> {
+    let user_id = path.into_inner();
+    let object_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| AppError::bad_request("Invalid user ID format"))?;
+
+    app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    resource_policy(&app_state, &user_id, &query).await?;
+
+    // Links and QR codes created in a workspace stay with it
+    let (workspace_urls, urls): (Vec<_>, Vec<_>) = app_state
+        .urls
+        .find(&UrlFilter {
+            user_id: Some(user_id.clone()),
+            ..Default::default()
+        })
+        .await?
+        .into_iter()
+        .partition(|url| url.workspace_id.is_some());
+    let (workspace_qr_codes, qr_codes): (Vec<_>, Vec<_>) = app_state
+        .qr_codes
+        .find(&QrCodeFilter {
+            user_id: Some(user_id.clone()),
+            ..Default::default()
+        })
+        .await?
+        .into_iter()
+        .partition(|qr| qr.workspace_id.is_some());
+
+    let mut visitors = 0;
+    for url in &urls {
+        visitors += app_state.visitors.count_by_code(&url.short_code).await?;
     }
+    let workspace_memberships = app_state
+        .workspaces
+        .find_workspaces(Some(&user_id))
+        .await?
+        .len();
+
+    let params = query.into_inner();
+    Ok(HttpResponse::Ok().json(DeletionPreviewResponse {
+        user_id,
+        policy: params.policy,
+        reassign_to: params.reassign_to,
+        urls: urls.len(),
+        short_codes: urls.into_iter().map(|url| url.short_code).collect(),
+        qr_codes: qr_codes.len(),
+        visitors,
+        workspace_urls: workspace_urls.len(),
+        workspace_qr_codes: workspace_qr_codes.len(),
+        workspace_memberships,
+    }))
+}
 
-    // Update user
-    users_collection
-        .update_one(doc! { "_id": object_id.clone() }, update_doc)
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to update user: {}", e)))?;
+/// Turn the deletion parameters into a policy, checking the user to reassign to
+pub async fn resource_policy(
+    app_state: &AppState,
+    user_id: &str,
+    params: &DeleteUserParams,
+) -> AppResult<ResourcePolicy> {
+    match (params.policy, params.reassign_to.as_deref()) {
+        (DeletePolicy::Reassign, Some(reassign_to)) => {
+            let object_id = ObjectId::parse_str(reassign_to)
+                .map_err(|_| AppError::bad_request("Invalid reassign_to user ID format"))?;
+            if reassign_to == user_id {
+                return Err(AppError::bad_request(
+                    "Resources cannot be reassigned to the user being deleted",
+                ));
+            }
+            let new_owner = app_state
+                .users
+                .find_by_id(&object_id)
+                .await?
+                .ok_or_else(|| AppError::not_found("User to reassign to not found"))?;
+            if !new_owner.is_active {
+                return Err(AppError::bad_request(
+                    "Resources cannot be reassigned to an inactive user",
+                ));
+            }
+            Ok(ResourcePolicy::Reassign(reassign_to.to_string()))
+        }
+        (DeletePolicy::Reassign, None) => Err(AppError::bad_request(
+            "reassign_to is required by the reassign policy",
+        )),
+        (_, Some(_)) => Err(AppError::bad_request(
+            "reassign_to only applies to the reassign policy",
+        )),
+        (DeletePolicy::Disable, None) => Ok(ResourcePolicy::Disable),
+        (DeletePolicy::Delete, None) => Ok(ResourcePolicy::Delete),
+    }
+}
 
-    // Retrieve updated user
-    let updated_user = users_collection
-        .find_one(doc! { "_id": object_id })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .ok_or_else(|| error::ErrorInternalServerError("User updated but not found"))?;
+/// Refuse to demote, disable or delete the last active admin, since nobody would be
+/// left to manage the users
+pub async fn check_not_last_admin(app_state: &AppState, user: &User) -> AppResult<()> {
+    if user.role != Role::Admin || !user.is_active {
+        return Ok(());
+    }
+
+    let object_id = user.id.context("User has no ID")?;
+    if !app_state
+        .users
+        .find_all_except(&object_id)
+        .await?
+        .iter()
+        .any(|other| other.role == Role::Admin && other.is_active)
+    {
+        return Err(AppError::conflict(
+            "The last active admin cannot be demoted, disabled or deleted",
+        ));
+    }
 
-    Ok(HttpResponse::Ok().json(UserResponse::from(updated_user)))
+    Ok(())
 }
 
-pub async fn delete_user(
+/// Delete a user together with their credentials, applying the policy to their links
+/// and QR codes. Returns whether the user existed.
+pub async fn remove_user(
+    app_state: &AppState,
+    user: &User,
+    policy: &ResourcePolicy,
+) -> AppResult<bool> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let object_id = user.id.context("User has no ID")?;
+    let user_id = object_id.to_hex();
+
+    // Everything that can refuse the deletion is checked before anything is removed, so
+    // a refused deletion leaves the user as they were
+    check_not_last_admin(app_state, user).await?;
+
+    // A workspace needs at least one owner, so its last owner has to hand it over first
+    if let Some(workspace) = app_state
+        .workspaces
+        .find_workspaces(Some(&user_id))
+        .await?
+        .into_iter()
+        .find(|workspace| workspace.is_last_owner(&user_id))
+    {
+        return Err(AppError::conflict(format!(
+            "The user is the last owner of the workspace \"{}\", make another member owner or delete the workspace first",
+            workspace.name
+        )));
+    }
+
+    // Revoke the tokens and API keys issued to the user before deleting the account, so
+    // a failure part way leaves an account that can be deleted again rather than
+    // working credentials of a deleted one
+    app_state.tokens.revoke_user_tokens(&user_id, now).await?;
+    app_state.api_keys.delete_by_user(&user_id).await?;
+    app_state.totp.delete_totp(&user_id).await?;
+    app_state
+        .identities
+        .delete_identities_by_user(&user_id)
+        .await?;
+    app_state.passkeys.delete_passkeys_by_user(&user_id).await?;
+
+    // Links and QR codes owned by a workspace stay with it; only the memberships go
+    app_state
+        .workspaces
+        .remove_user_memberships(&user_id)
+        .await?;
+
+    Ok(app_state
+        .users
+        .delete_with_resources(&object_id, policy, now)
+        .await?)
+}
+
+/// Lift a lockout caused by failed login attempts
+pub async fn unlock_user(
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     path: web::Path<String>,
-) -> Result<HttpResponse> {
+) -> AppResult<HttpResponse> {
     let user_id = path.into_inner();
     let object_id = ObjectId::parse_str(&user_id)
-        .map_err(|_| error::ErrorBadRequest("Invalid user ID format"))?;
-
-    let db = &app_state.db;
-    let users_collection = db.collection::<User>("users");
+        .map_err(|_| AppError::bad_request("Invalid user ID format"))?;
+
+    let before = app_state
+        .users
+        .find_by_id(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    let user = app_state
+        .users
+        .unlock(&object_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    let response = UserResponse::from(user);
+    let event = AuditEvent::new(AuditAction::UserUnlock, AuditTarget::User, &user_id)
+        .with_changes(Some(&UserResponse::from(before)), Some(&response));
+    record_audit_event(&app_state, &http_req, event).await;
+
+    Ok(HttpResponse::Ok().json(response))
+}
 
-    // Check if user exists
-    let user_exists = users_collection
-        .find_one(doc! { "_id": object_id.clone() })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Database error: {}", e)))?
-        .is_some();
+/// Turn off two-factor authentication for a user who lost their authenticator and recovery codes
+pub async fn reset_user_totp(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let user_id = path.into_inner();
 
-    if !user_exists {
-        return Err(error::ErrorNotFound("User not found"));
+    if !app_state.totp.delete_totp(&user_id).await? {
+        return Err(AppError::not_found(
+            "Two-factor authentication is not set up for this user",
+        ));
     }
 
-    // Delete user
-    users_collection
-        .delete_one(doc! { "_id": object_id })
-        .await
-        .map_err(|e| error::ErrorInternalServerError(format!("Failed to delete user: {}", e)))?;
+    let event = AuditEvent::new(AuditAction::UserTotpReset, AuditTarget::User, &user_id);
+    record_audit_event(&app_state, &http_req, event).await;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -0,0 +1,486 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use mongodb::bson::oid::ObjectId;
+use validator::Validate;
+
+use crate::errors::app_error::{AppError, AppResult};
+use crate::handlers::audit_handlers::record_audit_event;
+use crate::handlers::auth_handlers::issue_tokens;
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+use crate::models::workspace::{Workspace, WorkspaceMember, WorkspaceRole};
+use crate::repositories::errors::is_duplicate_key;
+use crate::repositories::url_repository::UrlFilter;
+use crate::repositories::workspace_repository::MemberChange;
+use crate::state::app_state::AppState;
+use crate::structs::workspace::{
+    AddMemberRequest, SwitchWorkspaceRequest, UpdateMemberRequest, WorkspaceListParams,
+    WorkspaceMemberResponse, WorkspaceRequest, WorkspaceResponse,
+};
+use crate::utils::jwt::Claims;
+use crate::utils::tokens::hash_token;
+
+fn get_claims(req: &HttpRequest) -> AppResult<Claims> {
+    req.extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("User claims not found in request")))
+}
+
+/// The workspace loaded by the `ResourceOwnership` guard of the route
+fn get_workspace_ext(req: &HttpRequest) -> AppResult<Workspace> {
+    req.extensions()
+        .get::<Workspace>()
+        .cloned()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Workspace not found in request")))
+}
+
+/// The role of a user in a workspace, or `None` if the user is not a member or the
+/// workspace does not exist
+pub async fn workspace_role(
+    app_state: &AppState,
+    workspace_id: &str,
+    user_id: &str,
+) -> AppResult<Option<WorkspaceRole>> {
+    let Ok(workspace_id) = ObjectId::parse_str(workspace_id) else {
+        return Ok(None);
+    };
+
+    Ok(app_state
+        .workspaces
+        .find_workspace(&workspace_id)
+        .await?
+        .and_then(|workspace| workspace.role_of(user_id)))
+}
+
+/// The workspace the caller has switched to, after checking that they are still a
+/// member with at least the `required` role. `None` means personal resources.
+pub async fn current_workspace(
+    app_state: &AppState,
+    claims: &Claims,
+    required: WorkspaceRole,
+) -> AppResult<Option<String>> {
+    let Some(workspace_id) = &claims.workspace_id else {
+        return Ok(None);
+    };
+
+    match workspace_role(app_state, workspace_id, &claims.user_id).await? {
+        Some(role) if role.includes(required) => Ok(Some(workspace_id.clone())),
+        Some(_) => Err(AppError::forbidden(
+            "Access denied: Your workspace role does not permit this action",
+        )),
+        None => Err(AppError::forbidden(
+            "You are no longer a member of the current workspace, switch workspaces",
+        )),
+    }
+}
+
+/// Whether the caller may change or delete a resource. A resource owned by a workspace
+/// belongs to its editors and owners; any other resource to the user who created it.
+pub async fn can_modify(
+    app_state: &AppState,
+    claims: &Claims,
+    user_id: Option<&str>,
+    workspace_id: Option<&str>,
+) -> AppResult<bool> {
+    if claims.is_admin() {
+        return Ok(true);
+    }
+
+    match workspace_id {
+        Some(workspace_id) => Ok(workspace_role(app_state, workspace_id, &claims.user_id)
+            .await?
+            .is_some_and(|role| role.includes(WorkspaceRole::Editor))),
+        None => Ok(user_id == Some(claims.user_id.as_str())),
+    }
+}
+
+/// Whether the caller may look into a resource, e.g. its history. A resource owned by a
+/// workspace is open to all its members; any other resource to the user who created it.
+pub async fn can_view(
+    app_state: &AppState,
+    claims: &Claims,
+    user_id: Option<&str>,
+    workspace_id: Option<&str>,
+) -> AppResult<bool> {
+    if claims.is_admin() {
+        return Ok(true);
+    }
+
+    match workspace_id {
+        Some(workspace_id) => Ok(workspace_role(app_state, workspace_id, &claims.user_id)
+            .await?
+            .is_some_and(|role| role.includes(WorkspaceRole::Viewer))),
+        None => Ok(user_id == Some(claims.user_id.as_str())),
+    }
+}
+
+/// Build the response for a workspace, looking up the members' usernames
+async fn to_workspace_response(
+    app_state: &AppState,
+    workspace: Workspace,
+    current_user_id: &str,
+) -> AppResult<WorkspaceResponse> {
+    let mut members = Vec::with_capacity(workspace.members.len());
+    for member in &workspace.members {
+        let username = match ObjectId::parse_str(&member.user_id) {
+            Ok(id) => app_state
+                .users
+                .find_by_id(&id)
+                .await?
+                .map(|user| user.username),
+            Err(_) => None,
+        };
+        members.push(WorkspaceMemberResponse {
+            user_id: member.user_id.clone(),
+            username,
+            role: member.role,
+            added_at: member.added_at,
+        });
+    }
+
+    Ok(WorkspaceResponse {
+        id: workspace.id.context("Workspace has no ID")?.to_hex(),
+        role: workspace.role_of(current_user_id),
+        name: workspace.name,
+        members,
+        created_by: workspace.created_by,
+        created_at: workspace.created_at,
+        updated_at: workspace.updated_at,
+    })
+}
+
+/// Reload a workspace after a change and build its response
+async fn reload_workspace_response(
+    app_state: &AppState,
+    workspace_id: &ObjectId,
+    current_user_id: &str,
+) -> AppResult<WorkspaceResponse> {
+    let workspace = app_state
+        .workspaces
+        .find_workspace(workspace_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Workspace not found"))?;
+
+    to_workspace_response(app_state, workspace, current_user_id).await
+}
+
+/// List the caller's workspaces, or every workspace for an admin asking for all
+pub async fn get_workspaces(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<WorkspaceListParams>,
+) -> AppResult<HttpResponse> {
+    let claims = get_claims(&req)?;
+
+    let all = query.all.unwrap_or(false);
+    if all && !claims.is_admin() {
+        return Err(AppError::forbidden(
+            "Access denied: Only admins can list every workspace",
+        ));
+    }
+    let member_id = (!all).then_some(claims.user_id.as_str());
+
+    let workspaces = app_state.workspaces.find_workspaces(member_id).await?;
+
+    let mut responses = Vec::with_capacity(workspaces.len());
+    for workspace in workspaces {
+        responses.push(to_workspace_response(&app_state, workspace, &claims.user_id).await?);
+    }
+
+    Ok(HttpResponse::Ok().json(responses))
+}
+
+/// Create a workspace owned by the caller
+pub async fn create_workspace(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<WorkspaceRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()?;
+    let claims = get_claims(&req)?;
+
+    let workspace = Workspace::new(body.name.trim().to_string(), claims.user_id.clone());
+    let inserted = app_state.workspaces.insert_workspace(&workspace).await?;
+    let response = to_workspace_response(&app_state, inserted, &claims.user_id).await?;
+
+    let event = AuditEvent::new(
+        AuditAction::WorkspaceCreate,
+        AuditTarget::Workspace,
+        &response.id,
+    )
+    .with_changes(None, Some(&serde_json::json!({ "name": &response.name })));
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Get a workspace with its members
+pub async fn get_workspace(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let claims = get_claims(&req)?;
+    let workspace = get_workspace_ext(&req)?;
+
+    let response = to_workspace_response(&app_state, workspace, &claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Rename a workspace
+pub async fn rename_workspace(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<WorkspaceRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()?;
+    let claims = get_claims(&req)?;
+    let workspace = get_workspace_ext(&req)?;
+    let workspace_id = workspace.id.context("Workspace has no ID")?;
+
+    let name = body.name.trim();
+    let now = chrono::Utc::now().timestamp_millis();
+    if !app_state
+        .workspaces
+        .rename_workspace(&workspace_id, name, now)
+        .await?
+    {
+        return Err(AppError::not_found("Workspace not found"));
+    }
+
+    let event = AuditEvent::new(
+        AuditAction::WorkspaceUpdate,
+        AuditTarget::Workspace,
+        workspace_id.to_hex(),
+    )
+    .with_changes(
+        Some(&serde_json::json!({ "name": workspace.name })),
+        Some(&serde_json::json!({ "name": name })),
+    );
+    record_audit_event(&app_state, &req, event).await;
+
+    let response = reload_workspace_response(&app_state, &workspace_id, &claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Delete a workspace. Its links have to be deleted first, so they are never left
+/// without an owner; direct QR codes go back to the users who generated them.
+pub async fn delete_workspace(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let workspace = get_workspace_ext(&req)?;
+    let workspace_id = workspace.id.context("Workspace has no ID")?;
+
+    let url_filter = UrlFilter {
+        workspace_id: Some(workspace_id.to_hex()),
+        ..Default::default()
+    };
+    if !app_state.urls.find(&url_filter).await?.is_empty() {
+        return Err(AppError::conflict(
+            "Workspace still owns links, delete them first",
+        ));
+    }
+
+    if !app_state.workspaces.delete_workspace(&workspace_id).await? {
+        return Err(AppError::not_found("Workspace not found"));
+    }
+    app_state
+        .qr_codes
+        .clear_workspace(&workspace_id.to_hex())
+        .await?;
+
+    let event = AuditEvent::new(
+        AuditAction::WorkspaceDelete,
+        AuditTarget::Workspace,
+        workspace_id.to_hex(),
+    )
+    .with_changes(Some(&serde_json::json!({ "name": workspace.name })), None);
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Add a user to a workspace by username
+pub async fn add_workspace_member(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<AddMemberRequest>,
+) -> AppResult<HttpResponse> {
+    let claims = get_claims(&req)?;
+    let workspace = get_workspace_ext(&req)?;
+    let workspace_id = workspace.id.context("Workspace has no ID")?;
+
+    let user = app_state
+        .users
+        .find_by_username(body.username.trim())
+        .await?
+        .filter(|user| user.is_active)
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    let user_id = user.id.context("User has no ID")?.to_hex();
+
+    let member = WorkspaceMember::new(user_id.clone(), body.role.unwrap_or_default());
+    match app_state
+        .workspaces
+        .add_member(&workspace_id, &member)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return Err(AppError::not_found("Workspace not found")),
+        Err(e) if is_duplicate_key(&e) => {
+            return Err(AppError::conflict(
+                "User is already a member of this workspace",
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let event = AuditEvent::new(
+        AuditAction::WorkspaceMemberAdd,
+        AuditTarget::Workspace,
+        workspace_id.to_hex(),
+    )
+    .with_changes(
+        None,
+        Some(&serde_json::json!({ "member": user_id, "role": member.role })),
+    );
+    record_audit_event(&app_state, &req, event).await;
+
+    let response = reload_workspace_response(&app_state, &workspace_id, &claims.user_id).await?;
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Change the role of a member. The last owner cannot be demoted.
+pub async fn update_workspace_member(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    web::Json(body): web::Json<UpdateMemberRequest>,
+) -> AppResult<HttpResponse> {
+    let (_, user_id) = path.into_inner();
+    let claims = get_claims(&req)?;
+    let workspace = get_workspace_ext(&req)?;
+    let workspace_id = workspace.id.context("Workspace has no ID")?;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let change = app_state
+        .workspaces
+        .set_member_role(&workspace_id, &user_id, body.role, now)
+        .await?;
+    check_member_change(change)?;
+
+    let event = AuditEvent::new(
+        AuditAction::WorkspaceMemberUpdate,
+        AuditTarget::Workspace,
+        workspace_id.to_hex(),
+    )
+    .with_changes(
+        Some(&serde_json::json!({ "member": &user_id, "role": workspace.role_of(&user_id) })),
+        Some(&serde_json::json!({ "member": &user_id, "role": body.role })),
+    );
+    record_audit_event(&app_state, &req, event).await;
+
+    let response = reload_workspace_response(&app_state, &workspace_id, &claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Remove a member from a workspace. Owners can remove anyone and members can leave;
+/// the last owner can do neither.
+pub async fn remove_workspace_member(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> AppResult<HttpResponse> {
+    let (_, user_id) = path.into_inner();
+    let claims = get_claims(&req)?;
+    let workspace = get_workspace_ext(&req)?;
+    let workspace_id = workspace.id.context("Workspace has no ID")?;
+
+    let is_owner = workspace.role_of(&claims.user_id) == Some(WorkspaceRole::Owner);
+    if !claims.is_admin() && !is_owner && claims.user_id != user_id {
+        return Err(AppError::forbidden(
+            "Access denied: Only workspace owners can remove other members",
+        ));
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let change = app_state
+        .workspaces
+        .remove_member(&workspace_id, &user_id, now)
+        .await?;
+    check_member_change(change)?;
+
+    let event = AuditEvent::new(
+        AuditAction::WorkspaceMemberRemove,
+        AuditTarget::Workspace,
+        workspace_id.to_hex(),
+    )
+    .with_changes(
+        Some(&serde_json::json!({ "member": &user_id, "role": workspace.role_of(&user_id) })),
+        None,
+    );
+    record_audit_event(&app_state, &req, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn check_member_change(change: MemberChange) -> AppResult<()> {
+    match change {
+        MemberChange::Changed => Ok(()),
+        MemberChange::NotMember => Err(AppError::not_found(
+            "User is not a member of this workspace",
+        )),
+        MemberChange::LastOwner => Err(AppError::conflict("A workspace needs at least one owner")),
+    }
+}
+
+/// Switch the session to a workspace, or back to personal resources without one.
+/// The refresh token of the session is rotated, so the workspace outlives token refreshes.
+pub async fn switch_workspace(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Json(body): web::Json<SwitchWorkspaceRequest>,
+) -> AppResult<HttpResponse> {
+    let claims = get_claims(&req)?;
+
+    let workspace_id = body.workspace_id.filter(|id| !id.is_empty());
+    if let Some(workspace_id) = &workspace_id
+        && workspace_role(&app_state, workspace_id, &claims.user_id)
+            .await?
+            .is_none()
+    {
+        return Err(AppError::forbidden(
+            "Access denied: You are not a member of this workspace",
+        ));
+    }
+
+    // The refresh token has to belong to the session of the access token
+    let token_hash = hash_token(&body.refresh_token);
+    let stored = app_state
+        .tokens
+        .find_refresh_token(&token_hash)
+        .await?
+        .filter(|stored| stored.user_id == claims.user_id && stored.family_id == claims.sid)
+        .filter(|stored| stored.revoked_at.is_none() && !stored.is_expired())
+        .ok_or_else(|| AppError::unauthorized("Invalid refresh token"))?;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    if !app_state
+        .tokens
+        .revoke_refresh_token(&token_hash, now)
+        .await?
+    {
+        return Err(AppError::unauthorized("Refresh token has been revoked"));
+    }
+
+    let user_id = ObjectId::parse_str(&stored.user_id).context("Invalid user ID in token")?;
+    let user = app_state
+        .users
+        .find_by_id(&user_id)
+        .await?
+        .filter(|user| user.is_active)
+        .ok_or_else(|| AppError::unauthorized("Account is disabled"))?;
+
+    let tokens = issue_tokens(&app_state, &user, Some(stored.family_id), workspace_id).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
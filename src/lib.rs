@@ -0,0 +1,14 @@
+//! The service as a library, so the binary in `main.rs` and the integration tests in
+//! `tests/` build the same application
+pub mod config;
+pub mod db;
+pub mod errors;
+pub mod handlers;
+pub mod mail;
+pub mod middlewares;
+pub mod models;
+pub mod repositories;
+pub mod routes;
+pub mod state;
+pub mod structs;
+pub mod utils;
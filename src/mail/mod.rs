@@ -0,0 +1,66 @@
+pub mod outbox;
+pub mod smtp;
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lettre::Message;
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+
+use crate::config::app_config::{MailConfig, MailTransport};
+use outbox::OutboxMailer;
+use smtp::SmtpMailer;
+
+/// A plain text email to a single recipient
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers outgoing mail
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<()>;
+}
+
+/// Create the mailer for the configured transport
+pub fn build_mailer(config: &MailConfig) -> Result<Arc<dyn Mailer>> {
+    let from: Mailbox = config
+        .from
+        .parse()
+        .with_context(|| format!("Invalid sender address {}", config.from))?;
+
+    Ok(match config.transport {
+        MailTransport::Outbox => Arc::new(OutboxMailer::new(from, &config.outbox_dir)?),
+        MailTransport::Smtp => Arc::new(SmtpMailer::new(from, config)?),
+    })
+}
+
+/// Send an email without making the caller wait for the mail server.
+/// Failures are logged, since there is nobody left to report them to.
+pub fn send_in_background(mailer: Arc<dyn Mailer>, email: Email) {
+    actix_web::rt::spawn(async move {
+        if let Err(e) = mailer.send(&email).await {
+            log::error!("Failed to send mail to {}: {:#}", email.to, e);
+        }
+    });
+}
+
+fn build_message(from: &Mailbox, email: &Email) -> Result<Message> {
+    let to: Mailbox = email
+        .to
+        .parse()
+        .with_context(|| format!("Invalid recipient address {}", email.to))?;
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(&email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body.clone())
+        .context("Failed to build mail message")
+}
@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{Email, Mailer, build_message};
+
+/// Writes every message as an `.eml` file instead of sending it, so mail can
+/// be inspected locally without a mail server
+pub struct OutboxMailer {
+    from: Mailbox,
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl OutboxMailer {
+    pub fn new(from: Mailbox, dir: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create mail outbox directory {}", dir))?;
+
+        Ok(Self {
+            from,
+            transport: AsyncFileTransport::new(dir),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        let message = build_message(&self.from, email)?;
+        let id = self
+            .transport
+            .send(message)
+            .await
+            .context("Failed to write mail to the outbox")?;
+
+        log::info!("Wrote mail to {} as {}.eml", email.to, id);
+        Ok(())
+    }
+}
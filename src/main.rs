@@ -1,60 +1,271 @@
-mod db;
-mod handlers;
-mod middlewares;
-mod models;
-mod routes;
-mod state;
-mod structs;
-mod utils;
-
-use crate::state::app_state::AppState;
-use actix_cors::Cors;
-use actix_web::{App, HttpServer, http, middleware::Logger, web};
-use db::mongodb::get_database;
+use actix_web::{App, HttpServer, middleware::Logger, web};
 use dotenv::dotenv;
 use env_logger::Env;
-use routes::init_routes;
+use makemeshort::config::app_config::{Config, StorageBackend};
+use makemeshort::db::memory::MemoryStore;
+use makemeshort::db::mongodb::{MongoStore, get_database};
+use makemeshort::db::sql::{SqlStore, get_pool};
+use makemeshort::middlewares::cors::cors;
+use makemeshort::models::user::Role;
+use makemeshort::repositories::user_repository::{UserRepository, UserUpdate};
+use makemeshort::routes::init_routes;
+use makemeshort::state::app_state::AppState;
+use makemeshort::utils::jwt::JwtKeys;
+use makemeshort::utils::oidc::OidcClient;
+use makemeshort::utils::policy::AccountPolicy;
+use makemeshort::utils::tls::{self, ReloadableCertResolver, reload_on_sighup};
+use makemeshort::utils::webauthn::Webauthn;
+use makemeshort::{db, mail};
 use std::env;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::sync::Arc;
+
+/// How often failed login attempts that no longer matter are dropped from memory
+const THROTTLE_PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Apply (or with `--dry-run`, list) pending migrations for the configured backend and exit
+async fn migrate_command(config: &Config, dry_run: bool) -> anyhow::Result<()> {
+    let reports = match config.storage.backend {
+        StorageBackend::MongoDb => {
+            let db = db::mongodb::connect(&config.storage).await?;
+            db::migrations::run_migrations(&db, dry_run).await?
+        }
+        StorageBackend::Sql => {
+            let pool = db::sql::connect(&config.storage).await?;
+            db::sql::run_migrations(&pool, dry_run).await?
+        }
+        StorageBackend::Memory => Vec::new(),
+    };
+
+    if reports.is_empty() {
+        println!("No pending migrations");
+    }
+
+    for report in reports {
+        let affected = report
+            .affected
+            .map(|count| format!(" ({} documents)", count))
+            .unwrap_or_default();
+        let status = if dry_run { "Pending" } else { "Applied" };
+        println!("{} {} {}{}", status, report.version, report.name, affected);
+    }
+
+    Ok(())
+}
+
+/// Give an existing user the `admin` role, for instances that have users but no administrator
+async fn promote_admin_command(config: &Config, username: &str) -> anyhow::Result<()> {
+    let users: Box<dyn UserRepository> = match config.storage.backend {
+        StorageBackend::MongoDb => Box::new(MongoStore::new(
+            db::mongodb::connect(&config.storage).await?,
+        )),
+        StorageBackend::Sql => Box::new(SqlStore::new(db::sql::connect(&config.storage).await?)),
+        StorageBackend::Memory => anyhow::bail!("The in-memory backend has no stored users"),
+    };
+
+    let user = users
+        .find_by_username(username)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No user named {}", username))?;
+    let update = UserUpdate {
+        role: Some(Role::Admin),
+        ..Default::default()
+    };
+    users
+        .update(&user.id.expect("stored users have an ID"), &update)
+        .await?;
+
+    println!("{} is now an admin", user.username);
+    Ok(())
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    let port_string = env::var("PORT").expect("PORT not set.");
-    let port = port_string.parse::<u16>().unwrap();
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-    // Initialize the database connection
-    let db = match get_database().await {
-        Ok(db) => db,
+    // Load and validate the configuration before doing anything else
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // `makemeshort migrate [--dry-run]` runs the migrations without starting the server
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        let dry_run = args.iter().any(|arg| arg == "--dry-run");
+        if let Err(e) = migrate_command(&config, dry_run).await {
+            eprintln!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // `makemeshort promote-admin <username>` makes an existing user an admin and exits
+    if args.first().map(String::as_str) == Some("promote-admin") {
+        let Some(username) = args.get(1) else {
+            eprintln!("Usage: makemeshort promote-admin <username>");
+            std::process::exit(2);
+        };
+        if let Err(e) = promote_admin_command(&config, username).await {
+            eprintln!("Promoting {} failed: {:#}", username, e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Load the TLS certificate up front so a bad certificate fails before connecting to the database
+    let tls_config = match (&config.tls.cert_file, &config.tls.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            match ReloadableCertResolver::new(cert_file, key_file).and_then(|resolver| {
+                let resolver = Arc::new(resolver);
+                reload_on_sighup(resolver.clone())?;
+                tls::server_config(resolver)
+            }) {
+                Ok(tls_config) => Some(tls_config),
+                Err(e) => {
+                    eprintln!("Error loading TLS certificate: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // Load the JWT signing keys
+    let jwt_keys = match JwtKeys::load(&config.auth) {
+        Ok(jwt_keys) => jwt_keys,
         Err(e) => {
-            eprintln!("Error connecting to the database: {}", e);
+            eprintln!("Error loading JWT signing keys: {:#}", e);
             std::process::exit(1);
         }
     };
 
-    // Create shared state
-    let app_state = web::Data::new(AppState { db });
+    // Set up the mail transport
+    let mailer = match mail::build_mailer(&config.mail) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            eprintln!("Error configuring the mail transport: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Load the username and password policy
+    let policy = match AccountPolicy::load(&config.policy) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Error loading the account policy: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Set up the OpenID Connect client if single sign-on is configured
+    let oidc = match OidcClient::from_config(&config) {
+        Ok(oidc) => oidc,
+        Err(e) => {
+            eprintln!("Error configuring single sign-on: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Set up the WebAuthn relying party for passkey logins
+    let webauthn = match Webauthn::new(&config) {
+        Ok(webauthn) => webauthn,
+        Err(e) => {
+            eprintln!("Error configuring passkeys: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Initialize the storage backend and create shared state
+    let app_state = match config.storage.backend {
+        StorageBackend::MongoDb => match get_database(&config.storage).await {
+            Ok(db) => AppState::new(
+                config,
+                jwt_keys,
+                mailer,
+                policy,
+                oidc,
+                webauthn,
+                MongoStore::new(db),
+            ),
+            Err(e) => {
+                eprintln!("Error connecting to the database: {}", e);
+                std::process::exit(1);
+            }
+        },
+        StorageBackend::Sql => match get_pool(&config.storage).await {
+            Ok(pool) => AppState::new(
+                config,
+                jwt_keys,
+                mailer,
+                policy,
+                oidc,
+                webauthn,
+                SqlStore::new(pool),
+            ),
+            Err(e) => {
+                eprintln!("Error connecting to the database: {}", e);
+                std::process::exit(1);
+            }
+        },
+        StorageBackend::Memory => {
+            log::warn!("Using in-memory storage, data will be lost on restart");
+            AppState::new(
+                config,
+                jwt_keys,
+                mailer,
+                policy,
+                oidc,
+                webauthn,
+                MemoryStore::new(),
+            )
+        }
+    };
+    let app_state = web::Data::new(app_state);
+
+    // Forget failed attempts once they no longer throttle anyone
+    let throttled = app_state.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(THROTTLE_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            throttled.login_throttle.prune();
+            throttled.link_password_throttle.prune();
+            throttled.link_code_throttle.prune();
+            throttled.password_reset_throttle.prune();
+        }
+    });
+
+    let server_config = app_state.config.server.clone();
 
     // Start the Actix Web server
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         // Create a logger with a custom format instead
         let logger = Logger::new("%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %D ms");
-        // Enable CORS for all origins
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:5173") // Default Bun dev server port
-            .allowed_origin("http://localhost:4173")
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-            .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
-            .allowed_header(http::header::CONTENT_TYPE)
-            .supports_credentials()
-            .max_age(3600);
         App::new()
             .wrap(logger)
-            .wrap(cors)
+            .wrap(cors(&app_state.config.cors))
             .app_data(app_state.clone())
             .configure(init_routes)
-    })
-    .bind(("127.0.0.1", port))?
-    .run()
-    .await
+    });
+
+    let bind_address = (server_config.bind_address, server_config.port);
+    let server = match (server_config.unix_socket, tls_config) {
+        (Some(socket_path), _) => {
+            // Remove a socket left behind by a previous run
+            if fs::metadata(&socket_path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                fs::remove_file(&socket_path)?;
+            }
+            server.bind_uds(socket_path)?
+        }
+        (None, Some(tls_config)) => server.bind_rustls_0_23(bind_address, tls_config)?,
+        (None, None) => server.bind(bind_address)?,
+    };
+
+    server.run().await
 }
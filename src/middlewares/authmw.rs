@@ -1,20 +1,30 @@
 use std::future::{Ready, ready};
+use std::rc::Rc;
 
 use actix_web::{
     Error, HttpMessage,
     dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
-    error::ErrorUnauthorized,
     http::header,
+    web,
 };
+use anyhow::Context;
 use futures_util::future::LocalBoxFuture;
+use mongodb::bson::oid::ObjectId;
 
-use crate::utils::jwt::{Claims, validate_token};
+use crate::errors::app_error::AppError;
+use crate::routes::api_key_scope;
+use crate::state::app_state::AppState;
+use crate::utils::jwt::Claims;
+use crate::utils::tokens::hash_token;
+
+/// Header carrying a personal API key
+pub const API_KEY_HEADER: &str = "X-API-Key";
 
 pub struct JwtAuth;
 
 impl<S, B> Transform<S, ServiceRequest> for JwtAuth
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -25,17 +35,19 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(JwtAuthMiddleware { service }))
+        ready(Ok(JwtAuthMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct JwtAuthMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -55,12 +67,26 @@ where
             return Box::pin(self.service.call(req));
         }
 
+        // API keys are sent in their own header instead of a bearer token
+        if let Some(api_key) = req.headers().get(API_KEY_HEADER) {
+            let api_key = api_key.to_str().unwrap_or_default().to_string();
+            let service = self.service.clone();
+
+            return Box::pin(async move {
+                let claims = authenticate_api_key(&req, &api_key).await?;
+                req.extensions_mut().insert(claims);
+                service.call(req).await
+            });
+        }
+
         // Get token from Authorization header
         let auth_header = req.headers().get(header::AUTHORIZATION);
         let auth_header = match auth_header {
             Some(header) => header,
             None => {
-                return Box::pin(async move { Err(ErrorUnauthorized("No authorization header")) });
+                return Box::pin(async move {
+                    Err(AppError::unauthorized("No authorization header").into())
+                });
             }
         };
 
@@ -68,31 +94,120 @@ where
         let auth_header_str = match auth_header.to_str() {
             Ok(header_str) => header_str,
             Err(_) => {
-                return Box::pin(
-                    async move { Err(ErrorUnauthorized("Invalid authorization header")) },
-                );
+                return Box::pin(async move {
+                    Err(AppError::unauthorized("Invalid authorization header").into())
+                });
             }
         };
 
         // Check if the header starts with "Bearer "
         if !auth_header_str.starts_with("Bearer ") {
-            return Box::pin(async move { Err(ErrorUnauthorized("Invalid authorization format")) });
+            return Box::pin(async move {
+                Err(AppError::unauthorized("Invalid authorization format").into())
+            });
         }
 
         // Extract the token
         let token = &auth_header_str[7..];
 
-        // Validate the token
-        let claims = match validate_token(token) {
+        // Validate the token against the configured secret
+        let Some(app_state) = req.app_data::<web::Data<AppState>>() else {
+            return Box::pin(async move {
+                Err(AppError::Internal(anyhow::anyhow!("App state not configured")).into())
+            });
+        };
+
+        let claims = match app_state.jwt.validate_token(token) {
             Ok(claims) => claims,
             Err(_) => {
-                return Box::pin(async move { Err(ErrorUnauthorized("Invalid token")) });
+                return Box::pin(
+                    async move { Err(AppError::unauthorized("Invalid token").into()) },
+                );
             }
         };
 
-        // Store the complete Claims object in request extensions for later use
-        req.extensions_mut().insert(claims);
+        let tokens = app_state.tokens.clone();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            // Reject tokens revoked by logout or by disabling/deleting the user
+            if tokens
+                .is_access_token_revoked(&claims.jti, &claims.user_id, claims.issued_at_ms())
+                .await
+                .map_err(AppError::Internal)?
+            {
+                return Err(AppError::unauthorized("Token has been revoked").into());
+            }
+
+            // Store the complete Claims object in request extensions for later use
+            req.extensions_mut().insert(claims);
 
-        Box::pin(self.service.call(req))
+            service.call(req).await
+        })
     }
 }
+
+/// Resolve an API key to the claims of its owner. The key must not be expired, its owner must
+/// be active, and it must carry the scope the requested endpoint requires.
+async fn authenticate_api_key(req: &ServiceRequest, api_key: &str) -> Result<Claims, AppError> {
+    let app_state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("App state not configured")))?;
+
+    let key = app_state
+        .api_keys
+        .find_by_hash(&hash_token(api_key))
+        .await?
+        .filter(|key| !key.is_expired())
+        .ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+
+    let user_id = ObjectId::parse_str(&key.user_id).context("Invalid user ID in API key")?;
+    let user = app_state
+        .users
+        .find_by_id(&user_id)
+        .await?
+        .filter(|user| user.is_active)
+        .ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+
+    // Endpoints without a scope (user management, key management, logout) are not available to API keys
+    let pattern = req.match_pattern().unwrap_or_default();
+    match api_key_scope(req.method(), &pattern) {
+        Some(scope) if key.scopes.contains(&scope) => {}
+        Some(scope) => {
+            return Err(AppError::forbidden(format!(
+                "API key is missing the {} scope",
+                scope.as_str()
+            )));
+        }
+        None => {
+            return Err(AppError::forbidden(
+                "This endpoint cannot be used with an API key",
+            ));
+        }
+    }
+
+    let now = chrono::Utc::now();
+    app_state
+        .api_keys
+        .set_last_used(
+            key.id.as_ref().context("API key has no ID")?,
+            now.timestamp_millis(),
+        )
+        .await?;
+
+    Ok(Claims {
+        sub: user.username,
+        exp: key
+            .expires_at
+            .map_or(0, |expires_at| (expires_at / 1000) as usize),
+        iat: now.timestamp() as usize,
+        iss: app_state.jwt.issuer().to_string(),
+        aud: app_state.jwt.audience().to_string(),
+        user_id: key.user_id,
+        role: user.role,
+        jti: String::new(),
+        sid: String::new(),
+        workspace_id: None,
+        iat_ms: now.timestamp_millis(),
+    })
+}
@@ -0,0 +1,26 @@
+use actix_cors::Cors;
+
+use crate::config::app_config::CorsConfig;
+
+/// Build the CORS middleware from the configured origins, methods and headers.
+/// The configuration is validated at startup, so every entry is known to parse.
+pub fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(config.allowed_methods.iter().map(String::as_str))
+        .allowed_headers(config.allowed_headers.iter().map(String::as_str))
+        .max_age(config.max_age);
+
+    for origin in &config.allowed_origins {
+        cors = if origin == "*" {
+            cors.allow_any_origin()
+        } else {
+            cors.allowed_origin(origin)
+        };
+    }
+
+    if config.supports_credentials {
+        cors = cors.supports_credentials();
+    }
+
+    cors
+}
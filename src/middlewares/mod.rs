@@ -1,2 +1,4 @@
 pub mod authmw;
+pub mod cors;
 pub mod res_owner;
+pub mod role_guard;
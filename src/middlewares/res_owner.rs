@@ -1,20 +1,54 @@
 use std::future::{Ready, ready};
+use std::rc::Rc;
 
+use crate::errors::app_error::AppError;
+use crate::models::workspace::{Workspace, WorkspaceRole};
+use crate::state::app_state::AppState;
 use crate::utils::jwt::Claims;
-use actix_web::error::ErrorForbidden;
 use actix_web::{
     Error, HttpMessage,
     dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    web,
 };
 use futures_util::future::LocalBoxFuture;
+use mongodb::bson::oid::ObjectId;
 
+/// What the URL parameter of a guarded route identifies
+#[derive(Debug, Clone, Copy)]
+pub enum Owner {
+    User,                     // A user; only that user may pass
+    Workspace(WorkspaceRole), // A workspace; members with at least this role may pass
+}
+
+/// Restrict a route to the owner of the resources named by a URL parameter.
+/// Administrators may always pass. For workspaces, the workspace is added to the
+/// request extensions for the handler.
 pub struct ResourceOwnership {
-    pub param_name: String, // Name of the URL parameter that contains the user ID
+    pub param_name: String, // Name of the URL parameter that contains the owner ID
+    pub owner: Owner,
+}
+
+impl ResourceOwnership {
+    /// The parameter is a user ID
+    pub fn user(param_name: &str) -> Self {
+        Self {
+            param_name: param_name.to_string(),
+            owner: Owner::User,
+        }
+    }
+
+    /// The parameter is a workspace ID, and members need at least the given role
+    pub fn workspace(param_name: &str, role: WorkspaceRole) -> Self {
+        Self {
+            param_name: param_name.to_string(),
+            owner: Owner::Workspace(role),
+        }
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for ResourceOwnership
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -26,20 +60,22 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(ResourceOwnershipMiddleware {
-            service,
+            service: Rc::new(service),
             param_name: self.param_name.clone(),
+            owner: self.owner,
         }))
     }
 }
 
 pub struct ResourceOwnershipMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     param_name: String,
+    owner: Owner,
 }
 
 impl<S, B> Service<ServiceRequest> for ResourceOwnershipMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -51,10 +87,12 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         // Get the current user ID from JWT claims
-        let current_user_id = match req.extensions().get::<Claims>() {
-            Some(claims) => claims.user_id.clone(),
+        let (current_user_id, is_admin) = match req.extensions().get::<Claims>() {
+            Some(claims) => (claims.user_id.clone(), claims.is_admin()),
             None => {
-                return Box::pin(async move { Err(ErrorForbidden("User not authenticated")) });
+                return Box::pin(async move {
+                    Err(AppError::forbidden("User not authenticated").into())
+                });
             }
         };
 
@@ -63,21 +101,75 @@ where
         let resource_owner_id = match path.get(&self.param_name) {
             Some(id) => id.to_string(),
             None => {
-                // If no user_id parameter, continue (might be a collection endpoint)
+                // If no owner parameter, continue (might be a collection endpoint)
                 return Box::pin(self.service.call(req));
             }
         };
 
-        // Check if the current user is accessing their own resources or is an admin
-        if current_user_id != resource_owner_id {
-            return Box::pin(async move {
-                Err(ErrorForbidden(
-                    "Access denied: You can only access your own resources",
-                ))
-            });
-        }
+        let required = match self.owner {
+            Owner::User => {
+                // Check if the current user is accessing their own resources or is an admin
+                if !is_admin && current_user_id != resource_owner_id {
+                    return Box::pin(async move {
+                        Err(AppError::forbidden(
+                            "Access denied: You can only access your own resources",
+                        )
+                        .into())
+                    });
+                }
+
+                // User is accessing their own resources (or is an admin), proceed
+                return Box::pin(self.service.call(req));
+            }
+            Owner::Workspace(required) => required,
+        };
+
+        // Workspace membership has to be looked up in storage
+        let service = self.service.clone();
+        Box::pin(async move {
+            let workspace_id = ObjectId::parse_str(&resource_owner_id)
+                .map_err(|_| AppError::bad_request("Invalid workspace ID format"))?;
+            // A guard of an enclosing scope may have loaded the workspace already
+            let loaded = req
+                .extensions()
+                .get::<Workspace>()
+                .filter(|workspace| workspace.id == Some(workspace_id))
+                .cloned();
+            let workspace = match loaded {
+                Some(workspace) => workspace,
+                None => {
+                    let app_state = req.app_data::<web::Data<AppState>>().ok_or_else(|| {
+                        AppError::Internal(anyhow::anyhow!("App state not configured"))
+                    })?;
+                    app_state
+                        .workspaces
+                        .find_workspace(&workspace_id)
+                        .await
+                        .map_err(AppError::Internal)?
+                        .ok_or_else(|| AppError::not_found("Workspace not found"))?
+                }
+            };
+
+            if !is_admin {
+                match workspace.role_of(&current_user_id) {
+                    Some(role) if role.includes(required) => {}
+                    Some(_) => {
+                        return Err(AppError::forbidden(
+                            "Access denied: Your workspace role does not permit this action",
+                        )
+                        .into());
+                    }
+                    None => {
+                        return Err(AppError::forbidden(
+                            "Access denied: You are not a member of this workspace",
+                        )
+                        .into());
+                    }
+                }
+            }
 
-        // User is accessing their own resources, proceed
-        Box::pin(self.service.call(req))
+            req.extensions_mut().insert(workspace);
+            service.call(req).await
+        })
     }
 }
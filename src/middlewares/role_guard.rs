@@ -0,0 +1,92 @@
+use std::future::{Ready, ready};
+
+use crate::errors::app_error::AppError;
+use crate::models::user::Role;
+use crate::utils::jwt::Claims;
+use actix_web::{
+    Error, HttpMessage,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+};
+use futures_util::future::LocalBoxFuture;
+
+pub struct RequireRole {
+    pub allowed: Vec<Role>, // Roles that may reach the wrapped service
+}
+
+impl RequireRole {
+    /// Only administrators may pass
+    pub fn admin() -> Self {
+        Self {
+            allowed: vec![Role::Admin],
+        }
+    }
+
+    /// Any role that is allowed to create or modify resources
+    pub fn writer() -> Self {
+        Self {
+            allowed: vec![Role::Admin, Role::Member],
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service,
+            allowed: self.allowed.clone(),
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: S,
+    allowed: Vec<Role>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Get the current user's role from JWT claims
+        let role = match req.extensions().get::<Claims>() {
+            Some(claims) => claims.role,
+            None => {
+                return Box::pin(async move {
+                    Err(AppError::forbidden("User not authenticated").into())
+                });
+            }
+        };
+
+        // Check the role against the allowed list
+        if !self.allowed.contains(&role) {
+            return Box::pin(async move {
+                Err(
+                    AppError::forbidden("Access denied: Your role does not permit this action")
+                        .into(),
+                )
+            });
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
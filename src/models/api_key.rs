@@ -0,0 +1,92 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A personal API key. Only the SHA-256 hash of the key is stored;
+/// the key itself is shown once, when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub name: String,
+    pub key_prefix: String, // First characters of the key, so users can tell their keys apart
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+/// What an API key is allowed to do, on top of the role of the user who owns it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "links:read")]
+    LinksRead,
+    #[serde(rename = "links:write")]
+    LinksWrite,
+    #[serde(rename = "qr:read")]
+    QrRead,
+    #[serde(rename = "qr:write")]
+    QrWrite,
+    #[serde(rename = "analytics:read")]
+    AnalyticsRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::LinksRead => "links:read",
+            Scope::LinksWrite => "links:write",
+            Scope::QrRead => "qr:read",
+            Scope::QrWrite => "qr:write",
+            Scope::AnalyticsRead => "analytics:read",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "links:read" => Ok(Scope::LinksRead),
+            "links:write" => Ok(Scope::LinksWrite),
+            "qr:read" => Ok(Scope::QrRead),
+            "qr:write" => Ok(Scope::QrWrite),
+            "analytics:read" => Ok(Scope::AnalyticsRead),
+            other => Err(anyhow::anyhow!("Unknown scope: {}", other)),
+        }
+    }
+}
+
+impl ApiKey {
+    pub fn new(
+        user_id: String,
+        name: String,
+        key_prefix: String,
+        key_hash: String,
+        scopes: Vec<Scope>,
+        expires_in_days: Option<u32>,
+    ) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+        let expires_at = expires_in_days.map(|days| now + (days as i64 * 24 * 60 * 60 * 1000)); // Add days in milliseconds
+
+        Self {
+            id: None,
+            user_id,
+            name,
+            key_prefix,
+            key_hash,
+            scopes,
+            created_at: now,
+            expires_at,
+            last_used_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| chrono::Utc::now().timestamp_millis() > expires_at)
+    }
+}
@@ -0,0 +1,291 @@
+use std::str::FromStr;
+
+use mongodb::bson::oid::ObjectId;
+use serde::de::IntoDeserializer;
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A record of a change made through the API. Audit events are only ever appended,
+/// never updated or deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub actor_id: Option<String>, // User who made the change, if known
+    pub actor_username: Option<String>,
+    pub action: AuditAction,
+    pub target_type: AuditTarget,
+    pub target_id: String, // User ID, short code or workspace ID of the target
+    pub changes: Option<Value>, // Changed fields as {"field": {"before": .., "after": ..}}
+    pub ip_hash: Option<String>, // Keyed hash of the client IP address
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    #[serde(rename = "user.create")]
+    UserCreate,
+    #[serde(rename = "user.update")]
+    UserUpdate,
+    #[serde(rename = "user.delete")]
+    UserDelete,
+    #[serde(rename = "user.unlock")]
+    UserUnlock,
+    #[serde(rename = "user.totp_reset")]
+    UserTotpReset,
+    #[serde(rename = "user.signup")]
+    UserSignup,
+    #[serde(rename = "user.init")]
+    UserInit,
+    #[serde(rename = "auth.login")]
+    AuthLogin,
+    #[serde(rename = "auth.login_failed")]
+    AuthLoginFailed,
+    #[serde(rename = "auth.login_locked")]
+    AuthLoginLocked, // Attempt on a locked or throttled account, answered like a wrong password
+    #[serde(rename = "auth.logout")]
+    AuthLogout,
+    #[serde(rename = "auth.refresh_reuse")]
+    AuthRefreshReuse,
+    #[serde(rename = "url.create")]
+    UrlCreate,
+    #[serde(rename = "url.update")]
+    UrlUpdate,
+    #[serde(rename = "url.delete")]
+    UrlDelete,
+    #[serde(rename = "qr.regenerate")]
+    QrRegenerate,
+    #[serde(rename = "workspace.create")]
+    WorkspaceCreate,
+    #[serde(rename = "workspace.update")]
+    WorkspaceUpdate,
+    #[serde(rename = "workspace.delete")]
+    WorkspaceDelete,
+    #[serde(rename = "workspace.member_add")]
+    WorkspaceMemberAdd,
+    #[serde(rename = "workspace.member_update")]
+    WorkspaceMemberUpdate,
+    #[serde(rename = "workspace.member_remove")]
+    WorkspaceMemberRemove,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::UserCreate => "user.create",
+            AuditAction::UserUpdate => "user.update",
+            AuditAction::UserDelete => "user.delete",
+            AuditAction::UserUnlock => "user.unlock",
+            AuditAction::UserTotpReset => "user.totp_reset",
+            AuditAction::UserSignup => "user.signup",
+            AuditAction::UserInit => "user.init",
+            AuditAction::AuthLogin => "auth.login",
+            AuditAction::AuthLoginFailed => "auth.login_failed",
+            AuditAction::AuthLoginLocked => "auth.login_locked",
+            AuditAction::AuthLogout => "auth.logout",
+            AuditAction::AuthRefreshReuse => "auth.refresh_reuse",
+            AuditAction::UrlCreate => "url.create",
+            AuditAction::UrlUpdate => "url.update",
+            AuditAction::UrlDelete => "url.delete",
+            AuditAction::QrRegenerate => "qr.regenerate",
+            AuditAction::WorkspaceCreate => "workspace.create",
+            AuditAction::WorkspaceUpdate => "workspace.update",
+            AuditAction::WorkspaceDelete => "workspace.delete",
+            AuditAction::WorkspaceMemberAdd => "workspace.member_add",
+            AuditAction::WorkspaceMemberUpdate => "workspace.member_update",
+            AuditAction::WorkspaceMemberRemove => "workspace.member_remove",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = anyhow::Error;
+
+    /// Parsed with the serde names, so the two cannot disagree
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let deserializer: StrDeserializer<serde::de::value::Error> = s.into_deserializer();
+        AuditAction::deserialize(deserializer)
+            .map_err(|_| anyhow::anyhow!("Unknown audit action: {}", s))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditTarget {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "url")]
+    Url,
+    #[serde(rename = "qr_code")]
+    QrCode,
+    #[serde(rename = "workspace")]
+    Workspace,
+}
+
+impl AuditTarget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditTarget::User => "user",
+            AuditTarget::Url => "url",
+            AuditTarget::QrCode => "qr_code",
+            AuditTarget::Workspace => "workspace",
+        }
+    }
+}
+
+impl FromStr for AuditTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(AuditTarget::User),
+            "url" => Ok(AuditTarget::Url),
+            "qr_code" => Ok(AuditTarget::QrCode),
+            "workspace" => Ok(AuditTarget::Workspace),
+            other => Err(anyhow::anyhow!("Unknown audit target: {}", other)),
+        }
+    }
+}
+
+impl AuditEvent {
+    /// An event without an actor, IP hash or changes yet; the handler fills those in
+    pub fn new(
+        action: AuditAction,
+        target_type: AuditTarget,
+        target_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: None,
+            actor_id: None,
+            actor_username: None,
+            action,
+            target_type,
+            target_id: target_id.into(),
+            changes: None,
+            ip_hash: None,
+            created_at: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Set the actor, for requests that are not made by a logged-in user (e.g. logins)
+    pub fn with_actor(
+        mut self,
+        actor_id: impl Into<String>,
+        actor_username: impl Into<String>,
+    ) -> Self {
+        self.actor_id = Some(actor_id.into());
+        self.actor_username = Some(actor_username.into());
+        self
+    }
+
+    /// Record the fields that differ between two serialized versions of the target.
+    /// A created target has no `before` and a deleted one no `after`.
+    pub fn with_changes<T: Serialize>(mut self, before: Option<&T>, after: Option<&T>) -> Self {
+        let fields = |value: Option<&T>| match value.map(serde_json::to_value) {
+            Some(Ok(Value::Object(fields))) => fields,
+            _ => Map::new(),
+        };
+        let before = fields(before);
+        let after = fields(after);
+
+        let mut changes = Map::new();
+        for key in before
+            .keys()
+            .chain(after.keys().filter(|key| !before.contains_key(*key)))
+        {
+            // A missing field counts as null, so only fields with a value show up on creation
+            let old = before.get(key).unwrap_or(&Value::Null);
+            let new = after.get(key).unwrap_or(&Value::Null);
+            if old != new {
+                changes.insert(
+                    key.clone(),
+                    serde_json::json!({ "before": old, "after": new }),
+                );
+            }
+        }
+
+        if !changes.is_empty() {
+            self.changes = Some(Value::Object(changes));
+        }
+        self
+    }
+
+    /// Record a change of a field whose values must not be stored, such as a password
+    pub fn with_redacted_change(mut self, field: &str) -> Self {
+        let changes = self
+            .changes
+            .get_or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(changes) = changes {
+            changes.insert(
+                field.to_string(),
+                serde_json::json!({ "before": "[redacted]", "after": "[redacted]" }),
+            );
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_names_match_serde() {
+        let actions = [
+            AuditAction::UserCreate,
+            AuditAction::UserUpdate,
+            AuditAction::UserDelete,
+            AuditAction::UserUnlock,
+            AuditAction::UserTotpReset,
+            AuditAction::UserSignup,
+            AuditAction::UserInit,
+            AuditAction::AuthLogin,
+            AuditAction::AuthLoginFailed,
+            AuditAction::AuthLoginLocked,
+            AuditAction::AuthLogout,
+            AuditAction::AuthRefreshReuse,
+            AuditAction::UrlCreate,
+            AuditAction::UrlUpdate,
+            AuditAction::UrlDelete,
+            AuditAction::QrRegenerate,
+            AuditAction::WorkspaceCreate,
+            AuditAction::WorkspaceUpdate,
+            AuditAction::WorkspaceDelete,
+            AuditAction::WorkspaceMemberAdd,
+            AuditAction::WorkspaceMemberUpdate,
+            AuditAction::WorkspaceMemberRemove,
+        ];
+        for action in actions {
+            // Fails to compile on a new variant, which then has to be listed above as well
+            match action {
+                AuditAction::UserCreate
+                | AuditAction::UserUpdate
+                | AuditAction::UserDelete
+                | AuditAction::UserUnlock
+                | AuditAction::UserTotpReset
+                | AuditAction::UserSignup
+                | AuditAction::UserInit
+                | AuditAction::AuthLogin
+                | AuditAction::AuthLoginFailed
+                | AuditAction::AuthLoginLocked
+                | AuditAction::AuthLogout
+                | AuditAction::AuthRefreshReuse
+                | AuditAction::UrlCreate
+                | AuditAction::UrlUpdate
+                | AuditAction::UrlDelete
+                | AuditAction::QrRegenerate
+                | AuditAction::WorkspaceCreate
+                | AuditAction::WorkspaceUpdate
+                | AuditAction::WorkspaceDelete
+                | AuditAction::WorkspaceMemberAdd
+                | AuditAction::WorkspaceMemberUpdate
+                | AuditAction::WorkspaceMemberRemove => {}
+            }
+
+            let name = serde_json::to_value(action).unwrap();
+            assert_eq!(name, action.as_str());
+            assert_eq!(action.as_str().parse::<AuditAction>().unwrap(), action);
+        }
+        assert!("user.nonsense".parse::<AuditAction>().is_err());
+    }
+}
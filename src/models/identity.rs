@@ -0,0 +1,32 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// An account at an external identity provider that can log in as a user.
+/// The provider identifies the account by its issuer and subject.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalIdentity {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>, // Address the provider reported at the first login
+    pub created_at: i64,
+    pub last_login_at: Option<i64>,
+}
+
+impl ExternalIdentity {
+    pub fn new(user_id: String, issuer: String, subject: String, email: Option<String>) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        Self {
+            id: None,
+            user_id,
+            issuer,
+            subject,
+            email,
+            created_at: now,
+            last_login_at: Some(now),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::Role;
+
+/// An invitation to sign up while public signup is disabled. Only the SHA-256 hash of
+/// the code is stored; the code itself is shown once, when the invite is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub code_prefix: String, // First characters of the code, so admins can tell invites apart
+    pub code_hash: String,
+    pub email: Option<String>, // Only this address can sign up with the invite, if set
+    pub role: Role,            // Role of the users who sign up with the invite
+    pub max_uses: i64,
+    pub uses: Vec<InviteUse>,
+    pub created_by: String, // ID of the admin who created the invite
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// A signup made with an invite
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteUse {
+    pub user_id: String,
+    pub username: String,
+    pub used_at: i64,
+}
+
+impl Invite {
+    pub fn new(
+        code_prefix: String,
+        code_hash: String,
+        email: Option<String>,
+        role: Role,
+        max_uses: u32,
+        created_by: String,
+        expires_in_days: Option<u32>,
+    ) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+        let expires_at = expires_in_days.map(|days| now + (days as i64 * 24 * 60 * 60 * 1000)); // Add days in milliseconds
+
+        Self {
+            id: None,
+            code_prefix,
+            code_hash,
+            email,
+            role,
+            max_uses: max_uses as i64,
+            uses: Vec::new(),
+            created_by,
+            created_at: now,
+            expires_at,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| chrono::Utc::now().timestamp_millis() > expires_at)
+    }
+
+    pub fn is_used_up(&self) -> bool {
+        self.uses.len() as i64 >= self.max_uses
+    }
+}
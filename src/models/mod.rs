@@ -1,4 +1,14 @@
+pub mod api_key;
+pub mod audit_event;
+pub mod identity;
+pub mod invite;
+pub mod one_time_token;
+pub mod passkey;
 pub mod qr_code;
+pub mod refresh_token;
+pub mod totp;
 pub mod url;
+pub mod url_version;
 pub mod url_visitor;
 pub mod user;
+pub mod workspace;
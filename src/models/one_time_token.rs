@@ -0,0 +1,69 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A single-use token mailed to a user, e.g. to reset their password.
+/// Only the SHA-256 hash of the token is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OneTimeToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub token_hash: String,
+    pub user_id: String,
+    pub purpose: TokenPurpose,
+    pub email: String, // Address the token was sent to
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub used_at: Option<i64>, // Set once the token has been used or replaced
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::EmailVerification => "email_verification",
+        }
+    }
+}
+
+impl FromStr for TokenPurpose {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "password_reset" => Ok(TokenPurpose::PasswordReset),
+            "email_verification" => Ok(TokenPurpose::EmailVerification),
+            other => Err(anyhow::anyhow!("Unknown token purpose: {}", other)),
+        }
+    }
+}
+
+impl OneTimeToken {
+    pub fn new(
+        token_hash: String,
+        user_id: String,
+        purpose: TokenPurpose,
+        email: String,
+        lifetime_minutes: i64,
+    ) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        Self {
+            id: None,
+            token_hash,
+            user_id,
+            purpose,
+            email,
+            created_at: now,
+            expires_at: now + lifetime_minutes * 60 * 1000, // Add minutes in milliseconds
+            used_at: None,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A WebAuthn credential (passkey) registered by a user. Only the public key is stored;
+/// the private key never leaves the authenticator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Passkey {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub credential_id: String, // Base64url credential ID chosen by the authenticator
+    pub name: String,
+    pub public_key: String,      // Base64url COSE public key
+    pub sign_count: i64, // Signature counter of the last login, to detect cloned authenticators
+    pub transports: Vec<String>, // How the browser can reach the authenticator, e.g. usb or internal
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+impl Passkey {
+    pub fn new(
+        user_id: String,
+        credential_id: String,
+        name: String,
+        public_key: String,
+        sign_count: i64,
+        transports: Vec<String>,
+    ) -> Self {
+        Self {
+            id: None,
+            user_id,
+            credential_id,
+            name,
+            public_key,
+            sign_count,
+            transports,
+            created_at: chrono::Utc::now().timestamp_millis(),
+            last_used_at: None,
+        }
+    }
+}
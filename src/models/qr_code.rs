@@ -1,5 +1,6 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct QrCode {
@@ -11,6 +12,8 @@ pub struct QrCode {
     pub generated_at: i64,       // When the QR code was generated (timestamp in milliseconds)
     pub target_type: TargetType, // Whether the QR points to the original or shortened URL
     pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>, // Workspace that owns the QR code, if any
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -21,6 +24,27 @@ pub enum TargetType {
     Shortened,
 }
 
+impl TargetType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetType::Original => "original",
+            TargetType::Shortened => "shortened",
+        }
+    }
+}
+
+impl FromStr for TargetType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(TargetType::Original),
+            "shortened" => Ok(TargetType::Shortened),
+            other => Err(anyhow::anyhow!("Unknown target type: {}", other)),
+        }
+    }
+}
+
 impl QrCode {
     pub fn new(
         short_code: String,
@@ -28,6 +52,7 @@ impl QrCode {
         svg_content: String,
         target_type: TargetType,
         user_id: Option<String>,
+        workspace_id: Option<String>,
     ) -> Self {
         Self {
             id: None,
@@ -37,6 +62,7 @@ impl QrCode {
             generated_at: chrono::Utc::now().timestamp_millis(),
             target_type,
             user_id,
+            workspace_id,
         }
     }
 }
@@ -0,0 +1,44 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A server-side refresh token. Only the SHA-256 hash of the token is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub token_hash: String,
+    pub user_id: String,
+    pub family_id: String, // Shared by every token rotated from the same login
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>, // Set once the token has been rotated or revoked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>, // Workspace the session had switched to
+}
+
+impl RefreshToken {
+    pub fn new(
+        token_hash: String,
+        user_id: String,
+        family_id: String,
+        workspace_id: Option<String>,
+        lifetime_days: i64,
+    ) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        Self {
+            id: None,
+            token_hash,
+            user_id,
+            family_id,
+            created_at: now,
+            expires_at: now + lifetime_days * 24 * 60 * 60 * 1000, // Add days in milliseconds
+            revoked_at: None,
+            workspace_id,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        chrono::Utc::now().timestamp_millis() > self.expires_at
+    }
+}
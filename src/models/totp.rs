@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+/// TOTP second factor of a user. The secret stays pending until the user
+/// confirms it with a code from their authenticator app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpCredential {
+    pub user_id: String,
+    pub secret: String, // Base32 encoded shared secret
+    pub enabled: bool,
+    pub last_used_step: Option<i64>, // Time step of the last accepted code, so codes cannot be replayed
+    pub recovery_codes: Vec<String>, // SHA-256 hashes of the unused recovery codes
+    pub created_at: i64,
+    pub enabled_at: Option<i64>,
+}
+
+impl TotpCredential {
+    pub fn new(user_id: String, secret: String) -> Self {
+        Self {
+            user_id,
+            secret,
+            enabled: false,
+            last_used_step: None,
+            recovery_codes: Vec::new(),
+            created_at: chrono::Utc::now().timestamp_millis(),
+            enabled_at: None,
+        }
+    }
+}
@@ -14,6 +14,20 @@ pub struct ShortenedUrl {
     #[serde(default)]
     pub clicks: i64, // Number of clicks/redirects tracked
     pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>, // Name to recognize the URL by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>, // Visitors have to enter the password before being redirected
+    #[serde(default = "first_version")]
+    pub version: i64, // Incremented by every edit; earlier versions are kept as `UrlVersion`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>, // Workspace that owns the URL, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_at: Option<i64>, // Set when the owner was deleted; the URL no longer redirects
+}
+
+fn first_version() -> i64 {
+    1
 }
 
 impl ShortenedUrl {
@@ -21,7 +35,9 @@ impl ShortenedUrl {
         original_url: String,
         short_code: String,
         expires_in_days: Option<u32>,
+        title: Option<String>,
         user_id: Option<String>,
+        workspace_id: Option<String>,
     ) -> Self {
         let now = chrono::Utc::now().timestamp_millis();
         let expires_at = expires_in_days.map(|days| now + (days as i64 * 24 * 60 * 60 * 1000)); // Add days in milliseconds
@@ -34,6 +50,11 @@ impl ShortenedUrl {
             expires_at,
             clicks: 0,
             user_id,
+            title,
+            password_hash: None,
+            version: first_version(),
+            workspace_id,
+            disabled_at: None,
         }
     }
 
@@ -0,0 +1,34 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::models::url::ShortenedUrl;
+
+/// An earlier state of a shortened URL, kept when an edit replaced it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UrlVersion {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub short_code: String,
+    pub version: i64, // Version of the URL this state belonged to
+    pub original_url: String,
+    pub expires_at: Option<i64>,
+    pub title: Option<String>,
+    pub replaced_at: i64, // When the edit was made (timestamp in milliseconds)
+    pub replaced_by: Option<String>, // User who made the edit
+}
+
+impl UrlVersion {
+    /// Record the current state of a URL that is about to be edited
+    pub fn of(url: &ShortenedUrl, replaced_by: Option<String>) -> Self {
+        Self {
+            id: None,
+            short_code: url.short_code.clone(),
+            version: url.version,
+            original_url: url.original_url.clone(),
+            expires_at: url.expires_at,
+            title: url.title.clone(),
+            replaced_at: chrono::Utc::now().timestamp_millis(),
+            replaced_by,
+        }
+    }
+}
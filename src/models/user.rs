@@ -1,13 +1,15 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub username: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
+    pub email_verified_at: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub full_name: Option<String>,
     pub password_hash: String,
@@ -15,6 +17,47 @@ pub struct User {
     pub updated_at: i64,
     pub last_login: Option<i64>,
     pub is_active: bool,
+    #[serde(default)]
+    pub role: Role, // Users created before roles existed are treated as members
+    #[serde(default)]
+    pub failed_login_attempts: i64, // Consecutive failures since the last successful login or lockout
+    pub last_failed_login: Option<i64>,
+    pub locked_at: Option<i64>, // Start of the most recent lockout
+    pub locked_until: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Role {
+    #[serde(rename = "admin")]
+    Admin,
+    #[serde(rename = "member")]
+    #[default]
+    Member,
+    #[serde(rename = "read_only")]
+    ReadOnly,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Member => "member",
+            Role::ReadOnly => "read_only",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "member" => Ok(Role::Member),
+            "read_only" => Ok(Role::ReadOnly),
+            other => Err(anyhow::anyhow!("Unknown role: {}", other)),
+        }
+    }
 }
 
 impl User {
@@ -23,6 +66,7 @@ impl User {
         email: Option<String>,
         full_name: Option<String>,
         password_hash: String,
+        role: Role,
     ) -> Self {
         let now = chrono::Utc::now().timestamp_millis();
 
@@ -30,42 +74,23 @@ impl User {
             id: None,
             username,
             email,
+            email_verified_at: None,
             full_name,
             password_hash,
             created_at: now,
             updated_at: now,
             last_login: None,
             is_active: true,
+            role,
+            failed_login_attempts: 0,
+            last_failed_login: None,
+            locked_at: None,
+            locked_until: None,
         }
     }
 
-    pub fn update_last_login(&mut self) {
-        self.last_login = Some(chrono::Utc::now().timestamp_millis());
-    }
-}
-
-// For API responses - stripped of sensitive data
-#[derive(Serialize)]
-pub struct UserResponse {
-    pub id: String,
-    pub username: String,
-    pub email: Option<String>,
-    pub full_name: Option<String>,
-    pub created_at: i64,
-    pub last_login: Option<i64>,
-    pub is_active: bool,
-}
-
-impl From<User> for UserResponse {
-    fn from(user: User) -> Self {
-        Self {
-            id: user.id.map(|id| id.to_hex()).unwrap_or_default(),
-            username: user.username,
-            email: user.email,
-            full_name: user.full_name,
-            created_at: user.created_at,
-            last_login: user.last_login,
-            is_active: user.is_active,
-        }
+    pub fn is_locked(&self) -> bool {
+        self.locked_until
+            .is_some_and(|locked_until| chrono::Utc::now().timestamp_millis() < locked_until)
     }
 }
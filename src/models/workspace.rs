@@ -0,0 +1,118 @@
+use std::str::FromStr;
+
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A team that owns short URLs and QR codes together, so they outlive any one member
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Workspace {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub members: Vec<WorkspaceMember>,
+    pub created_by: String, // ID of the user who created the workspace
+    pub created_at: i64,
+    pub updated_at: i64, // Last change to the workspace or its members
+}
+
+/// A user's membership of a workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceMember {
+    pub user_id: String,
+    pub role: WorkspaceRole,
+    pub added_at: i64,
+}
+
+/// What a member may do in a workspace. Each role includes the ones below it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorkspaceRole {
+    #[serde(rename = "owner")]
+    Owner, // Manage the workspace and its members
+    #[serde(rename = "editor")]
+    #[default]
+    Editor, // Create and delete the workspace's links and QR codes
+    #[serde(rename = "viewer")]
+    Viewer, // List the workspace's links and QR codes
+}
+
+impl WorkspaceRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkspaceRole::Owner => "owner",
+            WorkspaceRole::Editor => "editor",
+            WorkspaceRole::Viewer => "viewer",
+        }
+    }
+
+    /// Whether this role permits what `required` permits
+    pub fn includes(&self, required: WorkspaceRole) -> bool {
+        self.rank() >= required.rank()
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            WorkspaceRole::Owner => 2,
+            WorkspaceRole::Editor => 1,
+            WorkspaceRole::Viewer => 0,
+        }
+    }
+}
+
+impl FromStr for WorkspaceRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(WorkspaceRole::Owner),
+            "editor" => Ok(WorkspaceRole::Editor),
+            "viewer" => Ok(WorkspaceRole::Viewer),
+            other => Err(anyhow::anyhow!("Unknown workspace role: {}", other)),
+        }
+    }
+}
+
+impl Workspace {
+    /// A new workspace, owned by the user who creates it
+    pub fn new(name: String, created_by: String) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        Self {
+            id: None,
+            name,
+            members: vec![WorkspaceMember::new(
+                created_by.clone(),
+                WorkspaceRole::Owner,
+            )],
+            created_by,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// The role of a user in the workspace, if they are a member
+    pub fn role_of(&self, user_id: &str) -> Option<WorkspaceRole> {
+        self.members
+            .iter()
+            .find(|member| member.user_id == user_id)
+            .map(|member| member.role)
+    }
+
+    /// Whether the user is the only owner of the workspace
+    pub fn is_last_owner(&self, user_id: &str) -> bool {
+        self.role_of(user_id) == Some(WorkspaceRole::Owner)
+            && !self
+                .members
+                .iter()
+                .any(|member| member.user_id != user_id && member.role == WorkspaceRole::Owner)
+    }
+}
+
+impl WorkspaceMember {
+    pub fn new(user_id: String, role: WorkspaceRole) -> Self {
+        Self {
+            user_id,
+            role,
+            added_at: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::api_key::ApiKey;
+
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    /// Store a new API key and return it with its assigned ID
+    async fn insert(&self, key: &ApiKey) -> Result<ApiKey>;
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>>;
+
+    /// List the keys of a user, oldest first
+    async fn find_by_user(&self, user_id: &str) -> Result<Vec<ApiKey>>;
+
+    /// Find a key by ID, only if it belongs to the given user
+    async fn find_by_id(&self, user_id: &str, id: &ObjectId) -> Result<Option<ApiKey>>;
+
+    async fn set_last_used(&self, id: &ObjectId, timestamp: i64) -> Result<()>;
+
+    /// Delete a key of the given user, returning whether it existed
+    async fn delete(&self, user_id: &str, id: &ObjectId) -> Result<bool>;
+
+    /// Delete every key of a user, returning how many were removed
+    async fn delete_by_user(&self, user_id: &str) -> Result<u64>;
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::audit_event::{AuditAction, AuditEvent};
+
+/// Criteria for listing audit events
+#[derive(Debug, Default, Clone)]
+pub struct AuditFilter {
+    pub actor: Option<String>, // User ID or username of the actor
+    pub action: Option<AuditAction>,
+    pub from: Option<i64>, // Only events at or after this time (milliseconds)
+    pub to: Option<i64>,   // Only events before this time (milliseconds)
+    pub before: Option<AuditCursor>, // Only events older than this one, to fetch the next page
+}
+
+/// Position of an event in the newest-first order of the audit log
+#[derive(Debug, Clone, Copy)]
+pub struct AuditCursor {
+    pub created_at: i64,
+    pub id: ObjectId,
+}
+
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    /// Append an event to the audit log
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()>;
+
+    /// List events matching the filter, newest first, at most `limit` of them
+    async fn find_audit_events(&self, filter: &AuditFilter, limit: i64) -> Result<Vec<AuditEvent>>;
+}
@@ -0,0 +1,19 @@
+use std::fmt;
+
+/// Returned by repositories when a write would violate a uniqueness constraint,
+/// e.g. a short code or username that is already taken
+#[derive(Debug)]
+pub struct DuplicateKeyError;
+
+impl fmt::Display for DuplicateKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Duplicate key")
+    }
+}
+
+impl std::error::Error for DuplicateKeyError {}
+
+/// Check whether a repository error was caused by a uniqueness violation
+pub fn is_duplicate_key(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<DuplicateKeyError>().is_some()
+}
@@ -0,0 +1,8 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait HealthRepository: Send + Sync {
+    /// Check that the storage backend is reachable
+    async fn ping(&self) -> Result<()>;
+}
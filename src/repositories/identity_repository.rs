@@ -0,0 +1,19 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::models::identity::ExternalIdentity;
+
+/// Links between users and their accounts at external identity providers
+#[async_trait]
+pub trait IdentityRepository: Send + Sync {
+    async fn find_identity(&self, issuer: &str, subject: &str) -> Result<Option<ExternalIdentity>>;
+
+    /// Store a new identity. Fails with `DuplicateKeyError` if the issuer and
+    /// subject are already linked to a user.
+    async fn insert_identity(&self, identity: &ExternalIdentity) -> Result<ExternalIdentity>;
+
+    async fn set_identity_login(&self, issuer: &str, subject: &str, timestamp: i64) -> Result<()>;
+
+    /// Delete every identity of a user, returning how many were removed
+    async fn delete_identities_by_user(&self, user_id: &str) -> Result<u64>;
+}
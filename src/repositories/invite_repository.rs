@@ -0,0 +1,25 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::invite::{Invite, InviteUse};
+
+#[async_trait]
+pub trait InviteRepository: Send + Sync {
+    /// Store a new invite and return it with its assigned ID
+    async fn insert_invite(&self, invite: &Invite) -> Result<Invite>;
+
+    /// List every invite, newest first
+    async fn find_invites(&self) -> Result<Vec<Invite>>;
+
+    async fn find_invite(&self, id: &ObjectId) -> Result<Option<Invite>>;
+
+    async fn find_invite_by_hash(&self, code_hash: &str) -> Result<Option<Invite>>;
+
+    /// Record a signup with an invite, only if the invite has not expired at `used.used_at`
+    /// and has uses left. Returns whether it was recorded.
+    async fn redeem_invite(&self, id: &ObjectId, used: &InviteUse) -> Result<bool>;
+
+    /// Delete an invite, returning whether it existed
+    async fn delete_invite(&self, id: &ObjectId) -> Result<bool>;
+}
@@ -0,0 +1,14 @@
+pub mod api_key_repository;
+pub mod audit_repository;
+pub mod errors;
+pub mod health_repository;
+pub mod identity_repository;
+pub mod invite_repository;
+pub mod passkey_repository;
+pub mod qr_code_repository;
+pub mod token_repository;
+pub mod totp_repository;
+pub mod url_repository;
+pub mod user_repository;
+pub mod visitor_repository;
+pub mod workspace_repository;
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::passkey::Passkey;
+
+/// WebAuthn credentials, any number per user
+#[async_trait]
+pub trait PasskeyRepository: Send + Sync {
+    /// Store a new passkey and return it with its assigned ID. Fails with
+    /// `DuplicateKeyError` if the credential ID is already registered.
+    async fn insert_passkey(&self, passkey: &Passkey) -> Result<Passkey>;
+
+    async fn find_passkey(&self, credential_id: &str) -> Result<Option<Passkey>>;
+
+    /// List the passkeys of a user, oldest first
+    async fn find_passkeys_by_user(&self, user_id: &str) -> Result<Vec<Passkey>>;
+
+    /// Record a login with a passkey and its new signature counter
+    async fn record_passkey_use(
+        &self,
+        credential_id: &str,
+        sign_count: i64,
+        used_at: i64,
+    ) -> Result<()>;
+
+    /// Delete a passkey of the given user, returning whether it existed
+    async fn delete_passkey(&self, user_id: &str, id: &ObjectId) -> Result<bool>;
+
+    /// Delete every passkey of a user, returning how many were removed
+    async fn delete_passkeys_by_user(&self, user_id: &str) -> Result<u64>;
+}
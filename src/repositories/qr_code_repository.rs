@@ -0,0 +1,38 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::models::qr_code::{QrCode, TargetType};
+
+/// Criteria for listing QR codes
+#[derive(Debug, Default, Clone)]
+pub struct QrCodeFilter {
+    pub search: Option<String>, // Case-insensitive match on short code or original URL
+    pub target_type: Option<TargetType>,
+    pub direct_only: bool, // Only QR codes generated without a short URL
+    pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
+}
+
+#[async_trait]
+pub trait QrCodeRepository: Send + Sync {
+    async fn find_by_code(&self, code: &str, target_type: &TargetType) -> Result<Option<QrCode>>;
+
+    /// Find the direct QR code generated for an original URL, if any
+    async fn find_direct_by_url(&self, url: &str) -> Result<Option<QrCode>>;
+
+    async fn find(&self, filter: &QrCodeFilter) -> Result<Vec<QrCode>>;
+
+    /// Insert a QR code, or replace the SVG of the existing one with the same
+    /// short code and target type
+    async fn upsert(&self, qr_code: &QrCode) -> Result<()>;
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64>;
+
+    /// Point the QR codes of a short code at a new original URL. QR codes that encode
+    /// the original URL are deleted, as their image is out of date; the others only get
+    /// the new URL. Returns the number of deleted QR codes.
+    async fn retarget(&self, code: &str, original_url: &str) -> Result<u64>;
+
+    /// Hand the QR codes of a deleted workspace back to the users who generated them
+    async fn clear_workspace(&self, workspace_id: &str) -> Result<u64>;
+}
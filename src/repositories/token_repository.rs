@@ -0,0 +1,70 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::models::one_time_token::{OneTimeToken, TokenPurpose};
+use crate::models::refresh_token::RefreshToken;
+
+/// Refresh tokens, the access token revocation list and single-use mailed tokens
+#[async_trait]
+pub trait TokenRepository: Send + Sync {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<()>;
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>>;
+
+    /// Unrevoked refresh tokens of a user that expire after `now`, newest first.
+    /// Each one is the current token of an active session.
+    async fn find_active_refresh_tokens(
+        &self,
+        user_id: &str,
+        now: i64,
+    ) -> Result<Vec<RefreshToken>>;
+
+    /// Revoke a single refresh token. Returns false if it was already revoked,
+    /// so concurrent requests cannot rotate the same token twice.
+    async fn revoke_refresh_token(&self, token_hash: &str, revoked_at: i64) -> Result<bool>;
+
+    /// Revoke every refresh token rotated from the same login
+    async fn revoke_refresh_family(&self, family_id: &str, revoked_at: i64) -> Result<()>;
+
+    /// Add an access token to the revocation list until it expires. Returns false if
+    /// the token was already on the list, so one-shot tokens can be used up atomically.
+    async fn revoke_access_token(&self, jti: &str, expires_at: i64) -> Result<bool>;
+
+    /// Revoke every refresh token of a user and every access token issued to them before
+    /// `revoked_at`, so tokens of a login right after the revocation stay valid
+    async fn revoke_user_tokens(&self, user_id: &str, revoked_at: i64) -> Result<()>;
+
+    /// Whether an access token was revoked, either by itself or together with all of its user's tokens
+    async fn is_access_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at: i64,
+    ) -> Result<bool>;
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<()>;
+
+    /// Mark an unused, unexpired token of the given purpose as used and return it.
+    /// Returns `None` otherwise, so each token works only once.
+    async fn use_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        used_at: i64,
+    ) -> Result<Option<OneTimeToken>>;
+
+    /// Invalidate every unused token of a user for the given purpose
+    async fn invalidate_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+        at: i64,
+    ) -> Result<()>;
+
+    /// When the newest token of a user for the given purpose was issued, if ever
+    async fn last_one_time_token_at(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<i64>>;
+}
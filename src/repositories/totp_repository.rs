@@ -0,0 +1,35 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::models::totp::TotpCredential;
+
+/// TOTP secrets and recovery codes, at most one credential per user
+#[async_trait]
+pub trait TotpRepository: Send + Sync {
+    async fn find_totp(&self, user_id: &str) -> Result<Option<TotpCredential>>;
+
+    /// Store a pending credential, replacing any earlier one of the same user
+    async fn save_pending_totp(&self, credential: &TotpCredential) -> Result<()>;
+
+    /// Enable a pending credential with a fresh set of recovery codes.
+    /// Returns false if there is no pending credential.
+    async fn enable_totp(
+        &self,
+        user_id: &str,
+        recovery_codes: &[String],
+        enabled_at: i64,
+    ) -> Result<bool>;
+
+    /// Replace the recovery codes of an enabled credential
+    async fn set_recovery_codes(&self, user_id: &str, recovery_codes: &[String]) -> Result<()>;
+
+    /// Record that the code of a time step was used. Returns false if a code of
+    /// this or a later step was already accepted, so each code works only once.
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool>;
+
+    /// Remove a recovery code, returning whether it was still unused
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> Result<bool>;
+
+    /// Remove the credential of a user, returning whether there was one
+    async fn delete_totp(&self, user_id: &str) -> Result<bool>;
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::models::url::ShortenedUrl;
+use crate::models::url_version::UrlVersion;
+
+/// Criteria for listing shortened URLs
+#[derive(Debug, Default, Clone)]
+pub struct UrlFilter {
+    pub search: Option<String>, // Case-insensitive match on short code or original URL
+    pub user_id: Option<String>, // Only URLs owned by this user
+    pub workspace_id: Option<String>, // Only URLs owned by this workspace
+}
+
+#[async_trait]
+pub trait UrlRepository: Send + Sync {
+    /// Store a new shortened URL and return it with its assigned ID
+    async fn insert(&self, url: &ShortenedUrl) -> Result<ShortenedUrl>;
+
+    async fn find_by_code(&self, code: &str) -> Result<Option<ShortenedUrl>>;
+
+    async fn find(&self, filter: &UrlFilter) -> Result<Vec<ShortenedUrl>>;
+
+    async fn increment_clicks(&self, code: &str) -> Result<()>;
+
+    /// Store the destination, expiry, title, password and version of an edited URL, provided it is
+    /// still at the version recorded in `previous`, and keep `previous` in its history.
+    /// Returns false if the URL was edited or deleted in the meantime.
+    async fn update_with_history(&self, url: &ShortenedUrl, previous: &UrlVersion) -> Result<bool>;
+
+    /// Earlier versions of a URL, newest first
+    async fn find_versions(&self, code: &str) -> Result<Vec<UrlVersion>>;
+
+    /// Delete a URL and its history by short code, returning whether it existed
+    async fn delete_by_code(&self, code: &str) -> Result<bool>;
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::user::{Role, User};
+
+/// Fields to change on a user; `None` leaves the field untouched
+#[derive(Debug, Default, Clone)]
+pub struct UserUpdate {
+    pub username: Option<String>,
+    pub email: Option<String>, // Setting an address also marks it as unverified
+    pub full_name: Option<String>,
+    pub password_hash: Option<String>,
+    pub is_active: Option<bool>,
+    pub role: Option<Role>,
+}
+
+/// What happens to the personal short URLs and QR codes of a deleted user. Those owned
+/// by a workspace stay with it under every policy; only their creator is updated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourcePolicy {
+    Reassign(String), // Hand them to the user with this ID
+    Disable,          // Keep them without an owner; the URLs stop redirecting
+    Delete,           // Delete the URLs with their QR codes and analytics
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Store a new user and return it with its assigned ID
+    async fn insert(&self, user: &User) -> Result<User>;
+
+    async fn find_by_id(&self, id: &ObjectId) -> Result<Option<User>>;
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>>;
+
+    /// Find the users with the given email address, ignoring case
+    async fn find_by_email(&self, email: &str) -> Result<Vec<User>>;
+
+    /// List all users except the given one
+    async fn find_all_except(&self, id: &ObjectId) -> Result<Vec<User>>;
+
+    async fn count(&self) -> Result<u64>;
+
+    /// Apply an update and return the updated user, or `None` if it doesn't exist
+    async fn update(&self, id: &ObjectId, update: &UserUpdate) -> Result<Option<User>>;
+
+    /// Record a successful login and clear the failed login counter
+    async fn set_last_login(&self, id: &ObjectId, timestamp: i64) -> Result<()>;
+
+    /// Count a failed login and return the number of consecutive failures.
+    /// Failures before `reset_before` are forgotten and the count starts over.
+    async fn record_failed_login(&self, id: &ObjectId, at: i64, reset_before: i64) -> Result<i64>;
+
+    /// Lock the account until `locked_until` and clear the failed login counter
+    async fn lock(&self, id: &ObjectId, locked_at: i64, locked_until: i64) -> Result<()>;
+
+    /// Lift a lockout and clear the failed login counter, returning the user if it exists
+    async fn unlock(&self, id: &ObjectId) -> Result<Option<User>>;
+
+    /// Mark the email address of a user as verified. Returns false if the user
+    /// no longer has that address.
+    async fn set_email_verified(&self, id: &ObjectId, email: &str, at: i64) -> Result<bool>;
+
+    /// Delete a user, returning whether it existed
+    async fn delete(&self, id: &ObjectId) -> Result<bool>;
+
+    /// Delete a user and apply the policy to their URLs, QR codes and analytics as one
+    /// change, returning whether the user existed
+    async fn delete_with_resources(
+        &self,
+        id: &ObjectId,
+        policy: &ResourcePolicy,
+        at: i64,
+    ) -> Result<bool>;
+}
@@ -0,0 +1,16 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::models::url_visitor::UrlVisitor;
+
+#[async_trait]
+pub trait VisitorRepository: Send + Sync {
+    /// Record a visitor unless the same visitor hash was already seen for the short code.
+    /// Returns true if the visitor was new.
+    async fn insert_if_new(&self, visitor: &UrlVisitor) -> Result<bool>;
+
+    /// Count unique visitors of a short code
+    async fn count_by_code(&self, code: &str) -> Result<u64>;
+
+    async fn delete_by_code(&self, code: &str) -> Result<u64>;
+}
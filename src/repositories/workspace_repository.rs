@@ -0,0 +1,50 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::workspace::{Workspace, WorkspaceMember, WorkspaceRole};
+
+/// Outcome of changing or removing a membership
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberChange {
+    Changed,
+    NotMember,
+    LastOwner, // Refused, because the workspace would be left without an owner
+}
+
+#[async_trait]
+pub trait WorkspaceRepository: Send + Sync {
+    /// Store a new workspace and return it with its assigned ID
+    async fn insert_workspace(&self, workspace: &Workspace) -> Result<Workspace>;
+
+    async fn find_workspace(&self, id: &ObjectId) -> Result<Option<Workspace>>;
+
+    /// List the workspaces the user is a member of, or every workspace without a user,
+    /// newest first
+    async fn find_workspaces(&self, member_id: Option<&str>) -> Result<Vec<Workspace>>;
+
+    /// Rename a workspace, returning whether it exists
+    async fn rename_workspace(&self, id: &ObjectId, name: &str, at: i64) -> Result<bool>;
+
+    /// Delete a workspace with its memberships, returning whether it existed
+    async fn delete_workspace(&self, id: &ObjectId) -> Result<bool>;
+
+    /// Add a member, returning whether the workspace exists. A user who is already a
+    /// member is reported as `DuplicateKeyError`.
+    async fn add_member(&self, id: &ObjectId, member: &WorkspaceMember) -> Result<bool>;
+
+    /// Change a member's role; the last owner cannot be demoted
+    async fn set_member_role(
+        &self,
+        id: &ObjectId,
+        user_id: &str,
+        role: WorkspaceRole,
+        at: i64,
+    ) -> Result<MemberChange>;
+
+    /// Remove a member; the last owner cannot be removed
+    async fn remove_member(&self, id: &ObjectId, user_id: &str, at: i64) -> Result<MemberChange>;
+
+    /// Remove a user from every workspace, e.g. when the user is deleted
+    async fn remove_user_memberships(&self, user_id: &str) -> Result<u64>;
+}
@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod routes;
 
-pub use routes::init_routes;
+pub use routes::{api_key_scope, init_routes};
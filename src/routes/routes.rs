@@ -1,66 +1,310 @@
-use actix_web::web;
+use actix_web::http::Method;
+use actix_web::{HttpResponse, web};
 
-use crate::handlers::auth_handlers::{create_superuser, login, signup};
+use crate::errors::app_error::AppError;
+use crate::handlers::account_handlers::{
+    request_password_reset, resend_verification_email, reset_password, verify_email,
+};
+use crate::handlers::api_key_handlers::{
+    create_api_key, delete_api_key, get_api_key, get_api_keys,
+};
+use crate::handlers::audit_handlers::{export_audit_events, get_audit_events};
+use crate::handlers::auth_handlers::{
+    create_superuser, jwks, login, login_totp, logout, refresh, signup,
+};
 use crate::handlers::health_handlers::health_check;
+use crate::handlers::invite_handlers::{create_invite, delete_invite, get_invite, get_invites};
+use crate::handlers::me_handlers::{
+    change_password, delete_me, delete_session, get_me, get_sessions, update_me,
+};
+use crate::handlers::oidc_handlers::{oidc_callback, oidc_login};
+use crate::handlers::passkey_handlers::{
+    delete_passkey, get_passkeys, login_passkey, passkey_login_options,
+    passkey_registration_options, register_passkey,
+};
 use crate::handlers::qr_handlers::{
-    generate_direct_qr, get_all_qr_codes, get_user_qr_codes, regenerate_qr,
+    generate_direct_qr, get_all_qr_codes, get_user_qr_codes, get_workspace_qr_codes, regenerate_qr,
+};
+use crate::handlers::totp_handlers::{
+    disable_totp, enable_totp, get_totp_status, regenerate_recovery_codes, setup_totp,
 };
 use crate::handlers::url_handlers::{
-    create_short_url, get_all_urls, get_qr_code_direct, get_url_analytics, get_user_urls,
-    redirect_to_url,delete_short_url
+    create_short_url, delete_short_url, get_all_urls, get_qr_code_direct, get_url_analytics,
+    get_url_history, get_user_urls, get_workspace_urls, redirect_to_url, revert_short_url,
+    unlock_url, update_short_url,
 };
 use crate::handlers::user_handlers::{
-    create_user, delete_user, edit_user, get_all_users, get_user,
+    create_user, delete_user, edit_user, get_all_users, get_user, preview_user_deletion,
+    reset_user_totp, unlock_user,
+};
+use crate::handlers::workspace_handlers::{
+    add_workspace_member, create_workspace, delete_workspace, get_workspace, get_workspaces,
+    remove_workspace_member, rename_workspace, switch_workspace, update_workspace_member,
 };
 use crate::middlewares::authmw::JwtAuth;
 use crate::middlewares::res_owner::ResourceOwnership;
+use crate::middlewares::role_guard::RequireRole;
+use crate::models::api_key::Scope;
+use crate::models::workspace::WorkspaceRole;
+
+/// Scope an API key needs to call an endpoint, identified by method and route pattern.
+/// Endpoints not listed here cannot be called with an API key at all.
+pub fn api_key_scope(method: &Method, pattern: &str) -> Option<Scope> {
+    let scope = match (method.as_str(), pattern) {
+        ("POST", "/api/shorten") => Scope::LinksWrite,
+        ("GET", "/api/urls")
+        | ("GET", "/api/users/{user_id}/urls")
+        | ("GET", "/api/workspaces/{workspace_id}/urls") => Scope::LinksRead,
+        ("PATCH", "/api/urls/{code}")
+        | ("DELETE", "/api/urls/{code}")
+        | ("POST", "/api/urls/{code}/revert") => Scope::LinksWrite,
+        ("GET", "/api/urls/{code}/history") => Scope::LinksRead,
+        ("GET", "/api/qr")
+        | ("GET", "/api/qr/{code}/info")
+        | ("GET", "/api/users/{user_id}/qr")
+        | ("GET", "/api/workspaces/{workspace_id}/qr") => Scope::QrRead,
+        ("POST", "/api/qr") | ("GET", "/api/qr/{code}/regenerate") => Scope::QrWrite,
+        ("GET", "/api/analytics/{code}") => Scope::AnalyticsRead,
+        _ => return None,
+    };
+    Some(scope)
+}
 
 /// Configure the routes
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    // Report malformed request bodies, query strings and paths as problem+json as well
+    cfg.app_data(
+        web::JsonConfig::default()
+            .error_handler(|err, _| AppError::bad_request(err.to_string()).into()),
+    )
+    .app_data(
+        web::FormConfig::default()
+            .error_handler(|err, _| AppError::bad_request(err.to_string()).into()),
+    )
+    .app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _| AppError::bad_request(err.to_string()).into()),
+    )
+    .app_data(
+        web::PathConfig::default()
+            .error_handler(|err, _| AppError::bad_request(err.to_string()).into()),
+    )
+    .default_service(web::to(|| async {
+        Err::<HttpResponse, _>(AppError::not_found("Resource not found"))
+    }));
     // Define redirect route at the root level
-    cfg.route("/r/{code}", web::get().to(redirect_to_url));
-    // Authentication routes - no auth required
+    cfg.route("/r/{code}", web::get().to(redirect_to_url))
+        .route("/r/{code}", web::post().to(unlock_url));
+    // Public keys for verifying access tokens
+    cfg.route("/.well-known/jwks.json", web::get().to(jwks));
+    // Authentication routes - no auth required, except for logout, resending the verification email, two-factor setup and passkey management
     cfg.service(
         web::scope("/api/auth")
             .route("/login", web::post().to(login))
+            .route("/login/totp", web::post().to(login_totp))
             .route("/init", web::post().to(create_superuser))
-            .route("/signup", web::post().to(signup)),
+            .route("/signup", web::post().to(signup))
+            .route("/refresh", web::post().to(refresh))
+            .route("/password-reset", web::post().to(request_password_reset))
+            .route("/password-reset/confirm", web::post().to(reset_password))
+            .route("/verify-email", web::post().to(verify_email))
+            .route("/oidc/login", web::get().to(oidc_login))
+            .route("/oidc/callback", web::get().to(oidc_callback))
+            .route(
+                "/passkeys/login/options",
+                web::post().to(passkey_login_options),
+            )
+            .route("/passkeys/login", web::post().to(login_passkey))
+            .service(
+                web::resource("/verify-email/resend")
+                    .wrap(JwtAuth)
+                    .route(web::post().to(resend_verification_email)),
+            )
+            .service(
+                web::resource("/logout")
+                    .wrap(JwtAuth)
+                    .route(web::post().to(logout)),
+            )
+            .service(
+                web::scope("/totp")
+                    .wrap(JwtAuth)
+                    .route("", web::get().to(get_totp_status))
+                    .route("/setup", web::post().to(setup_totp))
+                    .route("/enable", web::post().to(enable_totp))
+                    .route("/disable", web::post().to(disable_totp))
+                    .route("/recovery-codes", web::post().to(regenerate_recovery_codes)),
+            )
+            .service(
+                web::scope("/passkeys")
+                    .wrap(JwtAuth)
+                    .route("", web::get().to(get_passkeys))
+                    .route(
+                        "/register/options",
+                        web::post().to(passkey_registration_options),
+                    )
+                    .route("/register", web::post().to(register_passkey))
+                    .route("/{passkey_id}", web::delete().to(delete_passkey)),
+            ),
     );
     // API routes - require authentication
     cfg.service(
         web::scope("/api")
             .wrap(JwtAuth)
-            .route("/shorten", web::post().to(create_short_url))
+            .service(
+                web::resource("/shorten")
+                    .wrap(RequireRole::writer())
+                    .route(web::post().to(create_short_url)),
+            )
             .route("/urls", web::get().to(get_all_urls))
-            .route("/urls/{code}", web::delete().to(delete_short_url))
+            .service(
+                web::resource("/urls/{code}")
+                    .wrap(RequireRole::writer())
+                    .route(web::patch().to(update_short_url))
+                    .route(web::delete().to(delete_short_url)),
+            )
+            .route("/urls/{code}/history", web::get().to(get_url_history))
+            .service(
+                web::resource("/urls/{code}/revert")
+                    .wrap(RequireRole::writer())
+                    .route(web::post().to(revert_short_url)),
+            )
             .service(
                 web::resource("/users/{user_id}/urls")
-                    .wrap(ResourceOwnership {
-                        param_name: "user_id".to_string(),
-                    })
+                    .wrap(ResourceOwnership::user("user_id"))
                     .route(web::get().to(get_user_urls)),
             )
             .service(
                 web::resource("/users/{user_id}/qr")
-                    .wrap(ResourceOwnership {
-                        param_name: "user_id".to_string(),
-                    })
+                    .wrap(ResourceOwnership::user("user_id"))
                     .route(web::get().to(get_user_qr_codes)),
             )
+            .service(
+                web::scope("/users/{user_id}/keys")
+                    .wrap(ResourceOwnership::user("user_id"))
+                    .route("", web::get().to(get_api_keys))
+                    .route("", web::post().to(create_api_key))
+                    .route("/{key_id}", web::get().to(get_api_key))
+                    .route("/{key_id}", web::delete().to(delete_api_key)),
+            )
+            // Self-service routes for the caller's own account
+            .service(
+                web::scope("/me")
+                    .route("", web::get().to(get_me))
+                    .route("", web::put().to(update_me))
+                    .route("", web::delete().to(delete_me))
+                    .route("/password", web::post().to(change_password))
+                    .route("/sessions", web::get().to(get_sessions))
+                    .route("/sessions/{session_id}", web::delete().to(delete_session)),
+            )
             .route("/health/check", web::get().to(health_check))
-            .route("/qr/{code}/regenerate", web::get().to(regenerate_qr))
+            .service(
+                web::resource("/qr/{code}/regenerate")
+                    .wrap(RequireRole::writer())
+                    .route(web::get().to(regenerate_qr)),
+            )
             .route("/qr/{code}/info", web::get().to(get_qr_code_direct))
             .route("/analytics/{code}", web::get().to(get_url_analytics))
-            .route("/qr", web::post().to(generate_direct_qr))
-            .route("/qr", web::get().to(get_all_qr_codes))
-            // User management routes
+            .service(
+                web::resource("/qr")
+                    .route(web::get().to(get_all_qr_codes))
+                    .route(
+                        web::post()
+                            .to(generate_direct_qr)
+                            .wrap(RequireRole::writer()),
+                    ),
+            )
+            // User management routes - admin only
             .service(
                 web::scope("/users")
+                    .wrap(RequireRole::admin())
                     .route("", web::get().to(get_all_users))
                     .route("", web::post().to(create_user))
                     .route("/{user_id}", web::get().to(get_user))
                     .route("/{user_id}", web::put().to(edit_user))
-                    .route("/{user_id}", web::delete().to(delete_user)),
+                    .route("/{user_id}", web::delete().to(delete_user))
+                    .route(
+                        "/{user_id}/deletion-preview",
+                        web::get().to(preview_user_deletion),
+                    )
+                    .route("/{user_id}/unlock", web::post().to(unlock_user))
+                    .route("/{user_id}/totp", web::delete().to(reset_user_totp)),
+            )
+            // Signup invites - admin only
+            .service(
+                web::scope("/invites")
+                    .wrap(RequireRole::admin())
+                    .route("", web::get().to(get_invites))
+                    .route("", web::post().to(create_invite))
+                    .route("/{invite_id}", web::get().to(get_invite))
+                    .route("/{invite_id}", web::delete().to(delete_invite)),
+            )
+            // Workspaces - members can view, editors can add links and QR codes, owners manage
+            .service(
+                web::scope("/workspaces")
+                    .route("", web::get().to(get_workspaces))
+                    .route(
+                        "",
+                        web::post().to(create_workspace).wrap(RequireRole::writer()),
+                    )
+                    .route("/switch", web::post().to(switch_workspace))
+                    .service(
+                        web::scope("/{workspace_id}")
+                            .wrap(ResourceOwnership::workspace(
+                                "workspace_id",
+                                WorkspaceRole::Viewer,
+                            ))
+                            .route("", web::get().to(get_workspace))
+                            .route(
+                                "",
+                                web::put()
+                                    .to(rename_workspace)
+                                    .wrap(ResourceOwnership::workspace(
+                                        "workspace_id",
+                                        WorkspaceRole::Owner,
+                                    )),
+                            )
+                            .route(
+                                "",
+                                web::delete().to(delete_workspace).wrap(
+                                    ResourceOwnership::workspace(
+                                        "workspace_id",
+                                        WorkspaceRole::Owner,
+                                    ),
+                                ),
+                            )
+                            .route("/urls", web::get().to(get_workspace_urls))
+                            .route("/qr", web::get().to(get_workspace_qr_codes))
+                            .route(
+                                "/members",
+                                web::post().to(add_workspace_member).wrap(
+                                    ResourceOwnership::workspace(
+                                        "workspace_id",
+                                        WorkspaceRole::Owner,
+                                    ),
+                                ),
+                            )
+                            .route(
+                                "/members/{user_id}",
+                                web::put().to(update_workspace_member).wrap(
+                                    ResourceOwnership::workspace(
+                                        "workspace_id",
+                                        WorkspaceRole::Owner,
+                                    ),
+                                ),
+                            )
+                            // Owners remove members; any member may leave
+                            .route(
+                                "/members/{user_id}",
+                                web::delete().to(remove_workspace_member),
+                            ),
+                    ),
+            )
+            // Audit log - admin only
+            .service(
+                web::scope("/audit")
+                    .wrap(RequireRole::admin())
+                    .route("", web::get().to(get_audit_events))
+                    .route("/export", web::get().to(export_audit_events)),
             ),
     );
 }
@@ -1,5 +1,136 @@
-use mongodb::Database;
+use std::sync::Arc;
+
+use crate::config::app_config::Config;
+use crate::mail::Mailer;
+use crate::repositories::api_key_repository::ApiKeyRepository;
+use crate::repositories::audit_repository::AuditRepository;
+use crate::repositories::health_repository::HealthRepository;
+use crate::repositories::identity_repository::IdentityRepository;
+use crate::repositories::invite_repository::InviteRepository;
+use crate::repositories::passkey_repository::PasskeyRepository;
+use crate::repositories::qr_code_repository::QrCodeRepository;
+use crate::repositories::token_repository::TokenRepository;
+use crate::repositories::totp_repository::TotpRepository;
+use crate::repositories::url_repository::UrlRepository;
+use crate::repositories::user_repository::UserRepository;
+use crate::repositories::visitor_repository::VisitorRepository;
+use crate::repositories::workspace_repository::WorkspaceRepository;
+use crate::utils::audit::AuditIpHasher;
+use crate::utils::client_ip::TrustedProxies;
+use crate::utils::jwt::JwtKeys;
+use crate::utils::login_throttle::LoginThrottle;
+use crate::utils::oidc::OidcClient;
+use crate::utils::password::Passwords;
+use crate::utils::policy::AccountPolicy;
+use crate::utils::webauthn::Webauthn;
+
+/// A storage backend that provides every repository the API needs
+pub trait Storage:
+    UrlRepository
+    + VisitorRepository
+    + QrCodeRepository
+    + UserRepository
+    + TokenRepository
+    + ApiKeyRepository
+    + TotpRepository
+    + IdentityRepository
+    + PasskeyRepository
+    + InviteRepository
+    + AuditRepository
+    + WorkspaceRepository
+    + HealthRepository
+{
+}
+
+impl<T> Storage for T where
+    T: UrlRepository
+        + VisitorRepository
+        + QrCodeRepository
+        + UserRepository
+        + TokenRepository
+        + ApiKeyRepository
+        + TotpRepository
+        + IdentityRepository
+        + PasskeyRepository
+        + InviteRepository
+        + AuditRepository
+        + WorkspaceRepository
+        + HealthRepository
+{
+}
 
 pub struct AppState {
-    pub db: Database,
+    pub config: Arc<Config>,
+    pub jwt: Arc<JwtKeys>,
+    pub mailer: Arc<dyn Mailer>,
+    pub urls: Arc<dyn UrlRepository>,
+    pub visitors: Arc<dyn VisitorRepository>,
+    pub qr_codes: Arc<dyn QrCodeRepository>,
+    pub users: Arc<dyn UserRepository>,
+    pub tokens: Arc<dyn TokenRepository>,
+    pub api_keys: Arc<dyn ApiKeyRepository>,
+    pub totp: Arc<dyn TotpRepository>,
+    pub identities: Arc<dyn IdentityRepository>,
+    pub passkeys: Arc<dyn PasskeyRepository>,
+    pub invites: Arc<dyn InviteRepository>,
+    pub audit: Arc<dyn AuditRepository>,
+    pub workspaces: Arc<dyn WorkspaceRepository>,
+    pub health: Arc<dyn HealthRepository>,
+    pub login_throttle: LoginThrottle,
+    pub link_password_throttle: LoginThrottle, // Wrong passwords of protected short URLs, per IP
+    pub link_code_throttle: LoginThrottle, // Wrong passwords per protected short URL, delay only
+    pub password_reset_throttle: LoginThrottle, // Password reset requests
+    pub passwords: Passwords,
+    pub policy: AccountPolicy,
+    pub oidc: Option<OidcClient>, // Set when single sign-on is configured
+    pub webauthn: Webauthn,
+    pub audit_ip_hasher: AuditIpHasher,
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl AppState {
+    /// Build the state with every repository served by the same storage backend
+    pub fn new<S: Storage + 'static>(
+        config: Config,
+        jwt: JwtKeys,
+        mailer: Arc<dyn Mailer>,
+        policy: AccountPolicy,
+        oidc: Option<OidcClient>,
+        webauthn: Webauthn,
+        storage: S,
+    ) -> Self {
+        let storage = Arc::new(storage);
+        let passwords = Passwords::new(&config.auth);
+        let audit_ip_hasher = AuditIpHasher::new(&config.audit);
+        let trusted_proxies = TrustedProxies::new(&config.server);
+
+        Self {
+            config: Arc::new(config),
+            jwt: Arc::new(jwt),
+            mailer,
+            urls: storage.clone(),
+            visitors: storage.clone(),
+            qr_codes: storage.clone(),
+            users: storage.clone(),
+            tokens: storage.clone(),
+            api_keys: storage.clone(),
+            totp: storage.clone(),
+            identities: storage.clone(),
+            passkeys: storage.clone(),
+            invites: storage.clone(),
+            audit: storage.clone(),
+            workspaces: storage.clone(),
+            health: storage,
+            login_throttle: LoginThrottle::default(),
+            link_password_throttle: LoginThrottle::default(),
+            link_code_throttle: LoginThrottle::default(),
+            password_reset_throttle: LoginThrottle::default(),
+            passwords,
+            policy,
+            oidc,
+            webauthn,
+            audit_ip_hasher,
+            trusted_proxies,
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use crate::models::refresh_token::RefreshToken;
+use crate::structs::user::DeletePolicy;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct PasswordResetRequest {
+    #[validate(email(message = "Invalid email address"))]
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmPasswordResetRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct UpdateProfileRequest {
+    #[validate(email(message = "Invalid email address"))]
+    pub email: Option<String>, // A new address has to be verified again
+    pub full_name: Option<String>,
+    pub current_password: Option<String>, // Required to change the email address
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+    #[serde(default)]
+    pub policy: DeletePolicy, // Only disable or delete; the links cannot be handed to someone else
+}
+
+/// An active login session, identified by its refresh token family
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub current: bool, // Whether the request was made with an access token of this session
+    pub last_refreshed_at: i64, // Login or last token refresh
+    pub expires_at: i64,
+}
+
+impl SessionResponse {
+    pub fn new(token: RefreshToken, current_session_id: &str) -> Self {
+        Self {
+            current: token.family_id == current_session_id,
+            id: token.family_id,
+            last_refreshed_at: token.created_at,
+            expires_at: token.expires_at,
+        }
+    }
+}
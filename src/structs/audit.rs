@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+use crate::models::audit_event::{AuditAction, AuditEvent, AuditTarget};
+
+#[derive(Deserialize, Validate)]
+pub struct AuditQueryParams {
+    pub actor: Option<String>,  // User ID or username of the actor
+    pub action: Option<String>, // e.g. "user.delete"
+    pub from: Option<i64>,      // Milliseconds, inclusive
+    pub to: Option<i64>,        // Milliseconds, exclusive
+    #[validate(range(min = 1, max = 1000, message = "Limit must be 1-1000"))]
+    pub limit: Option<i64>, // Page size of the listing, defaults to 100
+    pub before: Option<String>, // `next_cursor` of the previous page
+}
+
+#[derive(Serialize)]
+pub struct AuditEventResponse {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub actor_username: Option<String>,
+    pub action: AuditAction,
+    pub target_type: AuditTarget,
+    pub target_id: String,
+    pub changes: Option<Value>,
+    pub ip_hash: Option<String>,
+    pub created_at: i64,
+}
+
+impl From<AuditEvent> for AuditEventResponse {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            id: event.id.unwrap().to_hex(),
+            actor_id: event.actor_id,
+            actor_username: event.actor_username,
+            action: event.action,
+            target_type: event.target_type,
+            target_id: event.target_id,
+            changes: event.changes,
+            ip_hash: event.ip_hash,
+            created_at: event.created_at,
+        }
+    }
+}
+
+/// A page of the audit log, newest first
+#[derive(Serialize)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEventResponse>,
+    pub next_cursor: Option<String>, // Pass as `before` to get the next page; absent on the last page
+}
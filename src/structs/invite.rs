@@ -0,0 +1,53 @@
+use crate::models::invite::{Invite, InviteUse};
+use crate::models::user::Role;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct CreateInviteRequest {
+    #[validate(email(message = "Invalid email address"))]
+    pub email: Option<String>, // Anyone with the code can sign up if omitted
+    pub role: Option<Role>, // Defaults to member
+    #[validate(range(min = 1, max = 10000, message = "Max uses must be 1-10000"))]
+    pub max_uses: Option<u32>, // Defaults to a single use
+    pub expires_in_days: Option<u32>, // Never expires if omitted
+}
+
+#[derive(Serialize)]
+pub struct InviteResponse {
+    pub id: String,
+    pub code_prefix: String,
+    pub email: Option<String>,
+    pub role: Role,
+    pub max_uses: i64,
+    pub use_count: usize,
+    pub uses: Vec<InviteUse>,
+    pub created_by: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl From<Invite> for InviteResponse {
+    fn from(invite: Invite) -> Self {
+        Self {
+            id: invite.id.unwrap().to_hex(),
+            code_prefix: invite.code_prefix,
+            email: invite.email,
+            role: invite.role,
+            max_uses: invite.max_uses,
+            use_count: invite.uses.len(),
+            uses: invite.uses,
+            created_by: invite.created_by,
+            created_at: invite.created_at,
+            expires_at: invite.expires_at,
+        }
+    }
+}
+
+/// Returned once when an invite is created; the code cannot be retrieved afterwards
+#[derive(Serialize)]
+pub struct CreatedInviteResponse {
+    pub code: String,
+    #[serde(flatten)]
+    pub details: InviteResponse,
+}
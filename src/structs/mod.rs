@@ -1,3 +1,10 @@
+pub mod account;
+pub mod api_key;
+pub mod audit;
+pub mod invite;
+pub mod passkey;
 pub mod qr_request;
+pub mod totp;
 pub mod url_request;
 pub mod user;
+pub mod workspace;
@@ -0,0 +1,49 @@
+use crate::models::passkey::Passkey;
+use crate::utils::webauthn::{AuthenticationCredential, RegistrationCredential};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct RegisterPasskeyRequest {
+    pub challenge_token: String, // From the registration options
+    #[validate(length(min = 1, max = 100, message = "Name must be 1-100 characters"))]
+    pub name: String,
+    pub credential: RegistrationCredential,
+    pub password: String,     // Current password
+    pub code: Option<String>, // Authenticator or recovery code, if two-factor authentication is enabled
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginRequest {
+    pub challenge_token: String, // From the login options
+    pub credential: AuthenticationCredential,
+}
+
+/// Options to pass to the browser's WebAuthn API, and the token to send back with its result
+#[derive(Serialize)]
+pub struct PasskeyOptionsResponse {
+    pub challenge_token: String,
+    pub public_key: serde_json::Value,
+    pub expires_in: i64, // Seconds until the challenge token expires
+}
+
+#[derive(Serialize)]
+pub struct PasskeyResponse {
+    pub id: String,
+    pub name: String,
+    pub transports: Vec<String>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+impl From<Passkey> for PasskeyResponse {
+    fn from(passkey: Passkey) -> Self {
+        Self {
+            id: passkey.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: passkey.name,
+            transports: passkey.transports,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+        }
+    }
+}
@@ -26,12 +26,13 @@ pub struct QrRequest {
 pub struct QrCodeResponse {
     pub id: String,
     pub short_code: String,
-    pub original_url: String,
+    pub original_url: Option<String>, // Hidden for password-protected URLs the caller cannot change
     pub generated_at: i64,
     pub target_type: String,
     pub is_direct: bool,
     pub owned_by_current_user: bool,
     pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
     pub svg_content: String,
 }
 
@@ -0,0 +1,52 @@
+use crate::models::totp::TotpCredential;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String, // Code from the authenticator app, or a recovery code where accepted
+}
+
+/// Confirms a change to two-factor authentication with both factors
+#[derive(Deserialize)]
+pub struct TotpConfirmRequest {
+    pub password: String, // Current password
+    pub code: String,     // Code from the authenticator app, or a recovery code
+}
+
+#[derive(Serialize)]
+pub struct TotpStatusResponse {
+    pub enabled: bool,
+    pub enabled_at: Option<i64>,
+    pub recovery_codes_remaining: usize,
+}
+
+impl From<Option<TotpCredential>> for TotpStatusResponse {
+    fn from(credential: Option<TotpCredential>) -> Self {
+        match credential.filter(|credential| credential.enabled) {
+            Some(credential) => Self {
+                enabled: true,
+                enabled_at: credential.enabled_at,
+                recovery_codes_remaining: credential.recovery_codes.len(),
+            },
+            None => Self {
+                enabled: false,
+                enabled_at: None,
+                recovery_codes_remaining: 0,
+            },
+        }
+    }
+}
+
+/// Everything an authenticator app needs to enroll; the QR code encodes the provisioning URI
+#[derive(Serialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
+    pub qr_code_svg: String,
+}
+
+/// Returned once when recovery codes are generated; only their hashes are stored
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
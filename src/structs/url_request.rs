@@ -1,18 +1,59 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use validator::Validate;
 
+use crate::models::url_version::UrlVersion;
+
 #[derive(Deserialize, Serialize, Validate)]
 pub struct UrlRequest {
     #[validate(url(message = "Invalid URL format"))]
     pub url: String,
     pub custom_code: Option<String>,
     pub expires_in_days: Option<u32>,
+    #[validate(length(max = 200, message = "Title must be at most 200 characters"))]
+    pub title: Option<String>,
+    #[validate(length(max = 128, message = "Password must be at most 128 characters"))]
+    pub password: Option<String>, // Visitors have to enter it before being redirected
+}
+
+/// Changes to a shortened URL; fields that are left out keep their value
+#[derive(Deserialize, Validate)]
+pub struct UpdateUrlRequest {
+    #[validate(url(message = "Invalid URL format"))]
+    pub url: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub expires_at: Option<Option<i64>>, // Timestamp in milliseconds; null removes the expiry
+    #[serde(default, deserialize_with = "present")]
+    #[validate(length(max = 200, message = "Title must be at most 200 characters"))]
+    pub title: Option<Option<String>>, // Null removes the title
+    #[serde(default, deserialize_with = "present")]
+    #[validate(length(max = 128, message = "Password must be at most 128 characters"))]
+    pub password: Option<Option<String>>, // Null removes the password
+}
+
+/// Tell a field that is set to null apart from one that is left out
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Password entered by a visitor of a password-protected short URL
+#[derive(Deserialize)]
+pub struct UnlockUrlForm {
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RevertUrlRequest {
+    pub version: i64,
 }
 
 #[derive(Serialize)]
 pub struct UrlListResponse {
     pub id: Option<String>,
-    pub original_url: String,
+    pub original_url: Option<String>, // Hidden for password-protected URLs the caller cannot change
     pub short_code: String,
     pub created_at: Option<i64>,
     pub expires_at: Option<i64>,
@@ -21,6 +62,11 @@ pub struct UrlListResponse {
     pub clicks: i64,
     pub unique_clicks: usize,
     pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
+    pub title: Option<String>,
+    pub version: i64,
+    pub disabled_at: Option<i64>,
+    pub password_protected: bool,
     pub owned_by_current_user: bool,
 }
 
@@ -31,18 +77,53 @@ pub struct UrlResponse {
     pub short_code: String,
     pub expires_at: Option<i64>,
     pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
+    pub title: Option<String>,
+    pub version: i64,
+    pub password_protected: bool,
+}
+
+/// An earlier version of a shortened URL
+#[derive(Serialize)]
+pub struct UrlVersionResponse {
+    pub version: i64,
+    pub original_url: Option<String>, // Hidden for password-protected URLs the caller cannot change
+    pub expires_at: Option<i64>,
+    pub title: Option<String>,
+    pub replaced_at: i64,
+    pub replaced_by: Option<String>,
+}
+
+impl From<UrlVersion> for UrlVersionResponse {
+    fn from(version: UrlVersion) -> Self {
+        Self {
+            version: version.version,
+            original_url: Some(version.original_url),
+            expires_at: version.expires_at,
+            title: version.title,
+            replaced_at: version.replaced_at,
+            replaced_by: version.replaced_by,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UrlHistoryResponse {
+    pub short_code: String,
+    pub version: i64,                      // Current version
+    pub versions: Vec<UrlVersionResponse>, // Earlier versions, newest first
 }
 
 #[derive(Deserialize)]
 pub struct UrlSearchParams {
     pub search: Option<String>,
-    pub owned_only: Option<bool>,
+    pub owned_only: Option<bool>, // Only the current workspace's URLs, or the user's own without one
 }
 
 #[derive(Serialize)]
 pub struct UrlAnalyticsResponse {
     pub short_code: String,
-    pub original_url: String,
+    pub original_url: Option<String>, // Hidden for password-protected URLs the caller cannot change
     pub created_at: Option<i64>,
     pub expires_at: Option<i64>,
     pub clicks: i64,
@@ -52,4 +133,9 @@ pub struct UrlAnalyticsResponse {
     pub shortened_qr_generated_at: Option<i64>,
     pub original_qr_generated_at: Option<i64>,
     pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
+    pub title: Option<String>,
+    pub version: i64,
+    pub disabled_at: Option<i64>, // The URL no longer redirects
+    pub password_protected: bool,
 }
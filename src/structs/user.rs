@@ -1,12 +1,15 @@
-use crate::models::user::User;
+use crate::models::user::{Role, User};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct CreateUserRequest {
     pub username: String,
+    #[validate(email(message = "Invalid email address"))]
     pub email: Option<String>,
     pub full_name: Option<String>,
     pub password: String,
+    pub role: Option<Role>, // Defaults to member
 }
 
 #[derive(Deserialize)]
@@ -15,6 +18,7 @@ pub struct EditUserRequest {
     pub full_name: Option<String>,
     pub password: Option<String>,
     pub is_active: Option<bool>,
+    pub role: Option<Role>,
 }
 
 #[derive(Serialize)]
@@ -22,11 +26,16 @@ pub struct UserResponse {
     pub id: String,
     pub username: String,
     pub email: Option<String>,
+    pub email_verified_at: Option<i64>,
     pub full_name: Option<String>,
     pub created_at: i64,
     pub updated_at: i64,
     pub last_login: Option<i64>,
     pub is_active: bool,
+    pub role: Role,
+    pub failed_login_attempts: i64,
+    pub locked_at: Option<i64>,
+    pub locked_until: Option<i64>,
 }
 
 impl From<User> for UserResponse {
@@ -35,20 +44,59 @@ impl From<User> for UserResponse {
             id: user.id.unwrap().to_hex(),
             username: user.username,
             email: user.email,
+            email_verified_at: user.email_verified_at,
             full_name: user.full_name,
             created_at: user.created_at,
             updated_at: user.updated_at,
             last_login: user.last_login,
             is_active: user.is_active,
+            role: user.role,
+            failed_login_attempts: user.failed_login_attempts,
+            locked_at: user.locked_at,
+            locked_until: user.locked_until,
         }
     }
 }
 
-// Add a new SignupRequest struct
+/// What happens to the personal short URLs and QR codes of a deleted user
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletePolicy {
+    Reassign, // Hand them to another user
+    #[default]
+    Disable, // Keep them without an owner; the URLs stop redirecting
+    Delete,   // Delete them with their analytics
+}
+
 #[derive(Deserialize)]
+pub struct DeleteUserParams {
+    #[serde(default)]
+    pub policy: DeletePolicy,
+    pub reassign_to: Option<String>, // User ID; required by the reassign policy
+}
+
+/// What deleting a user with a policy would affect
+#[derive(Serialize)]
+pub struct DeletionPreviewResponse {
+    pub user_id: String,
+    pub policy: DeletePolicy,
+    pub reassign_to: Option<String>,
+    pub short_codes: Vec<String>, // Personal short URLs the policy applies to
+    pub urls: usize,
+    pub qr_codes: usize,
+    pub visitors: u64, // Recorded unique visitors of the personal short URLs
+    pub workspace_urls: usize, // Created in workspaces; these stay with the workspace
+    pub workspace_qr_codes: usize,
+    pub workspace_memberships: usize,
+}
+
+// Add a new SignupRequest struct
+#[derive(Deserialize, Validate)]
 pub struct SignupRequest {
     pub username: String,
+    #[validate(email(message = "Invalid email address"))]
     pub email: Option<String>,
     pub full_name: Option<String>,
     pub password: String,
+    pub invite_code: Option<String>, // Required when public signup is disabled
 }
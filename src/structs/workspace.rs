@@ -0,0 +1,50 @@
+use crate::models::workspace::WorkspaceRole;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct WorkspaceRequest {
+    #[validate(length(min = 1, max = 100, message = "Name must be 1-100 characters"))]
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct WorkspaceListParams {
+    pub all: Option<bool>, // List every workspace instead of the caller's; admins only
+}
+
+#[derive(Deserialize)]
+pub struct AddMemberRequest {
+    pub username: String,
+    pub role: Option<WorkspaceRole>, // Defaults to editor
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemberRequest {
+    pub role: WorkspaceRole,
+}
+
+#[derive(Deserialize)]
+pub struct SwitchWorkspaceRequest {
+    pub workspace_id: Option<String>, // Back to personal links and QR codes if omitted
+    pub refresh_token: String,        // Refresh token of the current session, rotated by the switch
+}
+
+#[derive(Serialize)]
+pub struct WorkspaceMemberResponse {
+    pub user_id: String,
+    pub username: Option<String>, // Missing if the user no longer exists
+    pub role: WorkspaceRole,
+    pub added_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct WorkspaceResponse {
+    pub id: String,
+    pub name: String,
+    pub role: Option<WorkspaceRole>, // Role of the caller, if a member
+    pub members: Vec<WorkspaceMemberResponse>,
+    pub created_by: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
@@ -0,0 +1,39 @@
+use rand::RngCore;
+use ring::hmac;
+
+use crate::config::app_config::AuditConfig;
+
+/// Hashes client IP addresses for the audit log with a secret key, so the log does not
+/// reveal addresses but events coming from the same address can still be linked
+pub struct AuditIpHasher {
+    key: hmac::Key,
+}
+
+impl AuditIpHasher {
+    pub fn new(config: &AuditConfig) -> Self {
+        let key = match &config.ip_hash_key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                log::warn!(
+                    "audit.ip_hash_key (AUDIT_IP_HASH_KEY) is not set; using a random key, so audit log IP hashes will not match across restarts"
+                );
+                let mut key = vec![0u8; 32];
+                rand::rng().fill_bytes(&mut key);
+                key
+            }
+        };
+
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+        }
+    }
+
+    /// Hex-encoded HMAC-SHA256 of an IP address
+    pub fn hash(&self, ip: &str) -> String {
+        hmac::sign(&self.key, ip.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use actix_web::HttpRequest;
+use anyhow::{Context, Result};
+
+use crate::config::app_config::ServerConfig;
+
+/// An IP address or a CIDR range such as `10.0.0.0/8`
+#[derive(Debug, Clone, Copy)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address = IpAddr::from_str(address.trim())
+            .with_context(|| format!("Invalid IP address {}", value))?
+            .to_canonical();
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .with_context(|| format!("Invalid network prefix in {}", value))?,
+            None => max_prefix,
+        };
+
+        Ok(Self { address, prefix })
+    }
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Works out the address of the client that sent a request. `X-Forwarded-For` can be
+/// set by anyone, so it is only believed when the connection comes from one of the
+/// configured reverse proxies, or over the Unix socket, which only local processes reach.
+pub struct TrustedProxies {
+    networks: Vec<IpNetwork>,
+}
+
+impl TrustedProxies {
+    /// The configuration is validated at startup, so every entry is known to parse
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            networks: config
+                .trusted_proxies
+                .iter()
+                .filter_map(|proxy| proxy.parse().ok())
+                .collect(),
+        }
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// The client address of a request, for throttling and hashing
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        let peer = req.peer_addr().map(|addr| addr.ip().to_canonical());
+        if let Some(peer) = peer
+            && !self.is_trusted(peer)
+        {
+            return peer.to_string();
+        }
+
+        // Every proxy appends the address it received the request from, so the client is
+        // the last address that is not one of our proxies. Anything left of it came from
+        // the client and is ignored.
+        let forwarded: Vec<&str> = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        let mut client = peer;
+        for address in forwarded.iter().rev() {
+            let Ok(address) = IpAddr::from_str(address.trim()) else {
+                break;
+            };
+            client = Some(address.to_canonical());
+            if !self.is_trusted(address) {
+                break;
+            }
+        }
+
+        client
+            .map(|client| client.to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
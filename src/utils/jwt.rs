@@ -1,43 +1,495 @@
 use anyhow::{Context, Result};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::pem::PemObject;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::env;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::config::app_config::{AuthConfig, JwtAlgorithm, SigningKeyConfig};
+use crate::models::user::Role;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,     // Subject (username)
     pub exp: usize,      // Expiration time
     pub iat: usize,      // Issued at
+    pub iss: String,     // Issuer
+    pub aud: String,     // Audience
     pub user_id: String, // Optional user ID
+    #[serde(default)]
+    pub role: Role, // Role of the user at the time the token was issued
+    #[serde(default)]
+    pub jti: String, // Unique token ID, used to revoke the token before it expires
+    #[serde(default)]
+    pub sid: String, // Login session (refresh token family) the token was issued for; empty for API keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>, // Workspace the session has switched to; new links and QR codes belong to it
+    #[serde(default)]
+    pub iat_ms: i64, // Issued at in milliseconds, to tell tokens from a revocation in the same second
 }
 
-pub fn create_token(username: &str, user_id: &str) -> Result<String> {
-    let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::days(10))
-        .context("Invalid timestamp")?
-        .timestamp() as usize;
+impl Claims {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
 
-    let issued_at = chrono::Utc::now().timestamp() as usize;
+    /// Issue time in milliseconds. Tokens from before the claim existed only have
+    /// whole seconds, which counts them as issued at the start of that second.
+    pub fn issued_at_ms(&self) -> i64 {
+        if self.iat_ms > 0 {
+            self.iat_ms
+        } else {
+            self.iat as i64 * 1000
+        }
+    }
+}
 
-    let claims = Claims {
-        sub: username.to_owned(),
-        exp: expiration,
-        iat: issued_at,
-        user_id: user_id.to_owned(),
-    };
+/// Claims of the short-lived token that carries a login from the password
+/// step to the second factor. Its audience differs from access tokens, so it
+/// can never be used as one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: String, // ID of the user who passed the password step
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+}
+
+/// How long a login challenge can be completed
+pub const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+
+/// Claims of the token that keeps the state of an OpenID Connect login in a cookie
+/// while the browser is at the identity provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcStateClaims {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String, // PKCE verifier; only its hash is sent with the authorization request
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+}
+
+/// How long a login at the identity provider can take
+pub const OIDC_STATE_LIFETIME_MINUTES: i64 = 10;
+
+/// Claims of the token in the cookie that lets a visitor through to a password-protected
+/// short URL without entering the password again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkAccessClaims {
+    pub sub: String, // Short code of the URL
+    pub pwd: String, // Fingerprint of the password hash, so changing the password locks visitors out
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+}
+
+/// How long a visitor can follow a password-protected short URL after entering the password
+pub const LINK_ACCESS_LIFETIME_MINUTES: i64 = 30;
+
+/// Claims of the token that carries the challenge of a passkey ceremony from the
+/// options request to the response. It can be used once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyChallengeClaims {
+    pub sub: String, // ID of the user registering a passkey; empty for logins
+    pub challenge: String,
+    pub jti: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+}
+
+/// The two passkey ceremonies, whose challenge tokens are not interchangeable
+#[derive(Debug, Clone, Copy)]
+pub enum PasskeyCeremony {
+    Registration,
+    Login,
+}
+
+/// How long a passkey ceremony can be completed
+pub const PASSKEY_CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+
+/// A key that verifies tokens. Retired keys are only kept until `valid_until`.
+struct VerificationKey {
+    kid: Option<String>,
+    decoding_key: DecodingKey,
+    jwk: Option<Jwk>, // Public key as published in the JWKS; never set for HS256
+    valid_until: Option<i64>,
+}
+
+impl VerificationKey {
+    fn is_valid(&self) -> bool {
+        self.valid_until
+            .is_none_or(|valid_until| Utc::now().timestamp() < valid_until)
+    }
+}
+
+/// Signs and verifies access tokens with the configured algorithm and keys
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    issuer: String,
+    audience: String,
+    lifetime_minutes: i64,
+    signing_kid: Option<String>,
+    encoding_key: EncodingKey,
+    keys: Vec<VerificationKey>,
+}
+
+impl JwtKeys {
+    /// Load the signing keys from the configuration
+    pub fn load(config: &AuthConfig) -> Result<Self> {
+        let algorithm = match config.jwt_algorithm {
+            JwtAlgorithm::HS256 => Algorithm::HS256,
+            JwtAlgorithm::RS256 => Algorithm::RS256,
+            JwtAlgorithm::EdDSA => Algorithm::EdDSA,
+        };
+
+        let mut keys = Self {
+            algorithm,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+            lifetime_minutes: config.token_lifetime_minutes,
+            signing_kid: None,
+            encoding_key: EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+            keys: Vec::new(),
+        };
+
+        // HS256 uses the shared secret and publishes nothing
+        if config.jwt_algorithm == JwtAlgorithm::HS256 {
+            keys.keys.push(VerificationKey {
+                kid: None,
+                decoding_key: DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+                jwk: None,
+                valid_until: None,
+            });
+            return Ok(keys);
+        }
+
+        for key_config in &config.signing_keys {
+            // Tokens signed by a retired key live for at most one more token lifetime
+            let valid_until = key_config
+                .retired_at
+                .map(|retired_at| retired_at.timestamp() + config.token_lifetime_minutes * 60);
+            if valid_until.is_some_and(|valid_until| valid_until <= Utc::now().timestamp()) {
+                log::info!(
+                    "Skipping signing key {}, its grace period has ended",
+                    key_config.kid
+                );
+                continue;
+            }
+
+            let pem = std::fs::read(&key_config.private_key_file).with_context(|| {
+                format!("Failed to read signing key {}", key_config.private_key_file)
+            })?;
+            let jwk = public_jwk(config.jwt_algorithm, key_config, &pem)
+                .with_context(|| format!("Invalid signing key {}", key_config.kid))?;
+
+            if key_config.retired_at.is_none() {
+                keys.signing_kid = Some(key_config.kid.clone());
+                keys.encoding_key = match config.jwt_algorithm {
+                    JwtAlgorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+                    _ => EncodingKey::from_ed_pem(&pem),
+                }
+                .with_context(|| format!("Invalid signing key {}", key_config.kid))?;
+            }
+
+            keys.keys.push(VerificationKey {
+                kid: Some(key_config.kid.clone()),
+                decoding_key: DecodingKey::from_jwk(&jwk)?,
+                jwk: Some(jwk),
+                valid_until,
+            });
+        }
+
+        Ok(keys)
+    }
+
+    pub fn create_token(
+        &self,
+        username: &str,
+        user_id: &str,
+        role: Role,
+        session_id: &str,
+        workspace_id: Option<&str>,
+    ) -> Result<String> {
+        let now = Utc::now();
+        let expiration = now
+            .checked_add_signed(chrono::Duration::minutes(self.lifetime_minutes))
+            .context("Invalid timestamp")?
+            .timestamp() as usize;
+
+        let claims = Claims {
+            sub: username.to_owned(),
+            exp: expiration,
+            iat: now.timestamp() as usize,
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            user_id: user_id.to_owned(),
+            role,
+            jti: uuid::Uuid::new_v4().to_string(),
+            sid: session_id.to_owned(),
+            workspace_id: workspace_id.map(str::to_owned),
+            iat_ms: now.timestamp_millis(),
+        };
+
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+
+        encode(&header, &claims, &self.encoding_key).context("Failed to create token")
+    }
 
-    let secret = env::var("JWT_SECRET").context("JWT_SECRET must be set")?;
-    let encoding_key = EncodingKey::from_secret(secret.as_bytes());
+    pub fn validate_token(&self, token: &str) -> Result<Claims> {
+        self.decode(token, &self.audience)
+    }
 
-    encode(&Header::default(), &claims, &encoding_key).context("Failed to create token")
+    /// Create a challenge token for a user who still has to pass the second factor
+    pub fn create_challenge_token(&self, user_id: &str) -> Result<String> {
+        let now = Utc::now();
+        let expiration = now
+            .checked_add_signed(chrono::Duration::minutes(CHALLENGE_LIFETIME_MINUTES))
+            .context("Invalid timestamp")?
+            .timestamp() as usize;
+
+        let claims = ChallengeClaims {
+            sub: user_id.to_owned(),
+            exp: expiration,
+            iat: now.timestamp() as usize,
+            iss: self.issuer.clone(),
+            aud: self.challenge_audience(),
+        };
+
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+
+        encode(&header, &claims, &self.encoding_key).context("Failed to create token")
+    }
+
+    pub fn validate_challenge_token(&self, token: &str) -> Result<ChallengeClaims> {
+        self.decode(token, &self.challenge_audience())
+    }
+
+    fn challenge_audience(&self) -> String {
+        format!("{}:mfa", self.audience)
+    }
+
+    /// Create the state token of an OpenID Connect login
+    pub fn create_oidc_state_token(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String> {
+        let now = Utc::now();
+        let expiration = now
+            .checked_add_signed(chrono::Duration::minutes(OIDC_STATE_LIFETIME_MINUTES))
+            .context("Invalid timestamp")?
+            .timestamp() as usize;
+
+        let claims = OidcStateClaims {
+            state: state.to_owned(),
+            nonce: nonce.to_owned(),
+            code_verifier: code_verifier.to_owned(),
+            exp: expiration,
+            iat: now.timestamp() as usize,
+            iss: self.issuer.clone(),
+            aud: self.oidc_state_audience(),
+        };
+
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+
+        encode(&header, &claims, &self.encoding_key).context("Failed to create token")
+    }
+
+    pub fn validate_oidc_state_token(&self, token: &str) -> Result<OidcStateClaims> {
+        self.decode(token, &self.oidc_state_audience())
+    }
+
+    fn oidc_state_audience(&self) -> String {
+        format!("{}:oidc", self.audience)
+    }
+
+    /// Create the access token of a password-protected short URL
+    pub fn create_link_access_token(&self, code: &str, fingerprint: &str) -> Result<String> {
+        let now = Utc::now();
+        let expiration = now
+            .checked_add_signed(chrono::Duration::minutes(LINK_ACCESS_LIFETIME_MINUTES))
+            .context("Invalid timestamp")?
+            .timestamp() as usize;
+
+        let claims = LinkAccessClaims {
+            sub: code.to_owned(),
+            pwd: fingerprint.to_owned(),
+            exp: expiration,
+            iat: now.timestamp() as usize,
+            iss: self.issuer.clone(),
+            aud: self.link_access_audience(),
+        };
+
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+
+        encode(&header, &claims, &self.encoding_key).context("Failed to create token")
+    }
+
+    pub fn validate_link_access_token(&self, token: &str) -> Result<LinkAccessClaims> {
+        self.decode(token, &self.link_access_audience())
+    }
+
+    fn link_access_audience(&self) -> String {
+        format!("{}:link", self.audience)
+    }
+
+    /// Create the challenge token of a passkey ceremony
+    pub fn create_passkey_challenge_token(
+        &self,
+        ceremony: PasskeyCeremony,
+        user_id: &str,
+        challenge: &str,
+    ) -> Result<String> {
+        let now = Utc::now();
+        let expiration = now
+            .checked_add_signed(chrono::Duration::minutes(
+                PASSKEY_CHALLENGE_LIFETIME_MINUTES,
+            ))
+            .context("Invalid timestamp")?
+            .timestamp() as usize;
+
+        let claims = PasskeyChallengeClaims {
+            sub: user_id.to_owned(),
+            challenge: challenge.to_owned(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            exp: expiration,
+            iat: now.timestamp() as usize,
+            iss: self.issuer.clone(),
+            aud: self.passkey_audience(ceremony),
+        };
+
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+
+        encode(&header, &claims, &self.encoding_key).context("Failed to create token")
+    }
+
+    pub fn validate_passkey_challenge_token(
+        &self,
+        ceremony: PasskeyCeremony,
+        token: &str,
+    ) -> Result<PasskeyChallengeClaims> {
+        self.decode(token, &self.passkey_audience(ceremony))
+    }
+
+    fn passkey_audience(&self, ceremony: PasskeyCeremony) -> String {
+        match ceremony {
+            PasskeyCeremony::Registration => format!("{}:passkey-registration", self.audience),
+            PasskeyCeremony::Login => format!("{}:passkey-login", self.audience),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, token: &str, audience: &str) -> Result<T> {
+        let header = decode_header(token).context("Failed to validate token")?;
+
+        // Pick the key the token names; tokens of retired keys past their grace period are rejected
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.kid == header.kid && key.is_valid())
+            .context("Unknown signing key")?;
+
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[audience]);
+
+        let token_data = decode::<T>(token, &key.decoding_key, &validation)
+            .context("Failed to validate token")?;
+
+        Ok(token_data.claims)
+    }
+
+    /// Public keys that currently verify tokens, including retired keys in their grace period
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter(|key| key.is_valid())
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
 }
 
-pub fn validate_token(token: &str) -> Result<Claims> {
-    let secret = env::var("JWT_SECRET").context("JWT_SECRET must be set")?;
-    let decoding_key = DecodingKey::from_secret(secret.as_bytes());
+/// Derive the public JWK of a PEM private key
+fn public_jwk(algorithm: JwtAlgorithm, key_config: &SigningKeyConfig, pem: &[u8]) -> Result<Jwk> {
+    let der = PrivateKeyDer::from_pem_slice(pem).context("Failed to parse PEM private key")?;
+
+    let (key_algorithm, parameters) = match algorithm {
+        JwtAlgorithm::RS256 => {
+            let key_pair = match &der {
+                PrivateKeyDer::Pkcs1(der) => RsaKeyPair::from_der(der.secret_pkcs1_der()),
+                PrivateKeyDer::Pkcs8(der) => RsaKeyPair::from_pkcs8(der.secret_pkcs8_der()),
+                _ => anyhow::bail!("Expected an RSA private key"),
+            }
+            .map_err(|e| anyhow::anyhow!("Invalid RSA private key: {}", e))?;
 
-    let token_data = decode::<Claims>(token, &decoding_key, &Validation::default())
-        .context("Failed to validate token")?;
+            let public_key = RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+            (
+                KeyAlgorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(&public_key.n),
+                    e: URL_SAFE_NO_PAD.encode(&public_key.e),
+                }),
+            )
+        }
+        JwtAlgorithm::EdDSA => {
+            let PrivateKeyDer::Pkcs8(der) = &der else {
+                anyhow::bail!("Expected a PKCS#8 Ed25519 private key");
+            };
+            let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.secret_pkcs8_der())
+                .map_err(|e| anyhow::anyhow!("Invalid Ed25519 private key: {}", e))?;
+
+            (
+                KeyAlgorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+                }),
+            )
+        }
+        JwtAlgorithm::HS256 => anyhow::bail!("HS256 keys have no public part"),
+    };
 
-    Ok(token_data.claims)
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(key_config.kid.clone()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
 }
@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+/// Longest delay imposed between failed login attempts, in seconds
+const MAX_BACKOFF_SECONDS: i64 = 60;
+
+/// Seconds to wait after `failures` consecutive failed logins: 1, 2, 4, ... up to a minute
+pub fn backoff_seconds(failures: i64) -> i64 {
+    if failures <= 0 {
+        return 0;
+    }
+
+    2_i64
+        .saturating_pow((failures - 1).min(32) as u32)
+        .min(MAX_BACKOFF_SECONDS)
+}
+
+/// Seconds left until `until` (Unix milliseconds), rounded up
+pub fn seconds_until(until: i64, now: i64) -> Option<u64> {
+    (until > now).then(|| ((until - now + 999) / 1000) as u64)
+}
+
+/// Most clients whose failed attempts are tracked at once. When full, the entry that
+/// would be forgotten soonest makes room, so memory stays bounded whatever clients send.
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+struct FailedAttempts {
+    failures: i64,
+    last_failed: i64,
+    locked_until: Option<i64>,
+    forget_at: i64, // The failures no longer affect the client after this
+}
+
+#[derive(Default)]
+struct Attempts {
+    by_client: HashMap<String, FailedAttempts>,
+    by_expiry: BTreeSet<(i64, String)>, // (forget_at, client), to prune without scanning
+}
+
+/// Failed login attempts per client IP. Wrong passwords of protected short URLs, per IP
+/// and per URL, and password reset requests are counted by separate instances.
+/// Kept in memory, so every server instance counts on its own and restarts start over.
+pub struct LoginThrottle {
+    attempts: Mutex<Attempts>,
+    capacity: usize,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            attempts: Mutex::default(),
+            capacity: MAX_TRACKED_CLIENTS,
+        }
+    }
+}
+
+impl LoginThrottle {
+    /// Seconds the client has to wait before it may try to log in again, if any
+    pub fn retry_after(&self, client: &str) -> Option<u64> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let attempts = self.attempts.lock().unwrap();
+        let entry = attempts.by_client.get(client)?;
+
+        let until = entry
+            .locked_until
+            .unwrap_or(entry.last_failed + backoff_seconds(entry.failures) * 1000);
+        seconds_until(until, now)
+    }
+
+    /// Count a failed login from `client`, locking it out for `lockout_ms` once it
+    /// reaches `max_failures`. Failures older than the lockout duration are forgotten.
+    pub fn record_failure(&self, client: &str, max_failures: i64, lockout_ms: i64) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut attempts = self.attempts.lock().unwrap();
+        let Attempts {
+            by_client,
+            by_expiry,
+        } = &mut *attempts;
+
+        match by_client.get(client) {
+            Some(entry) => {
+                by_expiry.remove(&(entry.forget_at, client.to_string()));
+            }
+            None if by_client.len() >= self.capacity => {
+                if let Some((_, oldest)) = by_expiry.pop_first() {
+                    by_client.remove(&oldest);
+                }
+            }
+            None => {}
+        }
+
+        let entry = by_client
+            .entry(client.to_string())
+            .or_insert(FailedAttempts {
+                failures: 0,
+                last_failed: now,
+                locked_until: None,
+                forget_at: now,
+            });
+
+        if entry.forget_at <= now
+            || entry
+                .locked_until
+                .is_some_and(|locked_until| locked_until <= now)
+        {
+            entry.failures = 0;
+            entry.locked_until = None;
+        }
+
+        entry.failures += 1;
+        entry.last_failed = now;
+        entry.forget_at = now + lockout_ms;
+
+        if entry.failures >= max_failures {
+            entry.locked_until = Some(now + lockout_ms);
+        }
+        by_expiry.insert((entry.forget_at, client.to_string()));
+    }
+
+    /// Count a failure that only delays the next attempt, however many there are.
+    /// Failures older than `forget_ms` are forgotten.
+    pub fn record_delay(&self, client: &str, forget_ms: i64) {
+        self.record_failure(client, i64::MAX, forget_ms);
+    }
+
+    /// Drop the entries that no longer affect anyone; called periodically
+    pub fn prune(&self) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut attempts = self.attempts.lock().unwrap();
+        let Attempts {
+            by_client,
+            by_expiry,
+        } = &mut *attempts;
+
+        while let Some((forget_at, _)) = by_expiry.first()
+            && *forget_at <= now
+        {
+            if let Some((_, client)) = by_expiry.pop_first() {
+                by_client.remove(&client);
+            }
+        }
+    }
+}
@@ -1,2 +1,12 @@
+pub mod audit;
+pub mod client_ip;
 pub mod hash_ip;
 pub mod jwt;
+pub mod login_throttle;
+pub mod oidc;
+pub mod password;
+pub mod policy;
+pub mod tls;
+pub mod tokens;
+pub mod totp;
+pub mod webauthn;
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use rand::RngCore;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::config::app_config::Config;
+
+/// Signature algorithms accepted on ID tokens. Symmetric algorithms are left out,
+/// the client secret is not meant to verify tokens.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The parts of the provider's discovery document the authorization code flow needs
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Claims of a validated ID token
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>, // Every other claim, e.g. name and preferred_username
+}
+
+impl IdTokenClaims {
+    /// Look up a string claim by name
+    pub fn claim(&self, name: &str) -> Option<&str> {
+        match name {
+            "iss" => Some(&self.iss),
+            "sub" => Some(&self.sub),
+            "email" => self.email.as_deref(),
+            "nonce" => self.nonce.as_deref(),
+            _ => self.extra.get(name).and_then(Value::as_str),
+        }
+    }
+}
+
+/// Relying party of the OpenID Connect authorization code flow with PKCE.
+/// The provider's metadata is discovered on first use and its signing keys are
+/// fetched again whenever an ID token names a key that is not known yet.
+pub struct OidcClient {
+    http: reqwest::Client,
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    metadata: RwLock<Option<Arc<ProviderMetadata>>>,
+    jwks: RwLock<Option<Arc<JwkSet>>>,
+}
+
+impl OidcClient {
+    /// Build the client if single sign-on is configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let Some(issuer_url) = &config.oidc.issuer_url else {
+            return Ok(None);
+        };
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Some(Self {
+            http,
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id: config.oidc.client_id.clone(),
+            client_secret: config.oidc.client_secret.clone(),
+            redirect_url: config.oidc_redirect_url(),
+            scopes: config.oidc.scopes.clone(),
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        }))
+    }
+
+    /// Issuer identifier of the provider, as it appears in ID tokens
+    pub fn issuer(&self) -> &str {
+        &self.issuer_url
+    }
+
+    /// URL of the provider's login page for a new authorization request
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String> {
+        let metadata = self.metadata().await?;
+
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_url.as_str()),
+                ("scope", self.scopes.as_str()),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", code_challenge(code_verifier).as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .context("Invalid authorization endpoint")?;
+
+        Ok(url.into())
+    }
+
+    /// Exchange an authorization code at the token endpoint and validate the returned ID token
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint);
+        match &self.client_secret {
+            Some(client_secret) => {
+                request = request.basic_auth(&self.client_id, Some(client_secret));
+            }
+            None => form.push(("client_id", self.client_id.as_str())),
+        }
+
+        let response = request
+            .form(&form)
+            .send()
+            .await
+            .context("Token request failed")?;
+        if !response.status().is_success() {
+            let status = response.status();
+            match response.json::<TokenErrorResponse>().await {
+                Ok(error) => bail!(
+                    "Token endpoint returned {}: {}",
+                    error.error,
+                    error.error_description.unwrap_or_default()
+                ),
+                Err(_) => bail!("Token endpoint returned {}", status),
+            }
+        }
+
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .context("Invalid token endpoint response")?;
+        let id_token = tokens
+            .id_token
+            .context("Token endpoint returned no ID token")?;
+
+        self.validate_id_token(&id_token, &metadata, nonce).await
+    }
+
+    /// Check the signature, issuer, audience, expiry and nonce of an ID token
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        metadata: &ProviderMetadata,
+        nonce: &str,
+    ) -> Result<IdTokenClaims> {
+        let header = decode_header(id_token).context("Invalid ID token")?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            bail!(
+                "ID token is signed with unsupported algorithm {:?}",
+                header.alg
+            );
+        }
+
+        let decoding_key = self
+            .decoding_key(header.kid.as_deref(), &metadata.jwks_uri)
+            .await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.client_id]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .context("Invalid ID token")?
+            .claims;
+
+        // Ties the token to the login that was started in this browser
+        if claims.nonce.as_deref() != Some(nonce) {
+            bail!("ID token nonce does not match");
+        }
+
+        Ok(claims)
+    }
+
+    /// Find the provider key that signed a token, refreshing the key set once if it is unknown
+    async fn decoding_key(&self, kid: Option<&str>, jwks_uri: &str) -> Result<DecodingKey> {
+        let cached = self.jwks.read().unwrap().clone();
+        let jwks = match cached {
+            Some(jwks) if find_key(&jwks, kid).is_some() => jwks,
+            _ => {
+                let jwks: Arc<JwkSet> = Arc::new(self.fetch(jwks_uri).await?);
+                *self.jwks.write().unwrap() = Some(jwks.clone());
+                jwks
+            }
+        };
+
+        let jwk = find_key(&jwks, kid).context("ID token is signed with an unknown key")?;
+        DecodingKey::from_jwk(jwk).context("Invalid provider key")
+    }
+
+    async fn metadata(&self) -> Result<Arc<ProviderMetadata>> {
+        if let Some(metadata) = self.metadata.read().unwrap().clone() {
+            return Ok(metadata);
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.issuer_url);
+        let metadata: ProviderMetadata = self.fetch(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != self.issuer_url {
+            bail!(
+                "Discovery document of {} names a different issuer: {}",
+                self.issuer_url,
+                metadata.issuer
+            );
+        }
+
+        let metadata = Arc::new(metadata);
+        *self.metadata.write().unwrap() = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    async fn fetch<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to fetch {}", url))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid response from {}", url))
+    }
+}
+
+fn find_key<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        // Without a key ID the token can only name the provider's single key
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
+
+/// Create a random PKCE code verifier
+pub fn code_verifier() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The S256 code challenge of a verifier, sent with the authorization request
+fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
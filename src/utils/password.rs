@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+
+use crate::config::app_config::AuthConfig;
+
+/// Salt of the hash computed in place of verifying a missing password; its value is irrelevant
+const DUMMY_SALT: &str = "bWFrZW1lc2hvcnRkdW1teQ";
+
+/// Hashes passwords with Argon2id and verifies both Argon2 and legacy bcrypt hashes.
+/// Hashing is slow on purpose, so it runs on the blocking thread pool instead of
+/// holding up the async workers.
+#[derive(Debug, Clone, Copy)]
+pub struct Passwords {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl Passwords {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            memory_kib: config.argon2_memory_kib,
+            iterations: config.argon2_iterations,
+            parallelism: config.argon2_parallelism,
+        }
+    }
+
+    /// Hash a password with the configured Argon2id parameters
+    pub async fn hash(&self, password: &str) -> Result<String> {
+        let argon2 = self.argon2()?;
+        let password = password.to_owned();
+
+        actix_web::web::block(move || {
+            let mut salt = [0u8; 16];
+            rand::rng().fill_bytes(&mut salt);
+            let salt = SaltString::encode_b64(&salt)
+                .map_err(|e| anyhow::anyhow!("Failed to encode salt: {}", e))?;
+
+            argon2
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))
+        })
+        .await
+        .context("Password hashing task failed")?
+    }
+
+    /// Check a password against a stored Argon2 or bcrypt hash
+    pub async fn verify(&self, password: &str, password_hash: &str) -> Result<bool> {
+        let argon2 = self.argon2()?;
+        let password = password.to_owned();
+        let password_hash = password_hash.to_owned();
+
+        actix_web::web::block(move || {
+            // Users created by single sign-on have no password. Hashing takes as long as
+            // verifying, so the response time does not tell them apart.
+            if password_hash.is_empty() {
+                let salt = SaltString::from_b64(DUMMY_SALT)
+                    .map_err(|e| anyhow::anyhow!("Invalid salt: {}", e))?;
+                argon2
+                    .hash_password(password.as_bytes(), &salt)
+                    .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+                return Ok(false);
+            }
+
+            if !password_hash.starts_with("$argon2") {
+                return bcrypt::verify(&password, &password_hash)
+                    .context("Failed to verify password");
+            }
+
+            // The parameters are read from the hash, so older hashes keep verifying
+            let parsed = PasswordHash::new(&password_hash)
+                .map_err(|e| anyhow::anyhow!("Invalid password hash: {}", e))?;
+            Ok(Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok())
+        })
+        .await
+        .context("Password verification task failed")?
+    }
+
+    /// Spend as long as verifying a password would, for an account that does not exist,
+    /// so response times do not reveal which accounts exist
+    pub async fn verify_missing(&self, password: &str) -> Result<bool> {
+        self.verify(password, "").await
+    }
+
+    /// Whether a stored hash should be replaced, because it is a bcrypt hash or
+    /// uses different Argon2id parameters than configured
+    pub fn needs_rehash(&self, password_hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(password_hash) else {
+            return true;
+        };
+        if parsed.algorithm != Algorithm::Argon2id.ident() {
+            return true;
+        }
+
+        Params::try_from(&parsed).map_or(true, |params| {
+            params.m_cost() != self.memory_kib
+                || params.t_cost() != self.iterations
+                || params.p_cost() != self.parallelism
+        })
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
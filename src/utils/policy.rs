@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::app_config::PolicyConfig;
+
+/// Upper bound on password length, so hashing stays cheap whatever the policy
+const MAX_PASSWORD_LENGTH: usize = 256;
+
+/// Rules that usernames and passwords must follow. Violations are added to
+/// `ValidationErrors`, so they are reported together with the derived request validation.
+pub struct AccountPolicy {
+    config: PolicyConfig,
+    username_pattern: Regex,
+    blocklist: HashSet<String>, // Lowercased
+}
+
+impl AccountPolicy {
+    /// Build the policy from the configuration, reading the password blocklist if one is set
+    pub fn load(config: &PolicyConfig) -> Result<Self> {
+        let username_pattern =
+            Regex::new(&config.username_pattern).context("Invalid username pattern")?;
+
+        let blocklist = match &config.password_blocklist_file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read password blocklist {}", path))?;
+                let blocklist: HashSet<String> = contents
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|line| !line.is_empty())
+                    .collect();
+                log::info!("Loaded {} passwords from {}", blocklist.len(), path);
+                blocklist
+            }
+            None => HashSet::new(),
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            username_pattern,
+            blocklist,
+        })
+    }
+
+    /// Whether users must have distinct email addresses
+    pub fn unique_emails(&self) -> bool {
+        self.config.unique_emails
+    }
+
+    /// Check a username against the length and character rules
+    pub fn check_username(&self, username: &str, errors: &mut ValidationErrors) {
+        let length = username.chars().count();
+        if length < self.config.username_min_length || length > self.config.username_max_length {
+            let mut error = ValidationError::new("length").with_message(Cow::Owned(format!(
+                "Username must be {}-{} characters",
+                self.config.username_min_length, self.config.username_max_length
+            )));
+            error.add_param(Cow::Borrowed("min"), &self.config.username_min_length);
+            error.add_param(Cow::Borrowed("max"), &self.config.username_max_length);
+            errors.add("username", error);
+        } else if !self.username_pattern.is_match(username) {
+            let mut error = ValidationError::new("username_characters").with_message(
+                Cow::Borrowed("Username contains characters that are not allowed"),
+            );
+            error.add_param(Cow::Borrowed("pattern"), &self.config.username_pattern);
+            errors.add("username", error);
+        }
+    }
+
+    /// Check a single password, for requests that only set a password
+    pub fn validate_password(
+        &self,
+        field: &'static str,
+        password: &str,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        self.check_password(field, password, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check a password against the length, character class and blocklist rules.
+    /// Errors are reported under `field`, the name of the password in the request.
+    pub fn check_password(
+        &self,
+        field: &'static str,
+        password: &str,
+        errors: &mut ValidationErrors,
+    ) {
+        let length = password.chars().count();
+        if length < self.config.password_min_length || length > MAX_PASSWORD_LENGTH {
+            let mut error = ValidationError::new("length").with_message(Cow::Owned(format!(
+                "Password must be {}-{} characters",
+                self.config.password_min_length, MAX_PASSWORD_LENGTH
+            )));
+            error.add_param(Cow::Borrowed("min"), &self.config.password_min_length);
+            error.add_param(Cow::Borrowed("max"), &MAX_PASSWORD_LENGTH);
+            errors.add(field, error);
+            return;
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_numeric()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ]
+        .into_iter()
+        .filter(|&present| present)
+        .count();
+        if classes < self.config.password_min_classes {
+            let mut error = ValidationError::new("password_classes").with_message(Cow::Owned(
+                format!(
+                    "Password must contain at least {} of lowercase letters, uppercase letters, digits and symbols",
+                    self.config.password_min_classes
+                ),
+            ));
+            error.add_param(Cow::Borrowed("min"), &self.config.password_min_classes);
+            errors.add(field, error);
+        }
+
+        if self.blocklist.contains(&password.to_lowercase()) {
+            errors.add(
+                field,
+                ValidationError::new("password_common").with_message(Cow::Borrowed(
+                    "Password is too common, choose a different one",
+                )),
+            );
+        }
+    }
+}
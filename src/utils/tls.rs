@@ -0,0 +1,88 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Serves the certificate loaded from disk and allows it to be swapped
+/// without restarting the server
+#[derive(Debug)]
+pub struct ReloadableCertResolver {
+    cert_file: String,
+    key_file: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCertResolver {
+    pub fn new(cert_file: &str, key_file: &str) -> Result<Self> {
+        let certified_key = load_certified_key(cert_file, key_file)?;
+
+        Ok(Self {
+            cert_file: cert_file.to_string(),
+            key_file: key_file.to_string(),
+            current: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    /// Re-read the certificate and key. The current certificate is kept if loading fails.
+    pub fn reload(&self) -> Result<()> {
+        let certified_key = load_certified_key(&self.cert_file, &self.key_file)?;
+        *self.current.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Load a PEM certificate chain and private key
+fn load_certified_key(cert_file: &str, key_file: &str) -> Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate {}", cert_file))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", cert_file);
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .with_context(|| format!("Failed to read TLS private key {}", key_file))?;
+    let signing_key = any_supported_type(&key)
+        .with_context(|| format!("Unsupported TLS private key {}", key_file))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Build the rustls server configuration serving certificates from the resolver
+pub fn server_config(resolver: Arc<ReloadableCertResolver>) -> Result<rustls::ServerConfig> {
+    Ok(
+        rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .context("Failed to configure TLS protocol versions")?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver),
+    )
+}
+
+/// Reload the certificate whenever the process receives SIGHUP
+pub fn reload_on_sighup(resolver: Arc<ReloadableCertResolver>) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(()) => log::info!("Reloaded TLS certificate"),
+                Err(e) => log::error!("Failed to reload TLS certificate: {:#}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
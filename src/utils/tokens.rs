@@ -0,0 +1,19 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Generate an opaque random token, hex encoded
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hash a token for storage. Tokens are random, so an unsalted SHA-256 is sufficient.
+pub fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    let result = hasher.finalize();
+
+    format!("{:x}", result)
+}
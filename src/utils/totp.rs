@@ -0,0 +1,129 @@
+use rand::RngCore;
+use ring::hmac;
+
+/// Length of a TOTP time step, in seconds
+const STEP_SECONDS: i64 = 30;
+
+/// Number of digits in a code
+const DIGITS: u32 = 6;
+
+/// Codes from this many steps before or after the current one are accepted, to allow for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Generate a random 160-bit secret, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::rng().fill_bytes(&mut bytes);
+    base32_encode(&bytes)
+}
+
+/// Build the `otpauth://` URI that authenticator apps import, usually by scanning it as a QR code
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Check a code against the secret at the given Unix time.
+/// Returns the time step the code belongs to, so callers can refuse to accept it twice.
+pub fn verify(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let key = base32_decode(secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = now / STEP_SECONDS;
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .find(|&step| generate_code(&key, step) == code)
+}
+
+/// The code an authenticator app shows for the secret at the given Unix time
+pub fn code_at(secret: &str, now: i64) -> Option<String> {
+    let key = base32_decode(secret)?;
+    Some(generate_code(&key, now / STEP_SECONDS))
+}
+
+/// RFC 6238 code for a time step (HMAC-SHA1 with dynamic truncation, RFC 4226)
+fn generate_code(key: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let tag = hmac::sign(&key, &(step as u64).to_be_bytes());
+    let digest = tag.as_ref();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        value % 10_u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// RFC 4648 base32 without padding
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+/// Percent-encode everything but unreserved characters (RFC 3986)
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,860 @@
+use std::io::Cursor;
+
+use anyhow::{Context, Result, bail, ensure};
+use base64::Engine;
+use base64::alphabet::URL_SAFE;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use ciborium::Value;
+use rand::RngCore;
+use reqwest::Url;
+use ring::signature::{
+    ECDSA_P256_SHA256_ASN1, ED25519, RSA_PKCS1_2048_8192_SHA256, RsaPublicKeyComponents,
+    UnparsedPublicKey,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::app_config::Config;
+use crate::models::passkey::Passkey;
+
+/// Base64url as used by WebAuthn. Browsers leave out the padding, but it is accepted either way.
+const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// How long the browser gives the user to complete a ceremony
+const CEREMONY_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+// COSE algorithm identifiers, in order of preference
+const COSE_ALG_EDDSA: i128 = -8;
+const COSE_ALG_ES256: i128 = -7;
+const COSE_ALG_RS256: i128 = -257;
+
+/// Result of `navigator.credentials.create()`, serialized with `toJSON()`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// Result of `navigator.credentials.get()`, serialized with `toJSON()`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+    #[serde(default)]
+    cross_origin: bool,
+}
+
+/// A credential that passed registration, ready to be stored
+pub struct VerifiedCredential {
+    pub credential_id: String,
+    pub public_key: String,
+    pub sign_count: u32,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    credential: Option<(Vec<u8>, &'a [u8])>, // Credential ID and COSE public key, on registration
+}
+
+enum CoseKey {
+    Ed25519 { x: Vec<u8> },
+    Es256 { x: Vec<u8>, y: Vec<u8> },
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+/// WebAuthn relying party. Builds the options for the browser's registration and
+/// authentication ceremonies and verifies their results. Only passkeys with user
+/// verification are accepted, since they replace the password. Attestation is not
+/// requested, so any authenticator can be registered.
+pub struct Webauthn {
+    rp_id: String,
+    rp_name: String,
+    origins: Vec<String>,
+}
+
+impl Webauthn {
+    /// Set up the relying party, defaulting the ID and origin to those of the public URL
+    pub fn new(config: &Config) -> Result<Self> {
+        let public_url = Url::parse(&config.server.public_url).context("Invalid public URL")?;
+
+        let rp_id = match &config.webauthn.rp_id {
+            Some(rp_id) => rp_id.to_lowercase(),
+            None => public_url
+                .host_str()
+                .context("Public URL has no host")?
+                .to_string(),
+        };
+
+        let origins = if config.webauthn.origins.is_empty() {
+            vec![public_url.origin().ascii_serialization()]
+        } else {
+            config
+                .webauthn
+                .origins
+                .iter()
+                .map(|origin| origin.trim_end_matches('/').to_string())
+                .collect()
+        };
+
+        // Browsers only allow an RP ID that is the origin's host or a parent domain of it
+        for origin in &origins {
+            let url = Url::parse(origin).with_context(|| format!("Invalid origin {}", origin))?;
+            let host = url.host_str().unwrap_or_default();
+            if host != rp_id && !host.ends_with(&format!(".{}", rp_id)) {
+                bail!(
+                    "WebAuthn RP ID {} does not match the host of origin {}",
+                    rp_id,
+                    origin
+                );
+            }
+        }
+
+        Ok(Self {
+            rp_id,
+            rp_name: config.webauthn.rp_name.clone(),
+            origins,
+        })
+    }
+
+    /// Options for `navigator.credentials.create()`. Passkeys the user already has are excluded,
+    /// so the same authenticator is not registered twice.
+    pub fn creation_options(
+        &self,
+        challenge: &str,
+        user_handle: &[u8],
+        username: &str,
+        display_name: &str,
+        existing: &[Passkey],
+    ) -> serde_json::Value {
+        let algorithms: Vec<serde_json::Value> = [COSE_ALG_EDDSA, COSE_ALG_ES256, COSE_ALG_RS256]
+            .iter()
+            .map(|alg| serde_json::json!({ "type": "public-key", "alg": *alg as i64 }))
+            .collect();
+
+        serde_json::json!({
+            "rp": { "id": self.rp_id, "name": self.rp_name },
+            "user": {
+                "id": BASE64URL.encode(user_handle),
+                "name": username,
+                "displayName": display_name,
+            },
+            "challenge": challenge,
+            "pubKeyCredParams": algorithms,
+            "timeout": CEREMONY_TIMEOUT_MS,
+            "excludeCredentials": credential_descriptors(existing),
+            "authenticatorSelection": {
+                "residentKey": "required",
+                "requireResidentKey": true,
+                "userVerification": "required",
+            },
+            "attestation": "none",
+        })
+    }
+
+    /// Options for `navigator.credentials.get()`. No credentials are listed, so the browser
+    /// offers every passkey it has for this site and the user does not type a username.
+    pub fn request_options(&self, challenge: &str) -> serde_json::Value {
+        serde_json::json!({
+            "challenge": challenge,
+            "rpId": self.rp_id,
+            "timeout": CEREMONY_TIMEOUT_MS,
+            "allowCredentials": [],
+            "userVerification": "required",
+        })
+    }
+
+    /// Verify the result of a registration ceremony started with `challenge`
+    pub fn verify_registration(
+        &self,
+        credential: &RegistrationCredential,
+        challenge: &str,
+    ) -> Result<VerifiedCredential> {
+        ensure!(
+            credential.credential_type == "public-key",
+            "Unexpected credential type"
+        );
+        let client_data_json = decode(&credential.response.client_data_json)?;
+        self.check_client_data(&client_data_json, "webauthn.create", challenge)?;
+
+        let attestation: Value =
+            ciborium::from_reader(decode(&credential.response.attestation_object)?.as_slice())
+                .context("Invalid attestation object")?;
+        let auth_data = map_get(&attestation, &Value::Text("authData".to_string()))
+            .and_then(Value::as_bytes)
+            .context("Attestation object has no authenticator data")?;
+
+        let auth_data = parse_authenticator_data(auth_data)?;
+        self.check_authenticator_data(&auth_data)?;
+        let (credential_id, public_key) = auth_data
+            .credential
+            .context("Authenticator data has no credential")?;
+        ensure!(
+            credential_id == decode(&credential.raw_id)?,
+            "Credential ID does not match"
+        );
+
+        // Refuse keys that could not be used to log in later
+        parse_cose_key(public_key)?;
+
+        Ok(VerifiedCredential {
+            credential_id: BASE64URL.encode(&credential_id),
+            public_key: BASE64URL.encode(public_key),
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Verify the result of an authentication ceremony started with `challenge`, made with
+    /// the stored passkey. Returns the new signature counter.
+    pub fn verify_authentication(
+        &self,
+        credential: &AuthenticationCredential,
+        challenge: &str,
+        passkey: &Passkey,
+        user_handle: &[u8],
+    ) -> Result<u32> {
+        ensure!(
+            credential.credential_type == "public-key",
+            "Unexpected credential type"
+        );
+        let client_data_json = decode(&credential.response.client_data_json)?;
+        self.check_client_data(&client_data_json, "webauthn.get", challenge)?;
+
+        if let Some(handle) = &credential.response.user_handle {
+            ensure!(
+                decode(handle)? == user_handle,
+                "User handle does not match the passkey"
+            );
+        }
+
+        let raw_auth_data = decode(&credential.response.authenticator_data)?;
+        let auth_data = parse_authenticator_data(&raw_auth_data)?;
+        self.check_authenticator_data(&auth_data)?;
+
+        // The signature covers the authenticator data and the hash of the client data
+        let mut message = raw_auth_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data_json));
+        let signature = decode(&credential.response.signature)?;
+        let key = parse_cose_key(&decode(&passkey.public_key)?)?;
+        verify_signature(&key, &message, &signature)?;
+
+        // Authenticators that keep a counter increase it on every use; a counter that does
+        // not grow means the key was copied
+        let stored = passkey.sign_count as u32;
+        if (auth_data.sign_count != 0 || stored != 0) && auth_data.sign_count <= stored {
+            bail!(
+                "Signature counter went from {} to {}, the authenticator may be cloned",
+                stored,
+                auth_data.sign_count
+            );
+        }
+
+        Ok(auth_data.sign_count)
+    }
+
+    fn check_client_data(&self, json: &[u8], ceremony_type: &str, challenge: &str) -> Result<()> {
+        let client_data: ClientData =
+            serde_json::from_slice(json).context("Invalid client data")?;
+
+        ensure!(
+            client_data.ceremony_type == ceremony_type,
+            "Unexpected ceremony type {}",
+            client_data.ceremony_type
+        );
+        ensure!(
+            client_data.challenge.trim_end_matches('=') == challenge,
+            "Challenge does not match"
+        );
+        ensure!(
+            self.origins.contains(&client_data.origin),
+            "Origin {} is not allowed",
+            client_data.origin
+        );
+        ensure!(
+            !client_data.cross_origin,
+            "Cross-origin ceremonies are not allowed"
+        );
+
+        Ok(())
+    }
+
+    fn check_authenticator_data(&self, auth_data: &AuthenticatorData) -> Result<()> {
+        ensure!(
+            auth_data.rp_id_hash == Sha256::digest(self.rp_id.as_bytes()).as_slice(),
+            "Credential belongs to a different RP ID"
+        );
+        ensure!(
+            auth_data.flags & FLAG_USER_PRESENT != 0,
+            "User presence was not confirmed"
+        );
+        ensure!(
+            auth_data.flags & FLAG_USER_VERIFIED != 0,
+            "User was not verified by the authenticator"
+        );
+        Ok(())
+    }
+}
+
+/// Create a random challenge for a ceremony
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    BASE64URL.encode(bytes)
+}
+
+/// Normalize a base64url credential ID sent by the browser to the stored form
+pub fn normalize_credential_id(raw_id: &str) -> Result<String> {
+    Ok(BASE64URL.encode(decode(raw_id)?))
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    BASE64URL.decode(value).context("Invalid base64url value")
+}
+
+fn credential_descriptors(passkeys: &[Passkey]) -> Vec<serde_json::Value> {
+    passkeys
+        .iter()
+        .map(|passkey| {
+            serde_json::json!({
+                "type": "public-key",
+                "id": passkey.credential_id,
+                "transports": passkey.transports,
+            })
+        })
+        .collect()
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>> {
+    ensure!(data.len() >= 37, "Authenticator data is too short");
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes(data[33..37].try_into()?);
+
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // AAGUID (16 bytes), credential ID length (2 bytes), credential ID, COSE key
+        ensure!(data.len() >= 55, "Attested credential data is too short");
+        let id_length = u16::from_be_bytes(data[53..55].try_into()?) as usize;
+        let id_end = 55 + id_length;
+        ensure!(data.len() > id_end, "Attested credential data is too short");
+
+        // The key is followed by extensions, so its length is only known after parsing it
+        let mut cursor = Cursor::new(&data[id_end..]);
+        let _: Value =
+            ciborium::from_reader(&mut cursor).context("Invalid credential public key")?;
+        let key_end = id_end + cursor.position() as usize;
+
+        Some((data[55..id_end].to_vec(), &data[id_end..key_end]))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags,
+        sign_count,
+        credential,
+    })
+}
+
+fn map_get<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value)
+}
+
+fn parse_cose_key(bytes: &[u8]) -> Result<CoseKey> {
+    let key: Value = ciborium::from_reader(bytes).context("Invalid COSE key")?;
+    let int = |label: i64| {
+        map_get(&key, &Value::Integer(label.into()))
+            .and_then(Value::as_integer)
+            .map(i128::from)
+    };
+    let bytes = |label: i64| {
+        map_get(&key, &Value::Integer(label.into()))
+            .and_then(Value::as_bytes)
+            .cloned()
+            .with_context(|| format!("COSE key parameter {} is missing", label))
+    };
+
+    // Key type (1), algorithm (3), curve (-1) and coordinates or RSA parameters
+    match (int(1), int(3)) {
+        (Some(1), Some(COSE_ALG_EDDSA)) if int(-1) == Some(6) => {
+            Ok(CoseKey::Ed25519 { x: bytes(-2)? })
+        }
+        (Some(2), Some(COSE_ALG_ES256)) if int(-1) == Some(1) => {
+            let (x, y) = (bytes(-2)?, bytes(-3)?);
+            ensure!(x.len() == 32 && y.len() == 32, "Invalid P-256 key");
+            Ok(CoseKey::Es256 { x, y })
+        }
+        (Some(3), Some(COSE_ALG_RS256)) => Ok(CoseKey::Rs256 {
+            n: bytes(-1)?,
+            e: bytes(-2)?,
+        }),
+        (kty, alg) => bail!(
+            "Unsupported COSE key type {:?} with algorithm {:?}",
+            kty,
+            alg
+        ),
+    }
+}
+
+fn verify_signature(key: &CoseKey, message: &[u8], signature: &[u8]) -> Result<()> {
+    let result = match key {
+        CoseKey::Ed25519 { x } => UnparsedPublicKey::new(&ED25519, x).verify(message, signature),
+        CoseKey::Es256 { x, y } => {
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+        }
+        CoseKey::Rs256 { n, e } => {
+            RsaPublicKeyComponents { n, e }.verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+        }
+    };
+
+    result.map_err(|_| anyhow::anyhow!("Invalid signature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    const CHALLENGE: &str = "c2lnbiBtZSBpbiwgcGxlYXNlIQ";
+    const ORIGIN: &str = "http://localhost:8080";
+    const USER_HANDLE: &[u8] = b"user-handle!";
+
+    enum Key {
+        Es256(EcdsaKeyPair),
+        Ed25519(Ed25519KeyPair),
+    }
+
+    /// A software authenticator producing what a browser would send
+    struct Authenticator {
+        key: Key,
+        credential_id: Vec<u8>,
+        rp_id: String,
+        flags: u8,
+    }
+
+    impl Authenticator {
+        fn es256() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let key =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+            Self::with_key(Key::Es256(key))
+        }
+
+        fn ed25519() -> Self {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            Self::with_key(Key::Ed25519(key))
+        }
+
+        fn with_key(key: Key) -> Self {
+            Self {
+                key,
+                credential_id: b"credential-1".to_vec(),
+                rp_id: "localhost".to_string(),
+                flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let int = |value: i64| Value::Integer(value.into());
+            let key = match &self.key {
+                Key::Es256(key) => {
+                    let point = key.public_key().as_ref();
+                    vec![
+                        (int(1), int(2)),
+                        (int(3), int(-7)),
+                        (int(-1), int(1)),
+                        (int(-2), Value::Bytes(point[1..33].to_vec())),
+                        (int(-3), Value::Bytes(point[33..].to_vec())),
+                    ]
+                }
+                Key::Ed25519(key) => vec![
+                    (int(1), int(1)),
+                    (int(3), int(-8)),
+                    (int(-1), int(6)),
+                    (int(-2), Value::Bytes(key.public_key().as_ref().to_vec())),
+                ],
+            };
+            cbor(&Value::Map(key))
+        }
+
+        fn authenticator_data(&self, sign_count: u32, cose_key: Option<&[u8]>) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            let flags = match cose_key {
+                Some(_) => self.flags | FLAG_ATTESTED_CREDENTIAL,
+                None => self.flags,
+            };
+            data.push(flags);
+            data.extend_from_slice(&sign_count.to_be_bytes());
+            if let Some(cose_key) = cose_key {
+                data.extend_from_slice(&[0; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                data.extend_from_slice(cose_key);
+            }
+            data
+        }
+
+        fn register(&self, challenge: &str) -> RegistrationCredential {
+            self.register_with_key(challenge, &self.cose_key())
+        }
+
+        fn register_with_key(&self, challenge: &str, cose_key: &[u8]) -> RegistrationCredential {
+            let attestation = Value::Map(vec![
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(vec![])),
+                (
+                    Value::Text("authData".into()),
+                    Value::Bytes(self.authenticator_data(0, Some(cose_key))),
+                ),
+            ]);
+
+            RegistrationCredential {
+                raw_id: BASE64URL.encode(&self.credential_id),
+                credential_type: "public-key".to_string(),
+                response: AttestationResponse {
+                    client_data_json: BASE64URL.encode(client_data(
+                        "webauthn.create",
+                        challenge,
+                        ORIGIN,
+                    )),
+                    attestation_object: BASE64URL.encode(cbor(&attestation)),
+                    transports: vec!["internal".to_string()],
+                },
+            }
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            match &self.key {
+                Key::Es256(key) => key
+                    .sign(&SystemRandom::new(), message)
+                    .unwrap()
+                    .as_ref()
+                    .to_vec(),
+                Key::Ed25519(key) => key.sign(message).as_ref().to_vec(),
+            }
+        }
+
+        fn assert(&self, challenge: &str, sign_count: u32) -> AuthenticationCredential {
+            let client_data_json = client_data("webauthn.get", challenge, ORIGIN);
+            let auth_data = self.authenticator_data(sign_count, None);
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&Sha256::digest(&client_data_json));
+
+            AuthenticationCredential {
+                raw_id: BASE64URL.encode(&self.credential_id),
+                credential_type: "public-key".to_string(),
+                response: AssertionResponse {
+                    client_data_json: BASE64URL.encode(client_data_json),
+                    authenticator_data: BASE64URL.encode(auth_data),
+                    signature: BASE64URL.encode(self.sign(&message)),
+                    user_handle: Some(BASE64URL.encode(USER_HANDLE)),
+                },
+            }
+        }
+
+        fn passkey(&self, sign_count: i64) -> Passkey {
+            Passkey::new(
+                "user".to_string(),
+                BASE64URL.encode(&self.credential_id),
+                "Test".to_string(),
+                BASE64URL.encode(self.cose_key()),
+                sign_count,
+                Vec::new(),
+            )
+        }
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": ceremony_type,
+            "challenge": challenge,
+            "origin": origin,
+            "crossOrigin": false,
+        }))
+        .unwrap()
+    }
+
+    fn relying_party() -> Webauthn {
+        Webauthn::new(&Config::default()).unwrap()
+    }
+
+    fn error_of<T>(result: Result<T>) -> String {
+        match result {
+            Ok(_) => panic!("expected the credential to be refused"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn registers_and_logs_in_with_es256_and_ed25519_passkeys() {
+        let rp = relying_party();
+        for authenticator in [Authenticator::es256(), Authenticator::ed25519()] {
+            let verified = rp
+                .verify_registration(&authenticator.register(CHALLENGE), CHALLENGE)
+                .unwrap();
+            assert_eq!(
+                verified.credential_id,
+                BASE64URL.encode(&authenticator.credential_id)
+            );
+            assert_eq!(
+                verified.public_key,
+                BASE64URL.encode(authenticator.cose_key())
+            );
+
+            let passkey = authenticator.passkey(verified.sign_count as i64);
+            let sign_count = rp
+                .verify_authentication(
+                    &authenticator.assert(CHALLENGE, 1),
+                    CHALLENGE,
+                    &passkey,
+                    USER_HANDLE,
+                )
+                .unwrap();
+            assert_eq!(sign_count, 1);
+        }
+    }
+
+    #[test]
+    fn refuses_passkeys_without_user_presence_or_verification() {
+        let rp = relying_party();
+        for (flags, error) in [
+            (
+                FLAG_USER_PRESENT,
+                "User was not verified by the authenticator",
+            ),
+            (FLAG_USER_VERIFIED, "User presence was not confirmed"),
+        ] {
+            let mut authenticator = Authenticator::es256();
+            let passkey = authenticator.passkey(0);
+            authenticator.flags = flags;
+
+            let registration =
+                rp.verify_registration(&authenticator.register(CHALLENGE), CHALLENGE);
+            assert_eq!(error_of(registration), error);
+            let login = rp.verify_authentication(
+                &authenticator.assert(CHALLENGE, 1),
+                CHALLENGE,
+                &passkey,
+                USER_HANDLE,
+            );
+            assert_eq!(error_of(login), error);
+        }
+    }
+
+    #[test]
+    fn refuses_credentials_of_another_rp_id() {
+        let rp = relying_party();
+        let mut authenticator = Authenticator::es256();
+        let passkey = authenticator.passkey(0);
+        authenticator.rp_id = "evil.example".to_string();
+
+        let registration = rp.verify_registration(&authenticator.register(CHALLENGE), CHALLENGE);
+        assert_eq!(
+            error_of(registration),
+            "Credential belongs to a different RP ID"
+        );
+        let login = rp.verify_authentication(
+            &authenticator.assert(CHALLENGE, 1),
+            CHALLENGE,
+            &passkey,
+            USER_HANDLE,
+        );
+        assert_eq!(error_of(login), "Credential belongs to a different RP ID");
+    }
+
+    #[test]
+    fn refuses_a_signature_counter_that_did_not_increase() {
+        let rp = relying_party();
+        let authenticator = Authenticator::es256();
+        let passkey = authenticator.passkey(5);
+
+        for sign_count in [5, 3] {
+            let login = rp.verify_authentication(
+                &authenticator.assert(CHALLENGE, sign_count),
+                CHALLENGE,
+                &passkey,
+                USER_HANDLE,
+            );
+            assert!(error_of(login).starts_with("Signature counter went from 5"));
+        }
+
+        // Authenticators without a counter always send 0
+        let passkey = authenticator.passkey(0);
+        let sign_count = rp
+            .verify_authentication(
+                &authenticator.assert(CHALLENGE, 0),
+                CHALLENGE,
+                &passkey,
+                USER_HANDLE,
+            )
+            .unwrap();
+        assert_eq!(sign_count, 0);
+    }
+
+    #[test]
+    fn refuses_unsupported_algorithms() {
+        let rp = relying_party();
+        let authenticator = Authenticator::es256();
+        let int = |value: i64| Value::Integer(value.into());
+
+        // ES384 on P-384, and ES256 claimed for a P-384 key
+        for (alg, curve) in [(-35, 2), (-7, 2)] {
+            let cose_key = cbor(&Value::Map(vec![
+                (int(1), int(2)),
+                (int(3), int(alg)),
+                (int(-1), int(curve)),
+                (int(-2), Value::Bytes(vec![1; 48])),
+                (int(-3), Value::Bytes(vec![2; 48])),
+            ]));
+            let registration = rp.verify_registration(
+                &authenticator.register_with_key(CHALLENGE, &cose_key),
+                CHALLENGE,
+            );
+            assert!(error_of(registration).starts_with("Unsupported COSE key type"));
+        }
+    }
+
+    #[test]
+    fn refuses_a_forged_signature() {
+        let rp = relying_party();
+        let authenticator = Authenticator::es256();
+        let passkey = Authenticator::es256().passkey(0);
+
+        let login = rp.verify_authentication(
+            &authenticator.assert(CHALLENGE, 1),
+            CHALLENGE,
+            &passkey,
+            USER_HANDLE,
+        );
+        assert_eq!(error_of(login), "Invalid signature");
+    }
+
+    #[test]
+    fn refuses_client_data_of_another_ceremony() {
+        let rp = relying_party();
+        let authenticator = Authenticator::es256();
+        let passkey = authenticator.passkey(0);
+
+        let registration = rp.verify_registration(&authenticator.register(CHALLENGE), "other");
+        assert_eq!(error_of(registration), "Challenge does not match");
+
+        // A registration response replayed as a login
+        let mut login = authenticator.assert(CHALLENGE, 1);
+        login.response.client_data_json =
+            BASE64URL.encode(client_data("webauthn.create", CHALLENGE, ORIGIN));
+        let result = rp.verify_authentication(&login, CHALLENGE, &passkey, USER_HANDLE);
+        assert_eq!(error_of(result), "Unexpected ceremony type webauthn.create");
+
+        let mut login = authenticator.assert(CHALLENGE, 1);
+        login.response.client_data_json = BASE64URL.encode(client_data(
+            "webauthn.get",
+            CHALLENGE,
+            "https://evil.example",
+        ));
+        let result = rp.verify_authentication(&login, CHALLENGE, &passkey, USER_HANDLE);
+        assert_eq!(
+            error_of(result),
+            "Origin https://evil.example is not allowed"
+        );
+
+        let result = rp.verify_authentication(
+            &authenticator.assert(CHALLENGE, 1),
+            CHALLENGE,
+            &passkey,
+            b"someone-else",
+        );
+        assert_eq!(error_of(result), "User handle does not match the passkey");
+    }
+
+    #[test]
+    fn refuses_malformed_authenticator_data() {
+        assert_eq!(
+            error_of(parse_authenticator_data(&[0; 36])),
+            "Authenticator data is too short"
+        );
+
+        // Attested credential flag set, but the credential ID runs past the end
+        let mut data = vec![0; 32];
+        data.push(FLAG_ATTESTED_CREDENTIAL);
+        data.extend_from_slice(&[0; 4 + 16]);
+        data.extend_from_slice(&100u16.to_be_bytes());
+        data.extend_from_slice(&[0; 10]);
+        assert_eq!(
+            error_of(parse_authenticator_data(&data)),
+            "Attested credential data is too short"
+        );
+    }
+
+    #[test]
+    fn refuses_a_registration_whose_credential_id_does_not_match() {
+        let rp = relying_party();
+        let mut credential = Authenticator::es256().register(CHALLENGE);
+        credential.raw_id = BASE64URL.encode(b"credential-2");
+
+        let registration = rp.verify_registration(&credential, CHALLENGE);
+        assert_eq!(error_of(registration), "Credential ID does not match");
+    }
+}
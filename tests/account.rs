@@ -0,0 +1,208 @@
+//! Self-service account changes that could hand the account to someone else
+mod common;
+
+use std::time::Duration;
+
+use actix_web::rt::time::sleep;
+use common::{PASSWORD, TestServer};
+use makemeshort::utils::totp;
+use mongodb::bson::oid::ObjectId;
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+/// Number of messages written to the outbox so far, once background sends have finished
+async fn mail_count(server: &TestServer) -> usize {
+    sleep(Duration::from_millis(200)).await;
+    std::fs::read_dir(&server.state.config.mail.outbox_dir)
+        .map(|entries| entries.count())
+        .unwrap_or(0)
+}
+
+#[actix_web::test]
+async fn changing_the_email_address_takes_the_password() {
+    let server = TestServer::start().await;
+    server.create_user("jane").await;
+    let token = server.login("jane", PASSWORD).await;
+
+    let (status, body) = server
+        .put(
+            "/api/me",
+            &token,
+            json!({ "email": "attacker@example.com" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, body) = server
+        .put(
+            "/api/me",
+            &token,
+            json!({ "email": "attacker@example.com", "current_password": "wrong password" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+    // The name alone can be changed without it
+    let (status, body) = server
+        .put("/api/me", &token, json!({ "full_name": "Jane Doe" }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["email"].is_null());
+
+    // After the backoff from the wrong password
+    sleep(Duration::from_millis(1100)).await;
+    let (status, body) = server
+        .put(
+            "/api/me",
+            &token,
+            json!({ "email": "jane@example.com", "current_password": PASSWORD }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["email"], "jane@example.com");
+    assert!(body["email_verified_at"].is_null());
+}
+
+#[actix_web::test]
+async fn password_reset_is_only_sent_to_verified_addresses() {
+    let server = TestServer::start().await;
+    let user_id = server.create_user("jane").await;
+    let token = server.login("jane", PASSWORD).await;
+    let (status, _) = server
+        .put(
+            "/api/me",
+            &token,
+            json!({ "email": "jane@example.com", "current_password": PASSWORD }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let request_reset = || {
+        server.send(
+            server
+                .request(Method::POST, "/api/auth/password-reset")
+                .json(&json!({ "email": "jane@example.com" })),
+        )
+    };
+
+    // Only the verification email
+    let sent = mail_count(&server).await;
+    let (status, _) = request_reset().await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(mail_count(&server).await, sent);
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let object_id = ObjectId::parse_str(&user_id).unwrap();
+    assert!(
+        server
+            .state
+            .users
+            .set_email_verified(&object_id, "jane@example.com", now)
+            .await
+            .unwrap()
+    );
+    let (status, _) = request_reset().await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(mail_count(&server).await, sent + 1);
+}
+
+#[actix_web::test]
+async fn registering_a_passkey_takes_both_factors() {
+    let server = TestServer::start().await;
+    server.create_user("jane").await;
+    let token = server.login("jane", PASSWORD).await;
+    let (_, setup) = server.post("/api/auth/totp/setup", &token, json!({})).await;
+    let code = totp::code_at(
+        setup["secret"].as_str().unwrap(),
+        chrono::Utc::now().timestamp(),
+    )
+    .unwrap();
+    let (status, enabled) = server
+        .post("/api/auth/totp/enable", &token, json!({ "code": code }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", enabled);
+
+    let (status, options) = server
+        .post("/api/auth/passkeys/register/options", &token, json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let register = |password: &str, code: Option<&str>| {
+        server.post(
+            "/api/auth/passkeys/register",
+            &token,
+            json!({
+                "challenge_token": options["challenge_token"],
+                "name": "Laptop",
+                "credential": {
+                    "id": "AAAA",
+                    "rawId": "AAAA",
+                    "type": "public-key",
+                    "response": { "clientDataJSON": "AAAA", "attestationObject": "AAAA" },
+                },
+                "password": password,
+                "code": code,
+            }),
+        )
+    };
+
+    let (status, body) = register("wrong password", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+    sleep(Duration::from_millis(1100)).await;
+    let (status, body) = register(PASSWORD, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["detail"],
+        "code is required when two-factor authentication is enabled"
+    );
+
+    // With both factors the credential itself is checked, with the challenge still unused
+    let recovery_code = enabled["recovery_codes"][0].as_str().unwrap();
+    let (status, body) = register(PASSWORD, Some(recovery_code)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(
+        body["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid passkey"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn own_password_changes_and_deletion_are_audited() {
+    let server = TestServer::start().await;
+    let user_id = server.create_user("jane").await;
+    let token = server.login("jane", PASSWORD).await;
+    let (status, _) = server
+        .post(
+            "/api/me/password",
+            &token,
+            json!({ "current_password": PASSWORD, "new_password": "Another-password-2" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let token = server.login("jane", "Another-password-2").await;
+    let (status, _) = server
+        .send(
+            server
+                .request(Method::DELETE, "/api/me")
+                .bearer_auth(&token)
+                .json(&json!({ "password": "Another-password-2" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let admin = server.admin_token().await;
+    let (_, log) = server
+        .get(&format!("/api/audit?actor={}", user_id), &admin)
+        .await;
+    let events = log["events"].as_array().expect("events");
+    let actions: Vec<_> = events.iter().map(|event| &event["action"]).collect();
+    assert!(actions.contains(&&json!("user.delete")), "{}", log);
+    let update = events
+        .iter()
+        .find(|event| event["action"] == "user.update")
+        .expect("password change");
+    assert_eq!(update["changes"]["password"]["after"], "[redacted]");
+}
@@ -0,0 +1,158 @@
+//! API keys only reach the endpoints their scopes allow, and stop working with their owner
+mod common;
+
+use common::{PASSWORD, TestServer};
+use reqwest::{Method, StatusCode};
+use serde_json::{Value, json};
+
+/// Create an API key for the user, returning its ID and the key itself
+async fn create_key(
+    server: &TestServer,
+    token: &str,
+    user_id: &str,
+    scopes: Value,
+) -> (String, String) {
+    let (status, body) = server
+        .post(
+            &format!("/api/users/{}/keys", user_id),
+            token,
+            json!({ "name": "script", "scopes": scopes }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "create key: {}", body);
+    (
+        body["id"].as_str().unwrap().to_string(),
+        body["key"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn call_with_key(
+    server: &TestServer,
+    method: Method,
+    path: &str,
+    key: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let request = server.request(method, path).header("X-API-Key", key);
+    let request = match body {
+        Some(body) => request.json(&body),
+        None => request,
+    };
+    server.send(request).await
+}
+
+#[actix_web::test]
+async fn api_key_is_limited_to_its_scopes() {
+    let server = TestServer::start().await;
+    let user_id = server.create_user("dave").await;
+    let token = server.login("dave", PASSWORD).await;
+    let (_, read_key) = create_key(&server, &token, &user_id, json!(["links:read"])).await;
+    let link = json!({ "url": "https://example.com/" });
+
+    let (status, _) = call_with_key(&server, Method::GET, "/api/urls", &read_key, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = call_with_key(
+        &server,
+        Method::POST,
+        "/api/shorten",
+        &read_key,
+        Some(link.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["detail"], "API key is missing the links:write scope");
+
+    let (_, write_key) = create_key(&server, &token, &user_id, json!(["links:write"])).await;
+    let (status, created) = call_with_key(
+        &server,
+        Method::POST,
+        "/api/shorten",
+        &write_key,
+        Some(link),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+
+    let analytics = format!("/api/analytics/{}", created["short_code"].as_str().unwrap());
+    let (status, body) = call_with_key(&server, Method::GET, &analytics, &write_key, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        body["detail"],
+        "API key is missing the analytics:read scope"
+    );
+}
+
+#[actix_web::test]
+async fn api_key_cannot_manage_the_account() {
+    let server = TestServer::start().await;
+    let user_id = server.create_user("erin").await;
+    let token = server.login("erin", PASSWORD).await;
+    let all_scopes = json!([
+        "links:read",
+        "links:write",
+        "qr:read",
+        "qr:write",
+        "analytics:read"
+    ]);
+    let (_, key) = create_key(&server, &token, &user_id, all_scopes.clone()).await;
+
+    let keys = format!("/api/users/{}/keys", user_id);
+    let requests = [
+        (Method::GET, "/api/me", None),
+        (Method::GET, keys.as_str(), None),
+        (
+            Method::POST,
+            keys.as_str(),
+            Some(json!({ "name": "escalated", "scopes": all_scopes })),
+        ),
+        (Method::POST, "/api/auth/totp/setup", None),
+    ];
+    for (method, path, body) in requests {
+        let (status, body) = call_with_key(&server, method.clone(), path, &key, body).await;
+        assert_eq!(
+            status,
+            StatusCode::FORBIDDEN,
+            "{} {}: {}",
+            method,
+            path,
+            body
+        );
+        assert_eq!(
+            body["detail"],
+            "This endpoint cannot be used with an API key"
+        );
+    }
+}
+
+#[actix_web::test]
+async fn api_key_stops_working_when_deleted_or_owner_disabled() {
+    let server = TestServer::start().await;
+    let user_id = server.create_user("frank").await;
+    let token = server.login("frank", PASSWORD).await;
+    let (key_id, deleted_key) = create_key(&server, &token, &user_id, json!(["links:read"])).await;
+    let (_, key) = create_key(&server, &token, &user_id, json!(["links:read"])).await;
+
+    let (status, _) = server
+        .delete(&format!("/api/users/{}/keys/{}", user_id, key_id), &token)
+        .await;
+    assert!(status.is_success());
+    let (status, body) = call_with_key(&server, Method::GET, "/api/urls", &deleted_key, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "Invalid API key");
+
+    let (status, _) = call_with_key(&server, Method::GET, "/api/urls", &key, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let admin = server.admin_token().await;
+    let (status, body) = server
+        .put(
+            &format!("/api/users/{}", user_id),
+            &admin,
+            json!({ "is_active": false }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = call_with_key(&server, Method::GET, "/api/urls", &key, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,260 @@
+//! Logins: lockouts, two-factor authentication, passkey challenges and the password login switch
+mod common;
+
+use std::time::Duration;
+
+use actix_web::rt::time::sleep;
+use common::{PASSWORD, TestServer};
+use makemeshort::utils::totp;
+use reqwest::{Method, StatusCode};
+use serde_json::{Value, json};
+
+/// Long enough for the backoff after a single failed attempt to pass
+const BACKOFF: Duration = Duration::from_millis(1100);
+
+fn totp_code(secret: &str, offset_seconds: i64) -> String {
+    totp::code_at(secret, chrono::Utc::now().timestamp() + offset_seconds).expect("TOTP code")
+}
+
+#[actix_web::test]
+async fn account_is_locked_after_repeated_wrong_passwords() {
+    let server = TestServer::start_with(|config| config.auth.max_failed_logins = 2).await;
+    let user_id = server.create_user("bob").await;
+
+    let (status, _) = server.try_login("bob", "wrong password").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Right after a failure the account backs off, with the answer of a wrong password
+    let (status, body) = server.try_login("bob", PASSWORD).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "Invalid username or password");
+
+    sleep(BACKOFF).await;
+    let (status, _) = server.try_login("bob", "wrong password").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Locked now, so the right password is refused as well
+    let (status, body) = server.try_login("bob", PASSWORD).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "Invalid username or password");
+
+    let admin = server.admin_token().await;
+    let (_, user) = server.get(&format!("/api/users/{}", user_id), &admin).await;
+    assert!(user["locked_until"].is_i64(), "not locked: {}", user);
+
+    let (status, _) = server
+        .post(&format!("/api/users/{}/unlock", user_id), &admin, json!({}))
+        .await;
+    assert!(status.is_success());
+    server.login("bob", PASSWORD).await;
+}
+
+#[actix_web::test]
+async fn login_in_the_second_of_a_revocation_is_not_revoked() {
+    let server = TestServer::start().await;
+    server.create_user("ivy").await;
+    let old_token = server.login("ivy", PASSWORD).await;
+
+    // Start at the beginning of a second, so the change and the new login share it
+    let millis = chrono::Utc::now().timestamp_subsec_millis() as u64;
+    sleep(Duration::from_millis(1000 - millis + 10)).await;
+    let second = chrono::Utc::now().timestamp();
+
+    let (status, body) = server
+        .post(
+            "/api/me/password",
+            &old_token,
+            json!({ "current_password": PASSWORD, "new_password": "Another-password-2" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{}", body);
+    let new_token = server.login("ivy", "Another-password-2").await;
+    assert_eq!(chrono::Utc::now().timestamp(), second, "test took too long");
+
+    let (status, _) = server.get("/api/me", &old_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = server.get("/api/me", &new_token).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[actix_web::test]
+async fn client_ip_backs_off_after_a_failed_login() {
+    let server = TestServer::start().await;
+    let login = |username: &str| {
+        server
+            .request_from(Method::POST, "/api/auth/login", "192.0.2.1")
+            .json(&json!({ "username": username, "password": "wrong password" }))
+    };
+
+    let (status, _) = server.send(login("nobody")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let response = login("someone-else").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key("retry-after"));
+
+    // Other clients are not affected
+    let (status, _) = server.try_login("nobody", "wrong password").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// Log in with the password, then with a second factor code
+async fn login_with_code(server: &TestServer, username: &str, code: &str) -> (StatusCode, Value) {
+    let (status, body) = server.try_login(username, PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mfa_required"], true, "no challenge: {}", body);
+    assert!(body["token"].is_null());
+
+    server
+        .send(
+            server
+                .request(Method::POST, "/api/auth/login/totp")
+                .json(&json!({
+                    "challenge_token": body["challenge_token"],
+                    "code": code,
+                })),
+        )
+        .await
+}
+
+#[actix_web::test]
+async fn totp_codes_and_recovery_codes_work_once() {
+    let server = TestServer::start().await;
+    server.create_user("carol").await;
+    let token = server.login("carol", PASSWORD).await;
+
+    let (status, setup) = server.post("/api/auth/totp/setup", &token, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = setup["secret"].as_str().unwrap().to_string();
+
+    let (status, enabled) = server
+        .post(
+            "/api/auth/totp/enable",
+            &token,
+            json!({ "code": totp_code(&secret, 0) }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", enabled);
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(enabled["recovery_codes"].clone()).unwrap();
+
+    // The step used to enable it is spent, the next one is still accepted
+    let code = totp_code(&secret, 30);
+    let (status, body) = login_with_code(&server, "carol", &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["token"].is_string());
+
+    let (status, body) = login_with_code(&server, "carol", &code).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "Invalid authentication code");
+
+    sleep(BACKOFF).await;
+    let (status, _) = login_with_code(&server, "carol", &recovery_codes[0]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = login_with_code(&server, "carol", &recovery_codes[0]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Turning it off takes the password as well as a second factor
+    sleep(BACKOFF).await;
+    let (status, body) = server
+        .post(
+            "/api/auth/totp/disable",
+            &token,
+            json!({ "password": "wrong password", "code": recovery_codes[1] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+    sleep(BACKOFF * 2).await;
+    let (status, body) = server
+        .post(
+            "/api/auth/totp/disable",
+            &token,
+            json!({ "password": PASSWORD, "code": recovery_codes[1] }),
+        )
+        .await;
+    assert!(status.is_success(), "{}: {}", status, body);
+
+    let (status, body) = server.try_login("carol", PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+}
+
+fn unknown_passkey(challenge_token: &Value) -> Value {
+    json!({
+        "challenge_token": challenge_token,
+        "credential": {
+            "id": "AAAA",
+            "rawId": "AAAA",
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": "AAAA",
+                "authenticatorData": "AAAA",
+                "signature": "AAAA",
+            },
+        },
+    })
+}
+
+#[actix_web::test]
+async fn passkey_login_challenge_is_used_up_by_the_first_attempt() {
+    let server = TestServer::start().await;
+
+    let (status, options) = server
+        .send(server.request(Method::POST, "/api/auth/passkeys/login/options"))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let attempt = unknown_passkey(&options["challenge_token"]);
+
+    let (status, body) = server
+        .send(
+            server
+                .request(Method::POST, "/api/auth/passkeys/login")
+                .json(&attempt),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "Unknown passkey");
+
+    let (status, body) = server
+        .send(
+            server
+                .request(Method::POST, "/api/auth/passkeys/login")
+                .json(&attempt),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "Invalid or expired challenge token");
+}
+
+#[actix_web::test]
+async fn password_login_can_be_switched_off() {
+    let server = TestServer::start_with(|config| config.auth.password_login_enabled = false).await;
+
+    let requests = [
+        (
+            "/api/auth/login",
+            json!({ "username": "admin", "password": "x" }),
+        ),
+        (
+            "/api/auth/login/totp",
+            json!({ "challenge_token": "x", "code": "123456" }),
+        ),
+        (
+            "/api/auth/signup",
+            json!({ "username": "dave", "password": PASSWORD }),
+        ),
+        (
+            "/api/auth/password-reset",
+            json!({ "email": "dave@example.com" }),
+        ),
+        ("/api/auth/passkeys/login/options", json!({})),
+        ("/api/auth/passkeys/login", unknown_passkey(&json!("x"))),
+    ];
+    for (path, body) in requests {
+        let (status, body) = server
+            .send(server.request(Method::POST, path).json(&body))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}: {}", path, body);
+    }
+}
@@ -0,0 +1,191 @@
+//! Runs the service on the in-memory backend and talks to it over HTTP, as clients do
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use actix_web::{App, HttpServer, web};
+use makemeshort::config::app_config::Config;
+use makemeshort::db::memory::MemoryStore;
+use makemeshort::mail;
+use makemeshort::routes::init_routes;
+use makemeshort::state::app_state::AppState;
+use makemeshort::utils::jwt::JwtKeys;
+use makemeshort::utils::oidc::OidcClient;
+use makemeshort::utils::policy::AccountPolicy;
+use makemeshort::utils::webauthn::Webauthn;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde_json::{Value, json};
+
+pub const ADMIN_USERNAME: &str = "admin";
+pub const ADMIN_PASSWORD: &str = "correct horse battery";
+pub const PASSWORD: &str = "Secret-password-1";
+
+pub struct TestServer {
+    pub url: String,
+    pub state: web::Data<AppState>,
+    client: reqwest::Client,
+    next_ip: AtomicU32,
+}
+
+impl TestServer {
+    /// Start a server with the default configuration and the superuser created
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Start a server after adjusting the configuration
+    pub async fn start_with(configure: impl FnOnce(&mut Config)) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind test server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let mut config = Config::default();
+        config.server.public_url = url.clone();
+        // Requests name their client address in X-Forwarded-For, so the IP throttles
+        // can be tested without them getting in the way of each other
+        config.server.trusted_proxies = vec!["127.0.0.1".to_string()];
+        config.auth.jwt_secret = "integration-test-secret".to_string();
+        config.auth.argon2_memory_kib = 1024;
+        config.auth.argon2_iterations = 1;
+        config.auth.superuser_username = Some(ADMIN_USERNAME.to_string());
+        config.auth.superuser_password = Some(ADMIN_PASSWORD.to_string());
+        config.mail.outbox_dir = std::env::temp_dir()
+            .join(format!("makemeshort-outbox-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        configure(&mut config);
+
+        let jwt = JwtKeys::load(&config.auth).expect("JWT keys");
+        let mailer = mail::build_mailer(&config.mail).expect("mailer");
+        let policy = AccountPolicy::load(&config.policy).expect("account policy");
+        let oidc = OidcClient::from_config(&config).expect("OIDC client");
+        let webauthn = Webauthn::new(&config).expect("WebAuthn relying party");
+        let state = web::Data::new(AppState::new(
+            config,
+            jwt,
+            mailer,
+            policy,
+            oidc,
+            webauthn,
+            MemoryStore::new(),
+        ));
+
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .configure(init_routes)
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("listen")
+        .run();
+        actix_web::rt::spawn(server);
+
+        let server = Self {
+            url,
+            state,
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap(),
+            next_ip: AtomicU32::new(1),
+        };
+        let (status, body) = server
+            .send(server.request(Method::POST, "/api/auth/init"))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "create superuser: {}", body);
+        server
+    }
+
+    /// A request from a client address no earlier request used
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let ip = self.next_ip.fetch_add(1, Ordering::Relaxed);
+        self.request_from(method, path, &format!("10.0.{}.{}", ip / 256, ip % 256))
+    }
+
+    /// A request from the given client address
+    pub fn request_from(&self, method: Method, path: &str, ip: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.url, path))
+            .header("X-Forwarded-For", ip)
+    }
+
+    /// Send a request, returning the status and the JSON body, or null without one
+    pub async fn send(&self, request: RequestBuilder) -> (StatusCode, Value) {
+        let response = request.send().await.expect("send request");
+        let status = response.status();
+        let text = response.text().await.expect("read response");
+        (status, serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    pub async fn get(&self, path: &str, token: &str) -> (StatusCode, Value) {
+        self.send(self.request(Method::GET, path).bearer_auth(token))
+            .await
+    }
+
+    pub async fn post(&self, path: &str, token: &str, body: Value) -> (StatusCode, Value) {
+        self.send(
+            self.request(Method::POST, path)
+                .bearer_auth(token)
+                .json(&body),
+        )
+        .await
+    }
+
+    pub async fn put(&self, path: &str, token: &str, body: Value) -> (StatusCode, Value) {
+        self.send(
+            self.request(Method::PUT, path)
+                .bearer_auth(token)
+                .json(&body),
+        )
+        .await
+    }
+
+    pub async fn delete(&self, path: &str, token: &str) -> (StatusCode, Value) {
+        self.send(self.request(Method::DELETE, path).bearer_auth(token))
+            .await
+    }
+
+    /// Log in with a password, returning the status and response
+    pub async fn try_login(&self, username: &str, password: &str) -> (StatusCode, Value) {
+        self.send(
+            self.request(Method::POST, "/api/auth/login")
+                .json(&json!({ "username": username, "password": password })),
+        )
+        .await
+    }
+
+    /// Log in with a password, returning the access token
+    pub async fn login(&self, username: &str, password: &str) -> String {
+        let (status, body) = self.try_login(username, password).await;
+        assert_eq!(status, StatusCode::OK, "login {}: {}", username, body);
+        body["token"].as_str().expect("access token").to_string()
+    }
+
+    pub async fn admin_token(&self) -> String {
+        self.login(ADMIN_USERNAME, ADMIN_PASSWORD).await
+    }
+
+    /// Create a member with the shared test password, returning their ID
+    pub async fn create_user(&self, username: &str) -> String {
+        let admin = self.admin_token().await;
+        let (status, body) = self
+            .post(
+                "/api/users",
+                &admin,
+                json!({ "username": username, "password": PASSWORD }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "create {}: {}", username, body);
+        body["id"].as_str().expect("user ID").to_string()
+    }
+
+    /// Shorten a URL, returning the response
+    pub async fn shorten(&self, token: &str, body: Value) -> Value {
+        let (status, body) = self.post("/api/shorten", token, body).await;
+        assert_eq!(status, StatusCode::CREATED, "shorten: {}", body);
+        body
+    }
+}
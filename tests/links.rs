@@ -0,0 +1,165 @@
+//! Password-protected short URLs: the password form, access cookies, throttling and
+//! hiding the destination
+mod common;
+
+use std::time::Duration;
+
+use actix_web::rt::time::sleep;
+use common::{PASSWORD, TestServer};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+const DESTINATION: &str = "https://example.com/secret-plans";
+
+/// Enter a password in the form of a short URL
+async fn unlock(server: &TestServer, code: &str, password: &str) -> reqwest::Response {
+    server
+        .request(Method::POST, &format!("/r/{}", code))
+        .form(&[("password", password)])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// The `name=value` part of the access cookie set by a response
+fn access_cookie(response: &reqwest::Response) -> String {
+    let cookie = response
+        .headers()
+        .get("set-cookie")
+        .expect("access cookie")
+        .to_str()
+        .unwrap();
+    cookie.split(';').next().unwrap().to_string()
+}
+
+#[actix_web::test]
+async fn protected_link_asks_for_the_password() {
+    let server = TestServer::start().await;
+    server.create_user("paula").await;
+    let token = server.login("paula", PASSWORD).await;
+    let link = server
+        .shorten(
+            &token,
+            json!({ "url": DESTINATION, "password": "open sesame" }),
+        )
+        .await;
+    let code = link["short_code"].as_str().unwrap();
+    let visit = |cookie: Option<&str>| {
+        let request = server.request(Method::GET, &format!("/r/{}", code));
+        match cookie {
+            Some(cookie) => request.header("Cookie", cookie),
+            None => request,
+        }
+        .send()
+    };
+
+    // The form instead of a redirect
+    let response = visit(None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("location").is_none());
+    assert!(!response.text().await.unwrap().contains(DESTINATION));
+
+    let response = unlock(&server, code, "wrong").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get("location").is_none());
+
+    // Every guess slows down the next one on this URL, whatever address it comes from
+    let response = unlock(&server, code, "open sesame").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key("retry-after"));
+
+    sleep(Duration::from_millis(1100)).await;
+    let response = unlock(&server, code, "open sesame").await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], DESTINATION);
+    let cookie = access_cookie(&response);
+
+    // The cookie lets the visitor through without the form
+    let response = visit(Some(&cookie)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers()["location"], DESTINATION);
+
+    // Until the password is changed
+    let (status, _) = server
+        .send(
+            server
+                .request(Method::PATCH, &format!("/api/urls/{}", code))
+                .bearer_auth(&token)
+                .json(&json!({ "password": "new sesame" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let response = visit(Some(&cookie)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("location").is_none());
+}
+
+#[actix_web::test]
+async fn protected_link_destination_is_only_shown_to_its_owner() {
+    let server = TestServer::start().await;
+    server.create_user("quinn").await;
+    server.create_user("rita").await;
+    let owner = server.login("quinn", PASSWORD).await;
+    let other = server.login("rita", PASSWORD).await;
+    let link = server
+        .shorten(
+            &owner,
+            json!({ "url": DESTINATION, "password": "open sesame" }),
+        )
+        .await;
+    let code = link["short_code"].as_str().unwrap();
+
+    let (_, urls) = server.get("/api/urls", &owner).await;
+    assert_eq!(urls[0]["original_url"], DESTINATION);
+    let (_, analytics) = server
+        .get(&format!("/api/analytics/{}", code), &owner)
+        .await;
+    assert_eq!(analytics["original_url"], DESTINATION);
+
+    // Others never see where it leads, and searching for it only finds it by its short code
+    let (_, urls) = server.get("/api/urls", &other).await;
+    assert_eq!(urls[0]["short_code"], code);
+    assert!(urls[0]["original_url"].is_null());
+    let (_, urls) = server.get("/api/urls?search=secret", &other).await;
+    assert_eq!(urls, json!([]));
+    let (_, urls) = server
+        .get(&format!("/api/urls?search={}", code), &other)
+        .await;
+    assert_eq!(urls[0]["short_code"], code);
+    assert!(urls[0]["original_url"].is_null());
+    let (status, analytics) = server
+        .get(&format!("/api/analytics/{}", code), &other)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(analytics["original_url"].is_null());
+
+    let (status, _) = server
+        .get(&format!("/api/qr/{}/regenerate", code), &owner)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, qr_codes) = server.get("/api/qr", &other).await;
+    assert_eq!(qr_codes[0]["short_code"], code);
+    assert!(qr_codes[0]["original_url"].is_null());
+}
+
+#[actix_web::test]
+async fn wrong_passwords_only_delay_a_protected_link() {
+    // Enough failures to lock an account are not enough to lock the link
+    let server = TestServer::start_with(|config| config.auth.max_failed_logins = 1).await;
+    server.create_user("paula").await;
+    let token = server.login("paula", PASSWORD).await;
+    let link = server
+        .shorten(
+            &token,
+            json!({ "url": DESTINATION, "password": "open sesame" }),
+        )
+        .await;
+    let code = link["short_code"].as_str().unwrap();
+
+    let response = unlock(&server, code, "wrong").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    sleep(Duration::from_millis(1100)).await;
+    let response = unlock(&server, code, "open sesame").await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+}
@@ -0,0 +1,474 @@
+//! Single sign-on against a mock identity provider that serves discovery, the signing keys
+//! and the token endpoint, and checks PKCE like a real provider
+mod common;
+
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::Mutex;
+
+use actix_web::{App, HttpResponse, HttpServer, http::header, web};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use common::TestServer;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use mongodb::bson::oid::ObjectId;
+use reqwest::{Method, StatusCode, Url};
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+const CLIENT_ID: &str = "makemeshort";
+const KEY_ID: &str = "idp-key";
+
+/// The user logged in at the identity provider
+#[derive(Clone)]
+struct Identity {
+    sub: String,
+    username: String,
+    email: String,
+    email_verified: bool,
+}
+
+impl Identity {
+    fn new(sub: &str, username: &str, email: &str) -> Self {
+        Self {
+            sub: sub.to_string(),
+            username: username.to_string(),
+            email: email.to_string(),
+            email_verified: true,
+        }
+    }
+}
+
+/// An authorization code handed out by the provider and not yet redeemed
+struct Grant {
+    redirect_uri: String,
+    code_challenge: String,
+    nonce: String,
+    identity: Identity,
+}
+
+struct Provider {
+    issuer: String,
+    signing_key: Vec<u8>, // PKCS#8
+    jwk: Value,
+    identity: Mutex<Identity>,
+    grants: Mutex<HashMap<String, Grant>>,
+    nonce_override: Mutex<Option<String>>, // Put in ID tokens instead of the requested nonce
+}
+
+struct MockIdp {
+    provider: web::Data<Provider>,
+}
+
+impl MockIdp {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind identity provider");
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        // Uncompressed point: 0x04, then x and y
+        let point = key_pair.public_key().as_ref();
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+            "kid": KEY_ID,
+            "alg": "ES256",
+            "use": "sig",
+        });
+
+        let provider = web::Data::new(Provider {
+            issuer,
+            signing_key: pkcs8.as_ref().to_vec(),
+            jwk,
+            identity: Mutex::new(Identity::new("nobody", "nobody", "nobody@example.com")),
+            grants: Mutex::default(),
+            nonce_override: Mutex::default(),
+        });
+
+        let app_provider = provider.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_provider.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(discovery),
+                )
+                .route("/jwks", web::get().to(jwks))
+                .route("/authorize", web::get().to(authorize))
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("listen")
+        .run();
+        actix_web::rt::spawn(server);
+
+        Self { provider }
+    }
+
+    fn url(&self) -> String {
+        self.provider.issuer.clone()
+    }
+
+    fn log_in_as(&self, identity: Identity) {
+        *self.provider.identity.lock().unwrap() = identity;
+    }
+}
+
+async fn discovery(provider: web::Data<Provider>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+    }))
+}
+
+async fn jwks(provider: web::Data<Provider>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "keys": [provider.jwk] }))
+}
+
+/// Log in the current identity straight away and send the browser back with a code
+async fn authorize(
+    provider: web::Data<Provider>,
+    web::Query(query): web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    assert_eq!(query["client_id"], CLIENT_ID);
+    assert_eq!(query["response_type"], "code");
+    assert_eq!(query["code_challenge_method"], "S256");
+
+    let code = uuid::Uuid::new_v4().to_string();
+    provider.grants.lock().unwrap().insert(
+        code.clone(),
+        Grant {
+            redirect_uri: query["redirect_uri"].clone(),
+            code_challenge: query["code_challenge"].clone(),
+            nonce: query["nonce"].clone(),
+            identity: provider.identity.lock().unwrap().clone(),
+        },
+    );
+
+    let location = Url::parse_with_params(
+        &query["redirect_uri"],
+        &[("code", code.as_str()), ("state", query["state"].as_str())],
+    )
+    .unwrap();
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, location.as_str()))
+        .finish()
+}
+
+fn invalid_grant(description: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": "invalid_grant",
+        "error_description": description,
+    }))
+}
+
+/// Redeem a code once, if the verifier matches the challenge of the authorization request
+async fn token(
+    provider: web::Data<Provider>,
+    web::Form(form): web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+    let Some(grant) = provider.grants.lock().unwrap().remove(&form["code"]) else {
+        return invalid_grant("unknown code");
+    };
+    if form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        || form["redirect_uri"] != grant.redirect_uri
+    {
+        return invalid_grant("code was issued to another client");
+    }
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+    if challenge != grant.code_challenge {
+        return invalid_grant("PKCE verification failed");
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let nonce = provider
+        .nonce_override
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or(grant.nonce);
+    let claims = json!({
+        "iss": provider.issuer,
+        "aud": CLIENT_ID,
+        "sub": grant.identity.sub,
+        "email": grant.identity.email,
+        "email_verified": grant.identity.email_verified,
+        "preferred_username": grant.identity.username,
+        "nonce": nonce,
+        "iat": now,
+        "exp": now + 300,
+    });
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = jsonwebtoken::encode(
+        &header,
+        &claims,
+        &EncodingKey::from_ec_der(&provider.signing_key),
+    )
+    .unwrap();
+
+    HttpResponse::Ok().json(json!({
+        "access_token": "unused",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))
+}
+
+/// A login started at the service and approved at the provider, up to the callback
+struct PendingLogin {
+    state_cookie: String,
+    callback: Url,
+}
+
+async fn start_login(server: &TestServer) -> PendingLogin {
+    let response = server
+        .request(Method::GET, "/api/auth/oidc/login")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let state_cookie = response.headers()["set-cookie"]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    let authorization_url = response.headers()["location"].to_str().unwrap().to_string();
+
+    let browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = browser.get(&authorization_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let callback = Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+
+    PendingLogin {
+        state_cookie,
+        callback,
+    }
+}
+
+/// Call the callback with the given query and state cookie
+async fn callback(
+    server: &TestServer,
+    query: &str,
+    state_cookie: Option<&str>,
+) -> (StatusCode, Value) {
+    let request = server.request(Method::GET, &format!("/api/auth/oidc/callback?{}", query));
+    let request = match state_cookie {
+        Some(cookie) => request.header("Cookie", cookie),
+        None => request,
+    };
+    server.send(request).await
+}
+
+impl PendingLogin {
+    fn query(&self) -> String {
+        self.callback.query().unwrap().to_string()
+    }
+
+    fn param(&self, name: &str) -> String {
+        self.callback
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .unwrap()
+            .1
+            .into_owned()
+    }
+
+    async fn finish(&self, server: &TestServer) -> (StatusCode, Value) {
+        callback(server, &self.query(), Some(&self.state_cookie)).await
+    }
+}
+
+/// Log in through the provider as the identity, returning the user it logged in as
+async fn log_in(server: &TestServer, idp: &MockIdp, identity: Identity) -> Value {
+    idp.log_in_as(identity);
+    let (status, body) = start_login(server).await.finish(server).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["token"].is_string());
+    body["user"].clone()
+}
+
+async fn start(
+    idp: &MockIdp,
+    configure: impl FnOnce(&mut makemeshort::config::app_config::Config),
+) -> TestServer {
+    let issuer = idp.url();
+    TestServer::start_with(|config| {
+        config.oidc.issuer_url = Some(issuer);
+        config.oidc.client_id = CLIENT_ID.to_string();
+        configure(config);
+    })
+    .await
+}
+
+#[actix_web::test]
+async fn identity_logs_in_as_the_same_user_every_time() {
+    let idp = MockIdp::start().await;
+    let server = start(&idp, |_| {}).await;
+
+    let user = log_in(
+        &server,
+        &idp,
+        Identity::new("sub-1", "alice", "alice@example.com"),
+    )
+    .await;
+    assert_eq!(user["username"], "alice");
+    assert!(user["email_verified_at"].is_i64());
+
+    // The identity is known by its subject, whatever the provider says about it now
+    let again = log_in(
+        &server,
+        &idp,
+        Identity::new("sub-1", "alicia", "alicia@example.com"),
+    )
+    .await;
+    assert_eq!(again["id"], user["id"]);
+
+    // Another identity with a taken username gets one of its own
+    let other = log_in(
+        &server,
+        &idp,
+        Identity::new("sub-2", "alice", "other@example.com"),
+    )
+    .await;
+    assert_ne!(other["id"], user["id"]);
+    assert_eq!(other["username"], "other");
+}
+
+#[actix_web::test]
+async fn identity_is_linked_to_the_user_with_the_same_verified_email() {
+    let idp = MockIdp::start().await;
+    let server = start(&idp, |_| {}).await;
+    let admin = server.admin_token().await;
+
+    let mut users = HashMap::new();
+    for (username, verified) in [("bob", true), ("carol", false)] {
+        let email = format!("{}@example.com", username);
+        let (status, body) = server
+            .post(
+                "/api/users",
+                &admin,
+                json!({ "username": username, "password": common::PASSWORD, "email": email }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        let user_id = body["id"].as_str().unwrap().to_string();
+        if verified {
+            let object_id = ObjectId::parse_str(&user_id).unwrap();
+            server
+                .state
+                .users
+                .set_email_verified(&object_id, &email, chrono::Utc::now().timestamp_millis())
+                .await
+                .unwrap();
+        }
+        users.insert(username, user_id);
+    }
+
+    let user = log_in(
+        &server,
+        &idp,
+        Identity::new("idp-bob", "robert", "bob@example.com"),
+    )
+    .await;
+    assert_eq!(user["id"], users["bob"]);
+
+    // Neither an unverified claim nor an unverified account is enough
+    let mut unverified = Identity::new("idp-bob-2", "bobby", "bob@example.com");
+    unverified.email_verified = false;
+    let user = log_in(&server, &idp, unverified).await;
+    assert_ne!(user["id"], users["bob"]);
+
+    let user = log_in(
+        &server,
+        &idp,
+        Identity::new("idp-carol", "caroline", "carol@example.com"),
+    )
+    .await;
+    assert_ne!(user["id"], users["carol"]);
+}
+
+#[actix_web::test]
+async fn unknown_identity_is_refused_without_user_creation() {
+    let idp = MockIdp::start().await;
+    let server = start(&idp, |config| config.oidc.create_users = false).await;
+
+    idp.log_in_as(Identity::new("sub-1", "dave", "dave@example.com"));
+    let (status, body) = start_login(&server).await.finish(&server).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+}
+
+#[actix_web::test]
+async fn callback_needs_the_state_of_the_login_started_in_the_browser() {
+    let idp = MockIdp::start().await;
+    let server = start(&idp, |_| {}).await;
+    idp.log_in_as(Identity::new("sub-1", "erin", "erin@example.com"));
+
+    let login = start_login(&server).await;
+    let other = start_login(&server).await;
+
+    let (status, body) = callback(&server, &login.query(), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let tampered = format!("code={}&state=forged", login.param("code"));
+    let (status, _) = callback(&server, &tampered, Some(&login.state_cookie)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = callback(&server, &login.query(), Some(&other.state_cookie)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The code was never redeemed, so the real callback still works
+    let (status, body) = login.finish(&server).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[actix_web::test]
+async fn code_of_another_login_fails_pkce() {
+    let idp = MockIdp::start().await;
+    let server = start(&idp, |_| {}).await;
+    idp.log_in_as(Identity::new("sub-1", "frank", "frank@example.com"));
+
+    // A code from the victim's login, injected into the attacker's own login
+    let victim = start_login(&server).await;
+    let attacker = start_login(&server).await;
+    let injected = format!(
+        "code={}&state={}",
+        victim.param("code"),
+        attacker.param("state")
+    );
+
+    let (status, body) = callback(&server, &injected, Some(&attacker.state_cookie)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", body);
+    assert_eq!(body["detail"], "Login with the identity provider failed");
+}
+
+#[actix_web::test]
+async fn id_token_must_carry_the_nonce_of_the_login() {
+    let idp = MockIdp::start().await;
+    let server = start(&idp, |_| {}).await;
+    idp.log_in_as(Identity::new("sub-1", "gina", "gina@example.com"));
+
+    *idp.provider.nonce_override.lock().unwrap() = Some("nonce-of-another-login".to_string());
+    let (status, body) = start_login(&server).await.finish(&server).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", body);
+
+    *idp.provider.nonce_override.lock().unwrap() = None;
+    let (status, body) = start_login(&server).await.finish(&server).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
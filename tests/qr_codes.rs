@@ -0,0 +1,68 @@
+//! QR codes are only regenerated by those who may change them, and keep their owner
+mod common;
+
+use common::{PASSWORD, TestServer};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+#[actix_web::test]
+async fn only_those_who_may_change_a_link_regenerate_its_qr_code() {
+    let server = TestServer::start().await;
+    let owner_id = server.create_user("quinn").await;
+    server.create_user("rita").await;
+    let owner = server.login("quinn", PASSWORD).await;
+    let other = server.login("rita", PASSWORD).await;
+    let link = server
+        .shorten(&owner, json!({ "url": "https://example.com/" }))
+        .await;
+    let regenerate = format!(
+        "/api/qr/{}/regenerate?force=true",
+        link["short_code"].as_str().unwrap()
+    );
+
+    let (status, _) = server.get(&regenerate, &other).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = server.get(&regenerate, &owner).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Links of a deleted user are disabled, and so are their QR codes
+    let admin = server.admin_token().await;
+    let (status, _) = server
+        .delete(&format!("/api/users/{}?policy=disable", owner_id), &admin)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = server.get(&regenerate, &admin).await;
+    assert_eq!(status, StatusCode::GONE);
+}
+
+#[actix_web::test]
+async fn regenerating_a_direct_qr_code_keeps_its_owner() {
+    let server = TestServer::start().await;
+    let owner_id = server.create_user("quinn").await;
+    server.create_user("rita").await;
+    let owner = server.login("quinn", PASSWORD).await;
+    let other = server.login("rita", PASSWORD).await;
+    let body = |force: bool| json!({ "url": "https://example.com/", "force_regenerate": force });
+
+    for (token, force, expected) in [
+        (&owner, false, StatusCode::OK),
+        (&other, false, StatusCode::OK),
+        (&other, true, StatusCode::FORBIDDEN),
+        (&owner, true, StatusCode::OK),
+    ] {
+        let (status, _) = server
+            .send(
+                server
+                    .request(Method::POST, "/api/qr")
+                    .bearer_auth(token)
+                    .json(&body(force)),
+            )
+            .await;
+        assert_eq!(status, expected, "force: {}", force);
+    }
+
+    let (_, qr_codes) = server.get("/api/qr", &owner).await;
+    let qr_codes = qr_codes.as_array().unwrap();
+    assert_eq!(qr_codes.len(), 1);
+    assert_eq!(qr_codes[0]["user_id"], owner_id);
+}
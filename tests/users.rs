@@ -0,0 +1,70 @@
+//! Admins managing users: role changes take effect at once, and an admin is always left
+mod common;
+
+use common::{ADMIN_PASSWORD, PASSWORD, TestServer};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+#[actix_web::test]
+async fn last_active_admin_cannot_be_demoted_disabled_or_deleted() {
+    let server = TestServer::start().await;
+    let admin = server.admin_token().await;
+    let (_, me) = server.get("/api/me", &admin).await;
+    let admin_path = format!("/api/users/{}", me["id"].as_str().unwrap());
+
+    for body in [json!({ "role": "member" }), json!({ "is_active": false })] {
+        let (status, body) = server.put(&admin_path, &admin, body).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    }
+    let (status, body) = server.delete(&admin_path, &admin).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    let (status, body) = server
+        .send(
+            server
+                .request(Method::DELETE, "/api/me")
+                .bearer_auth(&admin)
+                .json(&json!({ "password": ADMIN_PASSWORD })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    // With a second admin the first one can step down
+    let user_id = server.create_user("bob").await;
+    let (status, _) = server
+        .put(
+            &format!("/api/users/{}", user_id),
+            &admin,
+            json!({ "role": "admin" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = server
+        .put(&admin_path, &admin, json!({ "role": "member" }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[actix_web::test]
+async fn changing_the_role_ends_the_sessions() {
+    let server = TestServer::start().await;
+    let user_id = server.create_user("bob").await;
+    let token = server.login("bob", PASSWORD).await;
+    let admin = server.admin_token().await;
+
+    let (status, _) = server
+        .put(
+            &format!("/api/users/{}", user_id),
+            &admin,
+            json!({ "role": "read_only" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = server.get("/api/me", &token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // A new login picks up the new role
+    let token = server.login("bob", PASSWORD).await;
+    let (status, me) = server.get("/api/me", &token).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["role"], "read_only");
+}
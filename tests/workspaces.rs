@@ -0,0 +1,243 @@
+//! Workspace roles decide who may change and inspect a workspace's links, and deleting a
+//! user takes their credentials and personal links with them
+mod common;
+
+use common::{PASSWORD, TestServer};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+/// Log in and switch to a workspace, returning the new access token
+async fn switch_to(server: &TestServer, username: &str, workspace_id: &str) -> String {
+    let (status, session) = server.try_login(username, PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = server
+        .post(
+            "/api/workspaces/switch",
+            session["token"].as_str().unwrap(),
+            json!({ "workspace_id": workspace_id, "refresh_token": session["refresh_token"] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "switch {}: {}", username, body);
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn add_member(
+    server: &TestServer,
+    owner: &str,
+    workspace_id: &str,
+    username: &str,
+    role: &str,
+) {
+    let (status, body) = server
+        .post(
+            &format!("/api/workspaces/{}/members", workspace_id),
+            owner,
+            json!({ "username": username, "role": role }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "add {}: {}", username, body);
+}
+
+async fn patch(server: &TestServer, path: &str, token: &str) -> StatusCode {
+    let (status, _) = server
+        .send(
+            server
+                .request(Method::PATCH, path)
+                .bearer_auth(token)
+                .json(&json!({ "title": "Renamed" })),
+        )
+        .await;
+    status
+}
+
+#[actix_web::test]
+async fn workspace_roles_decide_who_may_change_links() {
+    let server = TestServer::start().await;
+    for username in ["olivia", "eve", "victor", "mallory"] {
+        server.create_user(username).await;
+    }
+    let owner = server.login("olivia", PASSWORD).await;
+    let (status, workspace) = server
+        .post("/api/workspaces", &owner, json!({ "name": "Marketing" }))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let workspace_id = workspace["id"].as_str().unwrap();
+    add_member(&server, &owner, workspace_id, "eve", "editor").await;
+    add_member(&server, &owner, workspace_id, "victor", "viewer").await;
+
+    let editor = switch_to(&server, "eve", workspace_id).await;
+    let viewer = switch_to(&server, "victor", workspace_id).await;
+    let outsider = server.login("mallory", PASSWORD).await;
+
+    let link = server
+        .shorten(&editor, json!({ "url": "https://example.com/campaign" }))
+        .await;
+    assert_eq!(link["workspace_id"], workspace_id);
+    let code = link["short_code"].as_str().unwrap();
+    let url = format!("/api/urls/{}", code);
+    let history = format!("/api/urls/{}/history", code);
+
+    // Viewers see the links, but cannot add or change them
+    let (status, _) = server
+        .post(
+            "/api/shorten",
+            &viewer,
+            json!({ "url": "https://example.com/" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(patch(&server, &url, &viewer).await, StatusCode::FORBIDDEN);
+    let (status, _) = server.get(&history, &viewer).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = server
+        .get(&format!("/api/workspaces/{}/urls", workspace_id), &viewer)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Others cannot see into the workspace at all
+    assert_eq!(patch(&server, &url, &outsider).await, StatusCode::FORBIDDEN);
+    let (status, _) = server.delete(&url, &outsider).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = server.get(&history, &outsider).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = server
+        .get(&format!("/api/workspaces/{}", workspace_id), &outsider)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Editors change links; only owners manage the members
+    assert_eq!(patch(&server, &url, &editor).await, StatusCode::OK);
+    let (status, _) = server
+        .post(
+            &format!("/api/workspaces/{}/members", workspace_id),
+            &editor,
+            json!({ "username": "mallory", "role": "owner" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(patch(&server, &url, &owner).await, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn last_owner_of_a_workspace_cannot_be_deleted() {
+    let server = TestServer::start().await;
+    let owner_id = server.create_user("olivia").await;
+    let editor_id = server.create_user("eve").await;
+    let owner = server.login("olivia", PASSWORD).await;
+    let (_, workspace) = server
+        .post("/api/workspaces", &owner, json!({ "name": "Marketing" }))
+        .await;
+    let workspace_id = workspace["id"].as_str().unwrap();
+    add_member(&server, &owner, workspace_id, "eve", "editor").await;
+    let link = server
+        .shorten(
+            &switch_to(&server, "olivia", workspace_id).await,
+            json!({ "url": "https://example.com/campaign" }),
+        )
+        .await;
+
+    let admin = server.admin_token().await;
+    let (status, body) = server
+        .delete(&format!("/api/users/{}", owner_id), &admin)
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    // A refused deletion leaves the owner's sessions as they were
+    let (status, _) = server.get("/api/me", &owner).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = server
+        .put(
+            &format!("/api/workspaces/{}/members/{}", workspace_id, editor_id),
+            &owner,
+            json!({ "role": "owner" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = server
+        .delete(&format!("/api/users/{}", owner_id), &admin)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // The workspace and its links stay with the remaining owner
+    let remaining = server.login("eve", PASSWORD).await;
+    let (status, workspace) = server
+        .get(&format!("/api/workspaces/{}", workspace_id), &remaining)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(workspace["members"].as_array().unwrap().len(), 1);
+    let response = server
+        .request(
+            Method::GET,
+            &format!("/r/{}", link["short_code"].as_str().unwrap()),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+}
+
+#[actix_web::test]
+async fn deleting_a_user_removes_their_credentials_and_links() {
+    let server = TestServer::start().await;
+    let admin = server.admin_token().await;
+    let (_, admin_user) = server.get("/api/me", &admin).await;
+    let admin_id = admin_user["id"].as_str().unwrap();
+
+    // What becomes of the personal links depends on the policy
+    let cases = [
+        ("disable", StatusCode::GONE),
+        ("delete", StatusCode::NOT_FOUND),
+        ("reassign", StatusCode::FOUND),
+    ];
+    for (policy, visit_status) in cases {
+        let username = format!("user-{}", policy);
+        let user_id = server.create_user(&username).await;
+        let token = server.login(&username, PASSWORD).await;
+        let link = server
+            .shorten(&token, json!({ "url": "https://example.com/" }))
+            .await;
+        let code = link["short_code"].as_str().unwrap();
+        let (status, key) = server
+            .post(
+                &format!("/api/users/{}/keys", user_id),
+                &token,
+                json!({ "name": "script", "scopes": ["links:read"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let mut path = format!("/api/users/{}?policy={}", user_id, policy);
+        if policy == "reassign" {
+            path.push_str(&format!("&reassign_to={}", admin_id));
+        }
+        let (status, body) = server.delete(&path, &admin).await;
+        assert_eq!(status, StatusCode::NO_CONTENT, "{}: {}", policy, body);
+
+        let response = server
+            .request(Method::GET, &format!("/r/{}", code))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), visit_status, "{}", policy);
+
+        // Nothing the user held lets them back in
+        let (status, _) = server.get("/api/urls", &token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", policy);
+        let (status, _) = server
+            .send(
+                server
+                    .request(Method::GET, "/api/urls")
+                    .header("X-API-Key", key["key"].as_str().unwrap()),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", policy);
+        let (status, _) = server.try_login(&username, PASSWORD).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", policy);
+    }
+
+    let (_, urls) = server
+        .get(&format!("/api/users/{}/urls", admin_id), &admin)
+        .await;
+    assert_eq!(urls.as_array().unwrap().len(), 1);
+}